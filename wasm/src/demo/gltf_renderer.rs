@@ -1,5 +1,4 @@
-use generational_arena::Index;
-use gltf::Gltf;
+use js_sys::Error;
 use log::info;
 use na::{Point2, Point3, UnitQuaternion, Vector2, Vector3, Vector4};
 use ncollide3d::procedural::{unit_quad, TriMesh};
@@ -8,9 +7,13 @@ use std::result::Result as StdResult;
 use wasm_bindgen::prelude::*;
 use web_sys::HtmlImageElement;
 
+use crate::handle::Handle;
 use crate::renderer::webgl::context::{Context, TexParam, TextureFormat, TextureKind};
-use crate::renderer::webgl::material::{PbrMaterial, SkyboxMaterial};
+use crate::renderer::webgl::gltf::GltfLoadOptions;
+use crate::renderer::webgl::light::Light;
+use crate::renderer::webgl::material::{PbrMaterial, SkyboxMaterial, TextureTransform};
 use crate::renderer::webgl::pass::Pass;
+use crate::renderer::webgl::post_process::{PostEffect, PostProcess};
 use crate::renderer::webgl::renderer::{Camera, Renderer, Sampler};
 use crate::renderer::webgl::turntable::Turntable;
 use crate::scene::node::{compose_matrix, Node};
@@ -21,10 +24,13 @@ use super::webgl_canvas::WebGlCanvas;
 #[wasm_bindgen]
 pub struct GLTFRendererDemo {
   renderer: Renderer,
-  camera_handle: Index,
+  camera_handle: Handle<Camera>,
+  whale_handle: Handle<Node>,
   canvas: WebGlCanvas,
   turntable: Turntable,
   passes: Vec<Pass>,
+  post_process: PostProcess,
+  skipped_calls: usize,
 }
 
 #[wasm_bindgen]
@@ -43,7 +49,6 @@ impl GLTFRendererDemo {
   ) -> StdResult<GLTFRendererDemo, JsValue> {
     let canvas = WebGlCanvas::new()?;
     let ctx = Context::new(canvas.gl.clone());
-    let gltf = Gltf::from_slice(gltf_data).unwrap();
     let mut turntable = Turntable::new(20.0, 0.01);
 
     turntable.roll = PI / 4.0;
@@ -52,7 +57,9 @@ impl GLTFRendererDemo {
 
     let camera_handle = renderer.cameras.insert(Camera::default());
 
-    let whale_handles = renderer.bake_gltf(&gltf);
+    let whale_handles = renderer
+      .load_gltf(gltf_data, &GltfLoadOptions::default())
+      .map_err(|e| Error::new(&format!("{}", e)))?;
 
     renderer
       .scene
@@ -82,6 +89,15 @@ impl GLTFRendererDemo {
       ],
     );
 
+    let environment = renderer
+      .bake_environment(skybox_texture)
+      .map_err(|e| Error::new(&format!("{}", e)))?;
+
+    // baking leaves the viewport at its own sizes
+    renderer
+      .ctx
+      .viewport(0, 0, canvas.width as i32, canvas.height as i32);
+
     let skybox_geometry_handle = renderer.bake_cuboid_geometry(Vector3::new(1.0, 1.0, 1.0));
     let skybox_material_handle =
       renderer.bake_material(SkyboxMaterial::new(skybox_texture).boxed());
@@ -102,7 +118,9 @@ impl GLTFRendererDemo {
     let cuboid_material_handle = renderer.bake_material(
       PbrMaterial::new()
         .set_color(Vector3::new(0.0, 0.0, 1.0))
-        .set_debug_cube_map(Some(skybox_texture))
+        .set_metallic(1.0)
+        .set_roughness(0.2)
+        .set_environment(Some(environment))
         // .set_color_map(Some(render_target_texture))
         .boxed(),
     );
@@ -137,7 +155,8 @@ impl GLTFRendererDemo {
         .set_color(Vector3::new(0.0, 0.8, 0.2))
         .set_cull_face(false)
         .set_color_map(Some(ground_texture_handle))
-        .set_uv_repeating(Vector2::new(8.0, 8.0))
+        .set_environment(Some(environment))
+        .set_uv_transform(TextureTransform::new().set_scale(Vector2::new(8.0, 8.0)))
         .boxed(),
     );
 
@@ -159,6 +178,16 @@ impl GLTFRendererDemo {
 
     renderer.insert_node(ground_node);
 
+    // low sun, the cascades tighten the shadows near the camera over the 60x60 terrain
+    renderer.insert_light(
+      Light::directional(Vector3::new(-0.5, -1.0, -0.3))
+        .set_cascade_splits(vec![8.0, 25.0, 60.0])
+        .set_shadow_map_size(2048),
+    );
+
+    // whale material variants included, switching them later doesn't stall
+    renderer.warm_up_shaders(renderer.scene.get_root_handle());
+
     let passes = vec![Pass::new()
       .set_clean_color(true)
       .set_clean_depth(true)
@@ -166,13 +195,27 @@ impl GLTFRendererDemo {
       .set_handler(move |renderer| {
         renderer.render_scene(renderer.scene.get_root_handle(), camera_handle);
       })];
+    let post_process = PostProcess::new()
+      .add_effect(PostEffect::Bloom {
+        threshold: 0.9,
+        intensity: 0.5,
+        levels: 5,
+      })
+      .add_effect(PostEffect::Fxaa)
+      .add_effect(PostEffect::Vignette {
+        intensity: 0.3,
+        smoothness: 0.5,
+      });
 
     Ok(GLTFRendererDemo {
       camera_handle,
+      whale_handle: whale_handles[0],
       canvas,
       renderer,
       turntable,
       passes,
+      post_process,
+      skipped_calls: 0,
     })
   }
 
@@ -184,13 +227,58 @@ impl GLTFRendererDemo {
     self.turntable.rotate(Point2::new(x, y));
   }
 
+  pub fn get_material_variants(&self) -> Vec<JsValue> {
+    self
+      .renderer
+      .get_material_variants(self.whale_handle)
+      .iter()
+      .map(|name| JsValue::from_str(name))
+      .collect()
+  }
+
+  pub fn select_material_variant(&mut self, variant: Option<String>) {
+    self
+      .renderer
+      .select_material_variant(self.whale_handle, variant.as_deref());
+  }
+
+  // GL calls dropped by the state cache during the last frame
+  pub fn get_skipped_calls(&self) -> usize {
+    self.skipped_calls
+  }
+
+  pub fn export_glb(&self) -> StdResult<Vec<u8>, JsValue> {
+    self
+      .renderer
+      .export_glb(self.renderer.scene.get_root_handle())
+      .map_err(|e| Error::new(&format!("{}", e)).into())
+  }
+
+  pub fn get_shader_tags(&self) -> Vec<JsValue> {
+    self
+      .renderer
+      .shaders
+      .keys()
+      .iter()
+      .map(|key| JsValue::from_str(&key.to_string()))
+      .collect()
+  }
+
+  // hot swaps the GLSL of a cached program, the error lists the compiler diagnostics
+  pub fn reload_shader(
+    &mut self,
+    tag: &str,
+    vertex_src: &str,
+    fragment_src: &str,
+  ) -> StdResult<(), JsValue> {
+    tag
+      .parse()
+      .and_then(|key| self.renderer.reload_shader(key, vertex_src, fragment_src))
+      .map_err(|e| Error::new(&format!("{}", e)).into())
+  }
+
   pub fn update(&mut self) {
-    if self.canvas.check_size() {
-      self
-        .renderer
-        .ctx
-        .viewport(0, 0, self.canvas.width as i32, self.canvas.height as i32);
-    }
+    self.canvas.check_size();
 
     self.renderer.ctx.clear_color(1.0, 1.0, 1.0, 1.0);
     self.renderer.ctx.clear(true, true);
@@ -210,9 +298,25 @@ impl GLTFRendererDemo {
       .turntable
       .update_camera(&mut self.renderer, self.camera_handle);
 
-    for pass in &self.passes {
-      pass.render(&mut self.renderer);
-    }
+    self
+      .renderer
+      .render_shadows(self.renderer.scene.get_root_handle(), self.camera_handle);
+
+    // sets the viewport the shadow maps changed back to the canvas size
+    let passes = &self.passes;
+
+    self.post_process.render(
+      &mut self.renderer,
+      self.canvas.width,
+      self.canvas.height,
+      |renderer| {
+        for pass in passes {
+          pass.render(renderer);
+        }
+      },
+    );
+
+    self.skipped_calls = self.renderer.ctx.take_skipped_calls();
   }
 }
 
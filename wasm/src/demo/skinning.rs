@@ -3,9 +3,9 @@ use js_sys::Error;
 use na::{Isometry3, Matrix4, Orthographic3, Vector3};
 use std::result::Result as StdResult;
 use wasm_bindgen::prelude::*;
-use web_sys::WebGlTexture;
 
 use super::webgl_canvas::WebGlCanvas;
+use crate::renderer::webgl::backend::{as_bytes, Backend, GpuTexture, TextureLayout};
 use crate::renderer::webgl::context::{
   BufferTarget, BufferUsage, Context, DrawMode, TexParam, TexParamName, TextureFormat, TextureKind,
   TypedArrayKind,
@@ -18,7 +18,7 @@ pub struct SkinningDemo {
   canvas: WebGlCanvas,
   ctx: Context,
   shader: Shader,
-  bone_matrix_texture: Option<WebGlTexture>,
+  bone_matrix_texture: Option<GpuTexture>,
   angle: f32,
   inverse_bone: Vec<Matrix4<f32>>,
 }
@@ -81,21 +81,15 @@ impl SkinningDemo {
 
     self
       .ctx
-      .bind_texture(TextureKind::Texture2d, self.bone_matrix_texture.as_ref());
+      .bind_texture(TextureKind::Texture2d, self.bone_matrix_texture);
 
     let bone_data = bone_matrices_to_vec(&bone_matrices);
 
     self
       .ctx
       .texture_data(
-        TextureKind::Texture2d,
-        0,
-        TextureFormat::RGBA,
-        4,
-        4,
-        0,
-        TextureFormat::RGBA,
-        &bone_data,
+        &TextureLayout::new(4, 4, TextureFormat::RGBA, TypedArrayKind::Float32),
+        as_bytes(&bone_data),
       )
       .map_err(|e| Error::new(&format!("{}", e)))?;
 
@@ -135,7 +129,7 @@ fn bone_matrices_to_vec(matrices: &[Matrix4<f32>]) -> Vec<f32> {
   data
 }
 
-fn create_skinning_stuff(ctx: &Context) -> Result<(Shader, Option<WebGlTexture>)> {
+fn create_skinning_stuff(ctx: &Context) -> Result<(Shader, Option<GpuTexture>)> {
   ctx.get_extension("OES_texture_float")?;
 
   let vert_src = include_str!("./shaders/skinning_vert.glsl");
@@ -159,7 +153,7 @@ fn create_skinning_stuff(ctx: &Context) -> Result<(Shader, Option<WebGlTexture>)
   let position_buffer = ctx.create_buffer(
     BufferTarget::ArrayBuffer,
     BufferUsage::StaticDraw,
-    &position,
+    as_bytes(&position),
   );
 
   let bone_ndx: Vec<f32> = vec![
@@ -178,7 +172,7 @@ fn create_skinning_stuff(ctx: &Context) -> Result<(Shader, Option<WebGlTexture>)
   let bone_ndx_buffer = ctx.create_buffer(
     BufferTarget::ArrayBuffer,
     BufferUsage::StaticDraw,
-    &bone_ndx,
+    as_bytes(&bone_ndx),
   );
 
   let weight: Vec<f32> = vec![
@@ -194,8 +188,11 @@ fn create_skinning_stuff(ctx: &Context) -> Result<(Shader, Option<WebGlTexture>)
     1.0, 0.0, 0.0, 0.0, // 9
   ];
 
-  let weight_buffer =
-    ctx.create_buffer(BufferTarget::ArrayBuffer, BufferUsage::StaticDraw, &weight);
+  let weight_buffer = ctx.create_buffer(
+    BufferTarget::ArrayBuffer,
+    BufferUsage::StaticDraw,
+    as_bytes(&weight),
+  );
 
   let indices: Vec<u16> = vec![
     0, 1, 0, 2, 1, 3, 2, 3, //
@@ -206,12 +203,12 @@ fn create_skinning_stuff(ctx: &Context) -> Result<(Shader, Option<WebGlTexture>)
   let indices_buffer = ctx.create_buffer(
     BufferTarget::ElementArrayBuffer,
     BufferUsage::StaticDraw,
-    &indices,
+    as_bytes(&indices),
   );
 
   let bone_matrix_texture = ctx.create_texture();
 
-  ctx.bind_texture(TextureKind::Texture2d, bone_matrix_texture.as_ref());
+  ctx.bind_texture(TextureKind::Texture2d, bone_matrix_texture);
 
   ctx.texture_parameter(
     TextureKind::Texture2d,
@@ -238,25 +235,25 @@ fn create_skinning_stuff(ctx: &Context) -> Result<(Shader, Option<WebGlTexture>)
 
   ctx.switch_attributes(3);
 
-  ctx.bind_buffer(BufferTarget::ArrayBuffer, position_buffer.as_ref());
+  ctx.bind_buffer(BufferTarget::ArrayBuffer, position_buffer);
   shader.bind_attribute(
     &AttributeName::Position,
     &AttributeOptions::new(TypedArrayKind::Float32, 2),
   );
 
-  ctx.bind_buffer(BufferTarget::ArrayBuffer, bone_ndx_buffer.as_ref());
+  ctx.bind_buffer(BufferTarget::ArrayBuffer, bone_ndx_buffer);
   shader.bind_attribute(
     &AttributeName::from_string("boneNdx"),
     &AttributeOptions::new(TypedArrayKind::Float32, 4),
   );
 
-  ctx.bind_buffer(BufferTarget::ArrayBuffer, weight_buffer.as_ref());
+  ctx.bind_buffer(BufferTarget::ArrayBuffer, weight_buffer);
   shader.bind_attribute(
     &AttributeName::from_string("weight"),
     &AttributeOptions::new(TypedArrayKind::Float32, 4),
   );
 
-  ctx.bind_buffer(BufferTarget::ElementArrayBuffer, indices_buffer.as_ref());
+  ctx.bind_buffer(BufferTarget::ElementArrayBuffer, indices_buffer);
 
   Ok((shader, bone_matrix_texture))
 }
@@ -0,0 +1,134 @@
+use generational_arena::{Arena, Index};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops;
+
+// Index into a Pool that remembers what it points at, so a texture handle can't be
+// passed where a material is expected.
+pub struct Handle<T: ?Sized> {
+  index: Index,
+  marker: PhantomData<fn() -> Box<T>>,
+}
+
+impl<T: ?Sized> Handle<T> {
+  pub fn new(index: Index) -> Self {
+    Handle {
+      index,
+      marker: PhantomData,
+    }
+  }
+
+  pub fn index(&self) -> Index {
+    self.index
+  }
+}
+
+impl<T: ?Sized> Clone for Handle<T> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<T: ?Sized> Copy for Handle<T> {}
+
+impl<T: ?Sized> PartialEq for Handle<T> {
+  fn eq(&self, other: &Self) -> bool {
+    self.index == other.index
+  }
+}
+
+impl<T: ?Sized> Eq for Handle<T> {}
+
+impl<T: ?Sized> Hash for Handle<T> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.index.hash(state);
+  }
+}
+
+impl<T: ?Sized> fmt::Debug for Handle<T> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let (index, generation) = self.index.into_raw_parts();
+
+    write!(f, "Handle({}, {})", index, generation)
+  }
+}
+
+// Arena handing out typed handles. H differs from T only for boxed trait objects,
+// e.g. Pool<Box<dyn Material>, dyn Material> gives Handle<dyn Material>.
+#[derive(Debug)]
+pub struct Pool<T, H: ?Sized = T> {
+  arena: Arena<T>,
+  marker: PhantomData<fn() -> Box<H>>,
+}
+
+impl<T, H: ?Sized> Default for Pool<T, H> {
+  fn default() -> Self {
+    Pool {
+      arena: Arena::new(),
+      marker: PhantomData,
+    }
+  }
+}
+
+impl<T, H: ?Sized> Pool<T, H> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn insert(&mut self, item: T) -> Handle<H> {
+    Handle::new(self.arena.insert(item))
+  }
+
+  pub fn get(&self, handle: Handle<H>) -> Option<&T> {
+    self.arena.get(handle.index)
+  }
+
+  pub fn get_mut(&mut self, handle: Handle<H>) -> Option<&mut T> {
+    self.arena.get_mut(handle.index)
+  }
+
+  pub fn remove(&mut self, handle: Handle<H>) -> Option<T> {
+    self.arena.remove(handle.index)
+  }
+
+  pub fn contains(&self, handle: Handle<H>) -> bool {
+    self.arena.contains(handle.index)
+  }
+
+  pub fn len(&self) -> usize {
+    self.arena.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.arena.is_empty()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (Handle<H>, &T)> {
+    self
+      .arena
+      .iter()
+      .map(|(index, item)| (Handle::new(index), item))
+  }
+
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<H>, &mut T)> {
+    self
+      .arena
+      .iter_mut()
+      .map(|(index, item)| (Handle::new(index), item))
+  }
+}
+
+impl<T, H: ?Sized> ops::Index<Handle<H>> for Pool<T, H> {
+  type Output = T;
+
+  fn index(&self, handle: Handle<H>) -> &T {
+    &self.arena[handle.index]
+  }
+}
+
+impl<T, H: ?Sized> ops::IndexMut<Handle<H>> for Pool<T, H> {
+  fn index_mut(&mut self, handle: Handle<H>) -> &mut T {
+    &mut self.arena[handle.index]
+  }
+}
@@ -2,5 +2,6 @@ extern crate generational_arena;
 extern crate nalgebra as na;
 
 pub mod demo;
+pub mod handle;
 pub mod renderer;
 pub mod scene;
@@ -0,0 +1,279 @@
+use gltf::Gltf;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use super::gltf::{GltfError, GltfLoadOptions, GltfRawExtensions};
+use super::light::Light;
+use super::material::Material;
+use super::renderer::{Accessor, InstanceSet, Mesh, Renderer, Texture};
+use crate::handle::Handle;
+use crate::scene::node::Node;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum AssetKey {
+  Url(String),
+  ContentHash(u64),
+}
+
+impl AssetKey {
+  pub fn url(url: &str) -> Self {
+    AssetKey::Url(url.to_string())
+  }
+
+  pub fn from_content(data: &[u8]) -> Self {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+
+    AssetKey::ContentHash(hasher.finish())
+  }
+}
+
+// everything baked for one asset, freed together when the last user releases it. Buffers,
+// images, samplers and geometries go with the accessors, textures and meshes using them.
+#[derive(Debug, Clone, Default)]
+pub struct AssetResources {
+  pub accessors: Vec<Handle<Accessor>>,
+  pub textures: Vec<Handle<Texture>>,
+  pub materials: Vec<Handle<dyn Material>>,
+  pub meshes: Vec<Handle<Mesh>>,
+  pub instance_sets: Vec<Handle<InstanceSet>>,
+  pub lights: Vec<Handle<Light>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedAsset {
+  pub scenes: Vec<Handle<Node>>,
+  pub default_scene: Option<Handle<Node>>,
+  pub resources: AssetResources,
+  pub options: GltfLoadOptions,
+  // subtrees created by instantiate_asset
+  pub instances: Vec<Handle<Node>>,
+  pub ref_count: usize,
+}
+
+pub type AssetCache = HashMap<AssetKey, CachedAsset>;
+
+impl Renderer {
+  // bakes the asset on the first call, every next call with the same key only adds a user.
+  // The options must match the ones of the first call, the baked scenes depend on them.
+  pub fn load_cached_gltf(
+    &mut self,
+    key: AssetKey,
+    data: &[u8],
+    options: &GltfLoadOptions,
+  ) -> Result<Vec<Handle<Node>>, GltfError> {
+    if let Some(asset) = self.assets.get_mut(&key) {
+      if asset.options != *options {
+        return Err(GltfError::OptionsMismatch);
+      }
+
+      asset.ref_count += 1;
+
+      return Ok(asset.scenes.clone());
+    }
+
+    let gltf = Gltf::from_slice(data).map_err(|e| GltfError::Parse {
+      reason: e.to_string(),
+    })?;
+    let raw_extensions = GltfRawExtensions::from_slice(data)?;
+    let default_scene = gltf.default_scene().map(|scene| scene.index());
+
+    let (scenes, resources) = self.bake_gltf_resources(&gltf, &raw_extensions, options)?;
+
+    self.assets.insert(
+      key,
+      CachedAsset {
+        scenes: scenes.clone(),
+        default_scene: scenes.get(default_scene.unwrap_or(0)).copied(),
+        resources,
+        options: options.clone(),
+        instances: vec![],
+        ref_count: 1,
+      },
+    );
+
+    Ok(scenes)
+  }
+
+  pub fn get_asset(&self, key: &AssetKey) -> Option<&CachedAsset> {
+    self.assets.get(key)
+  }
+
+  pub fn instantiate_asset(
+    &mut self,
+    key: &AssetKey,
+    parent_handle: Handle<Node>,
+  ) -> Option<Handle<Node>> {
+    let scene_handle = self.assets.get(key)?.default_scene?;
+    let instance_handle = self.scene.clone_subtree(scene_handle, parent_handle)?;
+
+    self.assets.get_mut(key)?.instances.push(instance_handle);
+
+    Some(instance_handle)
+  }
+
+  // the light is freed with the asset
+  pub fn insert_asset_light(&mut self, key: &AssetKey, light: Light) -> Option<Handle<Light>> {
+    self.assets.get(key)?;
+
+    let handle = self.insert_light(light);
+
+    self.assets.get_mut(key)?.resources.lights.push(handle);
+
+    Some(handle)
+  }
+
+  // returns the number of users left, the asset is unloaded when it drops to zero
+  pub fn release_asset(&mut self, key: &AssetKey) -> Option<usize> {
+    let asset = self.assets.get_mut(key)?;
+
+    asset.ref_count -= 1;
+
+    if asset.ref_count > 0 {
+      return Some(asset.ref_count);
+    }
+
+    let asset = self.assets.remove(key)?;
+
+    for handle in asset.instances.iter().chain(asset.scenes.iter()) {
+      self.scene.remove(*handle);
+    }
+
+    self.free_asset_resources(&asset.resources);
+
+    Some(0)
+  }
+
+  // nodes are gone by now, meshes and instance sets go first so the accessors and
+  // materials they used are free to delete
+  fn free_asset_resources(&mut self, resources: &AssetResources) {
+    for handle in &resources.meshes {
+      self.delete_mesh(*handle);
+    }
+
+    for handle in &resources.instance_sets {
+      self.delete_instance_set(*handle);
+    }
+
+    // the ones no primitive ended up using
+    for handle in &resources.accessors {
+      self.delete_accessor(*handle);
+    }
+
+    for handle in &resources.materials {
+      self.delete_material(*handle);
+    }
+
+    for handle in &resources.textures {
+      self.delete_texture(*handle);
+    }
+
+    for handle in &resources.lights {
+      self.delete_light(*handle);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use gltf::binary::{Glb, Header};
+  use na::Vector3;
+  use std::borrow::Cow;
+
+  use super::*;
+  use crate::renderer::webgl::recording::RecordingBackend;
+
+  // one triangle mesh under a single node
+  fn triangle_glb() -> Vec<u8> {
+    let json = r#"{
+      "asset": {"version": "2.0"},
+      "scenes": [{"nodes": [0]}],
+      "nodes": [{"mesh": 0}],
+      "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]}],
+      "materials": [{}],
+      "buffers": [{"byteLength": 36}],
+      "bufferViews": [{"buffer": 0, "byteLength": 36}],
+      "accessors": [
+        {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+         "min": [0, 0, 0], "max": [1, 1, 0]}
+      ]
+    }"#;
+    let bin: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+      .iter()
+      .flat_map(|v| v.to_le_bytes().to_vec())
+      .collect();
+
+    Glb {
+      header: Header {
+        magic: *b"glTF",
+        version: 2,
+        length: 0,
+      },
+      json: Cow::Borrowed(json.as_bytes()),
+      bin: Some(Cow::Borrowed(&bin)),
+    }
+    .to_vec()
+    .unwrap()
+  }
+
+  #[test]
+  fn users_share_the_asset_until_the_last_release() {
+    let mut renderer = Renderer::new(RecordingBackend::new());
+    let data = triangle_glb();
+    let key = AssetKey::from_content(&data);
+    let options = GltfLoadOptions::default();
+
+    let scenes = renderer
+      .load_cached_gltf(key.clone(), &data, &options)
+      .unwrap();
+    let meshes = renderer.meshes.iter().count();
+
+    assert_eq!(
+      renderer.load_cached_gltf(key.clone(), &data, &options),
+      Ok(scenes.clone())
+    );
+    assert_eq!(renderer.meshes.iter().count(), meshes);
+    assert_eq!(renderer.get_asset(&key).unwrap().ref_count, 2);
+
+    let root = renderer.scene.get_root_handle();
+    let instance = renderer.instantiate_asset(&key, root).unwrap();
+    let light = renderer
+      .insert_asset_light(&key, Light::directional(Vector3::new(0.0, -1.0, 0.0)))
+      .unwrap();
+    let resources = renderer.get_asset(&key).unwrap().resources.clone();
+
+    assert_eq!(renderer.release_asset(&key), Some(1));
+    assert!(renderer.scene.get_node(instance).is_some());
+    assert!(renderer.meshes.get(resources.meshes[0]).is_some());
+
+    assert_eq!(renderer.release_asset(&key), Some(0));
+    assert!(renderer.get_asset(&key).is_none());
+    assert!(renderer.scene.get_node(instance).is_none());
+    assert!(renderer.scene.get_node(scenes[0]).is_none());
+    assert!(renderer.meshes.get(resources.meshes[0]).is_none());
+    assert!(renderer.materials.get(resources.materials[0]).is_none());
+    assert!(renderer.lights.get(light).is_none());
+    assert!(renderer.accessors.is_empty());
+    assert!(renderer.geometries.is_empty());
+    assert!(renderer.buffers.is_empty());
+    assert_eq!(renderer.release_asset(&key), None);
+  }
+
+  #[test]
+  fn cached_assets_reject_other_options() {
+    let mut renderer = Renderer::new(RecordingBackend::new());
+    let data = triangle_glb();
+    let key = AssetKey::url("triangle.glb");
+
+    renderer
+      .load_cached_gltf(key.clone(), &data, &GltfLoadOptions::default())
+      .unwrap();
+
+    assert_eq!(
+      renderer.load_cached_gltf(key.clone(), &data, &GltfLoadOptions::partial()),
+      Err(GltfError::OptionsMismatch)
+    );
+    assert_eq!(renderer.get_asset(&key).unwrap().ref_count, 1);
+  }
+}
@@ -0,0 +1,178 @@
+use anyhow::Result;
+use na::{Matrix3, Matrix4, Vector2, Vector3, Vector4};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::slice;
+use web_sys::HtmlImageElement;
+
+use super::context::{
+  BlendFactor, BufferTarget, BufferUsage, DepthFunc, DrawMode, Feature, FramebufferAttachment,
+  TexParam, TexParamName, TextureFormat, TextureKind, TypedArrayKind,
+};
+use super::define::Define;
+use super::shader::{AttributeName, AttributeOptions, Shader};
+
+// Backend side objects are referred to by id, the backend keeps the actual GPU object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GpuBuffer(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GpuTexture(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GpuFramebuffer(pub u32);
+
+// Size and formats of a texture upload. WebGL1 wants the internal format to match the
+// pixel format and the border to be 0, so both are implied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureLayout {
+  pub target: TextureKind,
+  pub level: i32,
+  pub width: i32,
+  pub height: i32,
+  pub format: TextureFormat,
+  pub kind: TypedArrayKind,
+}
+
+impl TextureLayout {
+  // level 0 of a 2d texture
+  pub fn new(width: i32, height: i32, format: TextureFormat, kind: TypedArrayKind) -> Self {
+    TextureLayout {
+      target: TextureKind::Texture2d,
+      level: 0,
+      width,
+      height,
+      format,
+      kind,
+    }
+  }
+
+  pub fn set_target(mut self, target: TextureKind) -> Self {
+    self.target = target;
+    self
+  }
+
+  pub fn set_level(mut self, level: i32) -> Self {
+    self.level = level;
+    self
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Uniform<'a> {
+  Bool(bool),
+  Float(f32),
+  Integer(i32),
+  Vector2(&'a Vector2<f32>),
+  Vector3(&'a Vector3<f32>),
+  Vector4(&'a Vector4<f32>),
+  Matrix3(&'a Matrix3<f32>),
+  Matrix4(&'a Matrix4<f32>),
+  Matrix4Data(&'a [f32]),
+}
+
+// linked shader program, wrapped by Shader
+pub trait Program: Debug {
+  fn bind(&self);
+  fn attribute_locations(&self) -> &HashMap<AttributeName, u32>;
+  fn bind_attribute(&self, location: u32, options: &AttributeOptions);
+  fn has_uniform(&self, name: &str) -> bool;
+  // None when the program has no such active uniform
+  fn set_uniform(&self, name: &str, value: Uniform) -> Option<()>;
+}
+
+pub trait Backend: Debug {
+  fn supports_instancing(&self) -> bool;
+  // MAX_TEXTURE_IMAGE_UNITS, the samplers a fragment shader can use at once
+  fn max_texture_units(&self) -> u32;
+  // color attachments of TypedArrayKind::HalfFloat, sampled with linear filtering
+  fn supports_half_float_targets(&self) -> bool;
+
+  fn viewport(&self, x: i32, y: i32, width: i32, height: i32);
+  fn clear(&self, color: bool, depth: bool);
+  fn clear_color(&self, r: f32, g: f32, b: f32, a: f32);
+
+  fn create_shader(
+    &self,
+    vertex_src: &str,
+    fragment_src: &str,
+    defines: &[Define],
+  ) -> Result<Shader>;
+  // makes "#include <name>" available to shaders created afterwards
+  fn register_shader_chunk(&self, name: &str, src: &str);
+
+  fn create_buffer(
+    &self,
+    target: BufferTarget,
+    usage: BufferUsage,
+    data: &[u8],
+  ) -> Option<GpuBuffer>;
+  fn bind_buffer(&self, target: BufferTarget, buffer: Option<GpuBuffer>);
+  fn delete_buffer(&self, buffer: GpuBuffer);
+
+  fn create_texture(&self) -> Option<GpuTexture>;
+  fn delete_texture(&self, texture: GpuTexture);
+  fn active_texture(&self, unit: u32);
+  fn bind_texture(&self, target: TextureKind, texture: Option<GpuTexture>);
+  fn texture_parameter(&self, target: TextureKind, name: TexParamName, param: TexParam);
+  fn texture_data(&self, layout: &TextureLayout, data: &[u8]) -> Result<()>;
+  // allocates the texture without uploading, e.g. render target attachments
+  fn empty_texture_data(&self, layout: &TextureLayout) -> Result<()>;
+  fn texture_image_data(
+    &self,
+    target: TextureKind,
+    level: i32,
+    internal_format: TextureFormat,
+    format: TextureFormat,
+    kind: TypedArrayKind,
+    image: &HtmlImageElement,
+  ) -> Result<()>;
+  fn generate_mipmap(&self, target: TextureKind);
+  // copies the lower left corner of the bound framebuffer into the texture bound to
+  // the active unit
+  fn copy_texture_image_2d(
+    &self,
+    target: TextureKind,
+    level: i32,
+    internal_format: TextureFormat,
+    width: i32,
+    height: i32,
+  );
+
+  fn create_framebuffer(&self) -> Option<GpuFramebuffer>;
+  fn delete_framebuffer(&self, fb: GpuFramebuffer);
+  fn bind_framebuffer(&self, fb: Option<GpuFramebuffer>);
+  fn framebuffer_texture_2d(&self, attachment: FramebufferAttachment, texture: Option<GpuTexture>);
+  fn check_framebuffer_complete(&self) -> bool;
+
+  // enables attribute arrays 0..amount and disables the rest
+  fn switch_attributes(&self, amount: u32);
+  // disables the array at location and feeds every vertex the same value, until the next switch
+  fn constant_attribute(&self, location: u32, value: [f32; 4]);
+  fn set(&self, feature: Feature, enabled: bool);
+  fn depth_func(&self, func: DepthFunc);
+  fn blend_func(&self, src: BlendFactor, dst: BlendFactor);
+  fn depth_mask(&self, write: bool);
+  // calls dropped by the state cache since the last call, read once per frame
+  fn take_skipped_calls(&self) -> usize;
+
+  fn draw_arrays(&self, mode: DrawMode, first: i32, count: i32);
+  fn draw_elements(&self, mode: DrawMode, count: i32, kind: TypedArrayKind, offset: i32);
+  fn vertex_attrib_divisor(&self, location: u32, divisor: u32);
+  fn draw_arrays_instanced(&self, mode: DrawMode, first: i32, count: i32, instances: i32);
+  fn draw_elements_instanced(
+    &self,
+    mode: DrawMode,
+    count: i32,
+    kind: TypedArrayKind,
+    offset: i32,
+    instances: i32,
+  );
+}
+
+pub fn as_bytes<T>(data: &[T]) -> &[u8] {
+  let len = std::mem::size_of_val(data);
+  let ptr = data.as_ptr();
+
+  unsafe { slice::from_raw_parts(ptr as *const u8, len) }
+}
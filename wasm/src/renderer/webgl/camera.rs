@@ -1,12 +1,12 @@
-use generational_arena::Index;
 use na::{Matrix4, Perspective3};
 
-use super::renderer::Renderer;
+use super::renderer::{Camera, Renderer};
+use crate::handle::Handle;
 
 impl Renderer {
   pub fn update_camera(
     &mut self,
-    handle: Index,
+    handle: Handle<Camera>,
     view: Option<Matrix4<f32>>,
     projection: Option<Matrix4<f32>>,
   ) {
@@ -23,7 +23,7 @@ impl Renderer {
 
   pub fn make_perspective_camera(
     &mut self,
-    handle: Index,
+    handle: Handle<Camera>,
     aspect: f32,
     fovy: f32,
     near: f32,
@@ -1,31 +1,152 @@
+use super::backend::{Backend, GpuBuffer, GpuFramebuffer, GpuTexture, TextureLayout};
 use super::define::Define;
-use super::shader::Shader;
+use super::shader::{Shader, WebGlShaderProgram};
+use super::shader_cache::StageCache;
+use super::shader_chunks::ShaderChunks;
+use super::state_cache::StateCache;
 use anyhow::{anyhow, Result};
 use js_sys::{
   Float32Array, Int16Array, Int8Array, Object, Uint16Array, Uint32Array, Uint8Array, WebAssembly,
 };
 use num_traits::Num;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::default::Default;
+use std::rc::Rc;
 use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{
-  HtmlImageElement, WebGlBuffer, WebGlFramebuffer, WebGlRenderingContext, WebGlTexture,
+  AngleInstancedArrays, HtmlImageElement, WebGlBuffer, WebGlFramebuffer, WebGlRenderingContext,
+  WebGlShader, WebGlTexture,
 };
 
+// OesTextureHalfFloat::HALF_FLOAT_OES, WebGL1 has no constant of its own
+const HALF_FLOAT_OES: u32 = 0x8D61;
+
+// WebGL objects behind the ids handed out to the renderer
+#[derive(Debug)]
+struct ObjectTable<T> {
+  objects: RefCell<HashMap<u32, T>>,
+  next_id: Cell<u32>,
+}
+
+impl<T: Clone> ObjectTable<T> {
+  fn new() -> Self {
+    ObjectTable {
+      objects: RefCell::new(HashMap::new()),
+      next_id: Cell::new(1),
+    }
+  }
+
+  fn insert(&self, object: T) -> u32 {
+    let id = self.next_id.get();
+
+    self.next_id.set(id + 1);
+    self.objects.borrow_mut().insert(id, object);
+
+    id
+  }
+
+  fn get(&self, id: u32) -> Option<T> {
+    self.objects.borrow().get(&id).cloned()
+  }
+
+  fn remove(&self, id: u32) -> Option<T> {
+    self.objects.borrow_mut().remove(&id)
+  }
+}
+
 #[derive(Debug)]
 pub struct Context {
   gl: WebGlRenderingContext,
   attrib_amount: RefCell<u32>,
+  constant_attribs: RefCell<Vec<u32>>,
+  instanced_arrays: Option<AngleInstancedArrays>,
+  max_texture_units: u32,
+  half_float_targets: bool,
+  buffers: ObjectTable<WebGlBuffer>,
+  textures: ObjectTable<WebGlTexture>,
+  framebuffers: ObjectTable<WebGlFramebuffer>,
+  cache: Rc<StateCache>,
+  chunks: RefCell<ShaderChunks>,
+  stages: Rc<StageCache<WebGlShader>>,
 }
 
 impl Context {
   pub fn new(gl: WebGlRenderingContext) -> Context {
+    // optional, the renderer works without them but some formats will fail to upload
+    for name in &[
+      "OES_element_index_uint",
+      "WEBGL_depth_texture",
+      "OES_texture_float",
+      // explicit lods for the prefiltered environment, pbr_frag falls back to a bias
+      "EXT_shader_texture_lod",
+    ] {
+      let _ = gl.get_extension(name);
+    }
+
+    let instanced_arrays = gl
+      .get_extension("ANGLE_instanced_arrays")
+      .ok()
+      .flatten()
+      .map(|ext| ext.unchecked_into::<AngleInstancedArrays>());
+
+    // rendering to and filtering half floats, all three are needed for HDR targets
+    let half_float_targets = [
+      "OES_texture_half_float",
+      "OES_texture_half_float_linear",
+      "EXT_color_buffer_half_float",
+    ]
+    .iter()
+    .all(|name| matches!(gl.get_extension(name), Ok(Some(_))));
+
+    // WebGL1 guarantees 8
+    let max_texture_units = gl
+      .get_parameter(WebGlRenderingContext::MAX_TEXTURE_IMAGE_UNITS)
+      .ok()
+      .and_then(|value| value.as_f64())
+      .map_or(8, |value| value as u32);
+
     Context {
       gl,
       attrib_amount: RefCell::new(0),
+      constant_attribs: RefCell::new(vec![]),
+      instanced_arrays,
+      max_texture_units,
+      half_float_targets,
+      buffers: ObjectTable::new(),
+      textures: ObjectTable::new(),
+      framebuffers: ObjectTable::new(),
+      cache: Rc::new(StateCache::new()),
+      chunks: RefCell::new(ShaderChunks::new()),
+      stages: Rc::new(StageCache::new()),
     }
   }
 
+  // nodes only get instances after the renderer checked supports_instancing
+  fn instanced_arrays(&self) -> &AngleInstancedArrays {
+    self
+      .instanced_arrays
+      .as_ref()
+      .expect("ANGLE_instanced_arrays is not supported")
+  }
+
+  fn tex_image_2d(&self, layout: &TextureLayout, data: Option<&Object>) -> Result<()> {
+    self
+      .gl
+      .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+        layout.target.as_u32(),
+        layout.level,
+        layout.format.as_u32() as i32,
+        layout.width,
+        layout.height,
+        0,
+        layout.format.as_u32(),
+        layout.kind.as_u32(),
+        data,
+      )
+      .map_err(|e| anyhow!("{:?}", e))
+  }
+
   pub fn get_extension(&self, name: &str) -> Result<Object> {
     self
       .gl
@@ -34,11 +155,37 @@ impl Context {
       .ok_or_else(|| anyhow!("Unable to get extension {}", name))
   }
 
-  pub fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+  pub fn enable(&self, feature: Feature) {
+    if self.cache.set_feature(feature, true) {
+      self.gl.enable(feature.as_u32())
+    }
+  }
+
+  pub fn disable(&self, feature: Feature) {
+    if self.cache.set_feature(feature, false) {
+      self.gl.disable(feature.as_u32())
+    }
+  }
+}
+
+impl Backend for Context {
+  fn supports_instancing(&self) -> bool {
+    self.instanced_arrays.is_some()
+  }
+
+  fn max_texture_units(&self) -> u32 {
+    self.max_texture_units
+  }
+
+  fn supports_half_float_targets(&self) -> bool {
+    self.half_float_targets
+  }
+
+  fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
     self.gl.viewport(x, y, width, height);
   }
 
-  pub fn clear(&self, color: bool, depth: bool) {
+  fn clear(&self, color: bool, depth: bool) {
     let mut clear = 0;
 
     if color {
@@ -52,30 +199,42 @@ impl Context {
     self.gl.clear(clear);
   }
 
-  pub fn clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
+  fn clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
     self.gl.clear_color(r, g, b, a);
   }
 
-  pub fn create_shader(
+  fn create_shader(
     &self,
     vertex_src: &str,
     fragment_src: &str,
     defines: &[Define],
   ) -> Result<Shader> {
-    let shader = Shader::new(&self.gl, vertex_src, fragment_src, defines)?;
+    let program = WebGlShaderProgram::new(
+      &self.gl,
+      &self.cache,
+      &self.chunks.borrow(),
+      &self.stages,
+      vertex_src,
+      fragment_src,
+      defines,
+    )?;
 
-    Ok(shader)
+    Ok(Shader::new(Box::new(program)))
   }
 
-  pub fn create_buffer<T: BufferItem>(
+  fn register_shader_chunk(&self, name: &str, src: &str) {
+    self.chunks.borrow_mut().register(name, src);
+  }
+
+  fn create_buffer(
     &self,
     target: BufferTarget,
     usage: BufferUsage,
-    data: &[T],
-  ) -> Option<WebGlBuffer> {
-    let buffer = self.gl.create_buffer()?;
+    data: &[u8],
+  ) -> Option<GpuBuffer> {
+    let buffer = GpuBuffer(self.buffers.insert(self.gl.create_buffer()?));
 
-    self.gl.bind_buffer(target.as_u32(), Some(&buffer));
+    self.bind_buffer(target, Some(buffer));
 
     let array = get_typed_array_from_slice(data);
 
@@ -83,92 +242,84 @@ impl Context {
       .gl
       .buffer_data_with_array_buffer_view(target.as_u32(), &array, usage.as_u32());
 
-    self.gl.bind_buffer(target.as_u32(), None);
+    self.bind_buffer(target, None);
 
     Some(buffer)
   }
 
-  pub fn bind_buffer(&self, target: BufferTarget, buffer: Option<&WebGlBuffer>) {
-    self.gl.bind_buffer(target.as_u32(), buffer);
+  fn bind_buffer(&self, target: BufferTarget, buffer: Option<GpuBuffer>) {
+    if !self.cache.bind_buffer(target, buffer) {
+      return;
+    }
+
+    let buffer = buffer.and_then(|id| self.buffers.get(id.0));
+
+    self.gl.bind_buffer(target.as_u32(), buffer.as_ref());
+  }
+
+  fn delete_buffer(&self, buffer: GpuBuffer) {
+    self.cache.forget_buffer(buffer);
+
+    if let Some(buffer) = self.buffers.remove(buffer.0) {
+      self.gl.delete_buffer(Some(&buffer));
+    }
   }
 
-  pub fn create_texture(&self) -> Option<WebGlTexture> {
-    self.gl.create_texture()
+  fn create_texture(&self) -> Option<GpuTexture> {
+    let texture = self.gl.create_texture()?;
+
+    Some(GpuTexture(self.textures.insert(texture)))
+  }
+
+  fn delete_texture(&self, texture: GpuTexture) {
+    self.cache.forget_texture(texture);
+
+    if let Some(texture) = self.textures.remove(texture.0) {
+      self.gl.delete_texture(Some(&texture));
+    }
   }
 
-  pub fn active_texture(&self, unit: u32) {
+  fn active_texture(&self, unit: u32) {
+    if !self.cache.active_texture(unit) {
+      return;
+    }
+
     self
       .gl
       .active_texture(WebGlRenderingContext::TEXTURE0 + unit);
   }
 
-  pub fn bind_texture(&self, target: TextureKind, texture: Option<&WebGlTexture>) {
-    self.gl.bind_texture(target.as_u32(), texture)
+  fn bind_texture(&self, target: TextureKind, texture: Option<GpuTexture>) {
+    if !self.cache.bind_texture(target, texture) {
+      return;
+    }
+
+    let texture = texture.and_then(|id| self.textures.get(id.0));
+
+    self.gl.bind_texture(target.as_u32(), texture.as_ref())
   }
 
-  pub fn texture_parameter(&self, target: TextureKind, name: TexParamName, param: TexParam) {
+  fn texture_parameter(&self, target: TextureKind, name: TexParamName, param: TexParam) {
+    if !self.cache.texture_parameter(target, name, param) {
+      return;
+    }
+
     self
       .gl
       .tex_parameteri(target.as_u32(), name.as_u32(), param.as_u32() as i32)
   }
 
-  pub fn texture_data<T: BufferItem>(
-    &self,
-    target: TextureKind,
-    level: i32,
-    internal_format: TextureFormat,
-    width: i32,
-    height: i32,
-    border: i32,
-    format: TextureFormat,
-    data: &[T],
-  ) -> Result<()> {
-    let array = get_typed_array_from_slice(data);
+  fn texture_data(&self, layout: &TextureLayout, data: &[u8]) -> Result<()> {
+    let array = get_typed_array_from_bytes(layout.kind, data);
 
-    self
-      .gl
-      .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
-        target.as_u32(),
-        level,
-        internal_format.as_u32() as i32,
-        width,
-        height,
-        border,
-        format.as_u32(),
-        T::array_kind().as_u32(),
-        Some(array).as_ref(),
-      )
-      .map_err(|e| anyhow!("{:?}", e))
+    self.tex_image_2d(layout, Some(&array))
   }
 
-  pub fn empty_texture_data(
-    &self,
-    target: TextureKind,
-    level: i32,
-    internal_format: TextureFormat,
-    width: i32,
-    height: i32,
-    border: i32,
-    format: TextureFormat,
-    kind: TypedArrayKind,
-  ) -> Result<()> {
-    self
-      .gl
-      .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
-        target.as_u32(),
-        level,
-        internal_format.as_u32() as i32,
-        width,
-        height,
-        border,
-        format.as_u32(),
-        kind.as_u32(),
-        None,
-      )
-      .map_err(|e| anyhow!("{:?}", e))
+  fn empty_texture_data(&self, layout: &TextureLayout) -> Result<()> {
+    self.tex_image_2d(layout, None)
   }
 
-  pub fn texture_image_data(
+  fn texture_image_data(
     &self,
     target: TextureKind,
     level: i32,
@@ -190,35 +341,63 @@ impl Context {
       .map_err(|e| anyhow!("{:?}", e))
   }
 
-  pub fn generate_mipmap(&self, target: TextureKind) {
+  fn generate_mipmap(&self, target: TextureKind) {
     self.gl.generate_mipmap(target.as_u32())
   }
 
-  pub fn create_framebuffer(&self) -> Option<WebGlFramebuffer> {
-    self.gl.create_framebuffer()
+  fn copy_texture_image_2d(
+    &self,
+    target: TextureKind,
+    level: i32,
+    internal_format: TextureFormat,
+    width: i32,
+    height: i32,
+  ) {
+    self.gl.copy_tex_image_2d(
+      target.as_u32(),
+      level,
+      internal_format.as_u32(),
+      0,
+      0,
+      width,
+      height,
+      0,
+    );
+  }
+
+  fn create_framebuffer(&self) -> Option<GpuFramebuffer> {
+    let fb = self.gl.create_framebuffer()?;
+
+    Some(GpuFramebuffer(self.framebuffers.insert(fb)))
   }
 
-  pub fn bind_framebuffer(&self, fb: Option<&WebGlFramebuffer>) {
+  fn delete_framebuffer(&self, fb: GpuFramebuffer) {
+    if let Some(fb) = self.framebuffers.remove(fb.0) {
+      self.gl.delete_framebuffer(Some(&fb));
+    }
+  }
+
+  fn bind_framebuffer(&self, fb: Option<GpuFramebuffer>) {
+    let fb = fb.and_then(|id| self.framebuffers.get(id.0));
+
     self
       .gl
-      .bind_framebuffer(WebGlRenderingContext::FRAMEBUFFER, fb);
+      .bind_framebuffer(WebGlRenderingContext::FRAMEBUFFER, fb.as_ref());
   }
 
-  pub fn framebuffer_texture_2d(
-    &self,
-    attachment: FramebufferAttachment,
-    texture: Option<&WebGlTexture>,
-  ) {
+  fn framebuffer_texture_2d(&self, attachment: FramebufferAttachment, texture: Option<GpuTexture>) {
+    let texture = texture.and_then(|id| self.textures.get(id.0));
+
     self.gl.framebuffer_texture_2d(
       WebGlRenderingContext::FRAMEBUFFER,
       attachment.as_u32(),
       TextureKind::Texture2d.as_u32(),
-      texture,
+      texture.as_ref(),
       0,
     );
   }
 
-  pub fn check_framebuffer_complete(&self) -> bool {
+  fn check_framebuffer_complete(&self) -> bool {
     let status = self
       .gl
       .check_framebuffer_status(WebGlRenderingContext::FRAMEBUFFER);
@@ -226,9 +405,16 @@ impl Context {
     status == WebGlRenderingContext::FRAMEBUFFER_COMPLETE
   }
 
-  pub fn switch_attributes(&self, amount: u32) {
+  fn switch_attributes(&self, amount: u32) {
     let current_amount = *self.attrib_amount.borrow();
 
+    // constant attributes below the enabled range are arrays again
+    for location in self.constant_attribs.borrow_mut().drain(..) {
+      if location < amount.min(current_amount) {
+        self.gl.enable_vertex_attrib_array(location);
+      }
+    }
+
     if current_amount < amount {
       for location in current_amount..amount {
         self.gl.enable_vertex_attrib_array(location);
@@ -242,15 +428,18 @@ impl Context {
     self.attrib_amount.replace(amount);
   }
 
-  pub fn enable(&self, feature: Feature) {
-    self.gl.enable(feature.as_u32())
-  }
+  fn constant_attribute(&self, location: u32, value: [f32; 4]) {
+    if location < *self.attrib_amount.borrow() {
+      self.gl.disable_vertex_attrib_array(location);
+      self.constant_attribs.borrow_mut().push(location);
+    }
 
-  pub fn disable(&self, feature: Feature) {
-    self.gl.disable(feature.as_u32())
+    self
+      .gl
+      .vertex_attrib4f(location, value[0], value[1], value[2], value[3]);
   }
 
-  pub fn set(&self, feature: Feature, enabled: bool) {
+  fn set(&self, feature: Feature, enabled: bool) {
     if enabled {
       self.enable(feature);
     } else {
@@ -258,19 +447,74 @@ impl Context {
     }
   }
 
-  pub fn depth_func(&self, func: DepthFunc) {
+  fn depth_func(&self, func: DepthFunc) {
+    if !self.cache.depth_func(func) {
+      return;
+    }
+
     self.gl.depth_func(func.as_u32());
   }
 
-  pub fn draw_arrays(&self, mode: DrawMode, first: i32, count: i32) {
+  fn blend_func(&self, src: BlendFactor, dst: BlendFactor) {
+    if !self.cache.blend_func(src, dst) {
+      return;
+    }
+
+    self.gl.blend_func(src.as_u32(), dst.as_u32());
+  }
+
+  fn depth_mask(&self, write: bool) {
+    if !self.cache.depth_mask(write) {
+      return;
+    }
+
+    self.gl.depth_mask(write);
+  }
+
+  fn take_skipped_calls(&self) -> usize {
+    self.cache.take_skipped()
+  }
+
+  fn draw_arrays(&self, mode: DrawMode, first: i32, count: i32) {
     self.gl.draw_arrays(mode.as_u32(), first, count);
   }
 
-  pub fn draw_elements(&self, mode: DrawMode, count: i32, kind: TypedArrayKind, offset: i32) {
+  fn draw_elements(&self, mode: DrawMode, count: i32, kind: TypedArrayKind, offset: i32) {
     self
       .gl
       .draw_elements_with_i32(mode.as_u32(), count, kind.as_u32(), offset);
   }
+
+  fn vertex_attrib_divisor(&self, location: u32, divisor: u32) {
+    self
+      .instanced_arrays()
+      .vertex_attrib_divisor_angle(location, divisor);
+  }
+
+  fn draw_arrays_instanced(&self, mode: DrawMode, first: i32, count: i32, instances: i32) {
+    self
+      .instanced_arrays()
+      .draw_arrays_instanced_angle(mode.as_u32(), first, count, instances);
+  }
+
+  fn draw_elements_instanced(
+    &self,
+    mode: DrawMode,
+    count: i32,
+    kind: TypedArrayKind,
+    offset: i32,
+    instances: i32,
+  ) {
+    self
+      .instanced_arrays()
+      .draw_elements_instanced_angle_with_i32(
+        mode.as_u32(),
+        count,
+        kind.as_u32(),
+        offset,
+        instances,
+      );
+  }
 }
 
 pub enum Cleaning {
@@ -287,7 +531,7 @@ impl Cleaning {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum BufferTarget {
   ArrayBuffer,        // for generic data
   ElementArrayBuffer, // for indices only
@@ -302,6 +546,7 @@ impl BufferTarget {
   }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BufferUsage {
   StaticDraw,
   DynamicDraw,
@@ -318,24 +563,37 @@ impl BufferUsage {
   }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DrawMode {
-  Triangles,
+  Points,
   Lines,
+  LineLoop,
+  LineStrip,
+  Triangles,
+  TriangleStrip,
+  TriangleFan,
 }
 
 impl DrawMode {
   pub fn as_u32(&self) -> u32 {
     match self {
-      Self::Triangles => WebGlRenderingContext::TRIANGLES,
+      Self::Points => WebGlRenderingContext::POINTS,
       Self::Lines => WebGlRenderingContext::LINES,
+      Self::LineLoop => WebGlRenderingContext::LINE_LOOP,
+      Self::LineStrip => WebGlRenderingContext::LINE_STRIP,
+      Self::Triangles => WebGlRenderingContext::TRIANGLES,
+      Self::TriangleStrip => WebGlRenderingContext::TRIANGLE_STRIP,
+      Self::TriangleFan => WebGlRenderingContext::TRIANGLE_FAN,
     }
   }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Feature {
   CullFace,
   DepthTest,
+  Blend,
+  SampleAlphaToCoverage,
 }
 
 impl Feature {
@@ -343,10 +601,44 @@ impl Feature {
     match self {
       Self::CullFace => WebGlRenderingContext::CULL_FACE,
       Self::DepthTest => WebGlRenderingContext::DEPTH_TEST,
+      Self::Blend => WebGlRenderingContext::BLEND,
+      Self::SampleAlphaToCoverage => WebGlRenderingContext::SAMPLE_ALPHA_TO_COVERAGE,
     }
   }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendFactor {
+  Zero,
+  One,
+  SrcColor,
+  OneMinusSrcColor,
+  DstColor,
+  OneMinusDstColor,
+  SrcAlpha,
+  OneMinusSrcAlpha,
+  DstAlpha,
+  OneMinusDstAlpha,
+}
+
+impl BlendFactor {
+  pub fn as_u32(&self) -> u32 {
+    match self {
+      Self::Zero => WebGlRenderingContext::ZERO,
+      Self::One => WebGlRenderingContext::ONE,
+      Self::SrcColor => WebGlRenderingContext::SRC_COLOR,
+      Self::OneMinusSrcColor => WebGlRenderingContext::ONE_MINUS_SRC_COLOR,
+      Self::DstColor => WebGlRenderingContext::DST_COLOR,
+      Self::OneMinusDstColor => WebGlRenderingContext::ONE_MINUS_DST_COLOR,
+      Self::SrcAlpha => WebGlRenderingContext::SRC_ALPHA,
+      Self::OneMinusSrcAlpha => WebGlRenderingContext::ONE_MINUS_SRC_ALPHA,
+      Self::DstAlpha => WebGlRenderingContext::DST_ALPHA,
+      Self::OneMinusDstAlpha => WebGlRenderingContext::ONE_MINUS_DST_ALPHA,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DepthFunc {
   Less,
   Lequal,
@@ -361,7 +653,7 @@ impl DepthFunc {
   }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TextureKind {
   Texture2d,
   CubeMap,
@@ -388,6 +680,7 @@ impl TextureKind {
   }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TexParamName {
   TextureMinFilter,
   TextureMagFilter,
@@ -406,7 +699,7 @@ impl TexParamName {
   }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TexParam {
   Linear,
   Nearest,
@@ -433,7 +726,7 @@ impl TexParam {
   }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TextureFormat {
   RGBA,
   RGB,
@@ -448,9 +741,17 @@ impl TextureFormat {
       Self::Depth => WebGlRenderingContext::DEPTH_COMPONENT,
     }
   }
+
+  pub fn bytes_per_pixel(&self) -> usize {
+    match self {
+      Self::RGBA => 4,
+      Self::RGB => 3,
+      Self::Depth => 2,
+    }
+  }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FramebufferAttachment {
   ColorAttachment0,
   DepthAttachment,
@@ -465,7 +766,7 @@ impl FramebufferAttachment {
   }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TypedArrayKind {
   Int8,
   Uint8,
@@ -473,6 +774,8 @@ pub enum TypedArrayKind {
   Uint16,
   Uint32,
   Float32,
+  // texels only, OES_texture_half_float
+  HalfFloat,
 }
 
 impl Default for TypedArrayKind {
@@ -490,6 +793,7 @@ impl TypedArrayKind {
       TypedArrayKind::Uint16 => WebGlRenderingContext::UNSIGNED_SHORT,
       TypedArrayKind::Uint32 => WebGlRenderingContext::UNSIGNED_INT,
       TypedArrayKind::Float32 => WebGlRenderingContext::FLOAT,
+      TypedArrayKind::HalfFloat => HALF_FLOAT_OES,
     }
   }
 }
@@ -511,13 +815,42 @@ pub fn get_typed_array_from_slice<T: BufferItem>(data: &[T]) -> Object {
   get_typed_array::<T>(start, end)
 }
 
+// views bytes written from a slice of the given kind, e.g. f32 data passed through as_bytes
+pub fn get_typed_array_from_bytes(kind: TypedArrayKind, data: &[u8]) -> Object {
+  let buffer = get_memory_buffer();
+  let offset = data.as_ptr() as u32;
+  let len = data.len() as u32;
+
+  match kind {
+    TypedArrayKind::Int8 => Int8Array::new_with_byte_offset_and_length(&buffer, offset, len).into(),
+    TypedArrayKind::Uint8 => {
+      Uint8Array::new_with_byte_offset_and_length(&buffer, offset, len).into()
+    }
+    TypedArrayKind::Int16 => {
+      Int16Array::new_with_byte_offset_and_length(&buffer, offset, len / 2).into()
+    }
+    // half floats are uploaded from a Uint16Array
+    TypedArrayKind::Uint16 | TypedArrayKind::HalfFloat => {
+      Uint16Array::new_with_byte_offset_and_length(&buffer, offset, len / 2).into()
+    }
+    TypedArrayKind::Uint32 => {
+      Uint32Array::new_with_byte_offset_and_length(&buffer, offset, len / 4).into()
+    }
+    TypedArrayKind::Float32 => {
+      Float32Array::new_with_byte_offset_and_length(&buffer, offset, len / 4).into()
+    }
+  }
+}
+
 fn get_typed_array<T: BufferItem>(start: u32, end: u32) -> Object {
   let buffer = get_memory_buffer();
   match T::array_kind() {
     TypedArrayKind::Int8 => Int8Array::new(&buffer).subarray(start, end).into(),
     TypedArrayKind::Uint8 => Uint8Array::new(&buffer).subarray(start, end).into(),
     TypedArrayKind::Int16 => Int16Array::new(&buffer).subarray(start, end).into(),
-    TypedArrayKind::Uint16 => Uint16Array::new(&buffer).subarray(start, end).into(),
+    TypedArrayKind::Uint16 | TypedArrayKind::HalfFloat => {
+      Uint16Array::new(&buffer).subarray(start, end).into()
+    }
     TypedArrayKind::Uint32 => Uint32Array::new(&buffer).subarray(start, end).into(),
     TypedArrayKind::Float32 => Float32Array::new(&buffer).subarray(start, end).into(),
   }
@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Define {
   pub name: String,
   pub value: Option<String>,
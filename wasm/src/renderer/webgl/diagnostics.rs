@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::fmt;
+
+use super::shader_chunks::{SourceLine, SourceMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+  Vertex,
+  Fragment,
+  Link,
+}
+
+impl fmt::Display for ShaderStage {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ShaderStage::Vertex => write!(f, "vertex"),
+      ShaderStage::Fragment => write!(f, "fragment"),
+      ShaderStage::Link => write!(f, "link"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+  pub stage: ShaderStage,
+  pub severity: Severity,
+  // file and line before includes were resolved, None when the log gives no location
+  pub source: Option<SourceLine>,
+  pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let severity = match self.severity {
+      Severity::Error => "error",
+      Severity::Warning => "warning",
+    };
+
+    match &self.source {
+      Some(source) => write!(
+        f,
+        "{} {} {}:{}: {}",
+        self.stage, severity, source.file, source.line, self.message
+      ),
+      None => write!(f, "{} {}: {}", self.stage, severity, self.message),
+    }
+  }
+}
+
+// Returned by Backend::create_shader when a stage fails to compile or link, reach it
+// with anyhow's downcast_ref.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderError {
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ShaderError {
+  pub fn new(diagnostics: Vec<Diagnostic>) -> Self {
+    ShaderError { diagnostics }
+  }
+
+  pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+    self
+      .diagnostics
+      .iter()
+      .filter(|diagnostic| diagnostic.severity == Severity::Error)
+  }
+}
+
+impl fmt::Display for ShaderError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "shader compile error")?;
+
+    for diagnostic in &self.diagnostics {
+      write!(f, "\n{}", diagnostic)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Error for ShaderError {}
+
+// Parses a driver info log made of "ERROR: 0:LINE: message" and "WARNING: ..." lines.
+// Lines are mapped back through the source map, anything unrecognized is kept as an
+// error without location.
+pub fn parse_info_log(log: &str, stage: ShaderStage, map: &SourceMap) -> Vec<Diagnostic> {
+  log
+    .lines()
+    .map(|line| line.trim_matches(|c: char| c.is_whitespace() || c == '\0'))
+    .filter(|line| !line.is_empty())
+    .map(|line| {
+      let (severity, rest) = if let Some(rest) = line.strip_prefix("ERROR:") {
+        (Severity::Error, rest.trim_start())
+      } else if let Some(rest) = line.strip_prefix("WARNING:") {
+        (Severity::Warning, rest.trim_start())
+      } else {
+        (Severity::Error, line)
+      };
+
+      let (source, message) = match split_location(rest) {
+        Some((number, message)) => (map.get(number).cloned(), message),
+        None => (None, rest),
+      };
+
+      Diagnostic {
+        stage,
+        severity,
+        source,
+        message: message.to_string(),
+      }
+    })
+    .collect()
+}
+
+// "0:12: message" gives (12, "message"), the leading number is the source string index
+fn split_location(text: &str) -> Option<(usize, &str)> {
+  let mut parts = text.splitn(3, ':');
+  let _index: usize = parts.next()?.trim().parse().ok()?;
+  let line = parts.next()?.trim().parse().ok()?;
+
+  Some((line, parts.next()?.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::renderer::webgl::shader_chunks::ShaderChunks;
+
+  #[test]
+  fn info_log_lines_point_at_the_original_files() {
+    let mut chunks = ShaderChunks::new();
+
+    chunks.register("a", "float a;\n#include <b>\nfloat a2;");
+    chunks.register("b", "float b;");
+
+    let (_, map) = chunks
+      .resolve("#include <a>\nvoid main() {}", "fragment")
+      .unwrap();
+    let map = map.offset(2, "header");
+    let log = "ERROR: 0:5: 'a2' : redefinition\nWARNING: 0:6: 'main' : unused\n\
+               ERROR: 0:99: out of range\nERROR: 3 compilation errors.  No code generated.\n\0";
+
+    let diagnostics = parse_info_log(log, ShaderStage::Fragment, &map);
+    let error = ShaderError::new(diagnostics.clone());
+
+    assert_eq!(diagnostics.len(), 4);
+    assert_eq!(error.errors().count(), 3);
+    assert_eq!(
+      diagnostics[0].to_string(),
+      "fragment error <a>:3: 'a2' : redefinition"
+    );
+    assert_eq!(
+      diagnostics[1].to_string(),
+      "fragment warning fragment:2: 'main' : unused"
+    );
+    assert_eq!(diagnostics[2].source, None);
+    assert_eq!(diagnostics[2].message, "out of range");
+    assert_eq!(
+      diagnostics[3].message,
+      "3 compilation errors.  No code generated."
+    );
+  }
+}
@@ -1,6 +1,8 @@
+use crate::handle::Handle;
+
+use super::backend::TextureLayout;
 use super::context::{FramebufferAttachment, TexParam, TextureFormat, TextureKind, TypedArrayKind};
 use super::renderer::{RenderTarget, Renderer, Sampler};
-use generational_arena::Index;
 
 impl Renderer {
   pub fn bake_render_target(
@@ -9,33 +11,68 @@ impl Renderer {
     height: u32,
     sampler: Sampler,
     depth: bool,
-  ) -> Index {
+  ) -> Handle<RenderTarget> {
+    self
+      .try_bake_render_target(width, height, sampler, depth, TypedArrayKind::Uint8)
+      .expect("framebuffer is incomplete")
+  }
+
+  // Half float color for values past 1.0, e.g. before tone mapping. Falls back to 8 bit
+  // when the context can't render to half floats.
+  pub fn bake_hdr_render_target(
+    &mut self,
+    width: u32,
+    height: u32,
+    sampler: Sampler,
+    depth: bool,
+  ) -> Handle<RenderTarget> {
+    if self.ctx.supports_half_float_targets() {
+      // some drivers expose the extensions and still refuse the attachment
+      if let Some(target) = self.try_bake_render_target(
+        width,
+        height,
+        sampler.clone(),
+        depth,
+        TypedArrayKind::HalfFloat,
+      ) {
+        return target;
+      }
+    }
+
+    self.bake_render_target(width, height, sampler, depth)
+  }
+
+  // None when the framebuffer is incomplete, nothing is left behind then
+  fn try_bake_render_target(
+    &mut self,
+    width: u32,
+    height: u32,
+    sampler: Sampler,
+    depth: bool,
+    color_kind: TypedArrayKind,
+  ) -> Option<Handle<RenderTarget>> {
     let fb = self.ctx.create_framebuffer().unwrap();
     let color_image = self.ctx.create_texture().unwrap();
 
-    self.ctx.bind_framebuffer(Some(&fb));
+    self.ctx.bind_framebuffer(Some(fb));
 
     self
       .ctx
-      .bind_texture(TextureKind::Texture2d, Some(&color_image));
+      .bind_texture(TextureKind::Texture2d, Some(color_image));
 
     self
       .ctx
-      .empty_texture_data(
-        TextureKind::Texture2d,
-        0,
-        TextureFormat::RGBA,
+      .empty_texture_data(&TextureLayout::new(
         width as i32,
         height as i32,
-        0,
         TextureFormat::RGBA,
-        TypedArrayKind::Uint8,
-      )
+        color_kind,
+      ))
       .unwrap();
 
     self
       .ctx
-      .framebuffer_texture_2d(FramebufferAttachment::ColorAttachment0, Some(&color_image));
+      .framebuffer_texture_2d(FramebufferAttachment::ColorAttachment0, Some(color_image));
 
     let depth_image_option = match depth {
       true => {
@@ -43,25 +80,21 @@ impl Renderer {
 
         self
           .ctx
-          .bind_texture(TextureKind::Texture2d, Some(&depth_image));
+          .bind_texture(TextureKind::Texture2d, Some(depth_image));
 
         self
           .ctx
-          .empty_texture_data(
-            TextureKind::Texture2d,
-            0,
-            TextureFormat::Depth,
+          .empty_texture_data(&TextureLayout::new(
             width as i32,
             height as i32,
-            0,
             TextureFormat::Depth,
             TypedArrayKind::Uint16,
-          )
+          ))
           .unwrap();
 
         self
           .ctx
-          .framebuffer_texture_2d(FramebufferAttachment::DepthAttachment, Some(&depth_image));
+          .framebuffer_texture_2d(FramebufferAttachment::DepthAttachment, Some(depth_image));
 
         Some(depth_image)
       }
@@ -73,14 +106,26 @@ impl Renderer {
     self.ctx.bind_framebuffer(None);
 
     if !complete {
-      panic!("framebuffer is incomplete");
+      self.ctx.delete_framebuffer(fb);
+      self.ctx.delete_texture(color_image);
+
+      if let Some(depth_image) = depth_image_option {
+        self.ctx.delete_texture(depth_image);
+      }
+
+      return None;
     }
 
     // fb
     let fb_handle = self.insert_framebuffer(fb);
 
     // color texture
-    let color_texture_handle = self.compose_texture(color_image, sampler);
+    let pixels = width as usize * height as usize;
+    let color_bytes = match color_kind {
+      TypedArrayKind::HalfFloat => 2 * TextureFormat::RGBA.bytes_per_pixel(),
+      _ => TextureFormat::RGBA.bytes_per_pixel(),
+    };
+    let color_texture_handle = self.compose_texture(color_image, sampler, pixels * color_bytes);
 
     // depth texture
     let depth_texture_handle = if let Some(depth_image) = depth_image_option {
@@ -92,15 +137,16 @@ impl Renderer {
           min_filter: TexParam::Nearest,
           mag_filter: TexParam::Nearest,
         },
+        pixels * TextureFormat::Depth.bytes_per_pixel(),
       ))
     } else {
       None
     };
 
-    self.insert_render_target(RenderTarget {
+    Some(self.insert_render_target(RenderTarget {
       fb: fb_handle,
       color_texture: color_texture_handle,
       depth_texture: depth_texture_handle,
-    })
+    }))
   }
 }
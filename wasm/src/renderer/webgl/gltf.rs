@@ -1,224 +1,1201 @@
-use generational_arena::Index;
-use gltf::accessor::DataType;
-use gltf::mesh::Semantic;
+use gltf::accessor::{DataType, Dimensions};
+use gltf::buffer::Source;
+use gltf::image::Source as ImageDataSource;
+use gltf::material::AlphaMode;
+use gltf::mesh::{Mode, Semantic};
 use gltf::scene::Transform;
+use gltf::texture::{MagFilter, MinFilter, WrappingMode};
 use gltf::Gltf;
-use na::{Matrix4, Quaternion, UnitQuaternion, Vector3, Vector4};
+use image::{ImageFormat, RgbaImage};
+use log::warn;
+use na::{Matrix4, Quaternion, UnitQuaternion, Vector2, Vector3, Vector4};
+use serde_json::Value;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
 
-use crate::scene::node::{compose_matrix, Node};
+use crate::handle::Handle;
+use crate::scene::node::{compose_matrix, Node, UserData};
 
-use super::context::{BufferTarget, BufferUsage, TypedArrayKind};
-use super::material::PbrMaterial;
-use super::renderer::{Accessor, Geometry, Mesh, Primitive, Renderer};
+use super::asset_cache::AssetResources;
+use super::context::{
+  BufferTarget, BufferUsage, DrawMode, TexParam, TextureFormat, TypedArrayKind,
+};
+use super::material::{BlendMode, Material, PbrMap, PbrMaterial, TextureTransform};
+use super::meshopt::{decode_meshopt, MeshoptFilter, MeshoptMode, MeshoptView};
+use super::renderer::{
+  Accessor, Attributes, Buffer, Geometry, Image, ImageSource, InstanceSet, Mesh, Primitive,
+  Renderer, Sampler, Texture,
+};
 use super::shader::{AttributeName, AttributeOptions};
+use super::texture::is_power_of_two;
 
-pub type IndexMap = HashMap<usize, Index>;
+pub type IndexMap<T> = HashMap<usize, Handle<T>>;
+pub type AccessorIndexMap = HashMap<usize, Result<Handle<Accessor>, GltfError>>;
+
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[
+  "KHR_texture_transform",
+  "KHR_materials_unlit",
+  "KHR_materials_emissive_strength",
+  "KHR_materials_variants",
+  "KHR_mesh_quantization",
+  "EXT_meshopt_compression",
+  "EXT_mesh_gpu_instancing",
+];
+
+// only readable when the asset is baked with GltfRawExtensions::from_slice, the gltf crate
+// reads KHR_texture_transform itself except on normal and occlusion textures
+pub const RAW_EXTENSIONS: &[&str] = &[
+  "KHR_materials_emissive_strength",
+  "EXT_meshopt_compression",
+  "EXT_mesh_gpu_instancing",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GltfError {
+  Parse {
+    reason: String,
+  },
+  MissingBuffer {
+    path: String,
+  },
+  ViewOutOfRange {
+    path: String,
+    offset: usize,
+    length: usize,
+    available: usize,
+  },
+  UnsupportedAccessorType {
+    path: String,
+    reason: String,
+  },
+  UnsupportedExtension {
+    path: String,
+    name: String,
+  },
+  InvalidIndex {
+    path: String,
+    index: usize,
+  },
+  DecodeFailed {
+    path: String,
+    reason: String,
+  },
+  // the asset key is cached with other load options
+  OptionsMismatch,
+}
+
+impl GltfError {
+  pub fn path(&self) -> &str {
+    match self {
+      Self::Parse { .. } => "",
+      Self::MissingBuffer { path } => path,
+      Self::ViewOutOfRange { path, .. } => path,
+      Self::UnsupportedAccessorType { path, .. } => path,
+      Self::UnsupportedExtension { path, .. } => path,
+      Self::InvalidIndex { path, .. } => path,
+      Self::DecodeFailed { path, .. } => path,
+      Self::OptionsMismatch => "",
+    }
+  }
+}
+
+impl fmt::Display for GltfError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Parse { reason } => write!(f, "unable to parse asset, {}", reason),
+      Self::MissingBuffer { path } => write!(f, "{}: buffer data is missing", path),
+      Self::ViewOutOfRange {
+        path,
+        offset,
+        length,
+        available,
+      } => write!(
+        f,
+        "{}: range {}..{} is out of {} available bytes",
+        path,
+        offset,
+        offset + length,
+        available
+      ),
+      Self::UnsupportedAccessorType { path, reason } => {
+        write!(f, "{}: unsupported accessor, {}", path, reason)
+      }
+      Self::UnsupportedExtension { path, name } => {
+        write!(f, "{}: unsupported extension {}", path, name)
+      }
+      Self::InvalidIndex { path, index } => write!(f, "{}: invalid index {}", path, index),
+      Self::DecodeFailed { path, reason } => write!(f, "{}: unable to decode, {}", path, reason),
+      Self::OptionsMismatch => write!(f, "asset is already loaded with other options"),
+    }
+  }
+}
+
+impl std::error::Error for GltfError {}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GltfLoadOptions {
+  // when set, a primitive that fails to load is skipped instead of failing the whole asset
+  pub skip_invalid_primitives: bool,
+}
+
+impl GltfLoadOptions {
+  pub fn partial() -> Self {
+    GltfLoadOptions {
+      skip_invalid_primitives: true,
+    }
+  }
+}
+
+// Extensions the gltf crate drops while parsing, read straight from the asset json
+#[derive(Debug, Clone, Default)]
+pub struct GltfRawExtensions {
+  pub parsed: bool,
+  pub emissive_strengths: HashMap<usize, f32>,
+  // (material index, map) -> KHR_texture_transform of the normal and occlusion textures
+  pub texture_transforms: HashMap<(usize, PbrMap), GltfTextureTransform>,
+  pub meshopt_views: HashMap<usize, MeshoptView>,
+  // node index -> instance attribute semantic -> accessor index
+  pub instancing: HashMap<usize, HashMap<String, usize>>,
+}
+
+impl GltfRawExtensions {
+  pub fn from_slice(data: &[u8]) -> Result<Self, GltfError> {
+    let json = if data.starts_with(b"glTF") {
+      gltf::binary::Glb::from_slice(data)
+        .map_err(|e| GltfError::Parse {
+          reason: e.to_string(),
+        })?
+        .json
+        .into_owned()
+    } else {
+      data.to_vec()
+    };
+
+    let root: Value = serde_json::from_slice(&json).map_err(|e| GltfError::Parse {
+      reason: e.to_string(),
+    })?;
+
+    let mut emissive_strengths = HashMap::new();
+    let mut texture_transforms = HashMap::new();
+
+    if let Some(materials) = root["materials"].as_array() {
+      for (i, material) in materials.iter().enumerate() {
+        let strength =
+          &material["extensions"]["KHR_materials_emissive_strength"]["emissiveStrength"];
+
+        if let Some(strength) = strength.as_f64() {
+          emissive_strengths.insert(i, strength as f32);
+        }
+
+        for (map, name) in &[
+          (PbrMap::Normal, "normalTexture"),
+          (PbrMap::Occlusion, "occlusionTexture"),
+        ] {
+          let transform = &material[name]["extensions"]["KHR_texture_transform"];
+
+          if transform.is_object() {
+            texture_transforms.insert((i, *map), parse_texture_transform(transform));
+          }
+        }
+      }
+    }
+
+    let mut meshopt_views = HashMap::new();
+
+    if let Some(views) = root["bufferViews"].as_array() {
+      for (i, view) in views.iter().enumerate() {
+        let compression = &view["extensions"]["EXT_meshopt_compression"];
+
+        if compression.is_object() {
+          meshopt_views.insert(i, parse_meshopt_view(i, compression)?);
+        }
+      }
+    }
+
+    let mut instancing = HashMap::new();
+
+    if let Some(nodes) = root["nodes"].as_array() {
+      for (i, node) in nodes.iter().enumerate() {
+        let attributes = &node["extensions"]["EXT_mesh_gpu_instancing"]["attributes"];
+
+        if let Some(attributes) = attributes.as_object() {
+          let attributes = attributes
+            .iter()
+            .filter_map(|(name, accessor)| Some((name.clone(), accessor.as_u64()? as usize)))
+            .collect();
+
+          instancing.insert(i, attributes);
+        }
+      }
+    }
+
+    Ok(GltfRawExtensions {
+      parsed: true,
+      emissive_strengths,
+      texture_transforms,
+      meshopt_views,
+      instancing,
+    })
+  }
+}
+
+// missing or malformed properties keep their default
+fn parse_texture_transform(transform: &Value) -> GltfTextureTransform {
+  let vector = |name: &str, default: f32| {
+    let value = |i: usize| transform[name][i].as_f64().map_or(default, |v| v as f32);
+
+    Vector2::new(value(0), value(1))
+  };
+
+  GltfTextureTransform {
+    transform: TextureTransform::new()
+      .set_offset(vector("offset", 0.0))
+      .set_rotation(transform["rotation"].as_f64().unwrap_or(0.0) as f32)
+      .set_scale(vector("scale", 1.0)),
+    tex_coord: transform["texCoord"].as_u64().map(|v| v as u32),
+  }
+}
+
+fn parse_meshopt_view(index: usize, compression: &Value) -> Result<MeshoptView, GltfError> {
+  let path = format!("bufferViews[{}].extensions.EXT_meshopt_compression", index);
+  let invalid = |reason: &str| GltfError::DecodeFailed {
+    path: path.clone(),
+    reason: reason.to_string(),
+  };
+  let get_usize = |name: &str| compression[name].as_u64().map(|v| v as usize);
+
+  Ok(MeshoptView {
+    buffer: get_usize("buffer").ok_or_else(|| invalid("buffer is missing"))?,
+    byte_offset: get_usize("byteOffset").unwrap_or(0),
+    byte_length: get_usize("byteLength").ok_or_else(|| invalid("byteLength is missing"))?,
+    byte_stride: get_usize("byteStride").ok_or_else(|| invalid("byteStride is missing"))?,
+    count: get_usize("count").ok_or_else(|| invalid("count is missing"))?,
+    mode: compression["mode"]
+      .as_str()
+      .and_then(MeshoptMode::from_name)
+      .ok_or_else(|| invalid("unknown mode"))?,
+    filter: match compression["filter"].as_str() {
+      Some(name) => MeshoptFilter::from_name(name).ok_or_else(|| invalid("unknown filter"))?,
+      None => MeshoptFilter::None,
+    },
+  })
+}
+
+// KHR_texture_transform of a texture reference, its texCoord overrides the reference's
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GltfTextureTransform {
+  pub transform: TextureTransform,
+  pub tex_coord: Option<u32>,
+}
+
+// a texture reference of a material resolved to the baked texture
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GltfMapInfo {
+  pub texture: Handle<Texture>,
+  pub uv_set: u32,
+  pub transform: TextureTransform,
+}
+
+// None when the texture was not created, e.g. an external image
+fn get_gltf_map_info(
+  texture: usize,
+  tex_coord: u32,
+  transform: Option<GltfTextureTransform>,
+  texture_index: &IndexMap<Texture>,
+) -> Option<GltfMapInfo> {
+  Some(GltfMapInfo {
+    texture: *texture_index.get(&texture)?,
+    uv_set: transform
+      .and_then(|transform| transform.tex_coord)
+      .unwrap_or(tex_coord),
+    transform: transform
+      .map(|transform| transform.transform)
+      .unwrap_or_default(),
+  })
+}
+
+fn get_gltf_info_map(
+  info: &gltf::texture::Info,
+  texture_index: &IndexMap<Texture>,
+) -> Option<GltfMapInfo> {
+  let transform = info
+    .texture_transform()
+    .map(|transform| GltfTextureTransform {
+      transform: TextureTransform::new()
+        .set_offset(Vector2::from(transform.offset()))
+        .set_rotation(transform.rotation())
+        .set_scale(Vector2::from(transform.scale())),
+      tex_coord: transform.tex_coord(),
+    });
+
+  get_gltf_map_info(
+    info.texture().index(),
+    info.tex_coord(),
+    transform,
+    texture_index,
+  )
+}
+
+fn set_gltf_map(
+  material: PbrMaterial,
+  map: PbrMap,
+  info: Option<GltfMapInfo>,
+  path: &str,
+) -> PbrMaterial {
+  let info = match info {
+    Some(info) => info,
+    None => return material,
+  };
+
+  if info.uv_set > 1 {
+    warn!(
+      "{}.{}: TEXCOORD_{} is not supported, reading TEXCOORD_0",
+      path,
+      map.uniform(),
+      info.uv_set
+    );
+  }
+
+  material
+    .set_map(map, Some(info.texture))
+    .set_map_uv_set(map, info.uv_set)
+    .set_map_transform(map, info.transform)
+}
+
+// extras are kept only when they are an object, as Blender custom properties are
+pub fn parse_gltf_extras(extras: &gltf::json::Extras, path: &str) -> UserData {
+  let raw = match extras {
+    Some(raw) => raw,
+    None => return UserData::new(),
+  };
+
+  match serde_json::from_str(raw.get()) {
+    Ok(Value::Object(user_data)) => user_data,
+    Ok(_) => {
+      warn!("{}.extras: ignore non-object extras", path);
+      UserData::new()
+    }
+    Err(error) => {
+      warn!("{}.extras: {}", path, error);
+      UserData::new()
+    }
+  }
+}
+
+pub fn check_gltf_extensions(
+  gltf: &Gltf,
+  raw_extensions: &GltfRawExtensions,
+) -> Result<(), GltfError> {
+  for (i, name) in gltf.extensions_required().enumerate() {
+    if !SUPPORTED_EXTENSIONS.contains(&name)
+      || (RAW_EXTENSIONS.contains(&name) && !raw_extensions.parsed)
+    {
+      return Err(GltfError::UnsupportedExtension {
+        path: format!("extensionsRequired[{}]", i),
+        name: name.to_string(),
+      });
+    }
+  }
+
+  Ok(())
+}
+
+// Extensions the asset uses that weren't read from its raw json, the gltf crate drops them
+// from the parsed document so bake_gltf can't get them back. The asset loads without them.
+pub fn ignored_gltf_extensions<'a>(
+  gltf: &'a Gltf,
+  raw_extensions: &GltfRawExtensions,
+) -> Vec<&'a str> {
+  if raw_extensions.parsed {
+    return vec![];
+  }
+
+  // KHR_texture_transform is only lost on normal and occlusion textures
+  gltf
+    .extensions_used()
+    .filter(|name| RAW_EXTENSIONS.contains(name) || *name == "KHR_texture_transform")
+    .collect()
+}
+
+pub fn get_gltf_buffer_data<'a>(
+  gltf: &'a Gltf,
+  buffer_def: &gltf::Buffer,
+) -> Result<&'a [u8], GltfError> {
+  let buffer_path = format!("buffers[{}]", buffer_def.index());
+
+  match buffer_def.source() {
+    Source::Bin => gltf.blob.as_deref(),
+    Source::Uri(_) => None,
+  }
+  .ok_or(GltfError::MissingBuffer { path: buffer_path })
+}
+
+pub fn get_gltf_view_data<'a>(
+  gltf: &'a Gltf,
+  view_def: &gltf::buffer::View,
+) -> Result<&'a [u8], GltfError> {
+  let blob = get_gltf_buffer_data(gltf, &view_def.buffer())?;
+
+  let offset = view_def.offset();
+  let length = view_def.length();
+
+  if offset + length > blob.len() {
+    return Err(GltfError::ViewOutOfRange {
+      path: format!("bufferViews[{}]", view_def.index()),
+      offset,
+      length,
+      available: blob.len(),
+    });
+  }
+
+  Ok(&blob[offset..(offset + length)])
+}
+
+pub fn decode_gltf_meshopt_view(
+  gltf: &Gltf,
+  view_index: usize,
+  view: &MeshoptView,
+) -> Result<Vec<u8>, GltfError> {
+  let path = format!(
+    "bufferViews[{}].extensions.EXT_meshopt_compression",
+    view_index
+  );
+  let buffer_def = gltf
+    .buffers()
+    .nth(view.buffer)
+    .ok_or(GltfError::InvalidIndex {
+      path: format!("{}.buffer", path),
+      index: view.buffer,
+    })?;
+  let blob = get_gltf_buffer_data(gltf, &buffer_def)?;
+
+  // the decoded data fills the uncompressed view, checked before the decoder allocates it
+  let view_length = gltf
+    .views()
+    .nth(view_index)
+    .map(|view_def| view_def.length());
+
+  if view.count.checked_mul(view.byte_stride) != view_length {
+    return Err(GltfError::DecodeFailed {
+      path,
+      reason: format!(
+        "{} elements of {} bytes don't fill the view byteLength {:?}",
+        view.count, view.byte_stride, view_length
+      ),
+    });
+  }
+
+  if view
+    .byte_offset
+    .checked_add(view.byte_length)
+    .map_or(true, |end| end > blob.len())
+  {
+    return Err(GltfError::ViewOutOfRange {
+      path,
+      offset: view.byte_offset,
+      length: view.byte_length,
+      available: blob.len(),
+    });
+  }
+
+  decode_meshopt(
+    &blob[view.byte_offset..view.byte_offset + view.byte_length],
+    view.count,
+    view.byte_stride,
+    view.mode,
+    view.filter,
+  )
+  .map_err(|e| GltfError::DecodeFailed {
+    path,
+    reason: e.to_string(),
+  })
+}
+
+pub fn check_gltf_accessor(accessor_def: &gltf::Accessor) -> Result<(), GltfError> {
+  let path = format!("accessors[{}]", accessor_def.index());
+
+  if accessor_def.sparse().is_some() {
+    return Err(GltfError::UnsupportedAccessorType {
+      path: format!("{}.sparse", path),
+      reason: String::from("sparse storage"),
+    });
+  }
+
+  if let Some(view_def) = accessor_def.view() {
+    let item_size = accessor_def.size();
+    let stride = view_def.stride().unwrap_or(item_size);
+    let count = accessor_def.count();
+    let length = if count > 0 {
+      stride * (count - 1) + item_size
+    } else {
+      0
+    };
+
+    if accessor_def.offset() + length > view_def.length() {
+      return Err(GltfError::ViewOutOfRange {
+        path,
+        offset: accessor_def.offset(),
+        length,
+        available: view_def.length(),
+      });
+    }
+  }
+
+  Ok(())
+}
+
+// External images can't be fetched while baking, None leaves the maps using them unset.
+pub fn get_gltf_image_source(
+  gltf: &Gltf,
+  image_def: &gltf::Image,
+) -> Result<Option<ImageSource>, GltfError> {
+  match image_def.source() {
+    ImageDataSource::View { view, mime_type } => Ok(Some(ImageSource {
+      mime_type: mime_type.to_string(),
+      data: get_gltf_view_data(gltf, &view)?.to_vec(),
+    })),
+    ImageDataSource::Uri { .. } => Ok(None),
+  }
+}
+
+pub fn decode_gltf_image(source: &ImageSource, path: &str) -> Result<RgbaImage, GltfError> {
+  let format = match source.mime_type.as_str() {
+    "image/png" => ImageFormat::Png,
+    "image/jpeg" => ImageFormat::Jpeg,
+    mime_type => {
+      return Err(GltfError::DecodeFailed {
+        path: format!("{}.mimeType", path),
+        reason: format!("unsupported image type {}", mime_type),
+      })
+    }
+  };
+
+  image::load_from_memory_with_format(&source.data, format)
+    .map(|image| image.into_rgba8())
+    .map_err(|e| GltfError::DecodeFailed {
+      path: path.to_string(),
+      reason: e.to_string(),
+    })
+}
+
+// glTF leaves the filters to the implementation when unset and wraps with repeat
+pub fn get_gltf_sampler(sampler_def: &gltf::texture::Sampler) -> Sampler {
+  let wrap = |mode: WrappingMode| match mode {
+    WrappingMode::ClampToEdge => TexParam::ClampToEdge,
+    WrappingMode::MirroredRepeat | WrappingMode::Repeat => TexParam::Repeat,
+  };
+
+  Sampler {
+    mag_filter: match sampler_def.mag_filter() {
+      Some(MagFilter::Nearest) => TexParam::Nearest,
+      _ => TexParam::Linear,
+    },
+    min_filter: match sampler_def.min_filter() {
+      Some(MinFilter::Nearest) => TexParam::Nearest,
+      Some(MinFilter::Linear) => TexParam::Linear,
+      Some(MinFilter::NearestMipmapNearest) => TexParam::NearestMipMapNearest,
+      Some(MinFilter::LinearMipmapNearest) => TexParam::LinearMipMapNearest,
+      Some(MinFilter::NearestMipmapLinear) => TexParam::NearestMimMapLinear,
+      Some(MinFilter::LinearMipmapLinear) | None => TexParam::LinearMipMapLinear,
+    },
+    wrap_s: wrap(sampler_def.wrap_s()),
+    wrap_t: wrap(sampler_def.wrap_t()),
+  }
+}
 
 impl Renderer {
-  pub fn create_gltf_accessors(&mut self, gltf: &Gltf) -> IndexMap {
-    let mut buffer_index = IndexMap::new();
-    let mut accessor_index = IndexMap::new();
+  pub fn create_gltf_accessors(
+    &mut self,
+    gltf: &Gltf,
+    raw_extensions: &GltfRawExtensions,
+    options: &GltfLoadOptions,
+  ) -> Result<AccessorIndexMap, GltfError> {
+    let mut buffer_index: IndexMap<Buffer> = IndexMap::new();
+    let mut accessor_index = AccessorIndexMap::new();
 
     for accessor_def in gltf.accessors() {
-      if accessor_def.sparse().is_some() {
-        todo!("sparse");
+      let accessor_handle =
+        self.create_gltf_accessor(gltf, raw_extensions, &accessor_def, &mut buffer_index);
+
+      if let Err(error) = &accessor_handle {
+        if !options.skip_invalid_primitives {
+          return Err(error.clone());
+        }
       }
 
-      let accessor_handle = if let Some(view_def) = accessor_def.view() {
-        let view_index = view_def.index();
-        let blob = gltf.blob.as_ref().unwrap();
+      accessor_index.insert(accessor_def.index(), accessor_handle);
+    }
 
-        let buffer_handle = if let Some(handle) = buffer_index.get(&view_index) {
-          *handle
+    Ok(accessor_index)
+  }
+
+  fn create_gltf_accessor(
+    &mut self,
+    gltf: &Gltf,
+    raw_extensions: &GltfRawExtensions,
+    accessor_def: &gltf::Accessor,
+    buffer_index: &mut IndexMap<Buffer>,
+  ) -> Result<Handle<Accessor>, GltfError> {
+    check_gltf_accessor(accessor_def)?;
+
+    let view_def = accessor_def.view();
+    let view_index = view_def.as_ref().map(|view_def| view_def.index());
+
+    let buffer_handle = match view_index.and_then(|index| buffer_index.get(&index)) {
+      Some(handle) => *handle,
+      None => {
+        let data = match &view_def {
+          Some(view_def) => match raw_extensions.meshopt_views.get(&view_def.index()) {
+            Some(meshopt_view) => Cow::Owned(decode_gltf_meshopt_view(
+              gltf,
+              view_def.index(),
+              meshopt_view,
+            )?),
+            None => Cow::Borrowed(get_gltf_view_data(gltf, view_def)?),
+          },
+          // without a buffer view the accessor reads as zeros
+          None => Cow::Owned(vec![0; accessor_def.count() * accessor_def.size()]),
+        };
+        let acc_idx = accessor_def.index();
+        let is_index_buffer = gltf.meshes().any(|m| {
+          m.primitives().any(|p| match p.indices() {
+            Some(acc) => acc.index() == acc_idx,
+            None => false,
+          })
+        });
+        let buffer_target = if is_index_buffer {
+          BufferTarget::ElementArrayBuffer
         } else {
-          let offset = view_def.offset();
-          let length = view_def.length();
-
-          let data = &blob[offset..(offset + length)];
-          let acc_idx = accessor_def.index();
-          let is_index_buffer = gltf
-            .meshes()
-            .find(|m| {
-              m.primitives()
-                .find(|p| match p.indices() {
-                  Some(acc) => acc.index() == acc_idx,
-                  None => false,
-                })
-                .is_some()
-            })
-            .is_some();
-          let buffer_target = if is_index_buffer {
-            BufferTarget::ElementArrayBuffer
-          } else {
-            BufferTarget::ArrayBuffer
-          };
-          let handle = self.insert_buffer(buffer_target, BufferUsage::StaticDraw, data);
+          BufferTarget::ArrayBuffer
+        };
+        let handle = self.insert_buffer(buffer_target, BufferUsage::StaticDraw, &data);
+
+        if let Some(view_index) = view_index {
           buffer_index.insert(view_index, handle);
+        }
+
+        handle
+      }
+    };
+
+    Ok(self.accessors.insert(Accessor {
+      buffer: buffer_handle,
+      count: accessor_def.count() as i32,
+      options: AttributeOptions {
+        component_type: match accessor_def.data_type() {
+          DataType::U8 => TypedArrayKind::Uint8,
+          DataType::I8 => TypedArrayKind::Int8,
+          DataType::I16 => TypedArrayKind::Int16,
+          DataType::U16 => TypedArrayKind::Uint16,
+          DataType::U32 => TypedArrayKind::Uint32,
+          DataType::F32 => TypedArrayKind::Float32,
+        },
+        item_size: accessor_def.dimensions().multiplicity() as i32,
+        // KHR_mesh_quantization: normalized integers are expanded by vertexAttribPointer,
+        // unnormalized ones are dequantized by the node transform shipped with the asset
+        normalized: accessor_def.normalized(),
+        stride: view_def.and_then(|view_def| view_def.stride()).unwrap_or(0) as i32,
+        offset: accessor_def.offset() as i32,
+      },
+    }))
+  }
+
+  // Images are decoded once and shared by the textures sampling them, their encoded
+  // data is kept as the image source so export_glb can embed it again.
+  pub fn create_gltf_textures(
+    &mut self,
+    gltf: &Gltf,
+    options: &GltfLoadOptions,
+  ) -> Result<IndexMap<Texture>, GltfError> {
+    let mut image_index: HashMap<usize, Option<(Handle<Image>, bool)>> = HashMap::new();
+    let mut texture_index = IndexMap::new();
+
+    for texture_def in gltf.textures() {
+      let image_def = texture_def.source();
+      let image = match image_index.get(&image_def.index()) {
+        Some(image) => *image,
+        None => {
+          let path = format!("images[{}]", image_def.index());
+          let image = match self.create_gltf_image(gltf, &image_def, &path) {
+            Ok(image) => image,
+            Err(error) if options.skip_invalid_primitives => {
+              warn!("skip image {}: {}", path, error);
+              None
+            }
+            Err(error) => return Err(error),
+          };
 
-          handle
+          image_index.insert(image_def.index(), image);
+          image
+        }
+      };
+
+      if let Some((image_handle, power_of_two)) = image {
+        let sampler = get_gltf_sampler(&texture_def.sampler());
+        let sampler = if power_of_two {
+          sampler
+        } else {
+          sampler.without_mipmaps()
+        };
+        let texture = Texture {
+          source: image_handle,
+          sampler: self.insert_sampler(sampler),
         };
 
-        self.accessors.insert(Accessor {
-          buffer: buffer_handle,
-          count: accessor_def.count() as i32,
-          options: AttributeOptions {
-            component_type: match accessor_def.data_type() {
-              DataType::U8 => TypedArrayKind::Uint8,
-              DataType::I8 => TypedArrayKind::Int8,
-              DataType::I16 => TypedArrayKind::Int16,
-              DataType::U16 => TypedArrayKind::Uint16,
-              DataType::U32 => TypedArrayKind::Uint32,
-              DataType::F32 => TypedArrayKind::Float32,
-            },
-            item_size: accessor_def.dimensions().multiplicity() as i32,
-            normalized: accessor_def.normalized(),
-            stride: view_def.stride().unwrap_or(0) as i32,
-            offset: accessor_def.offset() as i32,
-          },
-        })
-      } else {
-        self.accessors.insert(Accessor {
-          buffer: Index::from_raw_parts(0, 0),
-          count: 0,
-          options: AttributeOptions {
-            component_type: TypedArrayKind::Float32,
-            item_size: 3,
-            normalized: false,
-            stride: 0,
-            offset: 0,
-          },
+        texture_index.insert(texture_def.index(), self.insert_texture(texture));
+      }
+    }
+
+    Ok(texture_index)
+  }
+
+  // the image and whether its size is a power of two
+  fn create_gltf_image(
+    &mut self,
+    gltf: &Gltf,
+    image_def: &gltf::Image,
+    path: &str,
+  ) -> Result<Option<(Handle<Image>, bool)>, GltfError> {
+    let source = match get_gltf_image_source(gltf, image_def)? {
+      Some(source) => source,
+      None => {
+        warn!("{}: external images are not loaded", path);
+        return Ok(None);
+      }
+    };
+    let pixels = decode_gltf_image(&source, path)?;
+    let (width, height) = pixels.dimensions();
+    let image_handle = self.bake_image_from_pixels(TextureFormat::RGBA, width, height, &pixels);
+
+    self.images.get_mut(image_handle).unwrap().source = Some(source);
+
+    Ok(Some((image_handle, is_power_of_two(width, height))))
+  }
+
+  pub fn create_gltf_materials(
+    &mut self,
+    gltf: &Gltf,
+    raw_extensions: &GltfRawExtensions,
+    texture_index: &IndexMap<Texture>,
+  ) -> Result<IndexMap<dyn Material>, GltfError> {
+    let mut material_index = IndexMap::new();
+
+    for (i, material_def) in gltf.materials().enumerate() {
+      let index = material_def.index().ok_or(GltfError::InvalidIndex {
+        path: format!("materials[{}]", i),
+        index: i,
+      })?;
+      let pbr = material_def.pbr_metallic_roughness();
+      let [r, g, b, a] = pbr.base_color_factor();
+      let vertex_colors = gltf.meshes().any(|mesh_def| {
+        mesh_def.primitives().any(|primitive_def| {
+          primitive_def.material().index() == Some(index)
+            && primitive_def.get(&Semantic::Colors(0)).is_some()
         })
+      });
+      let path = format!("materials[{}]", index);
+      let raw_transform = |map: PbrMap| {
+        raw_extensions
+          .texture_transforms
+          .get(&(index, map))
+          .copied()
+      };
+      let color_map = pbr
+        .base_color_texture()
+        .and_then(|info| get_gltf_info_map(&info, texture_index));
+      let metallic_roughness_map = pbr
+        .metallic_roughness_texture()
+        .and_then(|info| get_gltf_info_map(&info, texture_index));
+      let emissive_map = material_def
+        .emissive_texture()
+        .and_then(|info| get_gltf_info_map(&info, texture_index));
+      let normal_texture = material_def.normal_texture();
+      let normal_map = normal_texture.as_ref().and_then(|normal| {
+        get_gltf_map_info(
+          normal.texture().index(),
+          normal.tex_coord(),
+          raw_transform(PbrMap::Normal),
+          texture_index,
+        )
+      });
+      let occlusion_texture = material_def.occlusion_texture();
+      let occlusion_map = occlusion_texture.as_ref().and_then(|occlusion| {
+        get_gltf_map_info(
+          occlusion.texture().index(),
+          occlusion.tex_coord(),
+          raw_transform(PbrMap::Occlusion),
+          texture_index,
+        )
+      });
+      let emissive_strength = raw_extensions
+        .emissive_strengths
+        .get(&index)
+        .copied()
+        .unwrap_or(1.0);
+      let (blend, alpha_cutoff) = match material_def.alpha_mode() {
+        AlphaMode::Opaque => (BlendMode::Opaque, None),
+        AlphaMode::Mask => (
+          BlendMode::Opaque,
+          Some(material_def.alpha_cutoff().unwrap_or(0.5)),
+        ),
+        AlphaMode::Blend => (BlendMode::Alpha, None),
       };
+      let material = PbrMaterial::new()
+        .set_color(Vector3::new(r, g, b))
+        .set_opacity(a)
+        .set_metallic(pbr.metallic_factor())
+        .set_roughness(pbr.roughness_factor())
+        .set_blend(blend)
+        .set_alpha_cutoff(alpha_cutoff)
+        .set_emissive(Vector3::from(material_def.emissive_factor()))
+        .set_emissive_strength(emissive_strength)
+        .set_unlit(material_def.unlit())
+        .set_cull_face(!material_def.double_sided())
+        .set_vertex_colors(vertex_colors)
+        .set_name(material_def.name().map(|n| n.to_string()))
+        .set_user_data(parse_gltf_extras(material_def.extras(), &path))
+        .set_normal_scale(normal_texture.map_or(1.0, |normal| normal.scale()))
+        .set_occlusion_strength(occlusion_texture.map_or(1.0, |occlusion| occlusion.strength()));
+      let maps = [
+        (PbrMap::Color, color_map),
+        (PbrMap::MetallicRoughness, metallic_roughness_map),
+        (PbrMap::Normal, normal_map),
+        (PbrMap::Occlusion, occlusion_map),
+        (PbrMap::Emissive, emissive_map),
+      ];
+      let material = maps.iter().fold(material, |material, (map, info)| {
+        set_gltf_map(material, *map, *info, &path)
+      });
+      let material_handle = self.bake_material(material.boxed());
 
-      accessor_index.insert(accessor_def.index(), accessor_handle);
+      material_index.insert(index, material_handle);
     }
 
-    accessor_index
+    Ok(material_index)
   }
 
-  pub fn create_gltf_materials(&mut self, gltf: &Gltf) -> IndexMap {
-    let mut material_index = IndexMap::new();
+  fn create_gltf_primitive(
+    &mut self,
+    primitive_def: &gltf::Primitive,
+    path: &str,
+    accessor_index: &AccessorIndexMap,
+    materials_index: &IndexMap<dyn Material>,
+    variant_names: &[String],
+  ) -> Result<Primitive, GltfError> {
+    let get_accessor = |index: usize, path: String| -> Result<Handle<Accessor>, GltfError> {
+      match accessor_index.get(&index) {
+        Some(handle) => handle.clone(),
+        None => Err(GltfError::InvalidIndex { path, index }),
+      }
+    };
+
+    let mut attributes = Attributes::new();
 
-    for material_def in gltf.materials() {
-      let material_handle = self.bake_material(
-        PbrMaterial::new()
-          .set_color(Vector3::new(1.0, 0.0, 0.0))
-          .boxed(),
+    for (semantic_def, accessor_def) in primitive_def.attributes() {
+      let attr_name = match semantic_def {
+        Semantic::Positions => AttributeName::Position,
+        Semantic::Normals => AttributeName::Normal,
+        Semantic::Tangents => AttributeName::Tangent,
+        Semantic::Colors(0) => AttributeName::Color0,
+        Semantic::TexCoords(0) => AttributeName::Uv,
+        Semantic::TexCoords(1) => AttributeName::Uv1,
+        Semantic::Joints(0) => AttributeName::Joints0,
+        Semantic::Weights(0) => AttributeName::Weights0,
+        _ => AttributeName::Custom(semantic_def.to_string()),
+      };
+      let accessor_path = format!("{}.attributes.{}", path, semantic_def.to_string());
+
+      if let Dimensions::Mat2 | Dimensions::Mat3 | Dimensions::Mat4 = accessor_def.dimensions() {
+        return Err(GltfError::UnsupportedAccessorType {
+          path: accessor_path,
+          reason: format!(
+            "{:?} can't be bound as a vertex attribute",
+            accessor_def.dimensions()
+          ),
+        });
+      }
+
+      attributes.insert(
+        attr_name,
+        get_accessor(accessor_def.index(), accessor_path)?,
       );
+    }
+
+    let indices = match primitive_def.indices() {
+      Some(indices_def) => {
+        let indices_path = format!("{}.indices", path);
+
+        if indices_def.dimensions() != Dimensions::Scalar
+          || !matches!(
+            indices_def.data_type(),
+            DataType::U8 | DataType::U16 | DataType::U32
+          )
+        {
+          return Err(GltfError::UnsupportedAccessorType {
+            path: indices_path,
+            reason: format!(
+              "indices must be unsigned scalars, got {:?} {:?}",
+              indices_def.dimensions(),
+              indices_def.data_type()
+            ),
+          });
+        }
+
+        Some(get_accessor(indices_def.index(), indices_path)?)
+      }
+      None => None,
+    };
 
-      material_index.insert(material_def.index().unwrap(), material_handle);
+    let material = match primitive_def.material().index() {
+      Some(index) => Some(*materials_index.get(&index).ok_or(GltfError::InvalidIndex {
+        path: format!("{}.material", path),
+        index,
+      })?),
+      None => None,
+    };
+
+    let mut variants = HashMap::new();
+
+    for (i, mapping_def) in primitive_def.mappings().enumerate() {
+      let mapping_path = format!("{}.extensions.KHR_materials_variants.mappings[{}]", path, i);
+      let material_index = mapping_def.material().index().unwrap_or(usize::MAX);
+      let material_handle =
+        *materials_index
+          .get(&material_index)
+          .ok_or(GltfError::InvalidIndex {
+            path: format!("{}.material", mapping_path),
+            index: material_index,
+          })?;
+
+      for variant in mapping_def.variants() {
+        let name = variant_names
+          .get(*variant as usize)
+          .ok_or(GltfError::InvalidIndex {
+            path: format!("{}.variants", mapping_path),
+            index: *variant as usize,
+          })?;
+
+        variants.insert(name.clone(), material_handle);
+      }
     }
 
-    material_index
+    let draw_mode = match primitive_def.mode() {
+      Mode::Points => DrawMode::Points,
+      Mode::Lines => DrawMode::Lines,
+      Mode::LineLoop => DrawMode::LineLoop,
+      Mode::LineStrip => DrawMode::LineStrip,
+      Mode::Triangles => DrawMode::Triangles,
+      Mode::TriangleStrip => DrawMode::TriangleStrip,
+      Mode::TriangleFan => DrawMode::TriangleFan,
+    };
+
+    let geometry = self.insert_geometry(Geometry {
+      attributes,
+      indices,
+      draw_mode,
+    });
+
+    let requires_tangents = material
+      .iter()
+      .chain(variants.values())
+      .any(|material_handle| self.materials[*material_handle].requires_tangents());
+
+    if requires_tangents && self.ensure_tangents(geometry).is_none() {
+      warn!("{}: unable to generate tangents", path);
+    }
+
+    Ok(Primitive {
+      geometry,
+      material,
+      default_material: material,
+      variants,
+    })
   }
 
   pub fn create_gltf_meshes(
     &mut self,
     gltf: &Gltf,
-    accessor_index: &IndexMap,
-    materials_index: &IndexMap,
-  ) -> IndexMap {
+    accessor_index: &AccessorIndexMap,
+    materials_index: &IndexMap<dyn Material>,
+    options: &GltfLoadOptions,
+  ) -> Result<IndexMap<Mesh>, GltfError> {
     let mut mesh_index = IndexMap::new();
+    let variant_names: Vec<String> = match gltf.variants() {
+      Some(variants) => variants.map(|v| v.name().to_string()).collect(),
+      None => vec![],
+    };
 
     for mesh_def in gltf.meshes() {
       let mut primitives: Vec<Primitive> = vec![];
 
       for primitive_def in mesh_def.primitives() {
-        let mut attributes: HashMap<AttributeName, Index> = HashMap::new();
-
-        for (semantic_def, accessor_def) in primitive_def.attributes() {
-          let attr_name = match semantic_def {
-            Semantic::Positions => AttributeName::Position,
-            Semantic::Normals => AttributeName::Normal,
-            Semantic::TexCoords(value) => match value {
-              0 => AttributeName::Uv,
-              _ => AttributeName::Custom(semantic_def.to_string()),
-            },
-            _ => AttributeName::Custom(semantic_def.to_string()),
-          };
-          attributes.insert(
-            attr_name,
-            *accessor_index.get(&accessor_def.index()).unwrap(),
-          );
-        }
-
-        let indices;
-
-        if let Some(indices_accessor) = primitive_def.indices() {
-          indices = accessor_index.get(&indices_accessor.index()).cloned();
-        } else {
-          indices = None;
-        }
+        let path = format!(
+          "meshes[{}].primitives[{}]",
+          mesh_def.index(),
+          primitive_def.index()
+        );
 
-        let material;
-
-        if let Some(index) = primitive_def.material().index() {
-          material = materials_index.get(&index).cloned();
-        } else {
-          material = None;
+        match self.create_gltf_primitive(
+          &primitive_def,
+          &path,
+          accessor_index,
+          materials_index,
+          &variant_names,
+        ) {
+          Ok(primitive) => primitives.push(primitive),
+          Err(error) if options.skip_invalid_primitives => {
+            warn!("skip primitive {}: {}", path, error);
+          }
+          Err(error) => return Err(error),
         }
-
-        let geometry = self.insert_geometry(Geometry {
-          attributes,
-          indices,
-        });
-
-        primitives.push(Primitive { geometry, material });
       }
 
       let mesh_handle = self.meshes.insert(Mesh {
         primitives,
         name: mesh_def.name().map(|n| n.to_string()),
+        user_data: parse_gltf_extras(mesh_def.extras(), &format!("meshes[{}]", mesh_def.index())),
       });
 
       mesh_index.insert(mesh_def.index(), mesh_handle);
     }
 
-    mesh_index
+    Ok(mesh_index)
+  }
+
+  fn create_gltf_instances(
+    &mut self,
+    node: usize,
+    attributes: &HashMap<String, usize>,
+    accessor_index: &AccessorIndexMap,
+  ) -> Result<Handle<InstanceSet>, GltfError> {
+    let path = format!("nodes[{}].extensions.EXT_mesh_gpu_instancing", node);
+
+    if !self.ctx.supports_instancing() {
+      return Err(GltfError::UnsupportedExtension {
+        path,
+        name: "EXT_mesh_gpu_instancing".to_string(),
+      });
+    }
+
+    let mut instance_attributes = Attributes::new();
+    let mut count = None;
+
+    for (semantic, index) in attributes {
+      let name = match semantic.as_str() {
+        "TRANSLATION" => AttributeName::InstanceTranslation,
+        "ROTATION" => AttributeName::InstanceRotation,
+        "SCALE" => AttributeName::InstanceScale,
+        // application specific attributes like _ID are not drawn
+        _ => continue,
+      };
+
+      let handle = match accessor_index.get(index) {
+        Some(handle) => handle.clone()?,
+        None => {
+          return Err(GltfError::InvalidIndex {
+            path: format!("{}.attributes.{}", path, semantic),
+            index: *index,
+          })
+        }
+      };
+
+      count = Some(self.accessors.get(handle).unwrap().count);
+      instance_attributes.insert(name, handle);
+    }
+
+    let count = count.ok_or_else(|| GltfError::DecodeFailed {
+      path: path.clone(),
+      reason: "no TRANSLATION, ROTATION or SCALE attribute".to_string(),
+    })?;
+
+    let defaults: [(AttributeName, &[f32]); 3] = [
+      (AttributeName::InstanceTranslation, &[0.0, 0.0, 0.0]),
+      (AttributeName::InstanceRotation, &[0.0, 0.0, 0.0, 1.0]),
+      (AttributeName::InstanceScale, &[1.0, 1.0, 1.0]),
+    ];
+
+    for (name, value) in defaults.iter() {
+      if !instance_attributes.contains_key(name) {
+        let data: Vec<f32> = value
+          .iter()
+          .cycle()
+          .take(value.len() * count as usize)
+          .copied()
+          .collect();
+        let accessor =
+          self.bake_buffer_accessor(BufferTarget::ArrayBuffer, &data, value.len() as i32, count);
+
+        instance_attributes.insert(name.clone(), accessor);
+      }
+    }
+
+    Ok(self.instance_sets.insert(InstanceSet {
+      attributes: instance_attributes,
+      count,
+    }))
   }
 
-  pub fn create_gltf_nodes(&mut self, gltf: &Gltf, mesh_index: &IndexMap) -> IndexMap {
+  pub fn create_gltf_nodes(
+    &mut self,
+    gltf: &Gltf,
+    raw_extensions: &GltfRawExtensions,
+    mesh_index: &IndexMap<Mesh>,
+    accessor_index: &AccessorIndexMap,
+  ) -> Result<IndexMap<Node>, GltfError> {
     let mut node_index = IndexMap::new();
 
-    let nodes: Vec<Node> = gltf
-      .nodes()
-      .map(|node_def| {
-        let mut node = Node::new(None);
-
-        match node_def.transform() {
-          Transform::Decomposed {
-            translation,
-            rotation,
-            scale,
-          } => {
-            node.matrix_local = compose_matrix(
-              Some(Vector3::from_vec(translation.to_vec())),
-              Some(UnitQuaternion::from_quaternion(Quaternion::from(
-                Vector4::from_vec(rotation.to_vec()),
-              ))),
-              Some(Vector3::from_vec(scale.to_vec())),
-            )
-          }
-          Transform::Matrix { matrix: m } => {
-            node.matrix_local = Matrix4::new(
-              m[0][0], m[0][1], m[0][2], m[0][3], //
-              m[1][0], m[1][1], m[1][2], m[1][3], //
-              m[2][0], m[2][1], m[2][2], m[2][3], //
-              m[3][0], m[3][1], m[3][2], m[3][3], //
-            );
-          }
-        };
+    let mut nodes: Vec<Node> = vec![];
 
-        if let Some(mesh_def) = node_def.mesh() {
-          node.mesh = mesh_index.get(&mesh_def.index()).cloned();
+    for node_def in gltf.nodes() {
+      let mut node = Node::new(None);
+
+      match node_def.transform() {
+        Transform::Decomposed {
+          translation,
+          rotation,
+          scale,
+        } => {
+          node.matrix_local = compose_matrix(
+            Some(Vector3::from_vec(translation.to_vec())),
+            Some(UnitQuaternion::from_quaternion(Quaternion::from(
+              Vector4::from_vec(rotation.to_vec()),
+            ))),
+            Some(Vector3::from_vec(scale.to_vec())),
+          )
+        }
+        Transform::Matrix { matrix: m } => {
+          node.matrix_local = Matrix4::new(
+            m[0][0], m[0][1], m[0][2], m[0][3], //
+            m[1][0], m[1][1], m[1][2], m[1][3], //
+            m[2][0], m[2][1], m[2][2], m[2][3], //
+            m[3][0], m[3][1], m[3][2], m[3][3], //
+          );
         }
+      };
 
-        node.name = node_def.name().map(|n| n.to_string());
+      if let Some(mesh_def) = node_def.mesh() {
+        let mesh_handle = mesh_index
+          .get(&mesh_def.index())
+          .ok_or(GltfError::InvalidIndex {
+            path: format!("nodes[{}].mesh", node_def.index()),
+            index: mesh_def.index(),
+          })?;
 
-        node
-      })
-      .collect();
+        node.mesh = Some(*mesh_handle);
+
+        if let Some(attributes) = raw_extensions.instancing.get(&node_def.index()) {
+          node.instances =
+            Some(self.create_gltf_instances(node_def.index(), attributes, accessor_index)?);
+        }
+      }
+
+      node.name = node_def.name().map(|n| n.to_string());
+      node.user_data =
+        parse_gltf_extras(node_def.extras(), &format!("nodes[{}]", node_def.index()));
+
+      nodes.push(node);
+    }
 
     for (index, node) in nodes.iter().enumerate() {
       let handle = self.scene.insert(node.clone());
@@ -227,39 +1204,590 @@ impl Renderer {
     }
 
     for node_def in gltf.nodes() {
-      for child_def in node_def.children() {
-        let child_handle = node_index.get(&child_def.index()).unwrap();
-        let parent_handle = node_index.get(&node_def.index()).unwrap();
+      let parent_handle = *node_index.get(&node_def.index()).unwrap();
+
+      for (i, child_def) in node_def.children().enumerate() {
+        let child_handle = node_index
+          .get(&child_def.index())
+          .ok_or(GltfError::InvalidIndex {
+            path: format!("nodes[{}].children[{}]", node_def.index(), i),
+            index: child_def.index(),
+          })?;
 
-        self.scene.set_parent(*child_handle, *parent_handle);
+        self.scene.set_parent(*child_handle, parent_handle);
       }
     }
 
-    node_index
+    Ok(node_index)
   }
 
-  pub fn create_gltf_scenes(&mut self, gltf: &Gltf, node_index: &IndexMap) -> Vec<Index> {
-    gltf
-      .scenes()
-      .map(|scene_def| {
-        let scene_handle = self.scene.insert(Node::new(None));
+  pub fn create_gltf_scenes(
+    &mut self,
+    gltf: &Gltf,
+    node_index: &IndexMap<Node>,
+  ) -> Result<Vec<Handle<Node>>, GltfError> {
+    let mut scene_handles = vec![];
 
-        for node_def in scene_def.nodes() {
-          let node_handle = *node_index.get(&node_def.index()).unwrap();
-          self.scene.set_parent(node_handle, scene_handle);
-        }
+    for scene_def in gltf.scenes() {
+      let scene_handle = self.scene.insert(Node::new(None));
 
-        scene_handle
+      for (i, node_def) in scene_def.nodes().enumerate() {
+        let node_handle = *node_index
+          .get(&node_def.index())
+          .ok_or(GltfError::InvalidIndex {
+            path: format!("scenes[{}].nodes[{}]", scene_def.index(), i),
+            index: node_def.index(),
+          })?;
+
+        self.scene.set_parent(node_handle, scene_handle);
+      }
+
+      scene_handles.push(scene_handle);
+    }
+
+    Ok(scene_handles)
+  }
+
+  pub fn bake_gltf(&mut self, gltf: &Gltf) -> Result<Vec<Handle<Node>>, GltfError> {
+    self.bake_gltf_with_options(gltf, &GltfLoadOptions::default())
+  }
+
+  // bake_gltf can't see extensions that gltf crate drops (emissive strength),
+  // load_gltf parses the asset itself and keeps them
+  pub fn load_gltf(
+    &mut self,
+    data: &[u8],
+    options: &GltfLoadOptions,
+  ) -> Result<Vec<Handle<Node>>, GltfError> {
+    let gltf = Gltf::from_slice(data).map_err(|e| GltfError::Parse {
+      reason: e.to_string(),
+    })?;
+    let raw_extensions = GltfRawExtensions::from_slice(data)?;
+
+    self.bake_gltf_with_extensions(&gltf, &raw_extensions, options)
+  }
+
+  pub fn bake_gltf_with_options(
+    &mut self,
+    gltf: &Gltf,
+    options: &GltfLoadOptions,
+  ) -> Result<Vec<Handle<Node>>, GltfError> {
+    self.bake_gltf_with_extensions(gltf, &GltfRawExtensions::default(), options)
+  }
+
+  pub fn bake_gltf_with_extensions(
+    &mut self,
+    gltf: &Gltf,
+    raw_extensions: &GltfRawExtensions,
+    options: &GltfLoadOptions,
+  ) -> Result<Vec<Handle<Node>>, GltfError> {
+    self
+      .bake_gltf_resources(gltf, raw_extensions, options)
+      .map(|(scenes, _)| scenes)
+  }
+
+  // the scenes and the resources baked for them, the rest hangs off these and is
+  // freed with them
+  pub(crate) fn bake_gltf_resources(
+    &mut self,
+    gltf: &Gltf,
+    raw_extensions: &GltfRawExtensions,
+    options: &GltfLoadOptions,
+  ) -> Result<(Vec<Handle<Node>>, AssetResources), GltfError> {
+    check_gltf_extensions(gltf, raw_extensions)?;
+
+    for name in ignored_gltf_extensions(gltf, raw_extensions) {
+      warn!(
+        "{} is not applied, load the asset with load_gltf to keep it",
+        name
+      );
+    }
+
+    let accessor_index = self.create_gltf_accessors(gltf, raw_extensions, options)?;
+    let texture_index = self.create_gltf_textures(gltf, options)?;
+    let material_index = self.create_gltf_materials(gltf, raw_extensions, &texture_index)?;
+    let mesh_index = self.create_gltf_meshes(gltf, &accessor_index, &material_index, options)?;
+    let node_index = self.create_gltf_nodes(gltf, raw_extensions, &mesh_index, &accessor_index)?;
+    let scenes = self.create_gltf_scenes(gltf, &node_index)?;
+
+    let resources = AssetResources {
+      accessors: accessor_index
+        .values()
+        .filter_map(|handle| handle.as_ref().ok().copied())
+        .collect(),
+      textures: texture_index.values().copied().collect(),
+      materials: material_index.values().copied().collect(),
+      meshes: mesh_index.values().copied().collect(),
+      instance_sets: node_index
+        .values()
+        .filter_map(|handle| self.scene.get_node(*handle)?.instances)
+        .collect(),
+      lights: vec![],
+    };
+
+    Ok((scenes, resources))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use gltf::binary::{Glb, Header};
+
+  use super::*;
+  use crate::renderer::webgl::material::UniformValue;
+  use crate::renderer::webgl::recording::RecordingBackend;
+
+  fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+    Glb {
+      header: Header {
+        magic: *b"glTF",
+        version: 2,
+        length: 0,
+      },
+      json: Cow::Borrowed(json.as_bytes()),
+      bin: Some(Cow::Borrowed(bin)),
+    }
+    .to_vec()
+    .unwrap()
+  }
+
+  fn parse(json: &str, bin: &[u8]) -> Gltf {
+    Gltf::from_slice(&glb(json, bin)).unwrap()
+  }
+
+  #[test]
+  fn accessors_check_their_range_and_storage() {
+    let gltf = parse(
+      r#"{
+        "asset": {"version": "2.0"},
+        "buffers": [{"byteLength": 16}],
+        "bufferViews": [{"buffer": 0, "byteLength": 16}],
+        "accessors": [
+          {"bufferView": 0, "componentType": 5126, "count": 1, "type": "VEC3"},
+          {"bufferView": 0, "byteOffset": 8, "componentType": 5126, "count": 1, "type": "VEC3"},
+          {"bufferView": 0, "componentType": 5126, "count": 1, "type": "SCALAR",
+           "sparse": {"count": 1, "indices": {"bufferView": 0, "componentType": 5125},
+                      "values": {"bufferView": 0}}}
+        ]
+      }"#,
+      &[0; 16],
+    );
+    let accessors: Vec<_> = gltf.accessors().collect();
+
+    assert_eq!(check_gltf_accessor(&accessors[0]), Ok(()));
+    assert_eq!(
+      check_gltf_accessor(&accessors[1]),
+      Err(GltfError::ViewOutOfRange {
+        path: "accessors[1]".to_string(),
+        offset: 8,
+        length: 12,
+        available: 16,
       })
-      .collect()
+    );
+    assert!(matches!(
+      check_gltf_accessor(&accessors[2]),
+      Err(GltfError::UnsupportedAccessorType { path, .. }) if path == "accessors[2].sparse"
+    ));
   }
 
-  pub fn bake_gltf(&mut self, gltf: &Gltf) -> Vec<Index> {
-    let accessor_index = self.create_gltf_accessors(gltf);
-    let material_index = self.create_gltf_materials(gltf);
-    let mesh_index = self.create_gltf_meshes(gltf, &accessor_index, &material_index);
-    let node_index = self.create_gltf_nodes(gltf, &mesh_index);
+  #[test]
+  fn views_stay_inside_the_binary_chunk() {
+    let gltf = parse(
+      r#"{
+        "asset": {"version": "2.0"},
+        "buffers": [{"byteLength": 8}],
+        "bufferViews": [
+          {"buffer": 0, "byteOffset": 4, "byteLength": 4},
+          {"buffer": 0, "byteOffset": 4, "byteLength": 12}
+        ]
+      }"#,
+      &[0, 1, 2, 3, 4, 5, 6, 7],
+    );
+    let views: Vec<_> = gltf.views().collect();
+
+    assert_eq!(
+      get_gltf_view_data(&gltf, &views[0]),
+      Ok(&[4u8, 5, 6, 7][..])
+    );
+    assert_eq!(
+      get_gltf_view_data(&gltf, &views[1]),
+      Err(GltfError::ViewOutOfRange {
+        path: "bufferViews[1]".to_string(),
+        offset: 4,
+        length: 12,
+        available: 8,
+      })
+    );
+  }
+
+  #[test]
+  fn required_extensions_need_support_and_raw_parsing() {
+    let required = |name: &str| {
+      parse(
+        &format!(
+          r#"{{"asset": {{"version": "2.0"}}, "extensionsUsed": ["{0}"], "extensionsRequired": ["{0}"]}}"#,
+          name
+        ),
+        &[],
+      )
+    };
+    let raw = GltfRawExtensions {
+      parsed: true,
+      ..Default::default()
+    };
+
+    assert_eq!(
+      check_gltf_extensions(&required("KHR_materials_unlit"), &Default::default()),
+      Ok(())
+    );
+    assert_eq!(
+      check_gltf_extensions(&required("EXT_mesh_gpu_instancing"), &raw),
+      Ok(())
+    );
+    assert_eq!(
+      check_gltf_extensions(&required("EXT_mesh_gpu_instancing"), &Default::default()),
+      Err(GltfError::UnsupportedExtension {
+        path: "extensionsRequired[0]".to_string(),
+        name: "EXT_mesh_gpu_instancing".to_string(),
+      })
+    );
+    assert!(check_gltf_extensions(&required("EXT_unknown"), &raw).is_err());
+  }
+
+  #[test]
+  fn bake_gltf_reports_the_extensions_it_drops() {
+    let data = glb(
+      r#"{
+        "asset": {"version": "2.0"},
+        "extensionsUsed": ["KHR_materials_emissive_strength", "KHR_materials_unlit"],
+        "materials": [
+          {"extensions": {"KHR_materials_emissive_strength": {"emissiveStrength": 4}}}
+        ],
+        "scenes": [{"nodes": []}]
+      }"#,
+      &[],
+    );
+    let gltf = Gltf::from_slice(&data).unwrap();
+    let raw = GltfRawExtensions::from_slice(&data).unwrap();
+    let mut renderer = Renderer::new(RecordingBackend::new());
+
+    assert_eq!(
+      ignored_gltf_extensions(&gltf, &Default::default()),
+      ["KHR_materials_emissive_strength"]
+    );
+    assert!(ignored_gltf_extensions(&gltf, &raw).is_empty());
+    assert_eq!(renderer.bake_gltf(&gltf).map(|scenes| scenes.len()), Ok(1));
+    assert_eq!(raw.emissive_strengths.get(&0), Some(&4.0));
+  }
+
+  // validation asks for a view unless the accessor is sparse, which is rejected earlier
+  #[test]
+  fn accessors_without_a_view_read_as_zeros() {
+    let data = glb(
+      r#"{
+        "asset": {"version": "2.0"},
+        "buffers": [{"byteLength": 12}],
+        "bufferViews": [{"buffer": 0, "byteLength": 12}],
+        "accessors": [
+          {"bufferView": 0, "componentType": 5126, "count": 1, "type": "VEC3",
+           "min": [0, 0, 0], "max": [1, 1, 1]},
+          {"componentType": 5126, "count": 3, "type": "VEC3"}
+        ],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0, "NORMAL": 1}}]}]
+      }"#,
+      &[0; 12],
+    );
+    let gltf = Gltf::from_slice_without_validation(&data).unwrap();
+    let mut renderer = Renderer::new(RecordingBackend::new());
+    let accessors = renderer
+      .create_gltf_accessors(&gltf, &Default::default(), &Default::default())
+      .unwrap();
+    let buffer = |index: usize| {
+      let accessor = renderer
+        .accessors
+        .get(accessors[&index].clone().unwrap())
+        .unwrap();
+
+      (accessor.buffer, accessor.count)
+    };
+    let (position_buffer, _) = buffer(0);
+    let (normal_buffer, normal_count) = buffer(1);
+
+    assert_ne!(position_buffer, normal_buffer);
+    assert_eq!(normal_count, 3);
+    assert_eq!(
+      renderer.buffers.get(normal_buffer).unwrap().data,
+      vec![0; 36]
+    );
+  }
+
+  #[test]
+  fn materials_keep_a_uv_set_and_transform_per_map() {
+    let json = r#"{
+      "asset": {"version": "2.0"},
+      "extensionsUsed": ["KHR_texture_transform", "KHR_materials_emissive_strength"],
+      "images": [{"uri": "color.png"}],
+      "textures": [{"source": 0}],
+      "materials": [
+        {
+          "pbrMetallicRoughness": {
+            "baseColorTexture": {
+              "index": 0,
+              "extensions": {"KHR_texture_transform": {"offset": [0.5, 0], "texCoord": 1}}
+            }
+          },
+          "extensions": {"KHR_materials_emissive_strength": {"emissiveStrength": 4}}
+        },
+        {
+          "pbrMetallicRoughness": {"baseColorTexture": {"index": 0, "texCoord": 0}},
+          "extensions": {"KHR_materials_emissive_strength": {"emissiveStrength": "bright"}}
+        }
+      ]
+    }"#;
+    let data = glb(json, &[]);
+    let gltf = Gltf::from_slice(&data).unwrap();
+    let raw = GltfRawExtensions::from_slice(&data).unwrap();
+
+    assert_eq!(raw.emissive_strengths, [(0, 4.0)].iter().copied().collect());
+
+    let mut renderer = Renderer::new(RecordingBackend::new());
+    let texture =
+      renderer.bake_2d_texture_from_pixels(TextureFormat::RGBA, Sampler::default(), 1, 1, &[0; 4]);
+    let texture_index = [(0, texture)].iter().copied().collect();
+    let materials = renderer
+      .create_gltf_materials(&gltf, &raw, &texture_index)
+      .unwrap();
+
+    let defines = |index: usize| -> Vec<String> {
+      renderer.materials[materials[&index]]
+        .shader_variant()
+        .defines
+        .iter()
+        .map(|define| define.as_string())
+        .collect()
+    };
+
+    assert!(defines(0).contains(&"#define COLOR_MAP_UV v_uv1".to_string()));
+    assert!(defines(0).contains(&"#define USE_UV1".to_string()));
+    assert!(defines(1).contains(&"#define COLOR_MAP_UV v_uv".to_string()));
+    assert!(!defines(1).contains(&"#define USE_UV1".to_string()));
+
+    let params = |index: usize, renderer: &mut Renderer| {
+      renderer
+        .materials
+        .get_mut(materials[&index])
+        .unwrap()
+        .param_block_mut()
+        .unwrap()
+        .clone()
+    };
+    let first = params(0, &mut renderer);
+    let second = params(1, &mut renderer);
+    let offset = TextureTransform::new().set_offset(Vector2::new(0.5, 0.0));
+
+    assert_eq!(
+      first["colorMapTransform"],
+      UniformValue::Matrix3(offset.matrix())
+    );
+    assert_eq!(
+      first["normalMapTransform"],
+      UniformValue::Matrix3(na::Matrix3::identity())
+    );
+    assert_eq!(first["emissiveStrength"], UniformValue::Float(4.0));
+    assert_eq!(second["emissiveStrength"], UniformValue::Float(1.0));
+  }
+
+  #[test]
+  fn materials_import_every_map_with_its_tex_coord() {
+    let json = r#"{
+      "asset": {"version": "2.0"},
+      "extensionsUsed": ["KHR_texture_transform"],
+      "images": [{"uri": "map.png"}],
+      "textures": [{"source": 0}],
+      "materials": [
+        {
+          "pbrMetallicRoughness": {"metallicRoughnessTexture": {"index": 0}},
+          "normalTexture": {
+            "index": 0,
+            "scale": 0.5,
+            "extensions": {"KHR_texture_transform": {"scale": [2, 2], "texCoord": 1}}
+          },
+          "occlusionTexture": {"index": 0, "strength": 0.25},
+          "emissiveTexture": {"index": 0, "texCoord": 1}
+        },
+        {"occlusionTexture": {"index": 0, "texCoord": 1}}
+      ]
+    }"#;
+    let data = glb(json, &[]);
+    let gltf = Gltf::from_slice(&data).unwrap();
+    let raw = GltfRawExtensions::from_slice(&data).unwrap();
+    let scale = TextureTransform::new().set_scale(Vector2::new(2.0, 2.0));
+
+    assert_eq!(
+      raw.texture_transforms[&(0, PbrMap::Normal)],
+      GltfTextureTransform {
+        transform: scale,
+        tex_coord: Some(1),
+      }
+    );
+
+    let mut renderer = Renderer::new(RecordingBackend::new());
+    let texture =
+      renderer.bake_2d_texture_from_pixels(TextureFormat::RGBA, Sampler::default(), 1, 1, &[0; 4]);
+    let texture_index = [(0, texture)].iter().copied().collect();
+    let materials = renderer
+      .create_gltf_materials(&gltf, &raw, &texture_index)
+      .unwrap();
+
+    let defines = |index: usize| -> Vec<String> {
+      renderer.materials[materials[&index]]
+        .shader_variant()
+        .defines
+        .iter()
+        .map(|define| define.as_string())
+        .collect()
+    };
+    let first = defines(0);
+
+    for define in &[
+      "#define METALLIC_ROUGHNESS_MAP_UV v_uv",
+      "#define NORMAL_MAP_UV v_uv1",
+      "#define OCCLUSION_MAP_UV v_uv",
+      "#define EMISSIVE_MAP_UV v_uv1",
+    ] {
+      assert!(first.contains(&define.to_string()), "{}", define);
+    }
+    assert!(defines(1).contains(&"#define OCCLUSION_MAP_UV v_uv1".to_string()));
+
+    // normal maps make the primitives generate tangents
+    assert!(renderer.materials[materials[&0]].requires_tangents());
+    assert!(!renderer.materials[materials[&1]].requires_tangents());
+
+    let params = renderer
+      .materials
+      .get_mut(materials[&0])
+      .unwrap()
+      .param_block_mut()
+      .unwrap()
+      .clone();
+
+    assert_eq!(
+      params["normalMapTransform"],
+      UniformValue::Matrix3(scale.matrix())
+    );
+    assert_eq!(params["normalScale"], UniformValue::Float(0.5));
+    assert_eq!(params["occlusionStrength"], UniformValue::Float(0.25));
+  }
+
+  #[test]
+  fn meshopt_views_must_fill_their_byte_length() {
+    let gltf = parse(
+      r#"{
+        "asset": {"version": "2.0"},
+        "buffers": [{"byteLength": 16}],
+        "bufferViews": [{"buffer": 0, "byteLength": 16}]
+      }"#,
+      &[0; 16],
+    );
+    let view = |count: usize| MeshoptView {
+      buffer: 0,
+      byte_offset: 0,
+      byte_length: 16,
+      byte_stride: 4,
+      count,
+      mode: MeshoptMode::Attributes,
+      filter: MeshoptFilter::None,
+    };
+
+    for count in &[8, usize::MAX / 2] {
+      assert!(matches!(
+        decode_gltf_meshopt_view(&gltf, 0, &view(*count)),
+        Err(GltfError::DecodeFailed { reason, .. }) if reason.contains("byteLength")
+      ));
+    }
+  }
+
+  #[test]
+  fn extras_are_kept_when_they_are_an_object() {
+    let extras = |json: &str| -> gltf::json::Extras {
+      Some(serde_json::value::RawValue::from_string(json.to_string()).unwrap())
+    };
+
+    let user_data = parse_gltf_extras(&extras(r#"{"spawn": true, "team": "red"}"#), "nodes[0]");
+
+    assert_eq!(user_data["spawn"], Value::Bool(true));
+    assert_eq!(user_data["team"], Value::String("red".to_string()));
+    assert!(parse_gltf_extras(&extras("[1, 2]"), "nodes[0]").is_empty());
+    assert!(parse_gltf_extras(&extras("4"), "nodes[0]").is_empty());
+    assert!(parse_gltf_extras(&None, "nodes[0]").is_empty());
+  }
+
+  #[test]
+  fn nodes_meshes_and_materials_are_found_by_user_data() {
+    let positions: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+      .iter()
+      .flat_map(|v| v.to_le_bytes().to_vec())
+      .collect();
+    let data = glb(
+      r#"{
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0]}],
+        "nodes": [
+          {"name": "level", "children": [1, 2], "extras": {"level": 1}},
+          {"name": "spawn", "mesh": 0, "extras": {"spawn": true}},
+          {"name": "prop", "mesh": 1}
+        ],
+        "meshes": [
+          {"primitives": [{"attributes": {"POSITION": 0}, "material": 0}],
+           "extras": {"collider": "box"}},
+          {"primitives": [{"attributes": {"POSITION": 0}}]}
+        ],
+        "materials": [{"extras": {"surface": "metal"}}],
+        "buffers": [{"byteLength": 36}],
+        "bufferViews": [{"buffer": 0, "byteLength": 36}],
+        "accessors": [
+          {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+           "min": [0, 0, 0], "max": [1, 1, 0]}
+        ]
+      }"#,
+      &positions,
+    );
+
+    let mut renderer = Renderer::new(RecordingBackend::new());
+    let scenes = renderer
+      .load_gltf(&data, &GltfLoadOptions::default())
+      .unwrap();
+    let root = scenes[0];
+    let level = renderer.scene.find_by_name(root, "level").unwrap();
+    let spawn = renderer.scene.find_by_name(root, "spawn").unwrap();
+
+    assert_eq!(renderer.scene.find_by_name(root, "missing"), None);
+    assert_eq!(renderer.scene.find_by_user_data(root, "level"), vec![level]);
+    assert_eq!(renderer.scene.find_by_user_data(root, "spawn"), vec![spawn]);
+    assert_eq!(
+      renderer.scene.get_user_data(spawn, "spawn"),
+      Some(&Value::Bool(true))
+    );
+    assert_eq!(
+      renderer.find_by_mesh_user_data(root, "collider"),
+      vec![spawn]
+    );
+    assert_eq!(
+      renderer.find_by_material_user_data(root, "surface"),
+      vec![spawn]
+    );
+
+    let mesh = renderer.scene.get_node(spawn).unwrap().mesh.unwrap();
+    let material = renderer.meshes[mesh].primitives[0].material.unwrap();
 
-    self.create_gltf_scenes(gltf, &node_index)
+    assert_eq!(
+      renderer.get_mesh_user_data(mesh, "collider"),
+      Some(&Value::String("box".to_string()))
+    );
+    assert_eq!(
+      renderer.get_material_user_data(material, "surface"),
+      Some(&Value::String("metal".to_string()))
+    );
   }
 }
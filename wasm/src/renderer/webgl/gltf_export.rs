@@ -0,0 +1,1019 @@
+use anyhow::{anyhow, Result};
+use gltf::binary::{Glb, Header};
+use gltf::json;
+use gltf::json::validation::Checked::Valid;
+use na::{Matrix4, UnitQuaternion, Vector3};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::handle::Handle;
+use crate::scene::node::{decompose_matrix, Node};
+
+use super::context::{BufferTarget, DrawMode, TexParam, TypedArrayKind};
+use super::material::{MapExport, Material, MaterialExport};
+use super::renderer::{Accessor, Buffer, ImageSource, Mesh, Renderer, Sampler, Texture};
+use super::shader::{AttributeName, AttributeOptions};
+
+const UNLIT: &str = "KHR_materials_unlit";
+
+pub struct ExportPrimitive {
+  pub attributes: Vec<(AttributeName, u32)>,
+  pub indices: Option<u32>,
+  pub material: Option<u32>,
+  pub mode: DrawMode,
+}
+
+// writer indices of the textures of a material's maps
+#[derive(Default)]
+pub struct ExportMaterialTextures {
+  pub base_color: Option<u32>,
+  pub metallic_roughness: Option<u32>,
+  pub normal: Option<u32>,
+  pub occlusion: Option<u32>,
+  pub emissive: Option<u32>,
+}
+
+#[derive(Default)]
+pub struct GlbWriter {
+  root: json::Root,
+  bin: Vec<u8>,
+}
+
+impl GlbWriter {
+  pub fn new() -> Self {
+    GlbWriter::default()
+  }
+
+  pub fn push_view(&mut self, data: &[u8], target: Option<BufferTarget>, stride: i32) -> u32 {
+    let offset = self.bin.len();
+
+    self.bin.extend_from_slice(data);
+    align_to_four(&mut self.bin);
+
+    self.root.buffer_views.push(json::buffer::View {
+      buffer: json::Index::new(0),
+      byte_length: data.len() as u32,
+      byte_offset: Some(offset as u32),
+      byte_stride: if stride > 0 {
+        Some(stride as u32)
+      } else {
+        None
+      },
+      name: None,
+      target: target.map(|t| {
+        Valid(match t {
+          BufferTarget::ArrayBuffer => json::buffer::Target::ArrayBuffer,
+          BufferTarget::ElementArrayBuffer => json::buffer::Target::ElementArrayBuffer,
+        })
+      }),
+      extensions: None,
+      extras: Default::default(),
+    });
+
+    self.root.buffer_views.len() as u32 - 1
+  }
+
+  pub fn push_accessor(
+    &mut self,
+    view: u32,
+    count: i32,
+    options: &AttributeOptions,
+    bounds: Option<(Vec<f32>, Vec<f32>)>,
+  ) -> Result<u32> {
+    let type_ = match options.item_size {
+      1 => json::accessor::Type::Scalar,
+      2 => json::accessor::Type::Vec2,
+      3 => json::accessor::Type::Vec3,
+      4 => json::accessor::Type::Vec4,
+      9 => json::accessor::Type::Mat3,
+      16 => json::accessor::Type::Mat4,
+      size => return Err(anyhow!("unsupported accessor item size {}", size)),
+    };
+
+    let (min, max) = match bounds {
+      Some((min, max)) => (Some(min.into()), Some(max.into())),
+      None => (None, None),
+    };
+
+    self.root.accessors.push(json::Accessor {
+      buffer_view: Some(json::Index::new(view)),
+      byte_offset: options.offset as u32,
+      count: count as u32,
+      component_type: Valid(json::accessor::GenericComponentType(
+        match options.component_type {
+          TypedArrayKind::Int8 => json::accessor::ComponentType::I8,
+          TypedArrayKind::Uint8 => json::accessor::ComponentType::U8,
+          TypedArrayKind::Int16 => json::accessor::ComponentType::I16,
+          TypedArrayKind::Uint16 => json::accessor::ComponentType::U16,
+          TypedArrayKind::Uint32 => json::accessor::ComponentType::U32,
+          TypedArrayKind::Float32 => json::accessor::ComponentType::F32,
+          TypedArrayKind::HalfFloat => {
+            return Err(anyhow!("half float accessors can't be written to glTF"));
+          }
+        },
+      )),
+      extensions: None,
+      extras: Default::default(),
+      type_: Valid(type_),
+      min,
+      max,
+      name: None,
+      normalized: options.normalized,
+      sparse: None,
+    });
+
+    Ok(self.root.accessors.len() as u32 - 1)
+  }
+
+  pub fn push_image(&mut self, source: &ImageSource) -> u32 {
+    let view = self.push_view(&source.data, None, 0);
+
+    self.root.images.push(json::Image {
+      buffer_view: Some(json::Index::new(view)),
+      mime_type: Some(json::image::MimeType(source.mime_type.clone())),
+      name: None,
+      uri: None,
+      extensions: None,
+      extras: Default::default(),
+    });
+
+    self.root.images.len() as u32 - 1
+  }
+
+  pub fn push_sampler(&mut self, sampler: &Sampler) -> u32 {
+    use json::texture::{MagFilter, MinFilter, WrappingMode};
+
+    let wrap = |param: TexParam| match param {
+      TexParam::ClampToEdge => WrappingMode::ClampToEdge,
+      _ => WrappingMode::Repeat,
+    };
+
+    self.root.samplers.push(json::texture::Sampler {
+      mag_filter: Some(Valid(match sampler.mag_filter {
+        TexParam::Nearest => MagFilter::Nearest,
+        _ => MagFilter::Linear,
+      })),
+      min_filter: Some(Valid(match sampler.min_filter {
+        TexParam::Nearest => MinFilter::Nearest,
+        TexParam::NearestMipMapNearest => MinFilter::NearestMipmapNearest,
+        TexParam::LinearMipMapNearest => MinFilter::LinearMipmapNearest,
+        TexParam::NearestMimMapLinear => MinFilter::NearestMipmapLinear,
+        TexParam::LinearMipMapLinear => MinFilter::LinearMipmapLinear,
+        _ => MinFilter::Linear,
+      })),
+      name: None,
+      wrap_s: Valid(wrap(sampler.wrap_s)),
+      wrap_t: Valid(wrap(sampler.wrap_t)),
+      extensions: None,
+      extras: Default::default(),
+    });
+
+    self.root.samplers.len() as u32 - 1
+  }
+
+  pub fn push_texture(&mut self, image: u32, sampler: u32) -> u32 {
+    self.root.textures.push(json::Texture {
+      name: None,
+      sampler: Some(json::Index::new(sampler)),
+      source: json::Index::new(image),
+      extensions: None,
+      extras: Default::default(),
+    });
+
+    self.root.textures.len() as u32 - 1
+  }
+
+  pub fn push_material(
+    &mut self,
+    material: &MaterialExport,
+    textures: &ExportMaterialTextures,
+  ) -> u32 {
+    let c = material.base_color;
+    let e = material.emissive;
+    let info = |texture: Option<u32>, map: Option<MapExport>| {
+      Some(json::texture::Info {
+        index: json::Index::new(texture?),
+        tex_coord: map?.uv_set,
+        extensions: None,
+        extras: Default::default(),
+      })
+    };
+    let alpha_mode = match (material.alpha_cutoff, material.blend) {
+      (Some(_), _) => json::material::AlphaMode::Mask,
+      (None, true) => json::material::AlphaMode::Blend,
+      (None, false) => json::material::AlphaMode::Opaque,
+    };
+
+    if material.unlit && !self.root.extensions_used.iter().any(|e| e == UNLIT) {
+      self.root.extensions_used.push(UNLIT.to_string());
+    }
+
+    let material_def = json::Material {
+      alpha_cutoff: material.alpha_cutoff.map(json::material::AlphaCutoff),
+      alpha_mode: Valid(alpha_mode),
+      double_sided: material.double_sided,
+      pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+        base_color_factor: json::material::PbrBaseColorFactor([c.x, c.y, c.z, c.w]),
+        base_color_texture: info(textures.base_color, material.base_color_texture),
+        metallic_factor: json::material::StrengthFactor(material.metallic),
+        roughness_factor: json::material::StrengthFactor(material.roughness),
+        metallic_roughness_texture: info(
+          textures.metallic_roughness,
+          material.metallic_roughness_texture,
+        ),
+        ..Default::default()
+      },
+      normal_texture: info(textures.normal, material.normal_texture).map(|info| {
+        json::material::NormalTexture {
+          index: info.index,
+          scale: material.normal_scale,
+          tex_coord: info.tex_coord,
+          extensions: None,
+          extras: Default::default(),
+        }
+      }),
+      occlusion_texture: info(textures.occlusion, material.occlusion_texture).map(|info| {
+        json::material::OcclusionTexture {
+          index: info.index,
+          strength: json::material::StrengthFactor(material.occlusion_strength),
+          tex_coord: info.tex_coord,
+          extensions: None,
+          extras: Default::default(),
+        }
+      }),
+      emissive_texture: info(textures.emissive, material.emissive_texture),
+      // KHR_materials_emissive_strength is not written, brighter emission is clamped
+      emissive_factor: json::material::EmissiveFactor([e.x.min(1.0), e.y.min(1.0), e.z.min(1.0)]),
+      extensions: if material.unlit {
+        let mut extensions = json::extensions::material::Material::default();
+
+        extensions.unlit = Some(json::extensions::material::Unlit {});
+
+        Some(extensions)
+      } else {
+        None
+      },
+      ..Default::default()
+    };
+
+    self.root.materials.push(material_def);
+
+    self.root.materials.len() as u32 - 1
+  }
+
+  pub fn push_mesh(&mut self, name: Option<String>, primitives: &[ExportPrimitive]) -> Result<u32> {
+    let mut primitive_defs = vec![];
+
+    for primitive in primitives {
+      let mut attributes = HashMap::new();
+
+      for (name, accessor) in &primitive.attributes {
+        attributes.insert(semantic(name)?, json::Index::new(*accessor));
+      }
+
+      primitive_defs.push(json::mesh::Primitive {
+        attributes,
+        extensions: None,
+        extras: Default::default(),
+        indices: primitive.indices.map(json::Index::new),
+        material: primitive.material.map(json::Index::new),
+        mode: Valid(match primitive.mode {
+          DrawMode::Points => json::mesh::Mode::Points,
+          DrawMode::Lines => json::mesh::Mode::Lines,
+          DrawMode::LineLoop => json::mesh::Mode::LineLoop,
+          DrawMode::LineStrip => json::mesh::Mode::LineStrip,
+          DrawMode::Triangles => json::mesh::Mode::Triangles,
+          DrawMode::TriangleStrip => json::mesh::Mode::TriangleStrip,
+          DrawMode::TriangleFan => json::mesh::Mode::TriangleFan,
+        }),
+        targets: None,
+      });
+    }
+
+    self.root.meshes.push(json::Mesh {
+      extensions: None,
+      extras: Default::default(),
+      name,
+      primitives: primitive_defs,
+      weights: None,
+    });
+
+    Ok(self.root.meshes.len() as u32 - 1)
+  }
+
+  pub fn push_node(
+    &mut self,
+    name: Option<String>,
+    matrix: &Matrix4<f32>,
+    mesh: Option<u32>,
+    children: Vec<u32>,
+  ) -> u32 {
+    let (position, rotation, scale) = decompose_matrix(matrix);
+
+    self.root.nodes.push(json::Node {
+      camera: None,
+      children: if children.is_empty() {
+        None
+      } else {
+        Some(children.into_iter().map(json::Index::new).collect())
+      },
+      extensions: None,
+      extras: Default::default(),
+      matrix: None,
+      mesh: mesh.map(json::Index::new),
+      name,
+      rotation: if rotation == UnitQuaternion::identity() {
+        None
+      } else {
+        let q = rotation.coords;
+        Some(json::scene::UnitQuaternion([q.x, q.y, q.z, q.w]))
+      },
+      scale: if scale == Vector3::new(1.0, 1.0, 1.0) {
+        None
+      } else {
+        Some([scale.x, scale.y, scale.z])
+      },
+      translation: if position == Vector3::zeros() {
+        None
+      } else {
+        Some([position.x, position.y, position.z])
+      },
+      skin: None,
+      weights: None,
+    });
+
+    self.root.nodes.len() as u32 - 1
+  }
+
+  pub fn finish(mut self, scene_nodes: Vec<u32>) -> Result<Vec<u8>> {
+    self.root.asset = json::Asset {
+      copyright: None,
+      extensions: None,
+      extras: Default::default(),
+      generator: Some(String::from("junk")),
+      min_version: None,
+      version: String::from("2.0"),
+    };
+
+    self.root.scenes.push(json::Scene {
+      extensions: None,
+      extras: Default::default(),
+      name: None,
+      nodes: scene_nodes.into_iter().map(json::Index::new).collect(),
+    });
+    self.root.scene = Some(json::Index::new(0));
+
+    if !self.bin.is_empty() {
+      self.root.buffers.push(json::Buffer {
+        byte_length: self.bin.len() as u32,
+        name: None,
+        uri: None,
+        extensions: None,
+        extras: Default::default(),
+      });
+    }
+
+    let json_data = json::serialize::to_vec(&self.root)?;
+
+    let glb = Glb {
+      header: Header {
+        magic: *b"glTF",
+        version: 2,
+        length: 0,
+      },
+      json: Cow::Owned(json_data),
+      bin: if self.bin.is_empty() {
+        None
+      } else {
+        Some(Cow::Owned(self.bin))
+      },
+    };
+
+    glb.to_vec().map_err(|e| anyhow!("{:?}", e))
+  }
+}
+
+#[derive(Default)]
+struct ExportIndex {
+  views: HashMap<Handle<Buffer>, u32>,
+  accessors: HashMap<Handle<Accessor>, u32>,
+  textures: HashMap<Handle<Texture>, u32>,
+  materials: HashMap<Handle<dyn Material>, Option<u32>>,
+  meshes: HashMap<Handle<Mesh>, u32>,
+}
+
+impl Renderer {
+  pub fn export_glb(&self, root_handle: Handle<Node>) -> Result<Vec<u8>> {
+    let mut writer = GlbWriter::new();
+    let mut index = ExportIndex::default();
+
+    let root = self.export_gltf_node(&mut writer, &mut index, root_handle)?;
+
+    writer.finish(vec![root])
+  }
+
+  fn export_gltf_node(
+    &self,
+    writer: &mut GlbWriter,
+    index: &mut ExportIndex,
+    handle: Handle<Node>,
+  ) -> Result<u32> {
+    let node = self
+      .scene
+      .get_node(handle)
+      .ok_or_else(|| anyhow!("node {:?} not found", handle))?;
+
+    let mut children = vec![];
+
+    for child_handle in &node.children {
+      children.push(self.export_gltf_node(writer, index, *child_handle)?);
+    }
+
+    let mesh = match node.mesh {
+      Some(mesh_handle) => Some(self.export_gltf_mesh(writer, index, mesh_handle)?),
+      None => None,
+    };
+
+    Ok(writer.push_node(node.name.clone(), &node.matrix_local, mesh, children))
+  }
+
+  fn export_gltf_mesh(
+    &self,
+    writer: &mut GlbWriter,
+    index: &mut ExportIndex,
+    handle: Handle<Mesh>,
+  ) -> Result<u32> {
+    if let Some(mesh) = index.meshes.get(&handle) {
+      return Ok(*mesh);
+    }
+
+    let mesh = self
+      .meshes
+      .get(handle)
+      .ok_or_else(|| anyhow!("mesh {:?} not found", handle))?;
+
+    let mut primitives = vec![];
+
+    for primitive in &mesh.primitives {
+      let geometry = self
+        .geometries
+        .get(primitive.geometry)
+        .ok_or_else(|| anyhow!("geometry {:?} not found", primitive.geometry))?;
+
+      let mut attributes = vec![];
+
+      for (name, accessor_handle) in &geometry.attributes {
+        let with_bounds = *name == AttributeName::Position;
+        let accessor = self.export_gltf_accessor(writer, index, *accessor_handle, with_bounds)?;
+
+        attributes.push((name.clone(), accessor));
+      }
+
+      let indices = match geometry.indices {
+        Some(accessor_handle) => {
+          Some(self.export_gltf_accessor(writer, index, accessor_handle, false)?)
+        }
+        None => None,
+      };
+
+      let material = match primitive.material {
+        Some(material_handle) => self.export_gltf_material(writer, index, material_handle)?,
+        None => None,
+      };
+
+      primitives.push(ExportPrimitive {
+        attributes,
+        indices,
+        material,
+        mode: geometry.draw_mode,
+      });
+    }
+
+    let mesh_index = writer.push_mesh(mesh.name.clone(), &primitives)?;
+
+    index.meshes.insert(handle, mesh_index);
+
+    Ok(mesh_index)
+  }
+
+  fn export_gltf_accessor(
+    &self,
+    writer: &mut GlbWriter,
+    index: &mut ExportIndex,
+    handle: Handle<Accessor>,
+    with_bounds: bool,
+  ) -> Result<u32> {
+    if let Some(accessor) = index.accessors.get(&handle) {
+      return Ok(*accessor);
+    }
+
+    let accessor = self
+      .accessors
+      .get(handle)
+      .ok_or_else(|| anyhow!("accessor {:?} not found", handle))?;
+    let buffer = self
+      .buffers
+      .get(accessor.buffer)
+      .ok_or_else(|| anyhow!("buffer {:?} not found", accessor.buffer))?;
+
+    let view = match index.views.get(&accessor.buffer) {
+      Some(view) => *view,
+      None => {
+        let view = writer.push_view(&buffer.data, Some(buffer.target), accessor.options.stride);
+        index.views.insert(accessor.buffer, view);
+        view
+      }
+    };
+
+    // glTF requires them on POSITION
+    let bounds = if with_bounds {
+      Some(
+        compute_bounds(&buffer.data, accessor.count, &accessor.options)
+          .ok_or_else(|| anyhow!("unable to compute the bounds of accessor {:?}", handle))?,
+      )
+    } else {
+      None
+    };
+
+    let accessor_index = writer.push_accessor(view, accessor.count, &accessor.options, bounds)?;
+
+    index.accessors.insert(handle, accessor_index);
+
+    Ok(accessor_index)
+  }
+
+  fn export_gltf_material(
+    &self,
+    writer: &mut GlbWriter,
+    index: &mut ExportIndex,
+    handle: Handle<dyn Material>,
+  ) -> Result<Option<u32>> {
+    if let Some(material) = index.materials.get(&handle) {
+      return Ok(*material);
+    }
+
+    let material = self
+      .materials
+      .get(handle)
+      .ok_or_else(|| anyhow!("material {:?} not found", handle))?;
+
+    let material_index = match material.export() {
+      Some(export) => {
+        let mut texture = |map: Option<MapExport>| match map {
+          Some(map) => self.export_gltf_texture(writer, index, map.texture),
+          None => Ok(None),
+        };
+        let textures = ExportMaterialTextures {
+          base_color: texture(export.base_color_texture)?,
+          metallic_roughness: texture(export.metallic_roughness_texture)?,
+          normal: texture(export.normal_texture)?,
+          occlusion: texture(export.occlusion_texture)?,
+          emissive: texture(export.emissive_texture)?,
+        };
+
+        Some(writer.push_material(&export, &textures))
+      }
+      None => None,
+    };
+
+    index.materials.insert(handle, material_index);
+
+    Ok(material_index)
+  }
+
+  fn export_gltf_texture(
+    &self,
+    writer: &mut GlbWriter,
+    index: &mut ExportIndex,
+    handle: Handle<Texture>,
+  ) -> Result<Option<u32>> {
+    if let Some(texture) = index.textures.get(&handle) {
+      return Ok(Some(*texture));
+    }
+
+    let texture = self
+      .textures
+      .get(handle)
+      .ok_or_else(|| anyhow!("texture {:?} not found", handle))?;
+    let image = self
+      .images
+      .get(texture.source)
+      .ok_or_else(|| anyhow!("image {:?} not found", texture.source))?;
+    let sampler = self
+      .samplers
+      .get(texture.sampler)
+      .ok_or_else(|| anyhow!("sampler {:?} not found", texture.sampler))?;
+
+    // images uploaded straight from html elements have no cpu copy to embed
+    let source = match &image.source {
+      Some(source) => source,
+      None => return Ok(None),
+    };
+
+    let image_index = writer.push_image(source);
+    let sampler_index = writer.push_sampler(sampler);
+    let texture_index = writer.push_texture(image_index, sampler_index);
+
+    index.textures.insert(handle, texture_index);
+
+    Ok(Some(texture_index))
+  }
+}
+
+fn semantic(name: &AttributeName) -> Result<json::validation::Checked<json::mesh::Semantic>> {
+  let semantic = match name {
+    AttributeName::Position => json::mesh::Semantic::Positions,
+    AttributeName::Normal => json::mesh::Semantic::Normals,
+    AttributeName::Tangent => json::mesh::Semantic::Tangents,
+    AttributeName::Color0 => json::mesh::Semantic::Colors(0),
+    AttributeName::Uv => json::mesh::Semantic::TexCoords(0),
+    AttributeName::Uv1 => json::mesh::Semantic::TexCoords(1),
+    AttributeName::Joints0 => json::mesh::Semantic::Joints(0),
+    AttributeName::Weights0 => json::mesh::Semantic::Weights(0),
+    AttributeName::InstanceTranslation
+    | AttributeName::InstanceRotation
+    | AttributeName::InstanceScale => {
+      return Err(anyhow!("{:?} is not a mesh attribute", name));
+    }
+    AttributeName::Custom(custom) => {
+      let value = json::Value::String(custom.clone());
+
+      return match json::deserialize::from_value(value)? {
+        Valid(semantic) => Ok(Valid(semantic)),
+        _ => Ok(Valid(json::mesh::Semantic::Extras(custom.clone()))),
+      };
+    }
+  };
+
+  Ok(Valid(semantic))
+}
+
+fn compute_bounds(
+  data: &[u8],
+  count: i32,
+  options: &AttributeOptions,
+) -> Option<(Vec<f32>, Vec<f32>)> {
+  if count == 0 {
+    return None;
+  }
+
+  let item_size = options.item_size as usize;
+  // min/max are stored in accessor component values, so normalization is not applied
+  let values = options.read_components(data, count, false)?;
+
+  let mut min = vec![f32::MAX; item_size];
+  let mut max = vec![f32::MIN; item_size];
+
+  for item in values.chunks_exact(item_size) {
+    for (c, v) in item.iter().enumerate() {
+      min[c] = min[c].min(*v);
+      max[c] = max[c].max(*v);
+    }
+  }
+
+  Some((min, max))
+}
+
+fn align_to_four(data: &mut Vec<u8>) {
+  data.resize((data.len() + 3) & !3, 0);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::renderer::webgl::context::TextureFormat;
+  use crate::renderer::webgl::material::{BlendMode, PbrMap, PbrMaterial};
+  use crate::renderer::webgl::recording::RecordingBackend;
+  use generational_arena::Index;
+  use gltf::Gltf;
+  use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+  use na::{Point3, Vector4};
+  use ncollide3d::procedural::{IndexBuffer, TriMesh};
+  use ncollide3d::shape::Cuboid;
+  use ncollide3d::transformation::ToTriMesh;
+
+  fn f32_bytes(data: &[f32]) -> Vec<u8> {
+    data.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+  }
+
+  fn u32_bytes(data: &[u32]) -> Vec<u8> {
+    data.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+  }
+
+  fn png_bytes() -> Vec<u8> {
+    let image = RgbaImage::from_pixel(2, 2, Rgba([255, 128, 0, 255]));
+    let mut data = vec![];
+
+    DynamicImage::ImageRgba8(image)
+      .write_to(&mut data, ImageOutputFormat::Png)
+      .unwrap();
+
+    data
+  }
+
+  // bakes the asset and exports the first scene again
+  fn bake_and_export(data: &[u8]) -> Vec<u8> {
+    let mut renderer = Renderer::new(RecordingBackend::new());
+    let gltf = Gltf::from_slice(data).unwrap();
+    let scenes = renderer.bake_gltf(&gltf).unwrap();
+
+    renderer.export_glb(scenes[0]).unwrap()
+  }
+
+  fn write_cuboid_glb() -> (TriMesh<f32>, Vec<u8>) {
+    let mut cuboid: TriMesh<f32> = Cuboid::new(Vector3::new(1.0, 2.0, 3.0)).to_trimesh(());
+    cuboid.unify_index_buffer();
+
+    let positions: Vec<f32> = cuboid
+      .coords
+      .iter()
+      .flat_map(|p| vec![p.x, p.y, p.z])
+      .collect();
+    let indices: Vec<u32> = match &cuboid.indices {
+      IndexBuffer::Unified(indices) => indices.iter().flat_map(|i| vec![i.x, i.y, i.z]).collect(),
+      IndexBuffer::Split(_) => unreachable!(),
+    };
+
+    let mut writer = GlbWriter::new();
+
+    let position_data = f32_bytes(&positions);
+    let position_view = writer.push_view(&position_data, Some(BufferTarget::ArrayBuffer), 0);
+    let position_options = AttributeOptions::new(TypedArrayKind::Float32, 3);
+    let bounds = compute_bounds(
+      &position_data,
+      cuboid.coords.len() as i32,
+      &position_options,
+    );
+    let position = writer
+      .push_accessor(
+        position_view,
+        cuboid.coords.len() as i32,
+        &position_options,
+        bounds,
+      )
+      .unwrap();
+
+    let index_view = writer.push_view(
+      &u32_bytes(&indices),
+      Some(BufferTarget::ElementArrayBuffer),
+      0,
+    );
+    let index = writer
+      .push_accessor(
+        index_view,
+        indices.len() as i32,
+        &AttributeOptions::new(TypedArrayKind::Uint32, 1),
+        None,
+      )
+      .unwrap();
+
+    let image = writer.push_image(&ImageSource {
+      mime_type: String::from("image/png"),
+      data: png_bytes(),
+    });
+    let sampler = writer.push_sampler(&Sampler::default());
+    let texture = writer.push_texture(image, sampler);
+    let material = writer.push_material(
+      &MaterialExport {
+        base_color: Vector4::new(0.0, 0.5, 1.0, 1.0),
+        base_color_texture: Some(MapExport {
+          texture: Handle::new(Index::from_raw_parts(0, 0)),
+          uv_set: 0,
+        }),
+        metallic: 0.25,
+        roughness: 0.75,
+        metallic_roughness_texture: None,
+        normal_texture: None,
+        normal_scale: 1.0,
+        occlusion_texture: None,
+        occlusion_strength: 1.0,
+        emissive: Vector3::zeros(),
+        emissive_texture: None,
+        alpha_cutoff: None,
+        blend: false,
+        unlit: false,
+        double_sided: true,
+      },
+      &ExportMaterialTextures {
+        base_color: Some(texture),
+        ..Default::default()
+      },
+    );
+
+    let mesh = writer
+      .push_mesh(
+        Some(String::from("cuboid")),
+        &[ExportPrimitive {
+          attributes: vec![(AttributeName::Position, position)],
+          indices: Some(index),
+          material: Some(material),
+          mode: DrawMode::Triangles,
+        }],
+      )
+      .unwrap();
+
+    let matrix = crate::scene::node::compose_matrix(
+      Some(Vector3::new(1.0, 2.0, 3.0)),
+      Some(UnitQuaternion::from_euler_angles(0.3, 0.2, 0.1)),
+      Some(Vector3::new(2.0, 2.0, 2.0)),
+    );
+    let child = writer.push_node(Some(String::from("cuboid")), &matrix, Some(mesh), vec![]);
+    let root = writer.push_node(None, &Matrix4::identity(), None, vec![child]);
+
+    (cuboid, writer.finish(vec![root]).unwrap())
+  }
+
+  // the checks of the glTF validator on buffers, views and accessors
+  fn assert_structurally_valid(data: &[u8]) -> Gltf {
+    assert_eq!(data.len() % 4, 0);
+
+    let gltf = Gltf::from_slice(data).unwrap();
+    let blob = gltf.blob.as_ref().unwrap();
+
+    assert_eq!(blob.len() % 4, 0);
+    assert_eq!(gltf.buffers().next().unwrap().length(), blob.len());
+
+    for view in gltf.views() {
+      assert_eq!(view.offset() % 4, 0);
+      assert!(view.offset() + view.length() <= blob.len());
+
+      if let Some(stride) = view.stride() {
+        assert!((4..=252).contains(&stride) && stride % 4 == 0);
+        // index and image views are tightly packed
+        assert_eq!(view.target(), Some(gltf::buffer::Target::ArrayBuffer));
+      }
+    }
+
+    for accessor in gltf.accessors() {
+      let view = accessor.view().unwrap();
+      let component_size = accessor.data_type().size();
+      let stride = view.stride().unwrap_or_else(|| accessor.size());
+
+      assert_eq!(accessor.offset() % component_size, 0);
+      assert!(accessor.size() <= stride);
+      assert!(
+        accessor.offset() + stride * (accessor.count() - 1) + accessor.size() <= view.length()
+      );
+    }
+
+    for mesh in gltf.meshes() {
+      for primitive in mesh.primitives() {
+        let position = primitive.get(&gltf::Semantic::Positions).unwrap();
+
+        assert!(position.min().is_some());
+        assert!(position.max().is_some());
+      }
+    }
+
+    gltf
+  }
+
+  #[test]
+  fn glb_passes_structural_validation() {
+    let (_, data) = write_cuboid_glb();
+    let gltf = assert_structurally_valid(&bake_and_export(&data));
+
+    assert_eq!(gltf.default_scene().unwrap().nodes().count(), 1);
+  }
+
+  #[test]
+  fn materials_export_their_maps_and_alpha_mode() {
+    let mut renderer = Renderer::new(RecordingBackend::new());
+    let mut texture = || {
+      let texture = renderer.bake_2d_texture_from_pixels(
+        TextureFormat::RGBA,
+        Sampler::default(),
+        2,
+        2,
+        &[0; 16],
+      );
+      let image = renderer.textures[texture].source;
+
+      renderer.images[image].source = Some(ImageSource {
+        mime_type: String::from("image/png"),
+        data: png_bytes(),
+      });
+
+      texture
+    };
+    let material = PbrMaterial::new()
+      .set_normal_map(Some(texture()))
+      .set_normal_scale(0.5)
+      .set_occlusion_map(Some(texture()))
+      .set_occlusion_strength(0.25)
+      .set_metallic_roughness_map(Some(texture()))
+      .set_emissive_map(Some(texture()))
+      .set_emissive(Vector3::new(1.0, 0.5, 0.0))
+      .set_emissive_strength(0.5)
+      .set_map_uv_set(PbrMap::Occlusion, 1)
+      .set_alpha_cutoff(Some(0.3))
+      .set_unlit(true);
+
+    let geometry = renderer.bake_cuboid_geometry(Vector3::new(1.0, 1.0, 1.0));
+    let masked = renderer.bake_material(material.boxed());
+    let blended = renderer.bake_material(PbrMaterial::new().set_blend(BlendMode::Alpha).boxed());
+    let root = renderer.scene.get_root_handle();
+
+    for material in &[masked, blended] {
+      let mesh = renderer.compose_mesh(geometry, *material, None);
+      let mut node = Node::new(Some(root));
+
+      node.mesh = Some(mesh);
+      renderer.insert_node(node);
+    }
+
+    let gltf = assert_structurally_valid(&renderer.export_glb(root).unwrap());
+    let materials: Vec<_> = gltf.materials().collect();
+    let masked = &materials[0];
+
+    assert_eq!(masked.alpha_mode(), gltf::material::AlphaMode::Mask);
+    assert_eq!(masked.alpha_cutoff(), Some(0.3));
+    assert!(masked.unlit());
+    assert_eq!(masked.emissive_factor(), [0.5, 0.25, 0.0]);
+    assert_eq!(masked.normal_texture().unwrap().scale(), 0.5);
+    assert_eq!(masked.occlusion_texture().unwrap().strength(), 0.25);
+    assert_eq!(masked.occlusion_texture().unwrap().tex_coord(), 1);
+    assert!(masked.emissive_texture().is_some());
+    assert!(masked
+      .pbr_metallic_roughness()
+      .metallic_roughness_texture()
+      .is_some());
+    assert_eq!(
+      gltf.extensions_used().collect::<Vec<_>>(),
+      ["KHR_materials_unlit"]
+    );
+    assert_eq!(materials[1].alpha_mode(), gltf::material::AlphaMode::Blend);
+    assert!(!materials[1].unlit());
+  }
+
+  #[test]
+  fn half_float_accessors_are_an_export_error() {
+    let mut writer = GlbWriter::new();
+    let view = writer.push_view(&[0; 8], Some(BufferTarget::ArrayBuffer), 0);
+
+    assert!(writer
+      .push_accessor(
+        view,
+        1,
+        &AttributeOptions::new(TypedArrayKind::HalfFloat, 4),
+        None
+      )
+      .is_err());
+  }
+
+  #[test]
+  fn glb_round_trips_scene_data() {
+    let (cuboid, data) = write_cuboid_glb();
+    let data = bake_and_export(&data);
+    // and once more, the export bakes like the original
+    let data = bake_and_export(&data);
+
+    let gltf = Gltf::from_slice(&data).unwrap();
+    let blob = gltf.blob.clone().unwrap();
+
+    let mesh = gltf.meshes().next().unwrap();
+    let primitive = mesh.primitives().next().unwrap();
+    let reader = primitive.reader(|_| Some(&blob));
+
+    let positions: Vec<Point3<f32>> = reader
+      .read_positions()
+      .unwrap()
+      .map(|p| Point3::new(p[0], p[1], p[2]))
+      .collect();
+
+    assert_eq!(positions, cuboid.coords);
+    assert_eq!(mesh.name(), Some("cuboid"));
+
+    let material = primitive.material();
+
+    assert!(material.double_sided());
+    assert_eq!(
+      material.pbr_metallic_roughness().base_color_factor(),
+      [0.0, 0.5, 1.0, 1.0]
+    );
+    assert_eq!(material.pbr_metallic_roughness().metallic_factor(), 0.25);
+    assert_eq!(material.pbr_metallic_roughness().roughness_factor(), 0.75);
+
+    let texture = material
+      .pbr_metallic_roughness()
+      .base_color_texture()
+      .unwrap()
+      .texture();
+
+    match texture.source().source() {
+      gltf::image::Source::View { view, mime_type } => {
+        let start = view.offset();
+
+        assert_eq!(mime_type, "image/png");
+        assert_eq!(&blob[start..start + view.length()], &png_bytes()[..]);
+      }
+      gltf::image::Source::Uri { .. } => panic!("image is not embedded"),
+    }
+
+    let child = gltf.nodes().find(|n| n.mesh().is_some()).unwrap();
+    let (translation, _, scale) = child.transform().decomposed();
+
+    assert_eq!(translation, [1.0, 2.0, 3.0]);
+    assert!((scale[0] - 2.0).abs() < 1e-5);
+  }
+}
@@ -0,0 +1,396 @@
+use anyhow::{anyhow, Result};
+use na::{Matrix3, Vector3};
+
+use super::backend::{as_bytes, GpuBuffer, GpuFramebuffer, GpuTexture, TextureLayout};
+use super::context::{
+  BufferTarget, BufferUsage, DrawMode, Feature, FramebufferAttachment, TextureFormat, TextureKind,
+  TypedArrayKind,
+};
+use super::material::material::bind_texture;
+use super::renderer::{Renderer, Sampler, Texture};
+use super::shader::{AttributeName, AttributeOptions, Shader};
+use super::texture::with_mipmaps;
+use crate::handle::Handle;
+
+const IRRADIANCE_SIZE: u32 = 32;
+const SPECULAR_SIZE: u32 = 128;
+const BRDF_LUT_SIZE: u32 = 128;
+// a full chain down to 1x1, cube maps sampled with mipmaps must be complete
+const SPECULAR_LEVELS: u32 = 32 - SPECULAR_SIZE.leading_zeros();
+
+const FACES: [TextureKind; 6] = [
+  TextureKind::CubeMapPX,
+  TextureKind::CubeMapNX,
+  TextureKind::CubeMapPY,
+  TextureKind::CubeMapNY,
+  TextureKind::CubeMapPZ,
+  TextureKind::CubeMapNZ,
+];
+
+// one triangle covering the whole viewport
+pub(crate) const FULLSCREEN_TRIANGLE: [f32; 6] = [-1.0, -1.0, 3.0, -1.0, -1.0, 3.0];
+
+// Prefiltered lighting baked from an environment cube map, sampled by PbrMaterial.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Environment {
+  // diffuse irradiance divided by PI, ready to multiply with the albedo
+  pub irradiance_map: Handle<Texture>,
+  // GGX prefiltered radiance, roughness 0 at the base level and 1 at the last one
+  pub specular_map: Handle<Texture>,
+  // split sum scale and bias for F0 by N·V and roughness
+  pub brdf_lut: Handle<Texture>,
+  pub specular_levels: u32,
+}
+
+// Columns are the directions of the s and t texture axes and of the face itself, so
+// faceMatrix * vec3(s, t, 1.0) with s and t in -1..1 follows the GL cube map layout.
+fn face_matrix(face: TextureKind) -> Matrix3<f32> {
+  let (s, t, forward) = match face {
+    TextureKind::CubeMapPX => (-Vector3::z(), -Vector3::y(), Vector3::x()),
+    TextureKind::CubeMapNX => (Vector3::z(), -Vector3::y(), -Vector3::x()),
+    TextureKind::CubeMapPY => (Vector3::x(), Vector3::z(), Vector3::y()),
+    TextureKind::CubeMapNY => (Vector3::x(), -Vector3::z(), -Vector3::y()),
+    TextureKind::CubeMapPZ => (Vector3::x(), -Vector3::y(), Vector3::z()),
+    TextureKind::CubeMapNZ => (-Vector3::x(), -Vector3::y(), -Vector3::z()),
+    _ => panic!("{:?} is not a cube map face", face),
+  };
+
+  Matrix3::from_columns(&[s, t, forward])
+}
+
+pub(crate) fn draw_fullscreen(renderer: &Renderer, shader: &Shader, triangle: GpuBuffer) {
+  renderer
+    .ctx
+    .bind_buffer(BufferTarget::ArrayBuffer, Some(triangle));
+  shader.bind_attribute(
+    &AttributeName::Position,
+    &AttributeOptions::new(TypedArrayKind::Float32, 2),
+  );
+  renderer
+    .ctx
+    .switch_attributes(shader.get_attribute_locations().len() as u32);
+  renderer.ctx.draw_arrays(DrawMode::Triangles, 0, 3);
+}
+
+impl Renderer {
+  // Convolves a cube map into the irradiance, specular and BRDF textures PbrMaterial
+  // uses for ambient light and reflections. Faces are rendered into a scratch
+  // framebuffer and copied into the cube maps since WebGL1 can only attach level 0.
+  // The viewport is left at the size of the last draw, reset it before rendering.
+  pub fn bake_environment(&mut self, cube_map: Handle<Texture>) -> Result<Environment> {
+    let vert_src = include_str!("./shaders/ibl_vert.glsl");
+    let irradiance_shader = self.ctx.create_shader(
+      vert_src,
+      include_str!("./shaders/ibl_irradiance_frag.glsl"),
+      &[],
+    )?;
+    let prefilter_shader = self.ctx.create_shader(
+      vert_src,
+      include_str!("./shaders/ibl_prefilter_frag.glsl"),
+      &[],
+    )?;
+    let brdf_shader =
+      self
+        .ctx
+        .create_shader(vert_src, include_str!("./shaders/ibl_brdf_frag.glsl"), &[])?;
+
+    let triangle = self
+      .ctx
+      .create_buffer(
+        BufferTarget::ArrayBuffer,
+        BufferUsage::StaticDraw,
+        as_bytes(&FULLSCREEN_TRIANGLE),
+      )
+      .ok_or_else(|| anyhow!("unable to create the fullscreen triangle"))?;
+
+    self.ctx.set(Feature::DepthTest, false);
+    self.ctx.set(Feature::CullFace, false);
+    self.ctx.set(Feature::Blend, false);
+
+    // every face is rendered here before being copied into its cube map
+    let scratch_fb = self.ctx.create_framebuffer();
+    let scratch_texture = self.ctx.create_texture();
+    let cube_maps = match (scratch_fb, scratch_texture) {
+      (Some(scratch_fb), Some(scratch_texture)) => self.convolve_environment(
+        &irradiance_shader,
+        &prefilter_shader,
+        triangle,
+        cube_map,
+        scratch_fb,
+        scratch_texture,
+      ),
+      _ => Err(anyhow!("unable to create the scratch framebuffer")),
+    };
+
+    // the scratch objects go away whether the convolution worked or not
+    self.ctx.bind_framebuffer(None);
+
+    if let Some(scratch_fb) = scratch_fb {
+      self.ctx.delete_framebuffer(scratch_fb);
+    }
+
+    if let Some(scratch_texture) = scratch_texture {
+      self.ctx.delete_texture(scratch_texture);
+    }
+
+    let (irradiance_map, specular_map) = match cube_maps {
+      Ok(cube_maps) => cube_maps,
+      Err(err) => {
+        self.ctx.delete_buffer(triangle);
+        return Err(err);
+      }
+    };
+
+    let lut_target =
+      self.bake_render_target(BRDF_LUT_SIZE, BRDF_LUT_SIZE, Sampler::framebuffer(), false);
+    let target = self.targets.get(lut_target).unwrap();
+    let brdf_lut = target.color_texture;
+
+    self
+      .ctx
+      .bind_framebuffer(Some(*self.framebuffers.get(target.fb).unwrap()));
+    self
+      .ctx
+      .viewport(0, 0, BRDF_LUT_SIZE as i32, BRDF_LUT_SIZE as i32);
+    brdf_shader.bind();
+    draw_fullscreen(self, &brdf_shader, triangle);
+    self.ctx.bind_framebuffer(None);
+
+    self.ctx.delete_buffer(triangle);
+
+    let face_bytes = |size: u32| 6 * (size * size) as usize * TextureFormat::RGBA.bytes_per_pixel();
+
+    Ok(Environment {
+      irradiance_map: self.compose_texture(
+        irradiance_map,
+        Sampler::framebuffer(),
+        face_bytes(IRRADIANCE_SIZE),
+      ),
+      specular_map: self.compose_texture(
+        specular_map,
+        Sampler::default(),
+        with_mipmaps(face_bytes(SPECULAR_SIZE)),
+      ),
+      brdf_lut,
+      specular_levels: SPECULAR_LEVELS,
+    })
+  }
+
+  // the irradiance and specular cube maps, neither is left behind on failure
+  fn convolve_environment(
+    &self,
+    irradiance_shader: &Shader,
+    prefilter_shader: &Shader,
+    triangle: GpuBuffer,
+    cube_map: Handle<Texture>,
+    scratch_fb: GpuFramebuffer,
+    scratch_texture: GpuTexture,
+  ) -> Result<(GpuTexture, GpuTexture)> {
+    self.ctx.bind_framebuffer(Some(scratch_fb));
+    self
+      .ctx
+      .bind_texture(TextureKind::Texture2d, Some(scratch_texture));
+    self.ctx.empty_texture_data(&TextureLayout::new(
+      SPECULAR_SIZE as i32,
+      SPECULAR_SIZE as i32,
+      TextureFormat::RGBA,
+      TypedArrayKind::Uint8,
+    ))?;
+    self.ctx.framebuffer_texture_2d(
+      FramebufferAttachment::ColorAttachment0,
+      Some(scratch_texture),
+    );
+
+    if !self.ctx.check_framebuffer_complete() {
+      return Err(anyhow!("scratch framebuffer is incomplete"));
+    }
+
+    let irradiance_map = self.render_cube_map(
+      irradiance_shader,
+      triangle,
+      cube_map,
+      IRRADIANCE_SIZE,
+      1,
+      |_, _| {},
+    )?;
+
+    let specular_map = self.render_cube_map(
+      prefilter_shader,
+      triangle,
+      cube_map,
+      SPECULAR_SIZE,
+      SPECULAR_LEVELS,
+      |shader, level| {
+        shader.set_float("roughness", level as f32 / (SPECULAR_LEVELS - 1) as f32);
+        shader.set_float("faceSize", (SPECULAR_SIZE >> level) as f32);
+      },
+    );
+
+    match specular_map {
+      Ok(specular_map) => Ok((irradiance_map, specular_map)),
+      Err(err) => {
+        self.ctx.delete_texture(irradiance_map);
+        Err(err)
+      }
+    }
+  }
+
+  // renders every face of every level with the shader and copies it into a new cube map
+  fn render_cube_map(
+    &self,
+    shader: &Shader,
+    triangle: GpuBuffer,
+    source: Handle<Texture>,
+    size: u32,
+    levels: u32,
+    setup_level: impl Fn(&Shader, u32),
+  ) -> Result<GpuTexture> {
+    let cube_map = self
+      .ctx
+      .create_texture()
+      .ok_or_else(|| anyhow!("unable to create a cube map"))?;
+
+    shader.bind();
+
+    for level in 0..levels {
+      let level_size = (size >> level).max(1) as i32;
+
+      self.ctx.viewport(0, 0, level_size, level_size);
+      setup_level(shader, level);
+
+      for face in &FACES {
+        shader.set_matrix3("faceMatrix", &face_matrix(*face));
+
+        // the source is sampled from unit 0, the copy goes through unit 1
+        bind_texture(
+          self.ctx.as_ref(),
+          &self.images,
+          &self.textures,
+          &self.samplers,
+          shader,
+          source,
+          TextureKind::CubeMap,
+          "environmentMap",
+          0,
+        );
+        draw_fullscreen(self, shader, triangle);
+
+        self.ctx.active_texture(1);
+        self.ctx.bind_texture(TextureKind::CubeMap, Some(cube_map));
+        self.ctx.copy_texture_image_2d(
+          *face,
+          level as i32,
+          TextureFormat::RGBA,
+          level_size,
+          level_size,
+        );
+      }
+    }
+
+    self.ctx.bind_texture(TextureKind::CubeMap, None);
+
+    Ok(cube_map)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::renderer::webgl::recording::{Command, RecordingBackend};
+
+  // face and s, t in -1..1 picked by GL for a direction, from the cube map selection table
+  fn lookup(direction: Vector3<f32>) -> (TextureKind, f32, f32) {
+    let (x, y, z) = (direction.x, direction.y, direction.z);
+    let (face, sc, tc, ma) = if x.abs() >= y.abs() && x.abs() >= z.abs() {
+      if x > 0.0 {
+        (TextureKind::CubeMapPX, -z, -y, x)
+      } else {
+        (TextureKind::CubeMapNX, z, -y, x)
+      }
+    } else if y.abs() >= z.abs() {
+      if y > 0.0 {
+        (TextureKind::CubeMapPY, x, z, y)
+      } else {
+        (TextureKind::CubeMapNY, x, -z, y)
+      }
+    } else if z > 0.0 {
+      (TextureKind::CubeMapPZ, x, -y, z)
+    } else {
+      (TextureKind::CubeMapNZ, -x, -y, z)
+    };
+
+    (face, sc / ma.abs(), tc / ma.abs())
+  }
+
+  #[test]
+  fn face_matrices_follow_the_cube_map_layout() {
+    for face in &FACES {
+      let direction = face_matrix(*face) * Vector3::new(0.5, -0.25, 1.0);
+      let (lookup_face, s, t) = lookup(direction);
+
+      assert_eq!(lookup_face, *face);
+      assert!((s - 0.5).abs() < 1e-6 && (t + 0.25).abs() < 1e-6);
+    }
+  }
+
+  #[test]
+  fn environment_copies_every_face_and_level() {
+    let backend = RecordingBackend::new();
+    let log = backend.log();
+    let mut renderer = Renderer::new(backend);
+    let source = renderer.ctx.create_texture().unwrap();
+    let cube_map = renderer.compose_texture(source, Sampler::default(), 0);
+
+    let environment = renderer.bake_environment(cube_map).unwrap();
+    let log = log.borrow();
+    let copies: Vec<(i32, (i32, i32))> = log
+      .iter()
+      .filter_map(|command| match command {
+        Command::CopyTexImage { level, size, .. } => Some((*level, *size)),
+        _ => None,
+      })
+      .collect();
+
+    assert_eq!(environment.specular_levels, 8);
+    // irradiance faces first, then every level of the specular chain
+    assert_eq!(copies.len(), 6 + 6 * 8);
+    assert_eq!(copies[0], (0, (32, 32)));
+    assert_eq!(copies[6], (0, (128, 128)));
+    assert_eq!(copies.last(), Some(&(7, (1, 1))));
+    assert!(renderer.textures.get(environment.brdf_lut).is_some());
+
+    // the convolution programs only live for the bake
+    let programs = log
+      .iter()
+      .filter(|command| matches!(command, Command::CreateProgram { .. }))
+      .count();
+    let deleted = log
+      .iter()
+      .filter(|command| matches!(command, Command::DeleteProgram(_)))
+      .count();
+
+    assert_eq!((programs, deleted), (3, 3));
+  }
+
+  #[test]
+  fn failed_bakes_delete_their_scratch_objects() {
+    let backend = RecordingBackend::new().set_complete_framebuffers(false);
+    let log = backend.log();
+    let mut renderer = Renderer::new(backend);
+    let source = renderer.ctx.create_texture().unwrap();
+    let cube_map = renderer.compose_texture(source, Sampler::default(), 0);
+    let start = log.borrow().len();
+
+    assert!(renderer.bake_environment(cube_map).is_err());
+
+    let log = log.borrow();
+    let count = |filter: fn(&Command) -> bool| log[start..].iter().filter(|c| filter(c)).count();
+
+    assert_eq!(count(|c| matches!(c, Command::CreateBuffer { .. })), 1);
+    assert_eq!(count(|c| matches!(c, Command::DeleteBuffer(_))), 1);
+    assert_eq!(count(|c| matches!(c, Command::CreateFramebuffer(_))), 1);
+    assert_eq!(count(|c| matches!(c, Command::DeleteFramebuffer(_))), 1);
+    assert_eq!(count(|c| matches!(c, Command::CreateTexture(_))), 1);
+    assert_eq!(count(|c| matches!(c, Command::DeleteTexture(_))), 1);
+  }
+}
@@ -0,0 +1,200 @@
+use anyhow::{bail, Result};
+use na::Matrix4;
+
+use super::context::BufferTarget;
+use super::renderer::{Attributes, Geometry, InstanceSet, Mesh, Renderer};
+use super::shader::AttributeName;
+use crate::handle::Handle;
+use crate::scene::node::{decompose_matrix, Node};
+
+impl Renderer {
+  pub fn bake_instances(&mut self, matrices: &[Matrix4<f32>]) -> Handle<InstanceSet> {
+    let mut translations = Vec::with_capacity(matrices.len() * 3);
+    let mut rotations = Vec::with_capacity(matrices.len() * 4);
+    let mut scales = Vec::with_capacity(matrices.len() * 3);
+
+    for matrix in matrices {
+      let (t, r, s) = decompose_matrix(matrix);
+
+      translations.extend_from_slice(&[t.x, t.y, t.z]);
+      rotations.extend_from_slice(&[r.i, r.j, r.k, r.w]);
+      scales.extend_from_slice(&[s.x, s.y, s.z]);
+    }
+
+    let count = matrices.len() as i32;
+    let mut attributes = Attributes::new();
+
+    attributes.insert(
+      AttributeName::InstanceTranslation,
+      self.bake_buffer_accessor(BufferTarget::ArrayBuffer, &translations, 3, count),
+    );
+    attributes.insert(
+      AttributeName::InstanceRotation,
+      self.bake_buffer_accessor(BufferTarget::ArrayBuffer, &rotations, 4, count),
+    );
+    attributes.insert(
+      AttributeName::InstanceScale,
+      self.bake_buffer_accessor(BufferTarget::ArrayBuffer, &scales, 3, count),
+    );
+
+    self.instance_sets.insert(InstanceSet { attributes, count })
+  }
+
+  // instanced draws need ANGLE_instanced_arrays, nodes are only given instances when
+  // the backend supports it
+  fn check_instancing(&self) -> Result<()> {
+    if !self.ctx.supports_instancing() {
+      bail!("ANGLE_instanced_arrays is not supported");
+    }
+
+    Ok(())
+  }
+
+  pub fn bake_instanced_node(
+    &mut self,
+    mesh_handle: Handle<Mesh>,
+    matrices: &[Matrix4<f32>],
+  ) -> Result<Handle<Node>> {
+    self.check_instancing()?;
+
+    let instances = self.bake_instances(matrices);
+
+    let mut node = Node::new(None);
+    node.mesh = Some(mesh_handle);
+    node.instances = Some(instances);
+
+    Ok(self.insert_node(node))
+  }
+
+  pub fn set_node_instances(
+    &mut self,
+    node_handle: Handle<Node>,
+    instances: Option<Handle<InstanceSet>>,
+  ) -> Result<()> {
+    if instances.is_some() {
+      self.check_instancing()?;
+    }
+
+    if let Some(node) = self.scene.get_node_mut(node_handle) {
+      node.instances = instances;
+    }
+
+    Ok(())
+  }
+
+  pub(crate) fn draw_instanced(&self, geometry: &Geometry, count: i32, instances: i32) {
+    if let Some(accessor_handle) = geometry.indices {
+      let (accessor, indices) = match self.accessor_buffer(accessor_handle) {
+        Some(found) => found,
+        None => return,
+      };
+      self
+        .ctx
+        .bind_buffer(BufferTarget::ElementArrayBuffer, Some(indices.gpu_buffer));
+      self.ctx.draw_elements_instanced(
+        geometry.draw_mode,
+        accessor.count,
+        accessor.options.component_type,
+        0,
+        instances,
+      );
+    } else {
+      self
+        .ctx
+        .draw_arrays_instanced(geometry.draw_mode, 0, count, instances);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use na::{Translation3, Vector3};
+
+  use super::*;
+  use crate::renderer::webgl::material::{Material, PbrMaterial};
+  use crate::renderer::webgl::recording::{Command, CommandLog, RecordingBackend};
+  use crate::renderer::webgl::renderer::Camera;
+
+  fn renderer(instancing: bool) -> (Renderer, CommandLog) {
+    let backend = RecordingBackend::new().set_instancing(instancing);
+    let log = backend.log();
+
+    (Renderer::new(backend), log)
+  }
+
+  fn mesh(renderer: &mut Renderer) -> Handle<Mesh> {
+    let geometry = renderer.bake_cuboid_geometry(Vector3::new(1.0, 1.0, 1.0));
+    let material: Handle<dyn Material> = renderer.bake_material(PbrMaterial::new().boxed());
+
+    renderer.compose_mesh(geometry, material, None)
+  }
+
+  fn matrices() -> Vec<Matrix4<f32>> {
+    (0..3)
+      .map(|i| Translation3::new(i as f32, 0.0, 0.0).to_homogeneous())
+      .collect()
+  }
+
+  #[test]
+  fn instances_need_backend_support() {
+    let (mut renderer, log) = renderer(false);
+    let mesh = mesh(&mut renderer);
+
+    assert!(renderer.bake_instanced_node(mesh, &matrices()).is_err());
+
+    let mut node = Node::new(Some(renderer.scene.get_root_handle()));
+    node.mesh = Some(mesh);
+    let node = renderer.insert_node(node);
+    let instances = renderer.bake_instances(&matrices());
+
+    assert!(renderer.set_node_instances(node, Some(instances)).is_err());
+    assert!(renderer.scene.get_node(node).unwrap().instances.is_none());
+    assert!(renderer.set_node_instances(node, None).is_ok());
+
+    // the node still draws, one instance at a time
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+    renderer.render_scene(root, camera);
+
+    assert!(log.borrow().iter().any(|command| matches!(
+      command,
+      Command::Draw {
+        instances: None,
+        ..
+      }
+    )));
+  }
+
+  #[test]
+  fn instanced_nodes_draw_once_for_all_instances() {
+    let (mut renderer, log) = renderer(true);
+    let mesh = mesh(&mut renderer);
+    let node = renderer.bake_instanced_node(mesh, &matrices()).unwrap();
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.set_parent(node, root);
+
+    let camera = renderer.cameras.insert(Camera::default());
+
+    renderer.scene.update_matrix_world();
+    renderer.render_scene(root, camera);
+
+    let instanced_draws = log
+      .borrow()
+      .iter()
+      .filter(|command| {
+        matches!(
+          command,
+          Command::Draw {
+            instances: Some(3),
+            ..
+          }
+        )
+      })
+      .count();
+
+    assert_eq!(instanced_draws, 1);
+  }
+}
@@ -0,0 +1,718 @@
+use log::warn;
+use na::{Matrix4, Orthographic3, Perspective3, Point3, Vector3, Vector4};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use super::context::{DepthFunc, Feature, TextureKind};
+use super::define::Define;
+use super::material::material::bind_texture;
+use super::material::RenderContext;
+use super::render_queue::RenderQueue;
+use super::renderer::{Camera, RenderTarget, Renderer, Sampler, Texture};
+use super::shader::Shader;
+use super::shader_cache::ShaderVariant;
+use crate::handle::{Handle, Pool};
+use crate::scene::node::Node;
+
+// PbrMaterial shades with the first directional light and this many spot lights
+pub const MAX_SPOT_LIGHTS: usize = 2;
+pub const MAX_CASCADES: usize = 4;
+
+// casters this far outside a cascade on the side of the light still shadow it
+const CASTER_DISTANCE: f32 = 50.0;
+
+// the key light used when the scene has no directional light, an irradiance of PI keeps
+// a white lambertian surface facing it at 1.0
+const DEFAULT_LIGHT_DIRECTION: Vector3<f32> = Vector3::new(0.0, 1.0, 0.0);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LightKind {
+  // cascade_splits are the view distances where each cascade ends, at most MAX_CASCADES
+  Directional {
+    direction: Vector3<f32>,
+    cascade_splits: Vec<f32>,
+  },
+  // angle is the half angle of the cone, in radians
+  Spot {
+    position: Vector3<f32>,
+    direction: Vector3<f32>,
+    angle: f32,
+    range: f32,
+  },
+}
+
+// Directions are the way the light travels, e.g. -y for a sun straight overhead.
+#[derive(Debug, Clone)]
+pub struct Light {
+  pub kind: LightKind,
+  pub color: Vector3<f32>,
+  pub intensity: f32,
+  pub cast_shadows: bool,
+  pub shadow_map_size: u32,
+  // depth offset against shadow acne, grown on surfaces at a grazing angle
+  pub shadow_bias: f32,
+}
+
+impl Light {
+  pub fn directional(direction: Vector3<f32>) -> Self {
+    Light::new(LightKind::Directional {
+      direction,
+      cascade_splits: vec![100.0],
+    })
+  }
+
+  pub fn spot(position: Vector3<f32>, direction: Vector3<f32>, angle: f32, range: f32) -> Self {
+    Light::new(LightKind::Spot {
+      position,
+      direction,
+      angle,
+      range,
+    })
+  }
+
+  fn new(kind: LightKind) -> Self {
+    Light {
+      kind,
+      color: Vector3::new(1.0, 1.0, 1.0),
+      intensity: PI,
+      cast_shadows: true,
+      shadow_map_size: 1024,
+      shadow_bias: 0.001,
+    }
+  }
+
+  pub fn set_color(mut self, color: Vector3<f32>) -> Self {
+    self.color = color;
+    self
+  }
+
+  pub fn set_intensity(mut self, intensity: f32) -> Self {
+    self.intensity = intensity;
+    self
+  }
+
+  pub fn set_cast_shadows(mut self, cast_shadows: bool) -> Self {
+    self.cast_shadows = cast_shadows;
+    self
+  }
+
+  pub fn set_shadow_map_size(mut self, shadow_map_size: u32) -> Self {
+    self.shadow_map_size = shadow_map_size;
+    self
+  }
+
+  pub fn set_shadow_bias(mut self, shadow_bias: f32) -> Self {
+    self.shadow_bias = shadow_bias;
+    self
+  }
+
+  // ignored by spot lights, splits past MAX_CASCADES are dropped
+  pub fn set_cascade_splits(mut self, splits: Vec<f32>) -> Self {
+    if let LightKind::Directional { cascade_splits, .. } = &mut self.kind {
+      *cascade_splits = splits.into_iter().take(MAX_CASCADES).collect();
+    }
+    self
+  }
+
+  fn is_directional(&self) -> bool {
+    matches!(self.kind, LightKind::Directional { .. })
+  }
+
+  // number of shadow maps the light renders
+  fn shadow_map_count(&self) -> usize {
+    match (&self.kind, self.cast_shadows) {
+      (_, false) => 0,
+      (LightKind::Directional { cascade_splits, .. }, true) => cascade_splits.len(),
+      (LightKind::Spot { .. }, true) => 1,
+    }
+  }
+}
+
+pub type Lights = Pool<Light>;
+
+// Shader features the lights of the scene turn on, part of the variant of lit materials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+// Only the shadow maps a variant declares take a texture unit.
+pub struct LightFeatures {
+  // shadow maps of the directional light, one per cascade
+  pub cascades: usize,
+  pub spot_lights: bool,
+  // the spot lights sampling a shadow map
+  pub spot_shadows: [bool; MAX_SPOT_LIGHTS],
+}
+
+impl LightFeatures {
+  pub fn defines(&self) -> Vec<Define> {
+    let mut defines = vec![];
+
+    if self.cascades > 0 {
+      defines.push(Define::def("USE_DIRECTIONAL_SHADOWS"));
+    }
+
+    for i in 1..self.cascades {
+      defines.push(Define::def(&format!("USE_CASCADE{}", i)));
+    }
+
+    if self.spot_lights {
+      defines.push(Define::def("USE_SPOT_LIGHTS"));
+    }
+
+    for (i, shadow) in self.spot_shadows.iter().enumerate() {
+      if *shadow {
+        defines.push(Define::def(&format!("USE_SPOT_SHADOW{}", i)));
+      }
+    }
+
+    defines
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShadowMap {
+  // world to clip space of the light
+  pub view_projection: Matrix4<f32>,
+  pub target: Handle<RenderTarget>,
+  pub depth_texture: Handle<Texture>,
+  pub size: u32,
+  pub bias: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectionalFrame {
+  pub direction: Vector3<f32>,
+  pub radiance: Vector3<f32>,
+  // view distance where the cascade ends, empty when the light casts no shadows
+  pub cascades: Vec<(f32, ShadowMap)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpotFrame {
+  pub position: Vector3<f32>,
+  pub direction: Vector3<f32>,
+  pub radiance: Vector3<f32>,
+  pub cos_angle: f32,
+  pub range: f32,
+  pub shadow: Option<ShadowMap>,
+}
+
+// Lights as shaded in the current frame, built by Renderer::render_shadows.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LightFrame {
+  pub directional: Option<DirectionalFrame>,
+  pub spots: Vec<SpotFrame>,
+}
+
+impl LightFrame {
+  pub fn shadow_maps(&self) -> Vec<&ShadowMap> {
+    let cascades = self
+      .directional
+      .iter()
+      .flat_map(|directional| directional.cascades.iter().map(|(_, map)| map));
+    let spots = self.spots.iter().filter_map(|spot| spot.shadow.as_ref());
+
+    cascades.chain(spots).collect()
+  }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ShadowTargets {
+  pub(crate) size: u32,
+  pub(crate) targets: Vec<Handle<RenderTarget>>,
+}
+
+type LightEntry<'a> = (Handle<Light>, &'a Light);
+
+pub(crate) type ShadowTargetsByLight = HashMap<Handle<Light>, ShadowTargets>;
+
+fn light_up(direction: &Vector3<f32>) -> Vector3<f32> {
+  if direction.y.abs() > 0.99 {
+    Vector3::z()
+  } else {
+    Vector3::y()
+  }
+}
+
+// Orthographic view projection of a directional light covering the slice of the camera
+// frustum between the near and far view distances. The slice is bounded by a sphere so
+// the map keeps its size as the camera turns, and snapped to texels so edges don't
+// shimmer as it moves.
+pub fn cascade_view_projection(
+  camera: &Camera,
+  direction: &Vector3<f32>,
+  near: f32,
+  far: f32,
+  size: u32,
+) -> Matrix4<f32> {
+  let camera_world = camera.view.try_inverse().unwrap_or_else(Matrix4::identity);
+  let x = 1.0 / camera.projection[(0, 0)];
+  let y = 1.0 / camera.projection[(1, 1)];
+
+  let corners: Vec<Point3<f32>> = [near, far]
+    .iter()
+    .flat_map(|d| {
+      [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+        .iter()
+        .map(move |(sx, sy)| Point3::new(sx * x * d, sy * y * d, -d))
+    })
+    .map(|corner| camera_world.transform_point(&corner))
+    .collect();
+
+  let center = corners
+    .iter()
+    .fold(Vector3::zeros(), |sum, corner| sum + corner.coords)
+    / corners.len() as f32;
+  let radius = corners
+    .iter()
+    .map(|corner| (corner.coords - center).norm())
+    .fold(0.0, f32::max)
+    .ceil()
+    .max(1.0);
+
+  let direction = direction.normalize();
+  let up = light_up(&direction);
+  let rotation = Matrix4::look_at_rh(&Point3::origin(), &Point3::from(direction), &up);
+  let texel = 2.0 * radius / size as f32;
+
+  let mut snapped = rotation.transform_point(&Point3::from(center));
+  snapped.x = (snapped.x / texel).floor() * texel;
+  snapped.y = (snapped.y / texel).floor() * texel;
+
+  let center = rotation.transpose().transform_point(&snapped);
+  let eye = center - direction * (radius + CASTER_DISTANCE);
+  let view = Matrix4::look_at_rh(&eye, &center, &up);
+  let projection = Orthographic3::new(
+    -radius,
+    radius,
+    -radius,
+    radius,
+    0.0,
+    2.0 * radius + CASTER_DISTANCE,
+  );
+
+  projection.to_homogeneous() * view
+}
+
+pub fn spot_view_projection(
+  position: &Vector3<f32>,
+  direction: &Vector3<f32>,
+  angle: f32,
+  range: f32,
+) -> Matrix4<f32> {
+  let direction = direction.normalize();
+  let view = Matrix4::look_at_rh(
+    &Point3::from(*position),
+    &Point3::from(position + direction),
+    &light_up(&direction),
+  );
+  let fovy = (2.0 * angle).min(PI - 0.01);
+  let projection = Perspective3::new(1.0, fovy, range / 100.0, range);
+
+  projection.to_homogeneous() * view
+}
+
+impl Renderer {
+  pub fn insert_light(&mut self, light: Light) -> Handle<Light> {
+    self.lights.insert(light)
+  }
+
+  // the first directional light and the first MAX_SPOT_LIGHTS spot lights, the ones
+  // PbrMaterial shades with
+  fn active_lights(&self) -> (Option<LightEntry<'_>>, Vec<LightEntry<'_>>) {
+    let directional = self.lights.iter().find(|(_, light)| light.is_directional());
+    let spots = self
+      .lights
+      .iter()
+      .filter(|(_, light)| !light.is_directional())
+      .take(MAX_SPOT_LIGHTS)
+      .collect();
+
+    (directional, spots)
+  }
+
+  pub fn light_features(&self, receive_shadows: bool) -> LightFeatures {
+    let (directional, spots) = self.active_lights();
+    let mut spot_shadows = [false; MAX_SPOT_LIGHTS];
+
+    for (i, (_, light)) in spots.iter().enumerate() {
+      spot_shadows[i] = receive_shadows && light.cast_shadows;
+    }
+
+    LightFeatures {
+      cascades: match directional {
+        Some((_, light)) if receive_shadows => light.shadow_map_count(),
+        _ => 0,
+      },
+      spot_lights: !spots.is_empty(),
+      spot_shadows,
+    }
+  }
+
+  // depth targets of a light, rebaked when the count or size changed
+  fn shadow_targets(&mut self, handle: Handle<Light>) -> Vec<Handle<RenderTarget>> {
+    let light = self.lights.get(handle).unwrap();
+    let size = light.shadow_map_size;
+    let count = light.shadow_map_count();
+
+    if let Some(current) = self.shadow_targets.get(&handle) {
+      if current.size == size && current.targets.len() == count {
+        return current.targets.clone();
+      }
+    }
+
+    if let Some(stale) = self.shadow_targets.remove(&handle) {
+      for target in stale.targets {
+        self.delete_render_target(target);
+      }
+    }
+
+    let targets: Vec<Handle<RenderTarget>> = (0..count)
+      .map(|_| self.bake_render_target(size, size, Sampler::framebuffer(), true))
+      .collect();
+
+    self.shadow_targets.insert(
+      handle,
+      ShadowTargets {
+        size,
+        targets: targets.clone(),
+      },
+    );
+
+    targets
+  }
+
+  fn shadow_map(
+    &self,
+    light: &Light,
+    target: Handle<RenderTarget>,
+    view_projection: Matrix4<f32>,
+  ) -> ShadowMap {
+    ShadowMap {
+      view_projection,
+      target,
+      depth_texture: self.targets.get(target).unwrap().depth_texture.unwrap(),
+      size: light.shadow_map_size,
+      bias: light.shadow_bias,
+    }
+  }
+
+  fn build_light_frame(&mut self, camera: &Camera) -> LightFrame {
+    let (directional, spots) = self.active_lights();
+    let directional = directional.map(|(handle, light)| (handle, light.clone()));
+    let spots: Vec<(Handle<Light>, Light)> = spots
+      .into_iter()
+      .map(|(handle, light)| (handle, light.clone()))
+      .collect();
+    let mut frame = LightFrame::default();
+
+    if let Some((handle, light)) = directional {
+      if let LightKind::Directional {
+        direction,
+        cascade_splits,
+      } = &light.kind
+      {
+        let targets = self.shadow_targets(handle);
+        let mut near = 0.0;
+        let mut cascades = vec![];
+
+        for (split, target) in cascade_splits.iter().zip(targets) {
+          let view_projection =
+            cascade_view_projection(camera, direction, near, *split, light.shadow_map_size);
+
+          cascades.push((*split, self.shadow_map(&light, target, view_projection)));
+          near = *split;
+        }
+
+        frame.directional = Some(DirectionalFrame {
+          direction: direction.normalize(),
+          radiance: light.color * light.intensity,
+          cascades,
+        });
+      }
+    }
+
+    for (handle, light) in spots {
+      if let LightKind::Spot {
+        position,
+        direction,
+        angle,
+        range,
+      } = &light.kind
+      {
+        let shadow = self.shadow_targets(handle).first().map(|target| {
+          let view_projection = spot_view_projection(position, direction, *angle, *range);
+
+          self.shadow_map(&light, *target, view_projection)
+        });
+
+        frame.spots.push(SpotFrame {
+          position: *position,
+          direction: direction.normalize(),
+          radiance: light.color * light.intensity,
+          cos_angle: angle.cos(),
+          range: *range,
+          shadow,
+        });
+      }
+    }
+
+    frame
+  }
+
+  // Updates the lights PbrMaterial shades with and renders the depth of the casters under
+  // root into their shadow maps, call it before render_scene every frame. Like
+  // bake_environment it leaves the viewport at the size of the last map.
+  pub fn render_shadows(&mut self, root_handle: Handle<Node>, camera_handle: Handle<Camera>) {
+    let camera = self.cameras.get(camera_handle).unwrap().clone();
+    let frame = self.build_light_frame(&camera);
+
+    for map in frame.shadow_maps() {
+      self.render_shadow_map(root_handle, map);
+    }
+
+    self.ctx.bind_framebuffer(None);
+    self.light_frame = frame;
+  }
+
+  fn shadow_shader(&self, instanced: bool) -> Option<Rc<Shader>> {
+    let mut variant = ShaderVariant::new(
+      include_str!("./shaders/shadow_vert.glsl"),
+      include_str!("./shaders/shadow_frag.glsl"),
+      vec![],
+    );
+
+    if instanced {
+      variant = variant.add_define(Define::def("USE_INSTANCING"));
+    }
+
+    self.shaders.get_or_compile(self.ctx.as_ref(), &variant)
+  }
+
+  // Opaque and alpha tested primitives of the casters, alpha is not tested so cut out
+  // parts shadow as solid.
+  fn render_shadow_map(&self, root_handle: Handle<Node>, map: &ShadowMap) {
+    let target = self.targets.get(map.target).unwrap();
+    let fb = self.framebuffers.get(target.fb).unwrap();
+    let mut shaders: [Option<Option<Rc<Shader>>>; 2] = [None, None];
+
+    self.ctx.bind_framebuffer(Some(*fb));
+    self.ctx.viewport(0, 0, map.size as i32, map.size as i32);
+    self.ctx.clear_color(1.0, 1.0, 1.0, 1.0);
+    self.ctx.depth_mask(true);
+    self.ctx.clear(true, true);
+
+    self.ctx.set(Feature::DepthTest, true);
+    self.ctx.depth_func(DepthFunc::Less);
+    self.ctx.set(Feature::Blend, false);
+    self.ctx.set(Feature::SampleAlphaToCoverage, false);
+
+    for handle in self.scene.collect_visible_sub_items(root_handle) {
+      let node = self.scene.get_node(handle).unwrap();
+
+      if !node.cast_shadows {
+        continue;
+      }
+
+      let mesh = match node.mesh.and_then(|mesh| self.meshes.get(mesh)) {
+        Some(mesh) => mesh,
+        None => continue,
+      };
+      let instances = node
+        .instances
+        .and_then(|handle| self.instance_sets.get(handle));
+      let instanced = instances.is_some();
+
+      let shader =
+        match shaders[instanced as usize].get_or_insert_with(|| self.shadow_shader(instanced)) {
+          Some(shader) => shader.clone(),
+          None => return,
+        };
+
+      shader.bind();
+      shader.set_matrix4("lightViewProjection", &map.view_projection);
+      shader.set_matrix4("modelMatrix", &node.matrix_world);
+
+      for primitive in &mesh.primitives {
+        let params = match primitive
+          .material
+          .and_then(|handle| self.materials.get(handle))
+        {
+          Some(material) => material.params(),
+          None => continue,
+        };
+
+        match params.queue {
+          RenderQueue::Opaque | RenderQueue::AlphaTest => {}
+          _ => continue,
+        }
+
+        let geometry = match self.geometries.get(primitive.geometry) {
+          Some(geometry) => geometry,
+          None => {
+            warn!(
+              "skip shadow draw, geometry {:?} is missing",
+              primitive.geometry
+            );
+            continue;
+          }
+        };
+
+        self.ctx.set(Feature::CullFace, params.cull_face);
+        self.draw_geometry(&shader, geometry, instances);
+      }
+    }
+  }
+}
+
+fn bind_shadow_map(
+  shader: &Shader,
+  rc: &RenderContext,
+  map: &ShadowMap,
+  name: &str,
+  unit: &mut u32,
+) {
+  if !shader.has_uniform(name) {
+    return;
+  }
+
+  bind_texture(
+    rc.ctx,
+    rc.images,
+    rc.textures,
+    rc.samplers,
+    shader,
+    map.depth_texture,
+    TextureKind::Texture2d,
+    name,
+    *unit,
+  );
+  *unit += 1;
+}
+
+// Uploads the lights of the frame to the uniforms the program declares. The shadow maps
+// the variant declares are bound from first_unit on, returns the next free unit.
+pub fn bind_lights(shader: &Shader, rc: &RenderContext, first_unit: u32) -> u32 {
+  let frame = rc.lights;
+  let mut unit = first_unit;
+
+  match &frame.directional {
+    Some(directional) => {
+      shader.set_vector3("lightDirection", &-directional.direction);
+      shader.set_vector3("lightColor", &directional.radiance);
+    }
+    None => {
+      shader.set_vector3("lightDirection", &DEFAULT_LIGHT_DIRECTION);
+      shader.set_vector3("lightColor", &Vector3::new(PI, PI, PI));
+    }
+  }
+
+  if shader.has_uniform("cascadeCount") {
+    let cascades = frame
+      .directional
+      .as_ref()
+      .map_or(&[][..], |directional| &directional.cascades[..]);
+    let mut splits = Vector4::zeros();
+
+    shader.set_integer("cascadeCount", cascades.len() as i32);
+
+    for (i, (split, map)) in cascades.iter().enumerate() {
+      splits[i] = *split;
+
+      shader.set_matrix4(&format!("cascadeMatrix{}", i), &map.view_projection);
+      bind_shadow_map(shader, rc, map, &format!("cascadeMap{}", i), &mut unit);
+    }
+
+    shader.set_vector4("cascadeSplits", &splits);
+
+    if let Some((_, map)) = cascades.first() {
+      shader.set_float("directionalShadowBias", map.bias);
+      shader.set_float("directionalShadowTexel", 1.0 / map.size as f32);
+    }
+  }
+
+  if shader.has_uniform("spotLightCount") {
+    shader.set_integer("spotLightCount", frame.spots.len() as i32);
+
+    for (i, spot) in frame.spots.iter().enumerate() {
+      shader.set_vector3(&format!("spotPosition{}", i), &spot.position);
+      shader.set_vector3(&format!("spotDirection{}", i), &spot.direction);
+      shader.set_vector3(&format!("spotColor{}", i), &spot.radiance);
+      shader.set_float(&format!("spotCosAngle{}", i), spot.cos_angle);
+      shader.set_float(&format!("spotRange{}", i), spot.range);
+    }
+  }
+
+  for (i, spot) in frame.spots.iter().enumerate() {
+    if let Some(map) = &spot.shadow {
+      shader.set_matrix4(&format!("spotShadowMatrix{}", i), &map.view_projection);
+      shader.set_float(&format!("spotShadowBias{}", i), map.bias);
+      shader.set_float(&format!("spotShadowTexel{}", i), 1.0 / map.size as f32);
+      bind_shadow_map(shader, rc, map, &format!("spotShadowMap{}", i), &mut unit);
+    }
+  }
+
+  unit
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn in_clip_space(point: &Point3<f32>) -> bool {
+    point.iter().all(|v| v.abs() <= 1.0)
+  }
+
+  #[test]
+  fn cascades_cover_their_slice_of_the_view() {
+    let view = Matrix4::look_at_rh(
+      &Point3::new(0.0, 5.0, 10.0),
+      &Point3::origin(),
+      &Vector3::y(),
+    );
+    let projection = Perspective3::new(1.5, 1.0, 0.1, 100.0).to_homogeneous();
+    let camera = Camera::new(view, projection);
+    let camera_world = view.try_inverse().unwrap();
+    let direction = Vector3::new(-0.4, -1.0, -0.3);
+    let (near, far) = (5.0, 20.0);
+    let matrix = cascade_view_projection(&camera, &direction, near, far, 1024);
+
+    for d in &[near, (near + far) / 2.0, far] {
+      let x = d / projection[(0, 0)];
+      let y = d / projection[(1, 1)];
+
+      for (sx, sy) in &[
+        (-1.0, -1.0),
+        (1.0, -1.0),
+        (-1.0, 1.0),
+        (1.0, 1.0),
+        (0.0, 0.0),
+      ] {
+        let point = camera_world.transform_point(&Point3::new(sx * x, sy * y, -d));
+
+        assert!(in_clip_space(&matrix.transform_point(&point)));
+      }
+    }
+
+    // a caster above the slice is closer to the light than what it shadows
+    let receiver = camera_world.transform_point(&Point3::new(0.0, 0.0, -10.0));
+    let caster = receiver - direction.normalize() * 20.0;
+
+    assert!(matrix.transform_point(&caster).z < matrix.transform_point(&receiver).z);
+    assert!(in_clip_space(&matrix.transform_point(&caster)));
+  }
+
+  #[test]
+  fn spot_maps_cover_the_cone() {
+    let position = Vector3::new(0.0, 10.0, 0.0);
+    let direction = Vector3::new(0.0, -1.0, 0.0);
+    let matrix = spot_view_projection(&position, &direction, 0.5, 30.0);
+
+    let center = matrix.transform_point(&Point3::new(0.0, 0.0, 0.0));
+    let edge = matrix.transform_point(&Point3::new(10.0 * 0.5f32.tan() * 0.99, 0.0, 0.0));
+    let outside = matrix.transform_point(&Point3::new(10.0, 0.0, 0.0));
+
+    assert!(center.x.abs() < 1e-5 && center.y.abs() < 1e-5);
+    assert!(in_clip_space(&edge));
+    assert!(!in_clip_space(&outside));
+  }
+}
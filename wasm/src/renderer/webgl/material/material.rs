@@ -1,69 +1,403 @@
-use generational_arena::Index;
+use log::{error, warn};
+use na::{Matrix3, Matrix4, Vector2, Vector3, Vector4};
+use std::collections::BTreeMap;
 
-use crate::renderer::webgl::context::{Context, DepthFunc, DrawMode, TextureKind};
-use crate::renderer::webgl::renderer::{Camera, Images, Samplers, Textures};
+use crate::handle::Handle;
+use crate::renderer::webgl::backend::Backend;
+use crate::renderer::webgl::context::{BlendFactor, DepthFunc, TextureKind};
+use crate::renderer::webgl::light::LightFrame;
+use crate::renderer::webgl::render_queue::RenderQueue;
+use crate::renderer::webgl::renderer::{Camera, Images, Samplers, Texture, Textures};
 use crate::renderer::webgl::shader::Shader;
-use crate::scene::node::Node;
-use anyhow::Result;
+use crate::renderer::webgl::shader_cache::ShaderVariant;
+use crate::scene::node::{Node, UserData};
 use std::fmt::Debug;
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum UniformValue {
+  Float(f32),
+  Vector2(Vector2<f32>),
+  Vector3(Vector3<f32>),
+  Vector4(Vector4<f32>),
+  Matrix3(Matrix3<f32>),
+  Matrix4(Matrix4<f32>),
+  Integer(i32),
+  Bool(bool),
+  Texture(Handle<Texture>, TextureKind),
+}
+
+impl UniformValue {
+  pub fn same_kind(&self, other: &UniformValue) -> bool {
+    std::mem::discriminant(self) == std::mem::discriminant(other)
+  }
+}
+
+// Parameter block of a material instance, values are uploaded to the uniforms of the
+// same name. Sorted so textures always get the same units.
+pub type Uniforms = BTreeMap<String, UniformValue>;
+
+// Everything a material reads while setting up a draw.
+pub struct RenderContext<'a> {
+  pub ctx: &'a dyn Backend,
+  pub images: &'a Images,
+  pub textures: &'a Textures,
+  pub samplers: &'a Samplers,
+  pub node: &'a Node,
+  pub camera: &'a Camera,
+  pub lights: &'a LightFrame,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+  Opaque,
+  // straight alpha, color is not multiplied by alpha in the shader
+  Alpha,
+  Premultiplied,
+  Additive,
+  Multiply,
+  Custom { src: BlendFactor, dst: BlendFactor },
+}
+
+impl BlendMode {
+  // None means blending is disabled
+  pub fn factors(&self) -> Option<(BlendFactor, BlendFactor)> {
+    match self {
+      Self::Opaque => None,
+      Self::Alpha => Some((BlendFactor::SrcAlpha, BlendFactor::OneMinusSrcAlpha)),
+      Self::Premultiplied => Some((BlendFactor::One, BlendFactor::OneMinusSrcAlpha)),
+      Self::Additive => Some((BlendFactor::SrcAlpha, BlendFactor::One)),
+      Self::Multiply => Some((BlendFactor::DstColor, BlendFactor::Zero)),
+      Self::Custom { src, dst } => Some((*src, *dst)),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
 pub struct MaterialParams {
   pub cull_face: bool,
   pub depth_test: bool,
   pub depth_func: DepthFunc,
-  pub draw_mode: DrawMode,
+  pub queue: RenderQueue,
+  pub blend: BlendMode,
+  pub depth_write: bool,
+  pub alpha_to_coverage: bool,
+  // fragments with a lower alpha are discarded
+  pub alpha_cutoff: Option<f32>,
+}
+
+impl Default for MaterialParams {
+  fn default() -> Self {
+    MaterialParams {
+      cull_face: true,
+      depth_test: true,
+      depth_func: DepthFunc::Less,
+      queue: RenderQueue::Opaque,
+      blend: BlendMode::Opaque,
+      depth_write: true,
+      alpha_to_coverage: false,
+      alpha_cutoff: None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureTransform {
+  pub offset: Vector2<f32>,
+  pub rotation: f32,
+  pub scale: Vector2<f32>,
+}
+
+impl TextureTransform {
+  pub fn new() -> Self {
+    TextureTransform {
+      offset: Vector2::new(0.0, 0.0),
+      rotation: 0.0,
+      scale: Vector2::new(1.0, 1.0),
+    }
+  }
+
+  pub fn set_offset(mut self, offset: Vector2<f32>) -> Self {
+    self.offset = offset;
+    self
+  }
+
+  pub fn set_rotation(mut self, rotation: f32) -> Self {
+    self.rotation = rotation;
+    self
+  }
+
+  pub fn set_scale(mut self, scale: Vector2<f32>) -> Self {
+    self.scale = scale;
+    self
+  }
+
+  // translation * rotation * scale, as defined by KHR_texture_transform
+  pub fn matrix(&self) -> Matrix3<f32> {
+    let (s, c) = self.rotation.sin_cos();
+
+    let translation = Matrix3::new(
+      1.0,
+      0.0,
+      self.offset.x,
+      0.0,
+      1.0,
+      self.offset.y,
+      0.0,
+      0.0,
+      1.0,
+    );
+    let rotation = Matrix3::new(c, s, 0.0, -s, c, 0.0, 0.0, 0.0, 1.0);
+    let scale = Matrix3::new(
+      self.scale.x,
+      0.0,
+      0.0,
+      0.0,
+      self.scale.y,
+      0.0,
+      0.0,
+      0.0,
+      1.0,
+    );
+
+    translation * rotation * scale
+  }
+}
+
+impl Default for TextureTransform {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// a map of an exported material, the uv set is written as texCoord
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapExport {
+  pub texture: Handle<Texture>,
+  pub uv_set: u32,
 }
 
+pub struct MaterialExport {
+  pub base_color: Vector4<f32>,
+  pub base_color_texture: Option<MapExport>,
+  pub metallic: f32,
+  pub roughness: f32,
+  pub metallic_roughness_texture: Option<MapExport>,
+  pub normal_texture: Option<MapExport>,
+  pub normal_scale: f32,
+  pub occlusion_texture: Option<MapExport>,
+  pub occlusion_strength: f32,
+  pub emissive: Vector3<f32>,
+  pub emissive_texture: Option<MapExport>,
+  // MASK with a cutoff, else BLEND when blending and OPAQUE otherwise
+  pub alpha_cutoff: Option<f32>,
+  pub blend: bool,
+  pub unlit: bool,
+  pub double_sided: bool,
+}
+
+// A material is a template, the sources and defines picking the shader variant, plus a
+// parameter block. Materials asking for the same variant share the compiled program.
 pub trait Material: Debug {
-  fn get_tag(&self) -> String;
-  fn create_shader(&self, ctx: &Context) -> Result<Shader>;
-  fn setup_shader(
-    &self,
-    ctx: &Context,
-    images: &Images,
-    textures: &Textures,
-    samplers: &Samplers,
-    shader: &Shader,
-    node: &Node,
-    camera: &Camera,
-  );
+  fn shader_variant(&self) -> ShaderVariant<'_>;
+  fn setup_shader(&self, shader: &Shader, rc: &RenderContext);
   fn params(&self) -> MaterialParams;
+  fn export(&self) -> Option<MaterialExport> {
+    None
+  }
+  fn requires_tangents(&self) -> bool {
+    false
+  }
+  // lit materials get the light feature defines and bind_lights in setup_shader
+  fn receives_lights(&self) -> bool {
+    false
+  }
+  fn name(&self) -> Option<&str> {
+    None
+  }
+  fn user_data(&self) -> Option<&UserData> {
+    None
+  }
+  // texture handles the material samples, used to free them with the material
+  fn textures(&self) -> Vec<Handle<Texture>> {
+    vec![]
+  }
+  // values that stay editable after bake_material without changing the shader variant
+  fn param_block_mut(&mut self) -> Option<&mut Uniforms> {
+    None
+  }
+  // new material with the same template and a copy of the parameter block
+  fn instance(&self) -> Option<Box<dyn Material>> {
+    None
+  }
 }
 
-pub fn bind_several_maps(
-  ctx: &Context,
+// projection, view, model and normal matrices plus the camera position, when declared
+pub fn set_transform_uniforms(shader: &Shader, rc: &RenderContext) {
+  shader.set_matrix4("projectionMatrix", &rc.camera.projection);
+  shader.set_matrix4("viewMatrix", &rc.camera.view);
+  shader.set_matrix4("modelMatrix", &rc.node.matrix_world);
+
+  if shader.has_uniform("normalMatrix") {
+    shader.set_matrix3(
+      "normalMatrix",
+      &rc
+        .node
+        .matrix_world
+        .try_inverse()
+        .unwrap_or_else(Matrix4::identity)
+        .transpose()
+        .fixed_slice::<3, 3>(0, 0)
+        .into(),
+    );
+  }
+
+  if shader.has_uniform("cameraPosition") {
+    shader.set_vector3(
+      "cameraPosition",
+      &rc
+        .camera
+        .view
+        .try_inverse()
+        .unwrap_or_else(Matrix4::identity)
+        .column(3)
+        .xyz(),
+    );
+  }
+}
+
+// Uploads the values the program declares, the others are skipped and take no texture
+// unit. Textures are bound from first_unit on, returns the next free unit.
+pub fn bind_uniforms(
+  shader: &Shader,
+  rc: &RenderContext,
+  uniforms: &Uniforms,
+  first_unit: u32,
+) -> u32 {
+  bind_uniform_values(
+    rc.ctx,
+    rc.images,
+    rc.textures,
+    rc.samplers,
+    shader,
+    uniforms,
+    first_unit,
+  )
+}
+
+// bind_uniforms for draws without a node or camera, e.g. post effects
+pub fn bind_uniform_values(
+  ctx: &dyn Backend,
   images: &Images,
   textures: &Textures,
   samplers: &Samplers,
   shader: &Shader,
-  maps: &[(Option<Index>, TextureKind, &str)],
-) {
-  for (i, map) in maps.iter().enumerate() {
-    if let Some(map_handle) = map.0 {
+  uniforms: &Uniforms,
+  first_unit: u32,
+) -> u32 {
+  let mut unit = first_unit;
+
+  for (name, value) in uniforms.iter() {
+    if !shader.has_uniform(name) {
+      continue;
+    }
+
+    match value {
+      UniformValue::Float(v) => shader.set_float(name, *v),
+      UniformValue::Vector2(v) => shader.set_vector2(name, v),
+      UniformValue::Vector3(v) => shader.set_vector3(name, v),
+      UniformValue::Vector4(v) => shader.set_vector4(name, v),
+      UniformValue::Matrix3(m) => shader.set_matrix3(name, m),
+      UniformValue::Matrix4(m) => shader.set_matrix4(name, m),
+      UniformValue::Integer(v) => shader.set_integer(name, *v),
+      UniformValue::Bool(v) => shader.set_bool(name, *v),
+      UniformValue::Texture(texture, kind) => {
+        bind_texture(
+          ctx, images, textures, samplers, shader, *texture, *kind, name, unit,
+        );
+        unit += 1;
+
+        Some(())
+      }
+    };
+  }
+
+  unit
+}
+
+// Binds the maps the program declares from first_unit on, missing ones take no unit.
+// Returns the next free unit.
+pub fn bind_several_maps(
+  shader: &Shader,
+  rc: &RenderContext,
+  maps: &[(Option<Handle<Texture>>, TextureKind, &str)],
+  first_unit: u32,
+) -> u32 {
+  let mut unit = first_unit;
+
+  for (map, kind, name) in maps {
+    if let Some(map_handle) = map {
+      if !shader.has_uniform(name) {
+        continue;
+      }
+
       bind_texture(
-        ctx, images, textures, samplers, shader, map_handle, map.1, map.2, i as u32,
+        rc.ctx,
+        rc.images,
+        rc.textures,
+        rc.samplers,
+        shader,
+        *map_handle,
+        *kind,
+        name,
+        unit,
       );
+      unit += 1;
     }
   }
+
+  unit
 }
 
 pub fn bind_texture(
-  ctx: &Context,
+  ctx: &dyn Backend,
   images: &Images,
   textures: &Textures,
   samplers: &Samplers,
   shader: &Shader,
-  texture_handle: Index,
+  texture_handle: Handle<Texture>,
   texture_kind: TextureKind,
   uniform_name: &str,
   unit: u32,
 ) {
-  let texture = textures.get(texture_handle).unwrap();
-  let image = images.get(texture.source).unwrap();
-  let sampler = samplers.get(texture.sampler).unwrap();
+  // programs with more samplers than units fail to link, this catches the textures
+  // bound beside them, e.g. from a parameter block
+  if unit >= ctx.max_texture_units() {
+    error!(
+      "{} needs texture unit {}, the context has {}",
+      uniform_name,
+      unit,
+      ctx.max_texture_units()
+    );
+    return;
+  }
+
+  let found = textures
+    .get(texture_handle)
+    .and_then(|texture| Some((images.get(texture.source)?, samplers.get(texture.sampler)?)));
+
+  let (image, sampler) = match found {
+    Some(found) => found,
+    None => {
+      warn!(
+        "{} samples texture {:?}, which is missing",
+        uniform_name, texture_handle
+      );
+      return;
+    }
+  };
 
   ctx.active_texture(unit);
-  ctx.bind_texture(texture_kind, Some(&image));
+  ctx.bind_texture(texture_kind, Some(image.gpu_texture));
 
   sampler.set_params(texture_kind, ctx);
 
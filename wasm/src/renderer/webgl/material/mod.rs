@@ -1,7 +1,12 @@
 pub mod material;
 pub mod pbr_material;
+pub mod shader_material;
 pub mod skybox_material;
 
-pub use material::Material;
-pub use pbr_material::PbrMaterial;
+pub use material::{
+  BlendMode, MapExport, Material, MaterialExport, MaterialParams, RenderContext, TextureTransform,
+  UniformValue, Uniforms,
+};
+pub use pbr_material::{PbrMap, PbrMaterial};
+pub use shader_material::ShaderMaterial;
 pub use skybox_material::SkyboxMaterial;
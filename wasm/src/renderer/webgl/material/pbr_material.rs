@@ -1,44 +1,188 @@
-use generational_arena::Index;
-use na::{Matrix4, Vector2, Vector3, U3};
+use na::{Matrix3, Vector3, U3};
+use std::collections::HashMap;
 
-use anyhow::Result;
-
-use super::material::{bind_several_maps, Material, MaterialParams};
-use crate::renderer::webgl::context::{Context, DepthFunc, DrawMode, TextureKind};
+use super::material::{
+  bind_several_maps, bind_uniforms, set_transform_uniforms, BlendMode, MapExport, Material,
+  MaterialExport, MaterialParams, RenderContext, TextureTransform, UniformValue, Uniforms,
+};
+use crate::handle::Handle;
+use crate::renderer::webgl::context::{DepthFunc, TextureKind};
 use crate::renderer::webgl::define::Define;
-use crate::renderer::webgl::renderer::{Camera, Images, Samplers, Textures};
+use crate::renderer::webgl::ibl::Environment;
+use crate::renderer::webgl::light::bind_lights;
+use crate::renderer::webgl::render_queue::RenderQueue;
+use crate::renderer::webgl::renderer::Texture;
 use crate::renderer::webgl::shader::Shader;
-use crate::scene::node::Node;
+use crate::renderer::webgl::shader_cache::ShaderVariant;
+use crate::scene::node::UserData;
+
+// the 2d maps that sample with a uv set and a transform of their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PbrMap {
+  Color,
+  Occlusion,
+  Light,
+  Normal,
+  MetallicRoughness,
+  Emissive,
+}
+
+impl PbrMap {
+  pub const ALL: [PbrMap; 6] = [
+    PbrMap::Color,
+    PbrMap::Occlusion,
+    PbrMap::Light,
+    PbrMap::Normal,
+    PbrMap::MetallicRoughness,
+    PbrMap::Emissive,
+  ];
+
+  // sampler uniform, the transform uniform appends Transform
+  pub fn uniform(self) -> &'static str {
+    match self {
+      PbrMap::Color => "colorMap",
+      PbrMap::Occlusion => "occlusionMap",
+      PbrMap::Light => "lightMap",
+      PbrMap::Normal => "normalMap",
+      PbrMap::MetallicRoughness => "metallicRoughnessMap",
+      PbrMap::Emissive => "emissiveMap",
+    }
+  }
+
+  // the varying the map samples, as the define <MAP>_UV
+  fn uv_define(self) -> &'static str {
+    match self {
+      PbrMap::Color => "COLOR_MAP_UV",
+      PbrMap::Occlusion => "OCCLUSION_MAP_UV",
+      PbrMap::Light => "LIGHT_MAP_UV",
+      PbrMap::Normal => "NORMAL_MAP_UV",
+      PbrMap::MetallicRoughness => "METALLIC_ROUGHNESS_MAP_UV",
+      PbrMap::Emissive => "EMISSIVE_MAP_UV",
+    }
+  }
+
+  fn transform_uniform(self) -> String {
+    format!("{}Transform", self.uniform())
+  }
+}
 
-#[derive(Debug)]
+// The maps, flags and blending pick the shader variant. Colors and factors live in the
+// parameter block (color, opacity, emissive, emissiveStrength, metallic, roughness,
+// normalScale, occlusionStrength and a <map>Transform per map) and can change after baking.
+#[derive(Debug, Clone)]
 pub struct PbrMaterial {
-  color: Vector3<f32>,
-  color_map: Option<Index>,
-  debug_cube_map: Option<Index>,
-  uv_repeating: Vector2<f32>,
+  param_block: Uniforms,
+  color_map: Option<Handle<Texture>>,
+  debug_cube_map: Option<Handle<Texture>>,
+  occlusion_map: Option<Handle<Texture>>,
+  light_map: Option<Handle<Texture>>,
+  normal_map: Option<Handle<Texture>>,
+  // roughness in green, metalness in blue, scaled by the factors
+  metallic_roughness_map: Option<Handle<Texture>>,
+  emissive_map: Option<Handle<Texture>>,
+  // TEXCOORD_0 unless set, except the light map which reads TEXCOORD_1
+  uv_sets: HashMap<PbrMap, u32>,
+  // image based ambient light and reflections, a flat ambient term without it
+  environment: Option<Environment>,
+  vertex_colors: bool,
+  unlit: bool,
   cull_face: bool,
   depth_test: bool,
-  draw_mode: DrawMode,
+  blend: BlendMode,
+  // defaults to writing depth only when opaque
+  depth_write: Option<bool>,
+  alpha_to_coverage: bool,
+  alpha_cutoff: Option<f32>,
+  // derived from blend and alpha_cutoff when not set
+  queue: Option<RenderQueue>,
+  name: Option<String>,
+  user_data: UserData,
 }
 
 impl PbrMaterial {
   pub fn new() -> Self {
+    let mut param_block: Uniforms = [
+      ("color", UniformValue::Vector3(Vector3::new(0.0, 0.0, 0.0))),
+      ("opacity", UniformValue::Float(1.0)),
+      (
+        "emissive",
+        UniformValue::Vector3(Vector3::new(0.0, 0.0, 0.0)),
+      ),
+      ("emissiveStrength", UniformValue::Float(1.0)),
+      ("metallic", UniformValue::Float(0.0)),
+      ("roughness", UniformValue::Float(1.0)),
+      ("normalScale", UniformValue::Float(1.0)),
+      ("occlusionStrength", UniformValue::Float(1.0)),
+    ]
+    .iter()
+    .map(|(name, value)| (name.to_string(), value.clone()))
+    .collect();
+
+    for map in &PbrMap::ALL {
+      param_block.insert(
+        map.transform_uniform(),
+        UniformValue::Matrix3(Matrix3::identity()),
+      );
+    }
+
     PbrMaterial {
-      color: Vector3::new(0.0, 0.0, 0.0),
+      param_block,
       cull_face: true,
       depth_test: true,
-      draw_mode: DrawMode::Triangles,
+      blend: BlendMode::Opaque,
+      depth_write: None,
+      alpha_to_coverage: false,
+      alpha_cutoff: None,
+      queue: None,
       color_map: None,
       debug_cube_map: None,
-      uv_repeating: Vector2::new(1.0, 1.0),
+      occlusion_map: None,
+      light_map: None,
+      normal_map: None,
+      metallic_roughness_map: None,
+      emissive_map: None,
+      uv_sets: [(PbrMap::Light, 1)].iter().copied().collect(),
+      environment: None,
+      vertex_colors: false,
+      unlit: false,
+      name: None,
+      user_data: UserData::new(),
     }
   }
 
-  pub fn set_color(mut self, color: Vector3<f32>) -> Self {
-    self.color = color;
+  pub fn set_name(mut self, name: Option<String>) -> Self {
+    self.name = name;
+    self
+  }
+
+  pub fn set_user_data(mut self, user_data: UserData) -> Self {
+    self.user_data = user_data;
+    self
+  }
+
+  fn set_param(mut self, name: &str, value: UniformValue) -> Self {
+    self.param_block.insert(name.to_string(), value);
     self
   }
 
+  fn float_param(&self, name: &str) -> f32 {
+    match self.param_block.get(name) {
+      Some(UniformValue::Float(v)) => *v,
+      _ => 0.0,
+    }
+  }
+
+  fn vector3_param(&self, name: &str) -> Vector3<f32> {
+    match self.param_block.get(name) {
+      Some(UniformValue::Vector3(v)) => *v,
+      _ => Vector3::zeros(),
+    }
+  }
+
+  pub fn set_color(self, color: Vector3<f32>) -> Self {
+    self.set_param("color", UniformValue::Vector3(color))
+  }
+
   pub fn set_cull_face(mut self, cull_face: bool) -> Self {
     self.cull_face = cull_face;
     self
@@ -49,47 +193,179 @@ impl PbrMaterial {
     self
   }
 
-  pub fn set_draw_mode(mut self, draw_mode: DrawMode) -> Self {
-    self.draw_mode = draw_mode;
+  pub fn set_opacity(self, opacity: f32) -> Self {
+    self.set_param("opacity", UniformValue::Float(opacity))
+  }
+
+  pub fn set_blend(mut self, blend: BlendMode) -> Self {
+    self.blend = blend;
+    self
+  }
+
+  pub fn set_depth_write(mut self, depth_write: bool) -> Self {
+    self.depth_write = Some(depth_write);
+    self
+  }
+
+  pub fn set_alpha_to_coverage(mut self, alpha_to_coverage: bool) -> Self {
+    self.alpha_to_coverage = alpha_to_coverage;
+    self
+  }
+
+  pub fn set_alpha_cutoff(mut self, alpha_cutoff: Option<f32>) -> Self {
+    self.alpha_cutoff = alpha_cutoff;
+    self
+  }
+
+  pub fn set_queue(mut self, queue: RenderQueue) -> Self {
+    self.queue = Some(queue);
     self
   }
 
-  pub fn set_color_map(mut self, color_map: Option<Index>) -> Self {
+  pub fn set_color_map(mut self, color_map: Option<Handle<Texture>>) -> Self {
     self.color_map = color_map;
     self
   }
 
-  pub fn set_debug_cube_map(mut self, debug_cube_map: Option<Index>) -> Self {
+  pub fn set_debug_cube_map(mut self, debug_cube_map: Option<Handle<Texture>>) -> Self {
     self.debug_cube_map = debug_cube_map;
     self
   }
 
-  pub fn set_uv_repeating(mut self, uv_repeating: Vector2<f32>) -> Self {
-    self.uv_repeating = uv_repeating;
+  pub fn set_occlusion_map(mut self, occlusion_map: Option<Handle<Texture>>) -> Self {
+    self.occlusion_map = occlusion_map;
     self
   }
 
-  pub fn boxed(self) -> Box<Self> {
-    Box::new(self)
+  pub fn set_light_map(mut self, light_map: Option<Handle<Texture>>) -> Self {
+    self.light_map = light_map;
+    self
   }
-}
 
-impl Material for PbrMaterial {
-  fn get_tag(&self) -> String {
-    let mut tag = String::from("pbr");
+  pub fn set_normal_map(mut self, normal_map: Option<Handle<Texture>>) -> Self {
+    self.normal_map = normal_map;
+    self
+  }
 
-    if self.color_map.is_some() {
-      tag.push_str(":color_map");
+  pub fn set_metallic_roughness_map(
+    mut self,
+    metallic_roughness_map: Option<Handle<Texture>>,
+  ) -> Self {
+    self.metallic_roughness_map = metallic_roughness_map;
+    self
+  }
+
+  pub fn set_emissive_map(mut self, emissive_map: Option<Handle<Texture>>) -> Self {
+    self.emissive_map = emissive_map;
+    self
+  }
+
+  pub fn set_environment(mut self, environment: Option<Environment>) -> Self {
+    self.environment = environment;
+    self
+  }
+
+  pub fn set_metallic(self, metallic: f32) -> Self {
+    self.set_param("metallic", UniformValue::Float(metallic))
+  }
+
+  pub fn set_roughness(self, roughness: f32) -> Self {
+    self.set_param("roughness", UniformValue::Float(roughness))
+  }
+
+  // scales the x and y of normal map samples
+  pub fn set_normal_scale(self, normal_scale: f32) -> Self {
+    self.set_param("normalScale", UniformValue::Float(normal_scale))
+  }
+
+  // 0 ignores the occlusion map, 1 applies it fully
+  pub fn set_occlusion_strength(self, occlusion_strength: f32) -> Self {
+    self.set_param("occlusionStrength", UniformValue::Float(occlusion_strength))
+  }
+
+  pub fn set_vertex_colors(mut self, vertex_colors: bool) -> Self {
+    self.vertex_colors = vertex_colors;
+    self
+  }
+
+  pub fn set_emissive(self, emissive: Vector3<f32>) -> Self {
+    self.set_param("emissive", UniformValue::Vector3(emissive))
+  }
+
+  pub fn set_emissive_strength(self, emissive_strength: f32) -> Self {
+    self.set_param("emissiveStrength", UniformValue::Float(emissive_strength))
+  }
+
+  pub fn set_unlit(mut self, unlit: bool) -> Self {
+    self.unlit = unlit;
+    self
+  }
+
+  // the same transform for every map
+  pub fn set_uv_transform(self, uv_transform: TextureTransform) -> Self {
+    PbrMap::ALL.iter().fold(self, |material, map| {
+      material.set_map_transform(*map, uv_transform)
+    })
+  }
+
+  pub fn set_map_transform(self, map: PbrMap, transform: TextureTransform) -> Self {
+    self.set_param(
+      &map.transform_uniform(),
+      UniformValue::Matrix3(transform.matrix()),
+    )
+  }
+
+  // 1 samples TEXCOORD_1, any other set TEXCOORD_0
+  pub fn set_map_uv_set(mut self, map: PbrMap, uv_set: u32) -> Self {
+    self.uv_sets.insert(map, uv_set);
+    self
+  }
+
+  pub fn set_map(self, map: PbrMap, texture: Option<Handle<Texture>>) -> Self {
+    match map {
+      PbrMap::Color => self.set_color_map(texture),
+      PbrMap::Occlusion => self.set_occlusion_map(texture),
+      PbrMap::Light => self.set_light_map(texture),
+      PbrMap::Normal => self.set_normal_map(texture),
+      PbrMap::MetallicRoughness => self.set_metallic_roughness_map(texture),
+      PbrMap::Emissive => self.set_emissive_map(texture),
     }
+  }
 
-    if self.debug_cube_map.is_some() {
-      tag.push_str(":debug_cube_map");
+  fn map(&self, map: PbrMap) -> Option<Handle<Texture>> {
+    match map {
+      PbrMap::Color => self.color_map,
+      PbrMap::Occlusion => self.occlusion_map,
+      PbrMap::Light => self.light_map,
+      PbrMap::Normal => self.normal_map,
+      PbrMap::MetallicRoughness => self.metallic_roughness_map,
+      PbrMap::Emissive => self.emissive_map,
     }
+  }
+
+  fn uv_set(&self, map: PbrMap) -> u32 {
+    self.uv_sets.get(&map).copied().unwrap_or(0)
+  }
 
-    tag
+  pub fn boxed(self) -> Box<Self> {
+    Box::new(self)
   }
 
-  fn create_shader(&self, ctx: &Context) -> Result<Shader> {
+  fn queue(&self) -> RenderQueue {
+    if let Some(queue) = self.queue {
+      queue
+    } else if self.blend != BlendMode::Opaque {
+      RenderQueue::Transparent
+    } else if self.alpha_cutoff.is_some() || self.alpha_to_coverage {
+      RenderQueue::AlphaTest
+    } else {
+      RenderQueue::Opaque
+    }
+  }
+}
+
+impl Material for PbrMaterial {
+  fn shader_variant(&self) -> ShaderVariant<'_> {
     let vert_src = include_str!("./shaders/pbr_vert.glsl");
     let frag_src = include_str!("./shaders/pbr_frag.glsl");
 
@@ -103,46 +379,116 @@ impl Material for PbrMaterial {
       defines.push(Define::def("USE_DEBUG_CUBE_MAP"));
     }
 
-    ctx.create_shader(vert_src, frag_src, &defines)
-  }
-
-  fn setup_shader(
-    &self,
-    ctx: &Context,
-    images: &Images,
-    textures: &Textures,
-    samplers: &Samplers,
-    shader: &Shader,
-    node: &Node,
-    camera: &Camera,
-  ) {
-    shader.set_vector3("color", &self.color);
-    shader.set_vector2("uvRepeating", &self.uv_repeating);
-    shader.set_matrix4("projectionMatrix", &camera.projection);
-    shader.set_matrix4("viewMatrix", &camera.view);
-    shader.set_matrix4("modelMatrix", &node.matrix_world);
-    shader.set_matrix3(
-      "normalMatrix",
-      &node
-        .matrix_world
-        .try_inverse()
-        .unwrap_or_else(|| Matrix4::identity())
-        .transpose()
-        .fixed_slice::<3, 3>(0, 0)
-        .into(),
-    );
+    if self.occlusion_map.is_some() {
+      defines.push(Define::def("USE_OCCLUSION_MAP"));
+    }
+
+    if self.light_map.is_some() {
+      defines.push(Define::def("USE_LIGHT_MAP"));
+    }
+
+    let mut uv1 = false;
+
+    for map in &PbrMap::ALL {
+      if self.map(*map).is_none() {
+        continue;
+      }
+
+      let varying = if self.uv_set(*map) == 1 {
+        uv1 = true;
+        "v_uv1"
+      } else {
+        "v_uv"
+      };
+
+      defines.push(Define::new(map.uv_define(), Some(varying)));
+    }
+
+    if uv1 {
+      defines.push(Define::def("USE_UV1"));
+    }
+
+    if self.normal_map.is_some() {
+      defines.push(Define::def("USE_NORMAL_MAP"));
+    }
+
+    if self.metallic_roughness_map.is_some() {
+      defines.push(Define::def("USE_METALLIC_ROUGHNESS_MAP"));
+    }
+
+    if self.emissive_map.is_some() {
+      defines.push(Define::def("USE_EMISSIVE_MAP"));
+    }
+
+    if self.environment.is_some() {
+      defines.push(Define::def("USE_ENVIRONMENT"));
+    }
+
+    if self.vertex_colors {
+      defines.push(Define::def("USE_VERTEX_COLOR"));
+    }
+
+    if self.unlit {
+      defines.push(Define::def("USE_UNLIT"));
+    }
 
-    bind_several_maps(
-      ctx,
-      images,
-      textures,
-      samplers,
+    if self.alpha_cutoff.is_some() {
+      defines.push(Define::def("USE_ALPHA_TEST"));
+    }
+
+    if self.blend == BlendMode::Premultiplied {
+      defines.push(Define::def("PREMULTIPLIED_ALPHA"));
+    }
+
+    ShaderVariant::new(vert_src, frag_src, defines)
+  }
+
+  fn setup_shader(&self, shader: &Shader, rc: &RenderContext) {
+    set_transform_uniforms(shader, rc);
+
+    shader.set_float("alphaCutoff", self.alpha_cutoff.unwrap_or(0.0));
+
+    if let Some(environment) = &self.environment {
+      shader.set_float("specularLevels", environment.specular_levels as f32);
+    }
+
+    // units are handed out in turn to the maps, the parameter block and the shadow maps
+    let unit = bind_several_maps(
       shader,
+      rc,
       &[
         (self.color_map, TextureKind::Texture2d, "colorMap"),
         (self.debug_cube_map, TextureKind::CubeMap, "debugCubeMap"),
+        (self.occlusion_map, TextureKind::Texture2d, "occlusionMap"),
+        (self.light_map, TextureKind::Texture2d, "lightMap"),
+        (self.normal_map, TextureKind::Texture2d, "normalMap"),
+        (
+          self.metallic_roughness_map,
+          TextureKind::Texture2d,
+          "metallicRoughnessMap",
+        ),
+        (self.emissive_map, TextureKind::Texture2d, "emissiveMap"),
+        (
+          self.environment.map(|e| e.irradiance_map),
+          TextureKind::CubeMap,
+          "irradianceMap",
+        ),
+        (
+          self.environment.map(|e| e.specular_map),
+          TextureKind::CubeMap,
+          "specularMap",
+        ),
+        (
+          self.environment.map(|e| e.brdf_lut),
+          TextureKind::Texture2d,
+          "brdfLut",
+        ),
       ],
+      0,
     );
+    let unit = bind_uniforms(shader, rc, &self.param_block, unit);
+
+    bind_lights(shader, rc, unit);
   }
 
   fn params(&self) -> MaterialParams {
@@ -150,7 +496,209 @@ impl Material for PbrMaterial {
       cull_face: self.cull_face,
       depth_test: self.depth_test,
       depth_func: DepthFunc::Less,
-      draw_mode: DrawMode::Triangles,
+      queue: self.queue(),
+      blend: self.blend,
+      depth_write: self.depth_write.unwrap_or(self.blend == BlendMode::Opaque),
+      alpha_to_coverage: self.alpha_to_coverage,
+      alpha_cutoff: self.alpha_cutoff,
     }
   }
+
+  // map transforms and the light map are not exported
+  fn export(&self) -> Option<MaterialExport> {
+    let color = self.vector3_param("color");
+    let map = |map: PbrMap| {
+      self.map(map).map(|texture| MapExport {
+        texture,
+        uv_set: self.uv_set(map),
+      })
+    };
+
+    Some(MaterialExport {
+      base_color: color.push(self.float_param("opacity")),
+      base_color_texture: map(PbrMap::Color),
+      metallic: self.float_param("metallic"),
+      roughness: self.float_param("roughness"),
+      metallic_roughness_texture: map(PbrMap::MetallicRoughness),
+      normal_texture: map(PbrMap::Normal),
+      normal_scale: self.float_param("normalScale"),
+      occlusion_texture: map(PbrMap::Occlusion),
+      occlusion_strength: self.float_param("occlusionStrength"),
+      emissive: self.vector3_param("emissive") * self.float_param("emissiveStrength"),
+      emissive_texture: map(PbrMap::Emissive),
+      alpha_cutoff: self.alpha_cutoff,
+      blend: self.blend != BlendMode::Opaque,
+      unlit: self.unlit,
+      double_sided: !self.cull_face,
+    })
+  }
+
+  fn requires_tangents(&self) -> bool {
+    self.normal_map.is_some()
+  }
+
+  fn receives_lights(&self) -> bool {
+    !self.unlit
+  }
+
+  fn name(&self) -> Option<&str> {
+    self.name.as_deref()
+  }
+
+  fn user_data(&self) -> Option<&UserData> {
+    Some(&self.user_data)
+  }
+
+  fn param_block_mut(&mut self) -> Option<&mut Uniforms> {
+    Some(&mut self.param_block)
+  }
+
+  fn instance(&self) -> Option<Box<dyn Material>> {
+    Some(self.clone().boxed())
+  }
+
+  // environment maps are shared between materials and left to whoever baked them
+  fn textures(&self) -> Vec<Handle<Texture>> {
+    [
+      self.color_map,
+      self.debug_cube_map,
+      self.occlusion_map,
+      self.light_map,
+      self.normal_map,
+      self.metallic_roughness_map,
+      self.emissive_map,
+    ]
+    .iter()
+    .flatten()
+    .copied()
+    .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::renderer::webgl::context::TextureFormat;
+  use crate::renderer::webgl::recording::{Command, RecordingBackend};
+  use crate::renderer::webgl::renderer::{Camera, Renderer, Sampler};
+  use crate::scene::node::Node;
+
+  #[test]
+  fn instances_share_the_program_and_keep_their_params() {
+    let backend = RecordingBackend::new();
+    let log = backend.log();
+    let mut renderer = Renderer::new(backend);
+
+    let red = renderer.bake_material(
+      PbrMaterial::new()
+        .set_color(Vector3::new(1.0, 0.0, 0.0))
+        .boxed(),
+    );
+    let blue = renderer.instance_material(red).unwrap();
+
+    renderer
+      .set_material_param(
+        blue,
+        "color",
+        UniformValue::Vector3(Vector3::new(0.0, 0.0, 1.0)),
+      )
+      .unwrap();
+    assert!(renderer
+      .set_material_param(blue, "color", UniformValue::Float(1.0))
+      .is_none());
+
+    let geometry = renderer.bake_cuboid_geometry(Vector3::new(1.0, 1.0, 1.0));
+
+    for material in &[red, blue] {
+      let mesh = renderer.compose_mesh(geometry, *material, None);
+      let mut node = Node::new(Some(renderer.scene.get_root_handle()));
+
+      node.mesh = Some(mesh);
+      renderer.insert_node(node);
+    }
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+    renderer.render_scene(root, camera);
+
+    let log = log.borrow();
+    let programs = log
+      .iter()
+      .filter(|command| matches!(command, Command::CreateProgram { .. }))
+      .count();
+    let colors: Vec<&Vec<f32>> = log
+      .iter()
+      .filter_map(|command| match command {
+        Command::SetUniform { name, value, .. } if name == "color" => Some(value),
+        _ => None,
+      })
+      .collect();
+
+    assert_eq!(programs, 1);
+    assert_eq!(colors, vec![&vec![1.0, 0.0, 0.0], &vec![0.0, 0.0, 1.0]]);
+  }
+
+  fn uv_defines(material: &PbrMaterial) -> Vec<String> {
+    material
+      .shader_variant()
+      .defines
+      .iter()
+      .map(|define| define.as_string())
+      .filter(|define| define.contains("_UV"))
+      .collect()
+  }
+
+  #[test]
+  fn maps_pick_their_uv_set_and_keep_their_transform() {
+    let mut renderer = Renderer::new(RecordingBackend::new());
+    let texture = renderer.bake_2d_texture_from_pixels(
+      TextureFormat::RGBA,
+      Sampler::default(),
+      1,
+      1,
+      &[255; 4],
+    );
+    let material = PbrMaterial::new()
+      .set_color_map(Some(texture))
+      .set_occlusion_map(Some(texture))
+      .set_light_map(Some(texture));
+
+    assert_eq!(
+      uv_defines(&material),
+      vec![
+        "#define COLOR_MAP_UV v_uv",
+        "#define OCCLUSION_MAP_UV v_uv",
+        "#define LIGHT_MAP_UV v_uv1",
+        "#define USE_UV1",
+      ]
+    );
+
+    let material = material
+      .set_light_map(None)
+      .set_map_uv_set(PbrMap::Color, 1)
+      .set_uv_transform(TextureTransform::new().set_rotation(1.0))
+      .set_map_transform(
+        PbrMap::Occlusion,
+        TextureTransform::new().set_scale(na::Vector2::new(2.0, 2.0)),
+      );
+
+    assert_eq!(
+      uv_defines(&material),
+      vec![
+        "#define COLOR_MAP_UV v_uv1",
+        "#define OCCLUSION_MAP_UV v_uv",
+        "#define USE_UV1",
+      ]
+    );
+    assert_eq!(
+      material.param_block["colorMapTransform"],
+      UniformValue::Matrix3(TextureTransform::new().set_rotation(1.0).matrix())
+    );
+    assert_eq!(
+      material.param_block["occlusionMapTransform"],
+      UniformValue::Matrix3(Matrix3::new_nonuniform_scaling(&na::Vector2::new(2.0, 2.0)))
+    );
+  }
 }
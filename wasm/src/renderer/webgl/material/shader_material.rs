@@ -0,0 +1,234 @@
+use std::rc::Rc;
+
+use super::material::{
+  bind_uniforms, set_transform_uniforms, Material, MaterialParams, RenderContext, UniformValue,
+  Uniforms,
+};
+use crate::handle::Handle;
+use crate::renderer::webgl::define::Define;
+use crate::renderer::webgl::renderer::Texture;
+use crate::renderer::webgl::shader::Shader;
+use crate::renderer::webgl::shader_cache::ShaderVariant;
+use crate::scene::node::UserData;
+
+// sources and defines shared by every instance
+#[derive(Debug)]
+struct ShaderTemplate {
+  vertex_src: String,
+  fragment_src: String,
+  defines: Vec<Define>,
+}
+
+// Material defined by GLSL sources. The transform uniforms projectionMatrix, viewMatrix,
+// modelMatrix, normalMatrix and cameraPosition are set when the shader declares them,
+// everything else comes from the uniform map.
+#[derive(Debug, Clone)]
+pub struct ShaderMaterial {
+  template: Rc<ShaderTemplate>,
+  uniforms: Uniforms,
+  params: MaterialParams,
+  name: Option<String>,
+  user_data: UserData,
+}
+
+impl ShaderMaterial {
+  pub fn new(vertex_src: &str, fragment_src: &str) -> Self {
+    ShaderMaterial {
+      template: Rc::new(ShaderTemplate {
+        vertex_src: vertex_src.to_string(),
+        fragment_src: fragment_src.to_string(),
+        defines: vec![],
+      }),
+      uniforms: Uniforms::new(),
+      params: MaterialParams::default(),
+      name: None,
+      user_data: UserData::new(),
+    }
+  }
+
+  pub fn set_defines(mut self, defines: Vec<Define>) -> Self {
+    self.template = Rc::new(ShaderTemplate {
+      vertex_src: self.template.vertex_src.clone(),
+      fragment_src: self.template.fragment_src.clone(),
+      defines,
+    });
+    self
+  }
+
+  pub fn set_uniform(mut self, name: &str, value: UniformValue) -> Self {
+    self.uniforms.insert(name.to_string(), value);
+    self
+  }
+
+  pub fn set_params(mut self, params: MaterialParams) -> Self {
+    self.params = params;
+    self
+  }
+
+  pub fn set_name(mut self, name: Option<String>) -> Self {
+    self.name = name;
+    self
+  }
+
+  pub fn set_user_data(mut self, user_data: UserData) -> Self {
+    self.user_data = user_data;
+    self
+  }
+
+  pub fn boxed(self) -> Box<Self> {
+    Box::new(self)
+  }
+
+  pub fn uniforms(&self) -> &Uniforms {
+    &self.uniforms
+  }
+}
+
+impl Material for ShaderMaterial {
+  fn shader_variant(&self) -> ShaderVariant<'_> {
+    ShaderVariant::new(
+      &self.template.vertex_src,
+      &self.template.fragment_src,
+      self.template.defines.clone(),
+    )
+  }
+
+  fn setup_shader(&self, shader: &Shader, rc: &RenderContext) {
+    set_transform_uniforms(shader, rc);
+    bind_uniforms(shader, rc, &self.uniforms, 0);
+  }
+
+  fn params(&self) -> MaterialParams {
+    self.params.clone()
+  }
+
+  fn name(&self) -> Option<&str> {
+    self.name.as_deref()
+  }
+
+  fn user_data(&self) -> Option<&UserData> {
+    Some(&self.user_data)
+  }
+
+  fn textures(&self) -> Vec<Handle<Texture>> {
+    self
+      .uniforms
+      .values()
+      .filter_map(|value| match value {
+        UniformValue::Texture(texture, _) => Some(*texture),
+        _ => None,
+      })
+      .collect()
+  }
+
+  fn param_block_mut(&mut self) -> Option<&mut Uniforms> {
+    Some(&mut self.uniforms)
+  }
+
+  fn instance(&self) -> Option<Box<dyn Material>> {
+    Some(self.clone().boxed())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use na::Vector3;
+
+  use super::*;
+  use crate::renderer::webgl::context::TextureKind;
+  use crate::renderer::webgl::recording::{Command, RecordingBackend};
+  use crate::renderer::webgl::renderer::{Camera, Renderer, Sampler};
+  use crate::scene::node::Node;
+
+  const VERT: &str = "attribute vec3 position;
+uniform mat4 projectionMatrix;
+uniform mat4 viewMatrix;
+uniform mat4 modelMatrix;
+
+void main() {
+  gl_Position = projectionMatrix * viewMatrix * modelMatrix * vec4(position, 1.0);
+}";
+
+  const FRAG: &str = "uniform vec3 tint;
+uniform sampler2D pattern;
+uniform sampler2D detail;
+
+void main() {
+  gl_FragColor = vec4(tint, 1.0) * texture2D(pattern, vec2(0.0)) * texture2D(detail, vec2(0.0));
+}";
+
+  fn uniform_values(log: &[Command], uniform: &str) -> Vec<Vec<f32>> {
+    log
+      .iter()
+      .filter_map(|command| match command {
+        Command::SetUniform { name, value, .. } if name == uniform => Some(value.clone()),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn uniforms_are_bound_and_editable_after_baking() {
+    let backend = RecordingBackend::new();
+    let log = backend.log();
+    let mut renderer = Renderer::new(backend);
+
+    let gpu_texture = renderer.ctx.create_texture().unwrap();
+    let texture = renderer.compose_texture(gpu_texture, Sampler::default(), 0);
+
+    let material = renderer.bake_material(
+      ShaderMaterial::new(VERT, FRAG)
+        .set_uniform("tint", UniformValue::Vector3(Vector3::new(1.0, 0.5, 0.0)))
+        .set_uniform(
+          "detail",
+          UniformValue::Texture(texture, TextureKind::Texture2d),
+        )
+        .set_uniform(
+          "pattern",
+          UniformValue::Texture(texture, TextureKind::Texture2d),
+        )
+        .set_uniform("unused", UniformValue::Float(1.0))
+        .boxed(),
+    );
+
+    let geometry = renderer.bake_cuboid_geometry(Vector3::new(1.0, 1.0, 1.0));
+    let mesh = renderer.compose_mesh(geometry, material, None);
+    let mut node = Node::new(Some(renderer.scene.get_root_handle()));
+
+    node.mesh = Some(mesh);
+    renderer.insert_node(node);
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+    renderer.render_scene(root, camera);
+
+    assert_eq!(
+      uniform_values(&log.borrow(), "tint"),
+      vec![vec![1.0, 0.5, 0.0]]
+    );
+    // units follow the uniform names
+    assert_eq!(uniform_values(&log.borrow(), "detail"), vec![vec![0.0]]);
+    assert_eq!(uniform_values(&log.borrow(), "pattern"), vec![vec![1.0]]);
+    assert!(uniform_values(&log.borrow(), "unused").is_empty());
+
+    renderer
+      .set_material_param(
+        material,
+        "tint",
+        UniformValue::Vector3(Vector3::new(0.0, 0.0, 1.0)),
+      )
+      .unwrap();
+    renderer.render_scene(root, camera);
+
+    assert_eq!(
+      uniform_values(&log.borrow(), "tint").last(),
+      Some(&vec![0.0, 0.0, 1.0])
+    );
+    assert_eq!(
+      renderer.materials.get(material).unwrap().textures().len(),
+      2
+    );
+  }
+}
@@ -1,32 +1,367 @@
+#extension GL_EXT_shader_texture_lod : enable
+
 uniform vec3 color;
-uniform vec2 uvRepeating;
+uniform float opacity;
+uniform vec3 emissive;
+uniform float emissiveStrength;
+uniform float metallic;
+uniform float roughness;
+uniform float normalScale;
+uniform float occlusionStrength;
+uniform vec3 cameraPosition;
 
 varying vec3 v_position;
+varying vec3 v_worldPosition;
 varying vec3 v_normal;
 varying vec2 v_uv;
 
+#ifdef USE_UV1
+varying vec2 v_uv1;
+#endif
+
+#ifdef USE_VERTEX_COLOR
+varying vec4 v_color;
+#endif
+
+#ifdef USE_NORMAL_MAP
+varying vec3 v_tangent;
+varying vec3 v_bitangent;
+#endif
+
+#ifdef USE_ALPHA_TEST
+uniform float alphaCutoff;
+#endif
+
 #ifdef USE_COLOR_MAP
 uniform sampler2D colorMap;
+uniform mat3 colorMapTransform;
 #endif
 
 #ifdef USE_DEBUG_CUBE_MAP
 uniform samplerCube debugCubeMap;
 #endif
 
+#ifdef USE_OCCLUSION_MAP
+uniform sampler2D occlusionMap;
+uniform mat3 occlusionMapTransform;
+#endif
+
+#ifdef USE_LIGHT_MAP
+uniform sampler2D lightMap;
+uniform mat3 lightMapTransform;
+#endif
+
+#ifdef USE_NORMAL_MAP
+uniform sampler2D normalMap;
+uniform mat3 normalMapTransform;
+#endif
+
+#ifdef USE_METALLIC_ROUGHNESS_MAP
+uniform sampler2D metallicRoughnessMap;
+uniform mat3 metallicRoughnessMapTransform;
+#endif
+
+#ifdef USE_EMISSIVE_MAP
+uniform sampler2D emissiveMap;
+uniform mat3 emissiveMapTransform;
+#endif
+
+#ifdef USE_ENVIRONMENT
+uniform samplerCube irradianceMap;
+uniform samplerCube specularMap;
+uniform sampler2D brdfLut;
+uniform float specularLevels;
+#endif
+
+// <MAP>_UV names the varying each map samples
+vec2 mapUv(mat3 transform, vec2 uv) {
+  return (transform * vec3(uv, 1.0)).xy;
+}
+
+// color and emissive maps are sRGB encoded, lighting and the factors are linear
+vec3 sRGBToLinear(vec3 value) {
+  vec3 low = value / 12.92;
+  vec3 high = pow((value + 0.055) / 1.055, vec3(2.4));
+
+  return mix(low, high, step(vec3(0.04045), value));
+}
+
+#include <lighting>
+#include <shadow>
+
+// directional key light, lightDirection points towards it
+uniform vec3 lightDirection;
+uniform vec3 lightColor;
+
+const vec3 ambientColor = vec3(0.03);
+
+#ifdef USE_DIRECTIONAL_SHADOWS
+varying float v_viewDepth;
+
+uniform int cascadeCount;
+// view depth where each cascade ends
+uniform vec4 cascadeSplits;
+// only the cascades the light has are declared, each sampler takes a texture unit
+uniform mat4 cascadeMatrix0;
+uniform sampler2D cascadeMap0;
+#ifdef USE_CASCADE1
+uniform mat4 cascadeMatrix1;
+uniform sampler2D cascadeMap1;
+#endif
+#ifdef USE_CASCADE2
+uniform mat4 cascadeMatrix2;
+uniform sampler2D cascadeMap2;
+#endif
+#ifdef USE_CASCADE3
+uniform mat4 cascadeMatrix3;
+uniform sampler2D cascadeMap3;
+#endif
+uniform float directionalShadowBias;
+uniform float directionalShadowTexel;
+
+float directionalShadow(float NdotL) {
+  vec4 position = vec4(v_worldPosition, 1.0);
+  float bias = slopeBias(directionalShadowBias, NdotL);
+  float texel = directionalShadowTexel;
+
+  if (cascadeCount > 0 && v_viewDepth < cascadeSplits.x) {
+    return sampleShadow(cascadeMap0, cascadeMatrix0 * position, bias, texel);
+  }
+
+#ifdef USE_CASCADE1
+  if (cascadeCount > 1 && v_viewDepth < cascadeSplits.y) {
+    return sampleShadow(cascadeMap1, cascadeMatrix1 * position, bias, texel);
+  }
+#endif
+
+#ifdef USE_CASCADE2
+  if (cascadeCount > 2 && v_viewDepth < cascadeSplits.z) {
+    return sampleShadow(cascadeMap2, cascadeMatrix2 * position, bias, texel);
+  }
+#endif
+
+#ifdef USE_CASCADE3
+  if (cascadeCount > 3 && v_viewDepth < cascadeSplits.w) {
+    return sampleShadow(cascadeMap3, cascadeMatrix3 * position, bias, texel);
+  }
+#endif
+
+  // past the last cascade
+  return 1.0;
+}
+#endif
+
+#ifdef USE_SPOT_LIGHTS
+uniform int spotLightCount;
+uniform vec3 spotPosition0;
+uniform vec3 spotPosition1;
+// the direction the cone points at
+uniform vec3 spotDirection0;
+uniform vec3 spotDirection1;
+uniform vec3 spotColor0;
+uniform vec3 spotColor1;
+// cosine of the half angle of the cone
+uniform float spotCosAngle0;
+uniform float spotCosAngle1;
+uniform float spotRange0;
+uniform float spotRange1;
+
+// radiance reaching the fragment, l is set to the direction towards the light
+vec3 spotRadiance(vec3 position, vec3 direction, vec3 color, float cosAngle, float range, out vec3 l) {
+  vec3 toLight = position - v_worldPosition;
+  float lightDistance = length(toLight);
+
+  l = toLight / lightDistance;
+
+  float cone = smoothstep(cosAngle, mix(cosAngle, 1.0, 0.1), dot(-l, normalize(direction)));
+  float window = clamp(1.0 - pow(lightDistance / range, 4.0), 0.0, 1.0);
+
+  return color * cone * window * window / (lightDistance * lightDistance + 1.0);
+}
+#endif
+
+// only spot lights casting shadows declare a shadow map
+#ifdef USE_SPOT_SHADOW0
+uniform mat4 spotShadowMatrix0;
+uniform sampler2D spotShadowMap0;
+uniform float spotShadowBias0;
+uniform float spotShadowTexel0;
+#endif
+
+#ifdef USE_SPOT_SHADOW1
+uniform mat4 spotShadowMatrix1;
+uniform sampler2D spotShadowMap1;
+uniform float spotShadowBias1;
+uniform float spotShadowTexel1;
+#endif
+
+// cook-torrance specular plus lambert diffuse for light arriving along l
+vec3 directLight(
+  vec3 l,
+  vec3 radiance,
+  vec3 normal,
+  vec3 v,
+  vec3 albedo,
+  vec3 f0,
+  float metalness,
+  float perceptualRoughness
+) {
+  vec3 h = normalize(l + v);
+
+  float NdotL = max(dot(normal, l), 0.0);
+  float NdotV = max(dot(normal, v), 1e-4);
+  float NdotH = max(dot(normal, h), 0.0);
+  float VdotH = max(dot(v, h), 0.0);
+
+  vec3 F = fresnelSchlick(VdotH, f0);
+  float D = distributionGGX(NdotH, perceptualRoughness * perceptualRoughness);
+  float k = (perceptualRoughness + 1.0) * (perceptualRoughness + 1.0) / 8.0;
+  float G = geometrySmith(NdotV, NdotL, k);
+
+  vec3 specular = D * G * F / max(4.0 * NdotV * NdotL, 1e-4);
+  vec3 diffuseColor = (1.0 - F) * (1.0 - metalness) * albedo / PI;
+
+  return (diffuseColor + specular) * radiance * NdotL;
+}
+
 void main() {
   vec3 normal = normalize(v_normal);
 
+#ifdef USE_NORMAL_MAP
+  // geometry without tangents keeps the vertex normal
+  if (dot(v_tangent, v_tangent) > 1e-8) {
+    mat3 tbn = mat3(normalize(v_tangent), normalize(v_bitangent), normal);
+    vec3 normalSample = texture2D(normalMap, mapUv(normalMapTransform, NORMAL_MAP_UV)).rgb;
+    normal = normalize(tbn * ((normalSample * 2.0 - 1.0) * vec3(normalScale, normalScale, 1.0)));
+  }
+#endif
+
   vec3 albedo = color;
+  float alpha = opacity;
 
 #ifdef USE_COLOR_MAP
-  albedo = texture2D(colorMap, v_uv * uvRepeating).rgb;
+  vec4 colorSample = texture2D(colorMap, mapUv(colorMapTransform, COLOR_MAP_UV));
+  albedo *= sRGBToLinear(colorSample.rgb);
+  alpha *= colorSample.a;
+#endif
+
+#ifdef USE_VERTEX_COLOR
+  albedo *= v_color.rgb;
+  alpha *= v_color.a;
+#endif
+
+#ifdef USE_ALPHA_TEST
+  if (alpha < alphaCutoff) {
+    discard;
+  }
+#endif
+
+// unlit materials treat the albedo as baked lighting
+#ifdef USE_UNLIT
+  vec3 direct = vec3(0.0);
+  vec3 indirect = albedo;
+#else
+  float metalness = metallic;
+  float perceptualRoughness = roughness;
+
+#ifdef USE_METALLIC_ROUGHNESS_MAP
+  // glTF packs roughness in green and metalness in blue
+  vec2 metallicRoughnessUv = mapUv(metallicRoughnessMapTransform, METALLIC_ROUGHNESS_MAP_UV);
+  vec4 metallicRoughness = texture2D(metallicRoughnessMap, metallicRoughnessUv);
+  perceptualRoughness *= metallicRoughness.g;
+  metalness *= metallicRoughness.b;
+#endif
+
+  metalness = clamp(metalness, 0.0, 1.0);
+  perceptualRoughness = clamp(perceptualRoughness, 0.04, 1.0);
+
+  vec3 v = normalize(cameraPosition - v_worldPosition);
+  vec3 l = normalize(lightDirection);
+  vec4 worldPosition = vec4(v_worldPosition, 1.0);
+
+  float NdotV = max(dot(normal, v), 1e-4);
+
+  vec3 f0 = mix(vec3(0.04), albedo, metalness);
+  vec3 radiance = lightColor;
+
+#ifdef USE_DIRECTIONAL_SHADOWS
+  radiance *= directionalShadow(max(dot(normal, l), 0.0));
 #endif
 
-  vec3 diffuse = dot(vec3(0.0, 1.0, 0.0), normal) * albedo;
+  vec3 direct = directLight(l, radiance, normal, v, albedo, f0, metalness, perceptualRoughness);
+
+#ifdef USE_SPOT_LIGHTS
+  vec3 spotL;
+
+  if (spotLightCount > 0) {
+    radiance = spotRadiance(spotPosition0, spotDirection0, spotColor0, spotCosAngle0, spotRange0, spotL);
+
+#ifdef USE_SPOT_SHADOW0
+    float bias0 = slopeBias(spotShadowBias0, max(dot(normal, spotL), 0.0));
+    radiance *= sampleShadow(spotShadowMap0, spotShadowMatrix0 * worldPosition, bias0, spotShadowTexel0);
+#endif
+
+    direct += directLight(spotL, radiance, normal, v, albedo, f0, metalness, perceptualRoughness);
+  }
+
+  if (spotLightCount > 1) {
+    radiance = spotRadiance(spotPosition1, spotDirection1, spotColor1, spotCosAngle1, spotRange1, spotL);
+
+#ifdef USE_SPOT_SHADOW1
+    float bias1 = slopeBias(spotShadowBias1, max(dot(normal, spotL), 0.0));
+    radiance *= sampleShadow(spotShadowMap1, spotShadowMatrix1 * worldPosition, bias1, spotShadowTexel1);
+#endif
+
+    direct += directLight(spotL, radiance, normal, v, albedo, f0, metalness, perceptualRoughness);
+  }
+#endif
+
+#ifdef USE_ENVIRONMENT
+  vec3 kS = fresnelSchlickRoughness(NdotV, f0, perceptualRoughness);
+  vec3 kD = (1.0 - kS) * (1.0 - metalness);
+  vec3 irradiance = textureCube(irradianceMap, normal).rgb;
+  float specularLod = perceptualRoughness * (specularLevels - 1.0);
+#ifdef GL_EXT_shader_texture_lod
+  vec3 prefiltered = textureCubeLodEXT(specularMap, reflect(-v, normal), specularLod).rgb;
+#else
+  // without the extension the lod is a bias on the implicit lod, which stays close to 0
+  // for a reflection lookup so it lands near the wanted level
+  vec3 prefiltered = textureCube(specularMap, reflect(-v, normal), specularLod).rgb;
+#endif
+  vec2 brdf = texture2D(brdfLut, vec2(NdotV, perceptualRoughness)).rg;
+  vec3 indirect = kD * irradiance * albedo + prefiltered * (kS * brdf.x + brdf.y);
+#else
+  vec3 indirect = ambientColor * albedo;
+#endif
+#endif
+
+#ifdef USE_LIGHT_MAP
+  indirect += texture2D(lightMap, mapUv(lightMapTransform, LIGHT_MAP_UV)).rgb * albedo;
+#endif
+
+#ifdef USE_OCCLUSION_MAP
+  float occlusion = texture2D(occlusionMap, mapUv(occlusionMapTransform, OCCLUSION_MAP_UV)).r;
+  indirect *= mix(1.0, occlusion, occlusionStrength);
+#endif
+
+  vec3 diffuse = direct + indirect;
 
 #ifdef USE_DEBUG_CUBE_MAP
   diffuse = textureCube(debugCubeMap, normalize(v_position)).rgb;
 #endif
 
-  gl_FragColor = vec4(diffuse, 1.0);
+  vec3 emissiveColor = emissive * emissiveStrength;
+
+#ifdef USE_EMISSIVE_MAP
+  emissiveColor *=
+    sRGBToLinear(texture2D(emissiveMap, mapUv(emissiveMapTransform, EMISSIVE_MAP_UV)).rgb);
+#endif
+
+  diffuse += emissiveColor;
+
+#ifdef PREMULTIPLIED_ALPHA
+  diffuse *= alpha;
+#endif
+
+  gl_FragColor = vec4(diffuse, alpha);
 }
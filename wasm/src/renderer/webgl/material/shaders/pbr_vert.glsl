@@ -2,18 +2,99 @@ attribute vec3 position;
 attribute vec3 normal;
 attribute vec2 uv;
 
+#ifdef USE_UV1
+attribute vec2 uv1;
+#endif
+
+#ifdef USE_VERTEX_COLOR
+attribute vec4 color;
+#endif
+
+#ifdef USE_NORMAL_MAP
+attribute vec4 tangent;
+#endif
+
+#ifdef USE_INSTANCING
+attribute vec3 instanceTranslation;
+attribute vec4 instanceRotation;
+attribute vec3 instanceScale;
+
+vec3 rotateByQuat(vec4 q, vec3 v) {
+  return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+#endif
+
 uniform mat4 projectionMatrix;
 uniform mat4 viewMatrix;
 uniform mat4 modelMatrix;
 uniform mat3 normalMatrix;
 
 varying vec3 v_position;
+varying vec3 v_worldPosition;
 varying vec3 v_normal;
 varying vec2 v_uv;
 
+#ifdef USE_UV1
+varying vec2 v_uv1;
+#endif
+
+#ifdef USE_VERTEX_COLOR
+varying vec4 v_color;
+#endif
+
+#ifdef USE_NORMAL_MAP
+varying vec3 v_tangent;
+varying vec3 v_bitangent;
+#endif
+
+#ifdef USE_DIRECTIONAL_SHADOWS
+// picks the cascade
+varying float v_viewDepth;
+#endif
+
 void main() {
-  gl_Position = projectionMatrix * viewMatrix * modelMatrix * vec4(position, 1.0);
-  v_normal = normalMatrix * normal;
-  v_position = position;
+  vec3 localPosition = position;
+  vec3 localNormal = normal;
+
+#ifdef USE_NORMAL_MAP
+  vec3 localTangent = tangent.xyz;
+#endif
+
+#ifdef USE_INSTANCING
+  localPosition = rotateByQuat(instanceRotation, position * instanceScale) + instanceTranslation;
+  localNormal = rotateByQuat(instanceRotation, normal / instanceScale);
+
+#ifdef USE_NORMAL_MAP
+  localTangent = rotateByQuat(instanceRotation, tangent.xyz * instanceScale);
+#endif
+#endif
+
+  vec4 worldPosition = modelMatrix * vec4(localPosition, 1.0);
+
+  vec4 viewPosition = viewMatrix * worldPosition;
+
+  gl_Position = projectionMatrix * viewPosition;
+  v_worldPosition = worldPosition.xyz;
+  v_normal = normalMatrix * localNormal;
+  v_position = localPosition;
   v_uv = uv;
+
+#ifdef USE_UV1
+  v_uv1 = uv1;
+#endif
+
+#ifdef USE_VERTEX_COLOR
+  v_color = color;
+#endif
+
+#ifdef USE_NORMAL_MAP
+  // tangents of geometry without any read as zero and stay zero
+  vec3 worldTangent = mat3(modelMatrix) * localTangent;
+  v_tangent = dot(worldTangent, worldTangent) > 0.0 ? normalize(worldTangent) : vec3(0.0);
+  v_bitangent = cross(normalize(v_normal), v_tangent) * tangent.w;
+#endif
+
+#ifdef USE_DIRECTIONAL_SHADOWS
+  v_viewDepth = -viewPosition.z;
+#endif
 }
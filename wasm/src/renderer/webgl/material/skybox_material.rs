@@ -1,21 +1,20 @@
-use generational_arena::Index;
 use na::Matrix4;
 
-use anyhow::Result;
-
-use super::material::{bind_several_maps, Material, MaterialParams};
-use crate::renderer::webgl::context::{Context, DepthFunc, DrawMode, TextureKind};
-use crate::renderer::webgl::renderer::{Camera, Images, Samplers, Textures};
+use super::material::{bind_several_maps, BlendMode, Material, MaterialParams, RenderContext};
+use crate::handle::Handle;
+use crate::renderer::webgl::context::{DepthFunc, TextureKind};
+use crate::renderer::webgl::render_queue::RenderQueue;
+use crate::renderer::webgl::renderer::Texture;
 use crate::renderer::webgl::shader::Shader;
-use crate::scene::node::Node;
+use crate::renderer::webgl::shader_cache::ShaderVariant;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SkyboxMaterial {
-  skybox: Index,
+  skybox: Handle<Texture>,
 }
 
 impl SkyboxMaterial {
-  pub fn new(skybox: Index) -> Self {
+  pub fn new(skybox: Handle<Texture>) -> Self {
     SkyboxMaterial { skybox }
   }
 
@@ -25,34 +24,22 @@ impl SkyboxMaterial {
 }
 
 impl Material for SkyboxMaterial {
-  fn get_tag(&self) -> String {
-    String::from("skybox")
-  }
-
-  fn create_shader(&self, ctx: &Context) -> Result<Shader> {
-    let vert_src = include_str!("./shaders/skybox_vert.glsl");
-    let frag_src = include_str!("./shaders/skybox_frag.glsl");
-
-    ctx.create_shader(vert_src, frag_src, &[])
+  fn shader_variant(&self) -> ShaderVariant<'_> {
+    ShaderVariant::new(
+      include_str!("./shaders/skybox_vert.glsl"),
+      include_str!("./shaders/skybox_frag.glsl"),
+      vec![],
+    )
   }
 
-  fn setup_shader(
-    &self,
-    ctx: &Context,
-    images: &Images,
-    textures: &Textures,
-    samplers: &Samplers,
-    shader: &Shader,
-    _node: &Node,
-    camera: &Camera,
-  ) {
-    let mut view_without_translation = camera.view;
+  fn setup_shader(&self, shader: &Shader, rc: &RenderContext) {
+    let mut view_without_translation = rc.camera.view;
 
     view_without_translation[12] = 0.0;
     view_without_translation[13] = 0.0;
     view_without_translation[14] = 0.0;
 
-    let view_direction_projection_inverse = (camera.projection * view_without_translation)
+    let view_direction_projection_inverse = (rc.camera.projection * view_without_translation)
       .try_inverse()
       .unwrap_or_else(|| Matrix4::identity());
 
@@ -62,12 +49,10 @@ impl Material for SkyboxMaterial {
     );
 
     bind_several_maps(
-      ctx,
-      images,
-      textures,
-      samplers,
       shader,
+      rc,
       &[(Some(self.skybox), TextureKind::CubeMap, "skybox")],
+      0,
     );
   }
 
@@ -76,7 +61,19 @@ impl Material for SkyboxMaterial {
       cull_face: true,
       depth_test: true,
       depth_func: DepthFunc::Lequal,
-      draw_mode: DrawMode::Triangles,
+      queue: RenderQueue::Background,
+      blend: BlendMode::Opaque,
+      depth_write: true,
+      alpha_to_coverage: false,
+      alpha_cutoff: None,
     }
   }
+
+  fn textures(&self) -> Vec<Handle<Texture>> {
+    vec![self.skybox]
+  }
+
+  fn instance(&self) -> Option<Box<dyn Material>> {
+    Some(self.clone().boxed())
+  }
 }
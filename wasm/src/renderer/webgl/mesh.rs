@@ -1,12 +1,17 @@
-use generational_arena::Index;
-use log::info;
+use log::{info, warn};
 use na::{Point2, Point3, Vector3};
 use ncollide3d::procedural::{IndexBuffer, TriMesh};
 use ncollide3d::shape::{Ball, Cuboid};
 use ncollide3d::transformation::ToTriMesh;
+use serde_json::Value;
+use std::collections::HashMap;
 use std::slice;
 
-use super::context::{BufferItem, BufferTarget, BufferUsage};
+use crate::handle::Handle;
+use crate::scene::node::{Node, UserData};
+
+use super::context::{BufferItem, BufferTarget, BufferUsage, DrawMode};
+use super::material::Material;
 use super::renderer::{Accessor, Attributes, Geometry, Mesh, Primitive, Renderer};
 use super::shader::{AttributeName, AttributeOptions};
 
@@ -17,7 +22,7 @@ impl Renderer {
     data: &[T],
     item_size: i32,
     count: i32,
-  ) -> Index {
+  ) -> Handle<Accessor> {
     let buffer = self.insert_buffer(target, BufferUsage::StaticDraw, data);
     let attribute_options = AttributeOptions::new(T::array_kind(), item_size);
     let accessor = Accessor {
@@ -29,7 +34,7 @@ impl Renderer {
     self.insert_accessor(accessor)
   }
 
-  pub fn bake_tri_mesh_geometry(&mut self, mut tri_mesh: TriMesh<f32>) -> Index {
+  pub fn bake_tri_mesh_geometry(&mut self, mut tri_mesh: TriMesh<f32>) -> Handle<Geometry> {
     tri_mesh.unify_index_buffer();
 
     let mut attributes = Attributes::new();
@@ -81,28 +86,128 @@ impl Renderer {
     self.insert_geometry(Geometry {
       attributes,
       indices: Some(indices),
+      draw_mode: DrawMode::Triangles,
     })
   }
 
-  pub fn compose_mesh(&mut self, geometry: Index, material: Index, name: Option<String>) -> Index {
+  pub fn compose_mesh(
+    &mut self,
+    geometry: Handle<Geometry>,
+    material: Handle<dyn Material>,
+    name: Option<String>,
+  ) -> Handle<Mesh> {
+    let requires_tangents = matches!(
+      self.materials.get(material),
+      Some(material) if material.requires_tangents()
+    );
+
+    if requires_tangents && self.ensure_tangents(geometry).is_none() {
+      warn!("unable to generate tangents for {:?}", name);
+    }
+
     let primitive = Primitive {
       geometry,
       material: Some(material),
+      default_material: Some(material),
+      variants: HashMap::new(),
     };
 
     self.insert_mesh(Mesh {
       primitives: vec![primitive],
       name,
+      user_data: UserData::new(),
+    })
+  }
+
+  pub fn get_material_variants(&self, root_handle: Handle<Node>) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+
+    for node_handle in self.scene.collect_sub_items(root_handle) {
+      let node = self.scene.get_node(node_handle).unwrap();
+      let mesh = match node.mesh.and_then(|mesh| self.meshes.get(mesh)) {
+        Some(mesh) => mesh,
+        None => continue,
+      };
+
+      for primitive in &mesh.primitives {
+        names.extend(primitive.variants.keys().cloned());
+      }
+    }
+
+    names.sort();
+    names.dedup();
+
+    names
+  }
+
+  // switches every primitive under root_handle to the material mapped for the variant,
+  // primitives without a mapping (or None) go back to their default material
+  pub fn select_material_variant(&mut self, root_handle: Handle<Node>, variant: Option<&str>) {
+    for node_handle in self.scene.collect_sub_items(root_handle) {
+      let mesh_handle = self.scene.get_node(node_handle).unwrap().mesh.unwrap();
+      let mesh = match self.meshes.get_mut(mesh_handle) {
+        Some(mesh) => mesh,
+        None => continue,
+      };
+
+      for primitive in &mut mesh.primitives {
+        primitive.material = variant
+          .and_then(|name| primitive.variants.get(name).copied())
+          .or(primitive.default_material);
+      }
+    }
+  }
+
+  pub fn get_mesh_user_data(&self, mesh_handle: Handle<Mesh>, key: &str) -> Option<&Value> {
+    self.meshes.get(mesh_handle)?.user_data.get(key)
+  }
+
+  pub fn get_material_user_data(
+    &self,
+    material_handle: Handle<dyn Material>,
+    key: &str,
+  ) -> Option<&Value> {
+    self.materials.get(material_handle)?.user_data()?.get(key)
+  }
+
+  // nodes under parent_handle whose mesh has key in its user data
+  pub fn find_by_mesh_user_data(
+    &self,
+    parent_handle: Handle<Node>,
+    key: &str,
+  ) -> Vec<Handle<Node>> {
+    self.scene.find_sub_items(
+      parent_handle,
+      |node| matches!(node.mesh, Some(mesh) if self.get_mesh_user_data(mesh, key).is_some()),
+    )
+  }
+
+  // nodes under parent_handle drawing a primitive whose current material has key in its
+  // user data
+  pub fn find_by_material_user_data(
+    &self,
+    parent_handle: Handle<Node>,
+    key: &str,
+  ) -> Vec<Handle<Node>> {
+    self.scene.find_sub_items(parent_handle, |node| {
+      let mesh = match node.mesh.and_then(|mesh| self.meshes.get(mesh)) {
+        Some(mesh) => mesh,
+        None => return false,
+      };
+
+      mesh.primitives.iter().any(|primitive| {
+        matches!(primitive.material, Some(material) if self.get_material_user_data(material, key).is_some())
+      })
     })
   }
 
-  pub fn bake_cuboid_geometry(&mut self, half_extents: Vector3<f32>) -> Index {
+  pub fn bake_cuboid_geometry(&mut self, half_extents: Vector3<f32>) -> Handle<Geometry> {
     let cuboid: TriMesh<f32> = Cuboid::new(half_extents).to_trimesh(());
 
     self.bake_tri_mesh_geometry(cuboid)
   }
 
-  pub fn bake_ball_geometry(&mut self, radius: f32) -> Index {
+  pub fn bake_ball_geometry(&mut self, radius: f32) -> Handle<Geometry> {
     let ball: TriMesh<f32> = Ball::new(radius).to_trimesh((32, 32));
 
     self.bake_tri_mesh_geometry(ball)
@@ -0,0 +1,810 @@
+use anyhow::{anyhow, bail, Result};
+
+// Decoders for the EXT_meshopt_compression bitstreams,
+// see https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Vendor/EXT_meshopt_compression
+
+const VERTEX_HEADER: u8 = 0xa0;
+const INDEX_HEADER: u8 = 0xe0;
+const SEQUENCE_HEADER: u8 = 0xd0;
+
+const VERTEX_BLOCK_SIZE_BYTES: usize = 8192;
+const VERTEX_BLOCK_MAX_SIZE: usize = 256;
+const BYTE_GROUP_SIZE: usize = 16;
+const TAIL_MAX_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeshoptMode {
+  Attributes,
+  Triangles,
+  Indices,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeshoptFilter {
+  None,
+  Octahedral,
+  Quaternion,
+  Exponential,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshoptView {
+  pub buffer: usize,
+  pub byte_offset: usize,
+  pub byte_length: usize,
+  pub byte_stride: usize,
+  pub count: usize,
+  pub mode: MeshoptMode,
+  pub filter: MeshoptFilter,
+}
+
+impl MeshoptMode {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "ATTRIBUTES" => Some(Self::Attributes),
+      "TRIANGLES" => Some(Self::Triangles),
+      "INDICES" => Some(Self::Indices),
+      _ => None,
+    }
+  }
+}
+
+impl MeshoptFilter {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "NONE" => Some(Self::None),
+      "OCTAHEDRAL" => Some(Self::Octahedral),
+      "QUATERNION" => Some(Self::Quaternion),
+      "EXPONENTIAL" => Some(Self::Exponential),
+      _ => None,
+    }
+  }
+}
+
+pub fn decode_meshopt(
+  data: &[u8],
+  count: usize,
+  stride: usize,
+  mode: MeshoptMode,
+  filter: MeshoptFilter,
+) -> Result<Vec<u8>> {
+  let mut decoded = match mode {
+    MeshoptMode::Attributes => decode_vertex_buffer(data, count, stride)?,
+    MeshoptMode::Triangles => decode_index_buffer(data, count, stride)?,
+    MeshoptMode::Indices => decode_index_sequence(data, count, stride)?,
+  };
+
+  if filter != MeshoptFilter::None {
+    if mode != MeshoptMode::Attributes {
+      bail!("filter {:?} is only allowed for attributes", filter);
+    }
+
+    decode_filter(&mut decoded, count, stride, filter)?;
+  }
+
+  Ok(decoded)
+}
+
+// bytes taken by count items, counts come from the asset and may overflow
+fn output_size(count: usize, item_size: usize) -> Result<usize> {
+  count
+    .checked_mul(item_size)
+    .ok_or_else(|| anyhow!("{} items of {} bytes overflow", count, item_size))
+}
+
+fn unzigzag8(v: u8) -> u8 {
+  (0u8.wrapping_sub(v & 1)) ^ (v >> 1)
+}
+
+fn decode_bytes_group(data: &[u8], pos: usize, out: &mut [u8], bitslog2: u8) -> Result<usize> {
+  let truncated = || anyhow!("vertex data is truncated");
+
+  match bitslog2 {
+    0 => {
+      out.iter_mut().for_each(|b| *b = 0);
+
+      Ok(pos)
+    }
+    1 | 2 => {
+      let bits = 1usize << bitslog2;
+      let sentinel = ((1usize << bits) - 1) as u8;
+      let mut var = pos + BYTE_GROUP_SIZE * bits / 8;
+
+      for (i, item) in out.iter_mut().enumerate() {
+        let byte = *data.get(pos + i * bits / 8).ok_or_else(truncated)?;
+        let shift = 8 - bits - (i * bits) % 8;
+        let enc = (byte >> shift) & sentinel;
+
+        *item = if enc == sentinel {
+          var += 1;
+          *data.get(var - 1).ok_or_else(truncated)?
+        } else {
+          enc
+        };
+      }
+
+      Ok(var)
+    }
+    _ => {
+      out.copy_from_slice(data.get(pos..pos + BYTE_GROUP_SIZE).ok_or_else(truncated)?);
+
+      Ok(pos + BYTE_GROUP_SIZE)
+    }
+  }
+}
+
+fn decode_bytes(data: &[u8], mut pos: usize, buffer: &mut [u8]) -> Result<usize> {
+  let groups = buffer.len() / BYTE_GROUP_SIZE;
+  let header_size = (groups + 3) / 4;
+
+  if data.len() - pos < header_size {
+    bail!("vertex data is truncated");
+  }
+
+  let header = pos;
+
+  pos += header_size;
+
+  for (g, group) in buffer.chunks_exact_mut(BYTE_GROUP_SIZE).enumerate() {
+    if data.len() - pos < TAIL_MAX_SIZE {
+      bail!("vertex data is truncated");
+    }
+
+    let bitslog2 = (data[header + g / 4] >> ((g % 4) * 2)) & 3;
+
+    pos = decode_bytes_group(data, pos, group, bitslog2)?;
+  }
+
+  Ok(pos)
+}
+
+pub fn decode_vertex_buffer(data: &[u8], count: usize, stride: usize) -> Result<Vec<u8>> {
+  if stride == 0 || stride > 256 || stride % 4 != 0 {
+    bail!("vertex stride {} must be a multiple of 4 up to 256", stride);
+  }
+
+  if data.len() < 1 + stride {
+    bail!("vertex data is truncated");
+  }
+
+  if data[0] != VERTEX_HEADER {
+    bail!("unsupported vertex codec header {:#x}", data[0]);
+  }
+
+  let mut result = vec![0u8; output_size(count, stride)?];
+  let mut last_vertex = data[data.len() - stride..].to_vec();
+  let mut buffer = [0u8; VERTEX_BLOCK_MAX_SIZE];
+
+  let block_size =
+    ((VERTEX_BLOCK_SIZE_BYTES / stride) & !(BYTE_GROUP_SIZE - 1)).min(VERTEX_BLOCK_MAX_SIZE);
+
+  let mut pos = 1;
+  let mut offset = 0;
+
+  while offset < count {
+    let size = block_size.min(count - offset);
+    let aligned = (size + BYTE_GROUP_SIZE - 1) & !(BYTE_GROUP_SIZE - 1);
+
+    for (k, last) in last_vertex.iter_mut().enumerate() {
+      pos = decode_bytes(data, pos, &mut buffer[..aligned])?;
+
+      let mut p = *last;
+
+      for (i, delta) in buffer[..size].iter().enumerate() {
+        p = unzigzag8(*delta).wrapping_add(p);
+        result[(offset + i) * stride + k] = p;
+      }
+
+      *last = p;
+    }
+
+    offset += size;
+  }
+
+  if data.len() - pos != stride.max(TAIL_MAX_SIZE) {
+    bail!(
+      "vertex data has {} unexpected trailing bytes",
+      data.len() - pos
+    );
+  }
+
+  Ok(result)
+}
+
+fn write_index(out: &mut [u8], i: usize, index_size: usize, value: u32) {
+  if index_size == 2 {
+    out[i * 2..i * 2 + 2].copy_from_slice(&(value as u16).to_le_bytes());
+  } else {
+    out[i * 4..i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+  }
+}
+
+fn check_index_size(index_size: usize) -> Result<()> {
+  if index_size != 2 && index_size != 4 {
+    bail!("index size {} must be 2 or 4", index_size);
+  }
+
+  Ok(())
+}
+
+// ring buffer of the 16 most recent vertices or edges
+struct Fifo<T> {
+  items: [T; 16],
+  offset: usize,
+}
+
+impl<T: Copy> Fifo<T> {
+  fn new(empty: T) -> Self {
+    Fifo {
+      items: [empty; 16],
+      offset: 0,
+    }
+  }
+
+  fn get(&self, back: usize) -> T {
+    self.items[self.offset.wrapping_sub(back) & 15]
+  }
+
+  fn push(&mut self, item: T, advance: bool) {
+    self.items[self.offset] = item;
+    self.offset = (self.offset + advance as usize) & 15;
+  }
+}
+
+fn decode_vbyte(data: &[u8], pos: &mut usize) -> Result<u32> {
+  let mut read = || -> Result<u8> {
+    let byte = *data
+      .get(*pos)
+      .ok_or_else(|| anyhow!("index data is truncated"))?;
+    *pos += 1;
+    Ok(byte)
+  };
+
+  let lead = read()?;
+
+  if lead < 128 {
+    return Ok(lead as u32);
+  }
+
+  let mut result = (lead & 127) as u32;
+  let mut shift = 7;
+
+  for _ in 0..4 {
+    let group = read()?;
+    result |= ((group & 127) as u32) << shift;
+    shift += 7;
+
+    if group < 128 {
+      break;
+    }
+  }
+
+  Ok(result)
+}
+
+fn decode_index(data: &[u8], pos: &mut usize, last: u32) -> Result<u32> {
+  let v = decode_vbyte(data, pos)?;
+  let d = (v >> 1) ^ 0u32.wrapping_sub(v & 1);
+
+  Ok(last.wrapping_add(d))
+}
+
+pub fn decode_index_buffer(data: &[u8], count: usize, index_size: usize) -> Result<Vec<u8>> {
+  check_index_size(index_size)?;
+
+  if count % 3 != 0 {
+    bail!("triangle index count {} is not a multiple of 3", count);
+  }
+
+  if data.len() < 1 + count / 3 + 16 {
+    bail!("index data is truncated");
+  }
+
+  if data[0] & 0xf0 != INDEX_HEADER || data[0] & 0x0f > 1 {
+    bail!("unsupported index codec header {:#x}", data[0]);
+  }
+
+  let version = data[0] & 0x0f;
+  // version 1 uses fec 13/14 to encode the last index -1/+1
+  let fecmax = if version >= 1 { 13 } else { 15 };
+
+  let mut result = vec![0u8; output_size(count, index_size)?];
+
+  let mut edge_fifo = Fifo::new([u32::MAX; 2]);
+  let mut vertex_fifo = Fifo::new(u32::MAX);
+
+  let mut next = 0u32;
+  let mut last = 0u32;
+
+  let mut pos = 1 + count / 3;
+  let safe_end = data.len() - 16;
+  let codeaux_table = &data[safe_end..];
+
+  for i in (0..count).step_by(3) {
+    if pos > safe_end {
+      bail!("index data is truncated");
+    }
+
+    let codetri = data[1 + i / 3];
+
+    let [a, b, c] = if codetri < 0xf0 {
+      let fe = (codetri >> 4) as usize;
+      let [a, b] = edge_fifo.get(1 + fe);
+      let fec = (codetri & 15) as usize;
+
+      if fec < fecmax {
+        let c = if fec == 0 {
+          next
+        } else {
+          vertex_fifo.get(1 + fec)
+        };
+
+        if fec == 0 {
+          next += 1;
+        }
+
+        vertex_fifo.push(c, fec == 0);
+        edge_fifo.push([c, b], true);
+        edge_fifo.push([a, c], true);
+
+        [a, b, c]
+      } else {
+        let c = if fec != 15 {
+          // 13 => -1, 14 => +1
+          last.wrapping_add((fec as u32).wrapping_sub(fec as u32 ^ 3))
+        } else {
+          decode_index(data, &mut pos, last)?
+        };
+
+        last = c;
+
+        vertex_fifo.push(c, true);
+        edge_fifo.push([c, b], true);
+        edge_fifo.push([a, c], true);
+
+        [a, b, c]
+      }
+    } else if codetri < 0xfe {
+      let codeaux = codeaux_table[(codetri & 15) as usize];
+      let feb = (codeaux >> 4) as usize;
+      let fec = (codeaux & 15) as usize;
+
+      let a = next;
+      next += 1;
+
+      let b = if feb == 0 { next } else { vertex_fifo.get(feb) };
+
+      if feb == 0 {
+        next += 1;
+      }
+
+      let c = if fec == 0 { next } else { vertex_fifo.get(fec) };
+
+      if fec == 0 {
+        next += 1;
+      }
+
+      vertex_fifo.push(a, true);
+      vertex_fifo.push(b, feb == 0);
+      vertex_fifo.push(c, fec == 0);
+      edge_fifo.push([b, a], true);
+      edge_fifo.push([c, b], true);
+      edge_fifo.push([a, c], true);
+
+      [a, b, c]
+    } else {
+      let codeaux = *data
+        .get(pos)
+        .ok_or_else(|| anyhow!("index data is truncated"))?;
+      pos += 1;
+
+      let fea = if codetri == 0xfe { 0 } else { 15 };
+      let feb = (codeaux >> 4) as usize;
+      let fec = (codeaux & 15) as usize;
+
+      // codeaux 0 outside of the table resets the next index
+      if codeaux == 0 {
+        next = 0;
+      }
+
+      let mut a = if fea == 0 {
+        next += 1;
+        next - 1
+      } else {
+        0
+      };
+      let mut b = if feb == 0 {
+        next += 1;
+        next - 1
+      } else {
+        vertex_fifo.get(feb)
+      };
+      let mut c = if fec == 0 {
+        next += 1;
+        next - 1
+      } else {
+        vertex_fifo.get(fec)
+      };
+
+      if fea == 15 {
+        a = decode_index(data, &mut pos, last)?;
+        last = a;
+      }
+
+      if feb == 15 {
+        b = decode_index(data, &mut pos, last)?;
+        last = b;
+      }
+
+      if fec == 15 {
+        c = decode_index(data, &mut pos, last)?;
+        last = c;
+      }
+
+      vertex_fifo.push(a, true);
+      vertex_fifo.push(b, feb == 0 || feb == 15);
+      vertex_fifo.push(c, fec == 0 || fec == 15);
+      edge_fifo.push([b, a], true);
+      edge_fifo.push([c, b], true);
+      edge_fifo.push([a, c], true);
+
+      [a, b, c]
+    };
+
+    write_index(&mut result, i, index_size, a);
+    write_index(&mut result, i + 1, index_size, b);
+    write_index(&mut result, i + 2, index_size, c);
+  }
+
+  if pos != safe_end {
+    bail!("index data has unexpected trailing bytes");
+  }
+
+  Ok(result)
+}
+
+pub fn decode_index_sequence(data: &[u8], count: usize, index_size: usize) -> Result<Vec<u8>> {
+  check_index_size(index_size)?;
+
+  if data.len() < 1 + count + 4 {
+    bail!("index data is truncated");
+  }
+
+  if data[0] & 0xf0 != SEQUENCE_HEADER || data[0] & 0x0f > 1 {
+    bail!("unsupported index sequence header {:#x}", data[0]);
+  }
+
+  let mut result = vec![0u8; output_size(count, index_size)?];
+  let mut last = [0u32; 2];
+  let mut pos = 1;
+  let safe_end = data.len() - 4;
+
+  for i in 0..count {
+    if pos >= safe_end {
+      bail!("index data is truncated");
+    }
+
+    let v = decode_vbyte(data, &mut pos)?;
+
+    // the lowest bit picks one of two baselines, the rest is a zigzag delta
+    let baseline = (v & 1) as usize;
+    let v = v >> 1;
+    let d = (v >> 1) ^ 0u32.wrapping_sub(v & 1);
+    let index = last[baseline].wrapping_add(d);
+
+    last[baseline] = index;
+
+    write_index(&mut result, i, index_size, index);
+  }
+
+  if pos != safe_end {
+    bail!("index data has unexpected trailing bytes");
+  }
+
+  Ok(result)
+}
+
+fn round_to_int(v: f32) -> i32 {
+  (v + if v >= 0.0 { 0.5 } else { -0.5 }) as i32
+}
+
+pub fn decode_filter(
+  data: &mut [u8],
+  count: usize,
+  stride: usize,
+  filter: MeshoptFilter,
+) -> Result<()> {
+  if data.len() < output_size(count, stride)? {
+    bail!("filtered data is truncated");
+  }
+
+  match filter {
+    MeshoptFilter::None => {}
+    MeshoptFilter::Octahedral => match stride {
+      4 => data[..count * 4].chunks_exact_mut(4).for_each(|item| {
+        let v = [item[0] as i8, item[1] as i8, item[2] as i8];
+        let [x, y, z] = decode_octahedral([v[0] as f32, v[1] as f32, v[2] as f32], 127.0);
+
+        item[0] = x as i8 as u8;
+        item[1] = y as i8 as u8;
+        item[2] = z as i8 as u8;
+      }),
+      8 => data[..count * 8].chunks_exact_mut(8).for_each(|item| {
+        let v = [
+          i16::from_le_bytes([item[0], item[1]]),
+          i16::from_le_bytes([item[2], item[3]]),
+          i16::from_le_bytes([item[4], item[5]]),
+        ];
+        let decoded = decode_octahedral([v[0] as f32, v[1] as f32, v[2] as f32], 32767.0);
+
+        for (c, value) in decoded.iter().enumerate() {
+          item[c * 2..c * 2 + 2].copy_from_slice(&(*value as i16).to_le_bytes());
+        }
+      }),
+      _ => bail!("octahedral filter requires stride 4 or 8, got {}", stride),
+    },
+    MeshoptFilter::Quaternion => {
+      if stride != 8 {
+        bail!("quaternion filter requires stride 8, got {}", stride);
+      }
+
+      let scale = std::f32::consts::FRAC_1_SQRT_2;
+
+      for item in data[..count * 8].chunks_exact_mut(8) {
+        let v: Vec<i16> = item
+          .chunks_exact(2)
+          .map(|c| i16::from_le_bytes([c[0], c[1]]))
+          .collect();
+
+        // the scale is stored in the high bits of the last component
+        let sf = (v[3] | 3) as f32;
+        let ss = scale / sf;
+
+        let x = v[0] as f32 * ss;
+        let y = v[1] as f32 * ss;
+        let z = v[2] as f32 * ss;
+        let ww = 1.0 - x * x - y * y - z * z;
+        let w = ww.max(0.0).sqrt();
+
+        // the two lowest bits tell which component was dropped
+        let qc = (v[3] & 3) as usize;
+        let mut out = [0i16; 4];
+
+        out[(qc + 1) & 3] = round_to_int(x * 32767.0) as i16;
+        out[(qc + 2) & 3] = round_to_int(y * 32767.0) as i16;
+        out[(qc + 3) & 3] = round_to_int(z * 32767.0) as i16;
+        out[qc] = round_to_int(w * 32767.0) as i16;
+
+        for (c, value) in out.iter().enumerate() {
+          item[c * 2..c * 2 + 2].copy_from_slice(&value.to_le_bytes());
+        }
+      }
+    }
+    MeshoptFilter::Exponential => {
+      if stride % 4 != 0 {
+        bail!(
+          "exponential filter requires stride multiple of 4, got {}",
+          stride
+        );
+      }
+
+      for item in data[..count * stride].chunks_exact_mut(4) {
+        let v = i32::from_le_bytes([item[0], item[1], item[2], item[3]]);
+        let mantissa = (v << 8) >> 8;
+        let exponent = v >> 24;
+        let value = (mantissa as f64 * 2f64.powi(exponent)) as f32;
+
+        item.copy_from_slice(&value.to_le_bytes());
+      }
+    }
+  }
+
+  Ok(())
+}
+
+fn decode_octahedral(v: [f32; 3], max: f32) -> [i32; 3] {
+  // the third component stores the value of one
+  let one = v[2];
+  let mut x = v[0] / one;
+  let mut y = v[1] / one;
+  let z = 1.0 - x.abs() - y.abs();
+
+  // fold the lower hemisphere
+  let t = (-z).max(0.0);
+
+  x -= if x >= 0.0 { t } else { -t };
+  y -= if y >= 0.0 { t } else { -t };
+
+  let l = (x * x + y * y + z * z).sqrt();
+
+  [
+    round_to_int(x / l * max),
+    round_to_int(y / l * max),
+    round_to_int(z / l * max),
+  ]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // produced by meshopt_encodeVertexBuffer for 4 vertices of 16 bytes
+  const VERTEX_DATA: [u8; 106] = [
+    0xa0, 0x01, 0x3f, 0x00, 0x00, 0x00, 0x58, 0x57, 0x58, 0x01, 0x26, 0x00, 0x00, 0x00, 0x01, 0x0c,
+    0x00, 0x00, 0x00, 0x58, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x3f, 0x00, 0x00, 0x00,
+    0x6f, 0x70, 0x6f, 0x01, 0x0c, 0x00, 0x00, 0x00, 0x6f, 0x01, 0x3f, 0x00, 0x00, 0x00, 0x2f, 0x2f,
+    0x2f, 0x01, 0x3f, 0x00, 0x00, 0x00, 0x06, 0x08, 0x08, 0x01, 0x3f, 0x00, 0x00, 0x00, 0x0d, 0x0d,
+    0x0d, 0x00, 0x01, 0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0xcd, 0xab,
+  ];
+
+  const VERTEX_DECODED: [u8; 64] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0xcd, 0xab,
+    0x2c, 0x01, 0x00, 0x00, 0x00, 0x00, 0xc8, 0x00, 0xe8, 0x03, 0xf8, 0xff, 0x01, 0x00, 0xcd, 0xab,
+    0x00, 0x00, 0x2c, 0x01, 0x00, 0x00, 0x00, 0xc8, 0xd0, 0x07, 0xf1, 0xff, 0x02, 0x00, 0xcd, 0xab,
+    0x2c, 0x01, 0x2c, 0x01, 0x00, 0x00, 0xc8, 0xc8, 0xb8, 0x0b, 0xea, 0xff, 0x03, 0x00, 0xcd, 0xab,
+  ];
+
+  // produced by meshopt_encodeIndexBuffer (version 0)
+  const INDEX_DATA: [u8; 27] = [
+    0xe0, 0xf0, 0x10, 0xfe, 0xff, 0xf0, 0x0c, 0xff, 0x02, 0x02, 0x02, 0x00, 0x76, 0x87, 0x56, 0x67,
+    0x78, 0xa9, 0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69, 0x00, 0x00,
+  ];
+
+  fn codeaux_table() -> Vec<u8> {
+    vec![
+      0x00, 0x76, 0x87, 0x56, 0x67, 0x78, 0xa9, 0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69, 0x00,
+      0x00,
+    ]
+  }
+
+  fn to_u32(data: &[u8]) -> Vec<u32> {
+    data
+      .chunks_exact(4)
+      .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+      .collect()
+  }
+
+  fn to_u16(data: &[u8]) -> Vec<u16> {
+    data
+      .chunks_exact(2)
+      .map(|c| u16::from_le_bytes([c[0], c[1]]))
+      .collect()
+  }
+
+  #[test]
+  fn decodes_reference_vertex_buffer() {
+    let decoded = decode_vertex_buffer(&VERTEX_DATA, 4, 16).unwrap();
+
+    assert_eq!(decoded, VERTEX_DECODED.to_vec());
+  }
+
+  #[test]
+  fn decodes_vertex_buffer_across_blocks() {
+    // 300 u32 values i * 3, the first 256 vertices go to the first block
+    let mut data = vec![0xa0, 0xaa, 0xaa, 0xaa, 0xaa, 0x06];
+    data.extend(vec![0x66; 127]);
+    data.extend(vec![
+      0x00, 0x04, 0x10, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x2a,
+    ]);
+    data.extend(vec![0x66; 22]);
+    data.extend(vec![0x00, 0x00, 0x01, 0x80]);
+    data.extend(vec![0x00; 37]);
+
+    assert_eq!(data.len(), 217);
+
+    let decoded = to_u32(&decode_vertex_buffer(&data, 300, 4).unwrap());
+    let expected: Vec<u32> = (0..300).map(|i| i * 3).collect();
+
+    assert_eq!(decoded, expected);
+  }
+
+  #[test]
+  fn rejects_malformed_vertex_buffer() {
+    let mut data = VERTEX_DATA.to_vec();
+
+    data[0] = 0xa5;
+    assert!(decode_vertex_buffer(&data, 4, 16).is_err());
+
+    assert!(decode_vertex_buffer(&VERTEX_DATA[..60], 4, 16).is_err());
+    assert!(decode_vertex_buffer(&VERTEX_DATA, 4, 6).is_err());
+    // overflowing counts fail before anything is allocated
+    assert!(decode_vertex_buffer(&VERTEX_DATA, usize::MAX / 8, 16).is_err());
+  }
+
+  #[test]
+  fn decodes_reference_index_buffer() {
+    let expected = vec![0, 1, 2, 2, 1, 3, 4, 6, 5, 7, 8, 9];
+
+    assert_eq!(
+      to_u32(&decode_index_buffer(&INDEX_DATA, 12, 4).unwrap()),
+      expected
+    );
+    assert_eq!(
+      to_u16(&decode_index_buffer(&INDEX_DATA, 12, 2).unwrap()),
+      expected.iter().map(|i| *i as u16).collect::<Vec<_>>()
+    );
+  }
+
+  #[test]
+  fn decodes_index_buffer_version_1() {
+    // edge reuse with next (0x10), last + 1 (0x0e), last - 1 (0x0d) and an explicit delta (0x0f)
+    let mut data = vec![0xe1, 0xf0, 0x10, 0x0e, 0x0d, 0x0f, 0x0e];
+    data.extend(codeaux_table());
+
+    let decoded = to_u32(&decode_index_buffer(&data, 15, 4).unwrap());
+
+    assert_eq!(decoded, vec![0, 1, 2, 2, 1, 3, 2, 3, 1, 2, 1, 0, 2, 0, 7]);
+  }
+
+  #[test]
+  fn rejects_malformed_index_buffer() {
+    let mut data = INDEX_DATA.to_vec();
+
+    data[0] = 0xe2;
+    assert!(decode_index_buffer(&data, 12, 4).is_err());
+
+    assert!(decode_index_buffer(&INDEX_DATA[..20], 12, 4).is_err());
+    assert!(decode_index_buffer(&INDEX_DATA, 12, 3).is_err());
+    assert!(decode_index_buffer(&INDEX_DATA, 10, 4).is_err());
+  }
+
+  #[test]
+  fn decodes_index_sequence() {
+    let data = [0xd1, 0x14, 0x04, 0x91, 0x03, 0x04, 0x00, 0x00, 0x00, 0x00];
+
+    assert_eq!(
+      to_u32(&decode_index_sequence(&data, 4, 4).unwrap()),
+      vec![5, 6, 100, 7]
+    );
+    assert!(decode_index_sequence(&data, 5, 4).is_err());
+  }
+
+  #[test]
+  fn decodes_octahedral_filter() {
+    // x, y, one, preserved
+    let mut data = vec![127, 0, 127, 9, 0, 0, 127, 0, 3, 3, 4, 0xff];
+
+    decode_filter(&mut data, 3, 4, MeshoptFilter::Octahedral).unwrap();
+
+    let decoded: Vec<i8> = data.iter().map(|b| *b as i8).collect();
+
+    assert_eq!(decoded, vec![127, 0, 0, 9, 0, 0, 127, 0, 52, 52, -104, -1]);
+  }
+
+  #[test]
+  fn decodes_quaternion_filter() {
+    let mut data: Vec<u8> = [0i16, 0, 0, 32767, 16384, 0, 0, 32764]
+      .iter()
+      .flat_map(|v| v.to_le_bytes().to_vec())
+      .collect();
+
+    decode_filter(&mut data, 2, 8, MeshoptFilter::Quaternion).unwrap();
+
+    let decoded: Vec<i16> = data
+      .chunks_exact(2)
+      .map(|c| i16::from_le_bytes([c[0], c[1]]))
+      .collect();
+
+    assert_eq!(decoded, vec![0, 0, 0, 32767, 30651, 11585, 0, 0]);
+  }
+
+  #[test]
+  fn decodes_exponential_filter() {
+    let mut data: Vec<u8> = [(-2i32 << 24) | 6, (3 << 24) | (-5i32 & 0xffffff)]
+      .iter()
+      .flat_map(|v| v.to_le_bytes().to_vec())
+      .collect();
+
+    decode_filter(&mut data, 1, 8, MeshoptFilter::Exponential).unwrap();
+
+    let decoded: Vec<f32> = data
+      .chunks_exact(4)
+      .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+      .collect();
+
+    assert_eq!(decoded, vec![1.5, -40.0]);
+  }
+}
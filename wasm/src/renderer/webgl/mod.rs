@@ -1,12 +1,28 @@
+pub mod asset_cache;
+pub mod backend;
 pub mod camera;
 pub mod context;
 pub mod define;
+pub mod diagnostics;
 pub mod framebuffer;
 pub mod gltf;
+pub mod gltf_export;
+pub mod ibl;
+pub mod instancing;
+pub mod light;
 pub mod material;
 pub mod mesh;
+pub mod meshopt;
 pub mod pass;
+pub mod post_process;
+pub mod recording;
+pub mod render_queue;
 pub mod renderer;
+pub mod resources;
 pub mod shader;
+pub mod shader_cache;
+pub mod shader_chunks;
+pub mod state_cache;
+pub mod tangents;
 pub mod texture;
 pub mod turntable;
@@ -1,12 +1,12 @@
-use super::renderer::Renderer;
-use generational_arena::Index;
+use super::renderer::{RenderTarget, Renderer};
+use crate::handle::Handle;
 use na::Vector4;
 
 pub struct Pass {
   background_color: Vector4<f32>,
   clean_color: bool,
   clean_depth: bool,
-  render_target_handle: Option<Index>,
+  render_target_handle: Option<Handle<RenderTarget>>,
   handler: Box<dyn Fn(&mut Renderer)>,
 }
 
@@ -41,7 +41,10 @@ impl Pass {
     self
   }
 
-  pub fn set_render_target_handle(mut self, render_target_handle: Option<Index>) -> Self {
+  pub fn set_render_target_handle(
+    mut self,
+    render_target_handle: Option<Handle<RenderTarget>>,
+  ) -> Self {
     self.render_target_handle = render_target_handle;
     self
   }
@@ -59,7 +62,7 @@ impl Pass {
       let target = renderer.targets.get(render_target_handle).unwrap();
       let fb = renderer.framebuffers.get(target.fb).unwrap();
 
-      renderer.ctx.bind_framebuffer(Some(&fb));
+      renderer.ctx.bind_framebuffer(Some(*fb));
     }
 
     renderer.ctx.clear_color(
@@ -68,6 +71,11 @@ impl Pass {
       background_color.z,
       background_color.w,
     );
+    // a transparent draw may have left depth writes off, which also blocks clearing
+    if *clean_depth {
+      renderer.ctx.depth_mask(true);
+    }
+
     renderer.ctx.clear(*clean_color, *clean_depth);
 
     handler(renderer);
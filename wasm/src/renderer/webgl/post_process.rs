@@ -0,0 +1,600 @@
+use std::rc::Rc;
+
+use na::Vector2;
+
+use super::backend::{as_bytes, GpuBuffer};
+use super::context::{BlendFactor, BufferTarget, BufferUsage, Feature, TextureKind};
+use super::define::Define;
+use super::ibl::{draw_fullscreen, FULLSCREEN_TRIANGLE};
+use super::material::material::{bind_texture, bind_uniform_values};
+use super::material::Uniforms;
+use super::renderer::{RenderTarget, Renderer, Sampler, Texture};
+use super::shader::Shader;
+use super::shader_cache::ShaderVariant;
+use crate::handle::Handle;
+
+const VERT_SRC: &str = include_str!("./shaders/post_vert.glsl");
+const COPY_SRC: &str = include_str!("./shaders/post_copy_frag.glsl");
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapping {
+  Reinhard,
+  Aces,
+}
+
+// Effects run in order and each samples the output of the previous one as inputMap.
+// Targets are half float when the context can render to them, otherwise 8 bit and tone
+// mapping only sees what the scene left below 1.0.
+#[derive(Debug, Clone)]
+pub enum PostEffect {
+  ToneMapping {
+    operator: ToneMapping,
+    exposure: f32,
+  },
+  Gamma {
+    gamma: f32,
+  },
+  Fxaa,
+  // parts brighter than threshold blurred down a chain of half size targets, levels
+  // deep, and added back on the way up
+  Bloom {
+    threshold: f32,
+    intensity: f32,
+    levels: u32,
+  },
+  Vignette {
+    intensity: f32,
+    smoothness: f32,
+  },
+  // lut holds size slices of size x size texels side by side, blue picks the slice
+  ColorGrading {
+    lut: Handle<Texture>,
+    size: u32,
+    intensity: f32,
+  },
+  // fragment source sampling inputMap and sceneMap at v_uv, texelSize is set when
+  // declared and the uniform map is bound like ShaderMaterial's
+  Custom {
+    fragment_src: String,
+    uniforms: Uniforms,
+  },
+}
+
+impl PostEffect {
+  fn variant(&self) -> ShaderVariant<'_> {
+    let (fragment_src, defines) = match self {
+      PostEffect::ToneMapping { operator, .. } => (
+        include_str!("./shaders/post_tone_mapping_frag.glsl"),
+        match operator {
+          ToneMapping::Aces => vec![Define::def("TONE_MAP_ACES")],
+          ToneMapping::Reinhard => vec![],
+        },
+      ),
+      PostEffect::Gamma { .. } => (include_str!("./shaders/post_gamma_frag.glsl"), vec![]),
+      PostEffect::Fxaa => (include_str!("./shaders/post_fxaa_frag.glsl"), vec![]),
+      PostEffect::Bloom { .. } => (
+        include_str!("./shaders/post_bloom_composite_frag.glsl"),
+        vec![],
+      ),
+      PostEffect::Vignette { .. } => (include_str!("./shaders/post_vignette_frag.glsl"), vec![]),
+      PostEffect::ColorGrading { .. } => (
+        include_str!("./shaders/post_color_grading_frag.glsl"),
+        vec![],
+      ),
+      PostEffect::Custom { fragment_src, .. } => (fragment_src.as_str(), vec![]),
+    };
+
+    ShaderVariant::new(VERT_SRC, fragment_src, defines)
+  }
+
+  // uniforms besides inputMap, sceneMap and texelSize, textures from first_unit on
+  fn setup_shader(&self, renderer: &Renderer, shader: &Shader, first_unit: u32) {
+    match self {
+      PostEffect::ToneMapping { exposure, .. } => {
+        shader.set_float("exposure", *exposure);
+      }
+      PostEffect::Gamma { gamma } => {
+        shader.set_float("gamma", *gamma);
+      }
+      PostEffect::Fxaa => {}
+      PostEffect::Bloom { intensity, .. } => {
+        shader.set_float("intensity", *intensity);
+      }
+      PostEffect::Vignette {
+        intensity,
+        smoothness,
+      } => {
+        shader.set_float("intensity", *intensity);
+        shader.set_float("smoothness", *smoothness);
+      }
+      PostEffect::ColorGrading {
+        lut,
+        size,
+        intensity,
+      } => {
+        bind_map(renderer, shader, *lut, "lutMap", first_unit);
+        shader.set_float("lutSize", *size as f32);
+        shader.set_float("intensity", *intensity);
+      }
+      PostEffect::Custom { uniforms, .. } => {
+        bind_uniform_values(
+          renderer.ctx.as_ref(),
+          &renderer.images,
+          &renderer.textures,
+          &renderer.samplers,
+          shader,
+          uniforms,
+          first_unit,
+        );
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct PostTargets {
+  width: u32,
+  height: u32,
+  scene: Handle<RenderTarget>,
+  ping_pong: [Handle<RenderTarget>; 2],
+  // level i is the canvas size divided by 2^(i + 1)
+  bloom: Vec<Handle<RenderTarget>>,
+}
+
+fn bloom_size(width: u32, height: u32, level: usize) -> (u32, u32) {
+  (
+    (width >> (level + 1)).max(1),
+    (height >> (level + 1)).max(1),
+  )
+}
+
+fn bind_map(
+  renderer: &Renderer,
+  shader: &Shader,
+  texture: Handle<Texture>,
+  uniform_name: &str,
+  unit: u32,
+) {
+  if shader.has_uniform(uniform_name) {
+    bind_texture(
+      renderer.ctx.as_ref(),
+      &renderer.images,
+      &renderer.textures,
+      &renderer.samplers,
+      shader,
+      texture,
+      TextureKind::Texture2d,
+      uniform_name,
+      unit,
+    );
+  }
+}
+
+fn bind_target(renderer: &Renderer, target: Option<Handle<RenderTarget>>, width: u32, height: u32) {
+  let fb = target.map(|handle| {
+    let target = renderer.targets.get(handle).unwrap();
+    *renderer.framebuffers.get(target.fb).unwrap()
+  });
+
+  renderer.ctx.bind_framebuffer(fb);
+  renderer.ctx.viewport(0, 0, width as i32, height as i32);
+}
+
+fn color_texture(renderer: &Renderer, target: Handle<RenderTarget>) -> Handle<Texture> {
+  renderer.targets.get(target).unwrap().color_texture
+}
+
+// A chain of full screen effects over the scene. The scene is rendered into a target of
+// the canvas size, then every effect draws one triangle sampling the previous target into
+// the next of two ping-pong targets, the last one straight onto the canvas. Targets are
+// allocated on the first render and again whenever the size changes.
+#[derive(Debug, Default)]
+pub struct PostProcess {
+  effects: Vec<PostEffect>,
+  targets: Option<PostTargets>,
+  triangle: Option<GpuBuffer>,
+}
+
+impl PostProcess {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn add_effect(mut self, effect: PostEffect) -> Self {
+    self.effects.push(effect);
+    self
+  }
+
+  pub fn effects(&self) -> &[PostEffect] {
+    &self.effects
+  }
+
+  // parameters can be tweaked and effects reordered between frames
+  pub fn effects_mut(&mut self) -> &mut Vec<PostEffect> {
+    &mut self.effects
+  }
+
+  // where the scene ends up before the effects, e.g. to read its depth
+  pub fn scene_target(&self) -> Option<Handle<RenderTarget>> {
+    self.targets.as_ref().map(|targets| targets.scene)
+  }
+
+  fn bloom_levels(&self) -> usize {
+    self
+      .effects
+      .iter()
+      .filter_map(|effect| match effect {
+        PostEffect::Bloom { levels, .. } => Some(*levels as usize),
+        _ => None,
+      })
+      .max()
+      .unwrap_or(0)
+  }
+
+  fn ensure_targets(&mut self, renderer: &mut Renderer, width: u32, height: u32) {
+    let resized = match &self.targets {
+      Some(targets) => targets.width != width || targets.height != height,
+      None => true,
+    };
+
+    if resized {
+      self.release_targets(renderer);
+      self.targets = Some(PostTargets {
+        width,
+        height,
+        scene: renderer.bake_hdr_render_target(width, height, Sampler::framebuffer(), true),
+        ping_pong: [
+          renderer.bake_hdr_render_target(width, height, Sampler::framebuffer(), false),
+          renderer.bake_hdr_render_target(width, height, Sampler::framebuffer(), false),
+        ],
+        bloom: vec![],
+      });
+    }
+
+    // levels only grow, a shallower bloom uses the first ones
+    let levels = self.bloom_levels();
+    let targets = self.targets.as_mut().unwrap();
+
+    for level in targets.bloom.len()..levels {
+      let (level_width, level_height) = bloom_size(width, height, level);
+
+      targets.bloom.push(renderer.bake_hdr_render_target(
+        level_width,
+        level_height,
+        Sampler::framebuffer(),
+        false,
+      ));
+    }
+  }
+
+  fn release_targets(&mut self, renderer: &mut Renderer) {
+    if let Some(targets) = self.targets.take() {
+      let handles = [targets.scene]
+        .iter()
+        .chain(targets.ping_pong.iter())
+        .chain(targets.bloom.iter())
+        .copied()
+        .collect::<Vec<_>>();
+
+      for handle in handles {
+        renderer.delete_render_target(handle);
+      }
+    }
+  }
+
+  // Frees the targets and the triangle, the next render allocates them again.
+  pub fn release(&mut self, renderer: &mut Renderer) {
+    self.release_targets(renderer);
+
+    if let Some(triangle) = self.triangle.take() {
+      renderer.ctx.delete_buffer(triangle);
+    }
+  }
+
+  // Runs scene with the scene target bound and cleared, then the effects. Without effects
+  // the scene draws onto the canvas directly. Depth test, culling and blending are left
+  // off, passes set them up again anyway.
+  pub fn render(
+    &mut self,
+    renderer: &mut Renderer,
+    width: u32,
+    height: u32,
+    scene: impl FnOnce(&mut Renderer),
+  ) {
+    if self.effects.is_empty() {
+      bind_target(renderer, None, width, height);
+      scene(renderer);
+      return;
+    }
+
+    self.ensure_targets(renderer, width, height);
+
+    let triangle = *self.triangle.get_or_insert_with(|| {
+      renderer
+        .ctx
+        .create_buffer(
+          BufferTarget::ArrayBuffer,
+          BufferUsage::StaticDraw,
+          as_bytes(&FULLSCREEN_TRIANGLE),
+        )
+        .unwrap()
+    });
+    let targets = self.targets.clone().unwrap();
+
+    bind_target(renderer, Some(targets.scene), width, height);
+    renderer.ctx.clear_color(0.0, 0.0, 0.0, 0.0);
+    renderer.ctx.depth_mask(true);
+    renderer.ctx.clear(true, true);
+    scene(renderer);
+
+    renderer.ctx.set(Feature::DepthTest, false);
+    renderer.ctx.set(Feature::CullFace, false);
+    renderer.ctx.set(Feature::Blend, false);
+
+    let scene_texture = color_texture(renderer, targets.scene);
+    let texel_size = Vector2::new(1.0 / width as f32, 1.0 / height as f32);
+    let mut input = scene_texture;
+
+    for (i, effect) in self.effects.iter().enumerate() {
+      let output = if i + 1 == self.effects.len() {
+        None
+      } else {
+        Some(targets.ping_pong[i % 2])
+      };
+      let bloom = match effect {
+        PostEffect::Bloom {
+          threshold, levels, ..
+        } => render_bloom(
+          renderer,
+          triangle,
+          &targets,
+          input,
+          *threshold,
+          (*levels as usize).min(targets.bloom.len()),
+        ),
+        _ => None,
+      };
+      let shader = match effect_shader(renderer, effect) {
+        Some(shader) => shader,
+        None => return,
+      };
+
+      bind_target(renderer, output, width, height);
+      shader.bind();
+      bind_map(renderer, &shader, input, "inputMap", 0);
+      bind_map(renderer, &shader, scene_texture, "sceneMap", 1);
+      shader.set_vector2("texelSize", &texel_size);
+
+      let mut unit = 2;
+
+      if let Some(bloom) = bloom {
+        bind_map(renderer, &shader, bloom, "bloomMap", unit);
+        unit += 1;
+      }
+
+      effect.setup_shader(renderer, &shader, unit);
+      draw_fullscreen(renderer, &shader, triangle);
+
+      if let Some(output) = output {
+        input = color_texture(renderer, output);
+      }
+    }
+  }
+}
+
+// the effect's program, or a plain copy when it doesn't compile so the chain still shows
+// something; the error is logged by the shader cache
+fn effect_shader(renderer: &Renderer, effect: &PostEffect) -> Option<Rc<Shader>> {
+  let ctx = renderer.ctx.as_ref();
+
+  renderer
+    .shaders
+    .get_or_compile(ctx, &effect.variant())
+    .or_else(|| {
+      let copy = ShaderVariant::new(VERT_SRC, COPY_SRC, vec![]);
+
+      renderer.shaders.get_or_compile(ctx, &copy)
+    })
+}
+
+// Downsamples input through the bloom levels, thresholding on the first step, then adds
+// every level onto the next larger one with a tent filter. Returns the first level.
+fn render_bloom(
+  renderer: &Renderer,
+  triangle: GpuBuffer,
+  targets: &PostTargets,
+  input: Handle<Texture>,
+  threshold: f32,
+  levels: usize,
+) -> Option<Handle<Texture>> {
+  if levels == 0 {
+    return None;
+  }
+
+  let down_src = include_str!("./shaders/post_bloom_down_frag.glsl");
+  let ctx = renderer.ctx.as_ref();
+  let threshold_shader = renderer.shaders.get_or_compile(
+    ctx,
+    &ShaderVariant::new(VERT_SRC, down_src, vec![Define::def("USE_THRESHOLD")]),
+  )?;
+  let down_shader = renderer
+    .shaders
+    .get_or_compile(ctx, &ShaderVariant::new(VERT_SRC, down_src, vec![]))?;
+  let up_shader = renderer.shaders.get_or_compile(
+    ctx,
+    &ShaderVariant::new(
+      VERT_SRC,
+      include_str!("./shaders/post_bloom_up_frag.glsl"),
+      vec![],
+    ),
+  )?;
+  let size = |level: usize| bloom_size(targets.width, targets.height, level);
+  let texel_size =
+    |(width, height): (u32, u32)| Vector2::new(1.0 / width as f32, 1.0 / height as f32);
+
+  for level in 0..levels {
+    let (width, height) = size(level);
+    let (shader, source, source_size) = if level == 0 {
+      (&threshold_shader, input, (targets.width, targets.height))
+    } else {
+      (
+        &down_shader,
+        color_texture(renderer, targets.bloom[level - 1]),
+        size(level - 1),
+      )
+    };
+
+    bind_target(renderer, Some(targets.bloom[level]), width, height);
+    shader.bind();
+    bind_map(renderer, shader, source, "inputMap", 0);
+    shader.set_vector2("texelSize", &texel_size(source_size));
+    shader.set_float("threshold", threshold);
+    draw_fullscreen(renderer, shader, triangle);
+  }
+
+  renderer.ctx.set(Feature::Blend, true);
+  renderer.ctx.blend_func(BlendFactor::One, BlendFactor::One);
+
+  for level in (0..levels - 1).rev() {
+    let (width, height) = size(level);
+
+    bind_target(renderer, Some(targets.bloom[level]), width, height);
+    up_shader.bind();
+    bind_map(
+      renderer,
+      &up_shader,
+      color_texture(renderer, targets.bloom[level + 1]),
+      "inputMap",
+      0,
+    );
+    up_shader.set_vector2("texelSize", &texel_size(size(level + 1)));
+    draw_fullscreen(renderer, &up_shader, triangle);
+  }
+
+  renderer.ctx.set(Feature::Blend, false);
+
+  Some(color_texture(renderer, targets.bloom[0]))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::renderer::webgl::context::{TextureFormat, TypedArrayKind};
+  use crate::renderer::webgl::recording::{Command, RecordingBackend};
+
+  fn color_sizes(log: &[Command]) -> Vec<(i32, i32)> {
+    log
+      .iter()
+      .filter_map(|command| match command {
+        Command::TexImage {
+          format: TextureFormat::RGBA,
+          kind: TypedArrayKind::Uint8,
+          size,
+          ..
+        } => *size,
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn effects_chain_through_targets_of_the_canvas_size() {
+    let backend = RecordingBackend::new();
+    let log = backend.log();
+    let mut renderer = Renderer::new(backend);
+    let mut post_process = PostProcess::new()
+      .add_effect(PostEffect::Bloom {
+        threshold: 0.8,
+        intensity: 0.5,
+        levels: 3,
+      })
+      .add_effect(PostEffect::ToneMapping {
+        operator: ToneMapping::Aces,
+        exposure: 1.0,
+      })
+      .add_effect(PostEffect::Fxaa);
+    let mut scene_drawn = false;
+
+    post_process.render(&mut renderer, 64, 32, |_| scene_drawn = true);
+
+    {
+      let log = log.borrow();
+      let draws = log
+        .iter()
+        .filter(|command| matches!(command, Command::Draw { .. }))
+        .count();
+      let last_bind = log
+        .iter()
+        .rposition(|command| matches!(command, Command::BindFramebuffer(_)))
+        .unwrap();
+
+      assert!(scene_drawn);
+      // scene and ping-pong targets, then the bloom levels
+      assert_eq!(
+        color_sizes(&log),
+        vec![(64, 32), (64, 32), (64, 32), (32, 16), (16, 8), (8, 4)]
+      );
+      // three downsamples, two upsamples, the composite, tone mapping and FXAA
+      assert_eq!(draws, 8);
+      // the last effect draws onto the canvas
+      assert_eq!(log[last_bind], Command::BindFramebuffer(None));
+      assert!(matches!(log.last(), Some(Command::Draw { .. })));
+    }
+
+    log.borrow_mut().clear();
+    post_process.render(&mut renderer, 64, 32, |_| {});
+    assert!(color_sizes(&log.borrow()).is_empty());
+
+    post_process.render(&mut renderer, 32, 32, |_| {});
+
+    let log = log.borrow();
+    let deleted = log
+      .iter()
+      .filter(|command| matches!(command, Command::DeleteFramebuffer(_)))
+      .count();
+
+    assert_eq!(deleted, 6);
+    assert_eq!(
+      color_sizes(&log),
+      vec![(32, 32), (32, 32), (32, 32), (16, 16), (8, 8), (4, 4)]
+    );
+  }
+
+  #[test]
+  fn targets_are_half_float_when_the_context_renders_to_them() {
+    let backend = RecordingBackend::new().set_half_float_targets(true);
+    let log = backend.log();
+    let mut renderer = Renderer::new(backend);
+    let mut post_process = PostProcess::new().add_effect(PostEffect::Bloom {
+      threshold: 0.8,
+      intensity: 0.5,
+      levels: 1,
+    });
+
+    post_process.render(&mut renderer, 64, 32, |_| {});
+
+    let log = log.borrow();
+    let kinds: Vec<TypedArrayKind> = log
+      .iter()
+      .filter_map(|command| match command {
+        Command::TexImage {
+          format: TextureFormat::RGBA,
+          kind,
+          ..
+        } => Some(*kind),
+        _ => None,
+      })
+      .collect();
+
+    // the scene, the ping-pong targets and the bloom level
+    assert_eq!(kinds, vec![TypedArrayKind::HalfFloat; 4]);
+    // the scene keeps a depth attachment
+    assert!(log.iter().any(|command| matches!(
+      command,
+      Command::TexImage {
+        format: TextureFormat::Depth,
+        ..
+      }
+    )));
+  }
+}
@@ -0,0 +1,1390 @@
+use anyhow::Result;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use web_sys::HtmlImageElement;
+
+use super::backend::{
+  Backend, GpuBuffer, GpuFramebuffer, GpuTexture, Program, TextureLayout, Uniform,
+};
+use super::context::{
+  BlendFactor, BufferTarget, BufferUsage, DepthFunc, DrawMode, Feature, FramebufferAttachment,
+  TexParam, TexParamName, TextureFormat, TextureKind, TypedArrayKind,
+};
+use super::define::Define;
+use super::diagnostics::{Diagnostic, Severity, ShaderError, ShaderStage};
+use super::shader::{stage_source, AttributeName, AttributeOptions, Shader};
+use super::shader_cache::StageCache;
+use super::shader_chunks::{ShaderChunks, SourceMap};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  Viewport {
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+  },
+  ClearColor([f32; 4]),
+  Clear {
+    color: bool,
+    depth: bool,
+  },
+  CompileShader(ShaderStage),
+  DeleteShader(ShaderStage),
+  DeleteProgram(u32),
+  CreateProgram {
+    program: u32,
+    defines: Vec<String>,
+    attributes: Vec<String>,
+    uniforms: Vec<String>,
+  },
+  UseProgram(u32),
+  SetUniform {
+    program: u32,
+    name: String,
+    value: Vec<f32>,
+  },
+  VertexAttribPointer {
+    program: u32,
+    location: u32,
+    options: AttributeOptions,
+  },
+  CreateBuffer {
+    buffer: GpuBuffer,
+    target: BufferTarget,
+    usage: BufferUsage,
+    size: usize,
+  },
+  BindBuffer {
+    target: BufferTarget,
+    buffer: Option<GpuBuffer>,
+  },
+  DeleteBuffer(GpuBuffer),
+  CreateTexture(GpuTexture),
+  DeleteTexture(GpuTexture),
+  ActiveTexture(u32),
+  BindTexture {
+    target: TextureKind,
+    texture: Option<GpuTexture>,
+  },
+  TextureParameter {
+    target: TextureKind,
+    name: TexParamName,
+    param: TexParam,
+  },
+  // size is None for uploads from an image element
+  TexImage {
+    target: TextureKind,
+    level: i32,
+    format: TextureFormat,
+    kind: TypedArrayKind,
+    size: Option<(i32, i32)>,
+  },
+  GenerateMipmap(TextureKind),
+  CopyTexImage {
+    target: TextureKind,
+    level: i32,
+    size: (i32, i32),
+  },
+  CreateFramebuffer(GpuFramebuffer),
+  DeleteFramebuffer(GpuFramebuffer),
+  BindFramebuffer(Option<GpuFramebuffer>),
+  FramebufferTexture {
+    attachment: FramebufferAttachment,
+    texture: Option<GpuTexture>,
+  },
+  SwitchAttributes(u32),
+  ConstantAttribute {
+    location: u32,
+    value: [f32; 4],
+  },
+  SetFeature {
+    feature: Feature,
+    enabled: bool,
+  },
+  DepthFunc(DepthFunc),
+  BlendFunc {
+    src: BlendFactor,
+    dst: BlendFactor,
+  },
+  DepthMask(bool),
+  VertexAttribDivisor {
+    location: u32,
+    divisor: u32,
+  },
+  Draw {
+    program: Option<u32>,
+    mode: DrawMode,
+    count: i32,
+    indexed: bool,
+    instances: Option<i32>,
+  },
+}
+
+pub type CommandLog = Rc<RefCell<Vec<Command>>>;
+
+// Headless backend, GPU calls are only logged so tests can assert on them natively.
+#[derive(Debug)]
+pub struct RecordingBackend {
+  log: CommandLog,
+  next_id: Cell<u32>,
+  program: Rc<Cell<Option<u32>>>,
+  instancing: bool,
+  complete_framebuffers: bool,
+  max_texture_units: u32,
+  half_float_targets: bool,
+  chunks: RefCell<ShaderChunks>,
+  stages: Rc<StageCache<String>>,
+}
+
+impl Default for RecordingBackend {
+  fn default() -> Self {
+    RecordingBackend {
+      log: Rc::new(RefCell::new(vec![])),
+      next_id: Cell::new(1),
+      program: Rc::new(Cell::new(None)),
+      instancing: true,
+      complete_framebuffers: true,
+      max_texture_units: 16,
+      half_float_targets: false,
+      chunks: RefCell::new(ShaderChunks::new()),
+      stages: Rc::new(StageCache::new()),
+    }
+  }
+}
+
+impl RecordingBackend {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn set_instancing(mut self, instancing: bool) -> Self {
+    self.instancing = instancing;
+    self
+  }
+
+  pub fn set_complete_framebuffers(mut self, complete_framebuffers: bool) -> Self {
+    self.complete_framebuffers = complete_framebuffers;
+    self
+  }
+
+  pub fn set_max_texture_units(mut self, max_texture_units: u32) -> Self {
+    self.max_texture_units = max_texture_units;
+    self
+  }
+
+  pub fn set_half_float_targets(mut self, half_float_targets: bool) -> Self {
+    self.half_float_targets = half_float_targets;
+    self
+  }
+
+  // shared handle, stays readable after the backend is moved into the renderer
+  pub fn log(&self) -> CommandLog {
+    self.log.clone()
+  }
+
+  fn record(&self, command: Command) {
+    self.log.borrow_mut().push(command);
+  }
+
+  // #error stands in for a driver compile error, stages are shared as on WebGL
+  fn compile_stage(
+    &self,
+    stage: ShaderStage,
+    source: &str,
+    map: &SourceMap,
+    defines: &[Define],
+  ) -> Result<String, ShaderError> {
+    self.stages.get_or_compile(stage, source, || {
+      let (active, errors) = preprocess(source, defines);
+
+      if !errors.is_empty() {
+        return Err(ShaderError::new(
+          errors
+            .into_iter()
+            .map(|(line, message)| Diagnostic {
+              stage,
+              severity: Severity::Error,
+              source: map.get(line).cloned(),
+              message,
+            })
+            .collect(),
+        ));
+      }
+
+      self.record(Command::CompileShader(stage));
+
+      Ok(active)
+    })
+  }
+
+  fn release_stage(&self, stage: ShaderStage, source: &str) {
+    release_stage(&self.stages, &self.log, stage, source);
+  }
+
+  fn next_id(&self) -> u32 {
+    let id = self.next_id.get();
+    self.next_id.set(id + 1);
+    id
+  }
+}
+
+impl Backend for RecordingBackend {
+  fn supports_instancing(&self) -> bool {
+    self.instancing
+  }
+
+  fn max_texture_units(&self) -> u32 {
+    self.max_texture_units
+  }
+
+  fn supports_half_float_targets(&self) -> bool {
+    self.half_float_targets
+  }
+
+  fn viewport(&self, x: i32, y: i32, width: i32, height: i32) {
+    self.record(Command::Viewport {
+      x,
+      y,
+      width,
+      height,
+    });
+  }
+
+  fn clear(&self, color: bool, depth: bool) {
+    self.record(Command::Clear { color, depth });
+  }
+
+  fn clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
+    self.record(Command::ClearColor([r, g, b, a]));
+  }
+
+  fn create_shader(
+    &self,
+    vertex_src: &str,
+    fragment_src: &str,
+    defines: &[Define],
+  ) -> Result<Shader> {
+    let id = self.next_id();
+    let chunks = self.chunks.borrow();
+    let (vertex_source, vertex_map) =
+      stage_source(&chunks, vertex_src, ShaderStage::Vertex, defines)?;
+    let (fragment_source, fragment_map) =
+      stage_source(&chunks, fragment_src, ShaderStage::Fragment, defines)?;
+
+    let vertex = self.compile_stage(ShaderStage::Vertex, &vertex_source, &vertex_map, defines);
+    let fragment = self.compile_stage(
+      ShaderStage::Fragment,
+      &fragment_source,
+      &fragment_map,
+      defines,
+    );
+
+    let (vertex, fragment) = match (vertex, fragment) {
+      (Ok(vertex), Ok(fragment)) => (vertex, fragment),
+      (vertex, fragment) => {
+        if vertex.is_ok() {
+          self.release_stage(ShaderStage::Vertex, &vertex_source);
+        }
+
+        if fragment.is_ok() {
+          self.release_stage(ShaderStage::Fragment, &fragment_source);
+        }
+
+        let diagnostics = vertex
+          .err()
+          .into_iter()
+          .chain(fragment.err())
+          .flat_map(|error| error.diagnostics)
+          .collect();
+
+        return Err(ShaderError::new(diagnostics).into());
+      }
+    };
+
+    let attributes = declarations(&vertex, "attribute");
+    let uniforms: Vec<String> = declarations(&vertex, "uniform")
+      .into_iter()
+      .chain(declarations(&fragment, "uniform"))
+      .collect();
+
+    self.record(Command::CreateProgram {
+      program: id,
+      defines: defines.iter().map(|define| define.name.clone()).collect(),
+      attributes: attributes.clone(),
+      uniforms: uniforms.clone(),
+    });
+
+    let attribute_locations = attributes
+      .iter()
+      .enumerate()
+      .map(|(location, name)| (AttributeName::from_string(name), location as u32))
+      .collect();
+
+    Ok(Shader::new(Box::new(RecordingProgram {
+      id,
+      log: self.log.clone(),
+      current: self.program.clone(),
+      stages: self.stages.clone(),
+      sources: [
+        (ShaderStage::Vertex, vertex_source),
+        (ShaderStage::Fragment, fragment_source),
+      ],
+      attribute_locations,
+      uniforms,
+    })))
+  }
+
+  fn register_shader_chunk(&self, name: &str, src: &str) {
+    self.chunks.borrow_mut().register(name, src);
+  }
+
+  fn create_buffer(
+    &self,
+    target: BufferTarget,
+    usage: BufferUsage,
+    data: &[u8],
+  ) -> Option<GpuBuffer> {
+    let buffer = GpuBuffer(self.next_id());
+
+    self.record(Command::CreateBuffer {
+      buffer,
+      target,
+      usage,
+      size: data.len(),
+    });
+
+    Some(buffer)
+  }
+
+  fn bind_buffer(&self, target: BufferTarget, buffer: Option<GpuBuffer>) {
+    self.record(Command::BindBuffer { target, buffer });
+  }
+
+  fn delete_buffer(&self, buffer: GpuBuffer) {
+    self.record(Command::DeleteBuffer(buffer));
+  }
+
+  fn create_texture(&self) -> Option<GpuTexture> {
+    let texture = GpuTexture(self.next_id());
+
+    self.record(Command::CreateTexture(texture));
+
+    Some(texture)
+  }
+
+  fn delete_texture(&self, texture: GpuTexture) {
+    self.record(Command::DeleteTexture(texture));
+  }
+
+  fn active_texture(&self, unit: u32) {
+    self.record(Command::ActiveTexture(unit));
+  }
+
+  fn bind_texture(&self, target: TextureKind, texture: Option<GpuTexture>) {
+    self.record(Command::BindTexture { target, texture });
+  }
+
+  fn texture_parameter(&self, target: TextureKind, name: TexParamName, param: TexParam) {
+    self.record(Command::TextureParameter {
+      target,
+      name,
+      param,
+    });
+  }
+
+  fn texture_data(&self, layout: &TextureLayout, _data: &[u8]) -> Result<()> {
+    self.empty_texture_data(layout)
+  }
+
+  fn empty_texture_data(&self, layout: &TextureLayout) -> Result<()> {
+    self.record(Command::TexImage {
+      target: layout.target,
+      level: layout.level,
+      format: layout.format,
+      kind: layout.kind,
+      size: Some((layout.width, layout.height)),
+    });
+
+    Ok(())
+  }
+
+  fn texture_image_data(
+    &self,
+    target: TextureKind,
+    level: i32,
+    _internal_format: TextureFormat,
+    format: TextureFormat,
+    kind: TypedArrayKind,
+    _image: &HtmlImageElement,
+  ) -> Result<()> {
+    self.record(Command::TexImage {
+      target,
+      level,
+      format,
+      kind,
+      size: None,
+    });
+
+    Ok(())
+  }
+
+  fn generate_mipmap(&self, target: TextureKind) {
+    self.record(Command::GenerateMipmap(target));
+  }
+
+  fn copy_texture_image_2d(
+    &self,
+    target: TextureKind,
+    level: i32,
+    _internal_format: TextureFormat,
+    width: i32,
+    height: i32,
+  ) {
+    self.record(Command::CopyTexImage {
+      target,
+      level,
+      size: (width, height),
+    });
+  }
+
+  fn create_framebuffer(&self) -> Option<GpuFramebuffer> {
+    let fb = GpuFramebuffer(self.next_id());
+
+    self.record(Command::CreateFramebuffer(fb));
+
+    Some(fb)
+  }
+
+  fn delete_framebuffer(&self, fb: GpuFramebuffer) {
+    self.record(Command::DeleteFramebuffer(fb));
+  }
+
+  fn bind_framebuffer(&self, fb: Option<GpuFramebuffer>) {
+    self.record(Command::BindFramebuffer(fb));
+  }
+
+  fn framebuffer_texture_2d(&self, attachment: FramebufferAttachment, texture: Option<GpuTexture>) {
+    self.record(Command::FramebufferTexture {
+      attachment,
+      texture,
+    });
+  }
+
+  fn check_framebuffer_complete(&self) -> bool {
+    self.complete_framebuffers
+  }
+
+  fn switch_attributes(&self, amount: u32) {
+    self.record(Command::SwitchAttributes(amount));
+  }
+
+  fn constant_attribute(&self, location: u32, value: [f32; 4]) {
+    self.record(Command::ConstantAttribute { location, value });
+  }
+
+  fn set(&self, feature: Feature, enabled: bool) {
+    self.record(Command::SetFeature { feature, enabled });
+  }
+
+  fn depth_func(&self, func: DepthFunc) {
+    self.record(Command::DepthFunc(func));
+  }
+
+  fn blend_func(&self, src: BlendFactor, dst: BlendFactor) {
+    self.record(Command::BlendFunc { src, dst });
+  }
+
+  fn depth_mask(&self, write: bool) {
+    self.record(Command::DepthMask(write));
+  }
+
+  // nothing is cached, every call is recorded
+  fn take_skipped_calls(&self) -> usize {
+    0
+  }
+
+  fn draw_arrays(&self, mode: DrawMode, _first: i32, count: i32) {
+    self.record(Command::Draw {
+      program: self.program.get(),
+      mode,
+      count,
+      indexed: false,
+      instances: None,
+    });
+  }
+
+  fn draw_elements(&self, mode: DrawMode, count: i32, _kind: TypedArrayKind, _offset: i32) {
+    self.record(Command::Draw {
+      program: self.program.get(),
+      mode,
+      count,
+      indexed: true,
+      instances: None,
+    });
+  }
+
+  fn vertex_attrib_divisor(&self, location: u32, divisor: u32) {
+    self.record(Command::VertexAttribDivisor { location, divisor });
+  }
+
+  fn draw_arrays_instanced(&self, mode: DrawMode, _first: i32, count: i32, instances: i32) {
+    self.record(Command::Draw {
+      program: self.program.get(),
+      mode,
+      count,
+      indexed: false,
+      instances: Some(instances),
+    });
+  }
+
+  fn draw_elements_instanced(
+    &self,
+    mode: DrawMode,
+    count: i32,
+    _kind: TypedArrayKind,
+    _offset: i32,
+    instances: i32,
+  ) {
+    self.record(Command::Draw {
+      program: self.program.get(),
+      mode,
+      count,
+      indexed: true,
+      instances: Some(instances),
+    });
+  }
+}
+
+#[derive(Debug)]
+struct RecordingProgram {
+  id: u32,
+  log: CommandLog,
+  current: Rc<Cell<Option<u32>>>,
+  stages: Rc<StageCache<String>>,
+  sources: [(ShaderStage, String); 2],
+  attribute_locations: HashMap<AttributeName, u32>,
+  uniforms: Vec<String>,
+}
+
+impl Drop for RecordingProgram {
+  fn drop(&mut self) {
+    self.log.borrow_mut().push(Command::DeleteProgram(self.id));
+
+    for (stage, source) in &self.sources {
+      release_stage(&self.stages, &self.log, *stage, source);
+    }
+  }
+}
+
+fn release_stage(stages: &StageCache<String>, log: &CommandLog, stage: ShaderStage, source: &str) {
+  if stages.release(stage, source).is_some() {
+    log.borrow_mut().push(Command::DeleteShader(stage));
+  }
+}
+
+impl Program for RecordingProgram {
+  fn bind(&self) {
+    self.current.set(Some(self.id));
+    self.log.borrow_mut().push(Command::UseProgram(self.id));
+  }
+
+  fn attribute_locations(&self) -> &HashMap<AttributeName, u32> {
+    &self.attribute_locations
+  }
+
+  fn bind_attribute(&self, location: u32, options: &AttributeOptions) {
+    self.log.borrow_mut().push(Command::VertexAttribPointer {
+      program: self.id,
+      location,
+      options: options.clone(),
+    });
+  }
+
+  fn has_uniform(&self, name: &str) -> bool {
+    self.uniforms.iter().any(|uniform| uniform == name)
+  }
+
+  fn set_uniform(&self, name: &str, value: Uniform) -> Option<()> {
+    if !self.has_uniform(name) {
+      return None;
+    }
+
+    let value = match value {
+      Uniform::Bool(v) => vec![if v { 1.0 } else { 0.0 }],
+      Uniform::Float(v) => vec![v],
+      Uniform::Integer(v) => vec![v as f32],
+      Uniform::Vector2(v) => v.as_slice().to_vec(),
+      Uniform::Vector3(v) => v.as_slice().to_vec(),
+      Uniform::Vector4(v) => v.as_slice().to_vec(),
+      Uniform::Matrix3(m) => m.as_slice().to_vec(),
+      Uniform::Matrix4(m) => m.as_slice().to_vec(),
+      Uniform::Matrix4Data(data) => data.to_vec(),
+    };
+
+    self.log.borrow_mut().push(Command::SetUniform {
+      program: self.id,
+      name: name.to_string(),
+      value,
+    });
+
+    Some(())
+  }
+}
+
+// Drops the lines disabled by #ifdef / #ifndef / #else blocks, enough for the bundled
+// shaders. Active #error directives are returned by line, starting at 1.
+fn preprocess(src: &str, defines: &[Define]) -> (String, Vec<(usize, String)>) {
+  let defined = |name: &str| defines.iter().any(|define| define.name == name);
+  let mut stack: Vec<bool> = vec![];
+  let mut result = String::new();
+  let mut errors = vec![];
+
+  for (i, line) in src.lines().enumerate() {
+    let trimmed = line.trim();
+    let mut words = trimmed.split_whitespace();
+    let active = stack.iter().all(|enabled| *enabled);
+
+    match words.next() {
+      Some("#ifdef") => stack.push(defined(words.next().unwrap_or(""))),
+      Some("#ifndef") => stack.push(!defined(words.next().unwrap_or(""))),
+      Some("#else") => {
+        if let Some(enabled) = stack.last_mut() {
+          *enabled = !*enabled;
+        }
+      }
+      Some("#endif") => {
+        stack.pop();
+      }
+      Some("#error") if active => {
+        errors.push((i + 1, words.collect::<Vec<_>>().join(" ")));
+      }
+      _ if active => {
+        result.push_str(line);
+        result.push('\n');
+      }
+      _ => {}
+    }
+  }
+
+  (result, errors)
+}
+
+// names declared with the given qualifier, e.g. "attribute vec3 position;" gives "position"
+fn declarations(src: &str, qualifier: &str) -> Vec<String> {
+  src
+    .lines()
+    .filter_map(|line| {
+      let mut words = line.split_whitespace();
+
+      if words.next()? != qualifier {
+        return None;
+      }
+
+      let name = words.nth(1)?;
+      let name = name.split(&[';', '['][..]).next()?;
+
+      Some(name.to_string())
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use na::Vector3;
+
+  use super::*;
+  use crate::handle::Handle;
+  use crate::renderer::webgl::light::Light;
+  use crate::renderer::webgl::material::{
+    BlendMode, Material, PbrMaterial, ShaderMaterial, SkyboxMaterial,
+  };
+  use crate::renderer::webgl::pass::Pass;
+  use crate::renderer::webgl::renderer::{Camera, Geometry, Renderer, Sampler, VariantOptions};
+  use crate::scene::node::Node;
+
+  fn renderer() -> (Renderer, CommandLog) {
+    let backend = RecordingBackend::new();
+    let log = backend.log();
+
+    (Renderer::new(backend), log)
+  }
+
+  fn add_node(
+    renderer: &mut Renderer,
+    geometry: Handle<Geometry>,
+    material: Handle<dyn Material>,
+  ) -> Handle<Node> {
+    let mesh = renderer.compose_mesh(geometry, material, None);
+    let mut node = Node::new(Some(renderer.scene.get_root_handle()));
+
+    node.mesh = Some(mesh);
+
+    renderer.insert_node(node)
+  }
+
+  fn program_with_uniform(log: &[Command], uniform: &str) -> Option<u32> {
+    log.iter().find_map(|command| match command {
+      Command::CreateProgram {
+        program, uniforms, ..
+      } if uniforms.iter().any(|u| u == uniform) => Some(*program),
+      _ => None,
+    })
+  }
+
+  // depth func set before each draw, in draw order
+  fn draws_with_depth_func(log: &[Command]) -> Vec<(Option<u32>, Option<DepthFunc>)> {
+    let mut depth_func = None;
+    let mut draws = vec![];
+
+    for command in log {
+      match command {
+        Command::DepthFunc(func) => depth_func = Some(*func),
+        Command::Draw { program, .. } => draws.push((*program, depth_func)),
+        _ => {}
+      }
+    }
+
+    draws
+  }
+
+  #[test]
+  fn skybox_is_drawn_with_lequal_after_opaque_meshes() {
+    let (mut renderer, log) = renderer();
+
+    let cube_texture = renderer.ctx.create_texture().unwrap();
+    let skybox_texture = renderer.compose_texture(cube_texture, Sampler::default(), 0);
+
+    let ball = renderer.bake_ball_geometry(1.0);
+    let pbr = renderer.bake_material(PbrMaterial::new().boxed());
+    add_node(&mut renderer, ball, pbr);
+
+    let cube = renderer.bake_cuboid_geometry(Vector3::new(1.0, 1.0, 1.0));
+    let skybox = renderer.bake_material(SkyboxMaterial::new(skybox_texture).boxed());
+    add_node(&mut renderer, cube, skybox);
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+    renderer.render_scene(root, camera);
+
+    let log = log.borrow();
+    let skybox_program = program_with_uniform(&log, "skybox").unwrap();
+    let pbr_program = program_with_uniform(&log, "modelMatrix").unwrap();
+
+    assert_eq!(
+      draws_with_depth_func(&log),
+      vec![
+        (Some(pbr_program), Some(DepthFunc::Less)),
+        (Some(skybox_program), Some(DepthFunc::Lequal)),
+      ]
+    );
+    assert!(log.contains(&Command::BindTexture {
+      target: TextureKind::CubeMap,
+      texture: Some(cube_texture),
+    }));
+  }
+
+  #[test]
+  fn render_target_attaches_color_and_depth() {
+    let (mut renderer, log) = renderer();
+
+    let target_handle = renderer.bake_render_target(64, 32, Sampler::framebuffer(), true);
+    let target = renderer.targets[target_handle].clone();
+    let fb = renderer.framebuffers[target.fb];
+    let color = renderer.images[renderer.textures[target.color_texture].source].gpu_texture;
+    let depth =
+      renderer.images[renderer.textures[target.depth_texture.unwrap()].source].gpu_texture;
+
+    let log = log.borrow();
+
+    assert!(log.contains(&Command::BindFramebuffer(Some(fb))));
+    assert!(log.contains(&Command::FramebufferTexture {
+      attachment: FramebufferAttachment::ColorAttachment0,
+      texture: Some(color),
+    }));
+    assert!(log.contains(&Command::FramebufferTexture {
+      attachment: FramebufferAttachment::DepthAttachment,
+      texture: Some(depth),
+    }));
+    assert!(log.contains(&Command::TexImage {
+      target: TextureKind::Texture2d,
+      level: 0,
+      format: TextureFormat::Depth,
+      kind: TypedArrayKind::Uint16,
+      size: Some((64, 32)),
+    }));
+    assert_eq!(log.last(), Some(&Command::BindFramebuffer(None)));
+  }
+
+  #[test]
+  fn pass_renders_into_its_target() {
+    let (mut renderer, log) = renderer();
+
+    let target_handle = renderer.bake_render_target(16, 16, Sampler::framebuffer(), false);
+    let fb = renderer.framebuffers[renderer.targets[target_handle].fb];
+
+    log.borrow_mut().clear();
+
+    Pass::new()
+      .set_render_target_handle(Some(target_handle))
+      .set_clean_depth(false)
+      .set_handler(|renderer| renderer.ctx.draw_arrays(DrawMode::Triangles, 0, 3))
+      .render(&mut renderer);
+
+    assert_eq!(
+      *log.borrow(),
+      vec![
+        Command::BindFramebuffer(Some(fb)),
+        Command::ClearColor([1.0, 1.0, 1.0, 1.0]),
+        Command::Clear {
+          color: true,
+          depth: false,
+        },
+        Command::Draw {
+          program: None,
+          mode: DrawMode::Triangles,
+          count: 3,
+          indexed: false,
+          instances: None,
+        },
+        Command::BindFramebuffer(None),
+      ]
+    );
+  }
+
+  #[test]
+  fn transparent_material_blends_without_depth_writes() {
+    let (mut renderer, log) = renderer();
+
+    let ball = renderer.bake_ball_geometry(1.0);
+    let glass = renderer.bake_material(
+      PbrMaterial::new()
+        .set_opacity(0.5)
+        .set_blend(BlendMode::Alpha)
+        .boxed(),
+    );
+    add_node(&mut renderer, ball, glass);
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.render_scene(root, camera);
+
+    let log = log.borrow();
+    let draw = log
+      .iter()
+      .position(|command| matches!(command, Command::Draw { .. }))
+      .unwrap();
+    let state = &log[..draw];
+
+    assert!(state.contains(&Command::SetFeature {
+      feature: Feature::Blend,
+      enabled: true,
+    }));
+    assert!(state.contains(&Command::BlendFunc {
+      src: BlendFactor::SrcAlpha,
+      dst: BlendFactor::OneMinusSrcAlpha,
+    }));
+    assert!(state.contains(&Command::DepthMask(false)));
+    assert!(state.iter().any(|command| matches!(
+      command,
+      Command::SetUniform { name, value, .. } if name == "opacity" && value == &vec![0.5]
+    )));
+  }
+
+  #[test]
+  fn deleting_geometry_frees_its_buffers() {
+    let (mut renderer, log) = renderer();
+
+    let geometry = renderer.bake_cuboid_geometry(Vector3::new(1.0, 1.0, 1.0));
+    let created = log
+      .borrow()
+      .iter()
+      .filter(|command| matches!(command, Command::CreateBuffer { .. }))
+      .count();
+
+    renderer.delete_geometry(geometry);
+
+    let deleted = log
+      .borrow()
+      .iter()
+      .filter(|command| matches!(command, Command::DeleteBuffer(_)))
+      .count();
+
+    assert!(created > 0);
+    assert_eq!(created, deleted);
+    assert!(renderer.buffers.is_empty());
+  }
+
+  #[test]
+  fn warm_up_compiles_each_variant_once_and_shares_stages() {
+    let (mut renderer, log) = renderer();
+    let ball = renderer.bake_ball_geometry(1.0);
+
+    // equal variants share a program, unlit only changes the fragment stage
+    for material in [
+      PbrMaterial::new().boxed(),
+      PbrMaterial::new().boxed(),
+      PbrMaterial::new().set_unlit(true).boxed(),
+    ] {
+      let material = renderer.bake_material(material);
+      add_node(&mut renderer, ball, material);
+    }
+
+    let root = renderer.scene.get_root_handle();
+    let count = |log: &CommandLog, expected: &Command| {
+      log
+        .borrow()
+        .iter()
+        .filter(|command| *command == expected)
+        .count()
+    };
+
+    assert!(log
+      .borrow()
+      .iter()
+      .all(|command| !matches!(command, Command::CreateProgram { .. })));
+    assert_eq!(renderer.warm_up_shaders(root), 2);
+    assert_eq!(renderer.warm_up_shaders(root), 0);
+    assert_eq!(count(&log, &Command::CompileShader(ShaderStage::Vertex)), 1);
+    assert_eq!(
+      count(&log, &Command::CompileShader(ShaderStage::Fragment)),
+      2
+    );
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let programs = log
+      .borrow()
+      .iter()
+      .filter(|command| matches!(command, Command::CreateProgram { .. }))
+      .count();
+
+    renderer.scene.update_matrix_world();
+    renderer.render_scene(root, camera);
+
+    assert_eq!(programs, 2);
+    assert!(
+      log
+        .borrow()
+        .iter()
+        .filter(|command| matches!(command, Command::CreateProgram { .. }))
+        .count()
+        == programs
+    );
+  }
+
+  #[test]
+  fn failed_shaders_are_reported_and_reloads_fall_back() {
+    let (mut renderer, log) = renderer();
+    let vert = "attribute vec3 position;\nuniform mat4 modelMatrix;\nvoid main() {}";
+    let frag = "void main() {}";
+    let broken = "#ifdef USE_INSTANCING\n#else\n#error no instancing\n#endif\nvoid main() {}";
+
+    let cube = renderer.bake_cuboid_geometry(Vector3::new(1.0, 1.0, 1.0));
+    let material = renderer.bake_material(ShaderMaterial::new(vert, frag).boxed());
+    let failed = renderer.bake_material(ShaderMaterial::new(vert, broken).boxed());
+    add_node(&mut renderer, cube, material);
+    add_node(&mut renderer, cube, failed);
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+    let key = renderer
+      .variant_key(material, VariantOptions::default())
+      .unwrap();
+    let draws = |log: &CommandLog| {
+      log
+        .borrow()
+        .iter()
+        .filter_map(|command| match command {
+          Command::Draw { program, .. } => *program,
+          _ => None,
+        })
+        .collect::<Vec<_>>()
+    };
+
+    renderer.scene.update_matrix_world();
+    // the material without a program is skipped
+    renderer.render_scene(root, camera);
+
+    let program = program_with_uniform(&log.borrow(), "modelMatrix").unwrap();
+
+    assert_eq!(draws(&log), vec![program]);
+
+    let error = renderer.reload_shader(key, vert, broken).unwrap_err();
+    let diagnostics = &error.downcast_ref::<ShaderError>().unwrap().diagnostics;
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+      diagnostics[0].to_string(),
+      "fragment error fragment:3: no instancing"
+    );
+
+    log.borrow_mut().clear();
+    renderer.render_scene(root, camera);
+    assert_eq!(draws(&log), vec![program]);
+
+    log.borrow_mut().clear();
+    renderer
+      .reload_shader(key, vert, "uniform float reloaded;\nvoid main() {}")
+      .unwrap();
+    renderer.render_scene(root, camera);
+
+    let reloaded = program_with_uniform(&log.borrow(), "reloaded");
+
+    assert!(reloaded.is_some() && reloaded != Some(program));
+    // the replaced program is deleted once the cache lets go of it
+    assert!(log.borrow().contains(&Command::DeleteProgram(program)));
+    // its fragment stage went with it, the vertex stage is linked by the new program
+    assert!(log
+      .borrow()
+      .contains(&Command::DeleteShader(ShaderStage::Fragment)));
+    assert!(!log
+      .borrow()
+      .contains(&Command::DeleteShader(ShaderStage::Vertex)));
+    assert_eq!(draws(&log), vec![reloaded.unwrap()]);
+
+    // failed variants can be fixed by a reload as well
+    let failed_key = renderer
+      .variant_key(failed, VariantOptions::default())
+      .unwrap();
+
+    renderer.reload_shader(failed_key, vert, frag).unwrap();
+    log.borrow_mut().clear();
+    renderer.render_scene(root, camera);
+
+    assert_eq!(draws(&log).len(), 2);
+    assert!(renderer
+      .reload_shader("0".parse().unwrap(), vert, frag)
+      .is_err());
+  }
+
+  #[test]
+  fn preprocess_follows_defines() {
+    let src = "attribute vec3 position;\n#ifdef USE_UV1\nattribute vec2 uv1;\n#else\nattribute vec2 uv;\n#endif\n";
+
+    assert_eq!(
+      declarations(&preprocess(src, &[]).0, "attribute"),
+      vec!["position", "uv"]
+    );
+    assert_eq!(
+      declarations(&preprocess(src, &[Define::def("USE_UV1")]).0, "attribute"),
+      vec!["position", "uv1"]
+    );
+  }
+
+  #[test]
+  fn extension_directives_go_before_the_precision_statement() {
+    let src = "uniform float a;\n#extension GL_EXT_shader_texture_lod : enable\n#error here\n";
+    let (source, map) = stage_source(
+      &ShaderChunks::new(),
+      src,
+      ShaderStage::Fragment,
+      &[Define::def("A")],
+    )
+    .unwrap();
+    let lines: Vec<&str> = source.lines().collect();
+
+    assert_eq!(lines[0], "#extension GL_EXT_shader_texture_lod : enable");
+    assert_eq!(
+      lines
+        .iter()
+        .filter(|line| line.contains("#extension"))
+        .count(),
+      1
+    );
+    assert!(lines.contains(&"precision highp float;"));
+
+    // the directive leaves an empty line, the source keeps its line numbers
+    let error = lines
+      .iter()
+      .position(|line| *line == "#error here")
+      .unwrap();
+
+    assert_eq!(map.get(error + 1).map(|source| source.line), Some(3));
+  }
+
+  #[test]
+  fn maps_take_units_in_turn_up_to_the_context_limit() {
+    let backend = RecordingBackend::new().set_max_texture_units(2);
+    let log = backend.log();
+    let mut renderer = Renderer::new(backend);
+    let pixels = [255; 4];
+    let mut texture = || {
+      renderer.bake_2d_texture_from_pixels(TextureFormat::RGBA, Sampler::default(), 1, 1, &pixels)
+    };
+    let (color, emissive, normal) = (texture(), texture(), texture());
+
+    let ball = renderer.bake_ball_geometry(1.0);
+    let material = renderer.bake_material(
+      PbrMaterial::new()
+        .set_color_map(Some(color))
+        .set_emissive_map(Some(emissive))
+        .set_normal_map(Some(normal))
+        .boxed(),
+    );
+    add_node(&mut renderer, ball, material);
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+    log.borrow_mut().clear();
+    renderer.render_scene(root, camera);
+
+    let log = log.borrow();
+    let unit = |sampler: &str| {
+      log.iter().find_map(|command| match command {
+        Command::SetUniform { name, value, .. } if name == sampler => Some(value[0]),
+        _ => None,
+      })
+    };
+
+    // no unit is reserved for the maps the material doesn't have
+    assert_eq!(unit("colorMap"), Some(0.0));
+    assert_eq!(unit("normalMap"), Some(1.0));
+    // past the limit of the context
+    assert_eq!(unit("emissiveMap"), None);
+    assert!(!log.contains(&Command::ActiveTexture(2)));
+  }
+
+  #[test]
+  fn shadow_maps_render_casters_and_receivers_sample_them() {
+    let (mut renderer, log) = renderer();
+
+    let ball = renderer.bake_ball_geometry(1.0);
+    let pbr = renderer.bake_material(PbrMaterial::new().boxed());
+    add_node(&mut renderer, ball, pbr);
+    let ground = add_node(&mut renderer, ball, pbr);
+    let unshadowed = add_node(&mut renderer, ball, pbr);
+
+    renderer.scene.get_node_mut(ground).unwrap().cast_shadows = false;
+    renderer
+      .scene
+      .get_node_mut(unshadowed)
+      .unwrap()
+      .receive_shadows = false;
+
+    renderer.insert_light(
+      Light::directional(Vector3::new(0.0, -1.0, 0.0))
+        .set_cascade_splits(vec![10.0, 50.0])
+        .set_shadow_map_size(512),
+    );
+    renderer.insert_light(
+      Light::spot(
+        Vector3::new(0.0, 10.0, 0.0),
+        Vector3::new(0.0, -1.0, 0.0),
+        0.5,
+        30.0,
+      )
+      .set_cast_shadows(false),
+    );
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+    renderer.render_shadows(root, camera);
+    renderer.render_scene(root, camera);
+
+    let log = log.borrow();
+    let depth_maps = log
+      .iter()
+      .filter(|command| {
+        matches!(
+          command,
+          Command::TexImage {
+            format: TextureFormat::Depth,
+            size: Some((512, 512)),
+            ..
+          }
+        )
+      })
+      .count();
+    let shadow_program = program_with_uniform(&log, "lightViewProjection").unwrap();
+    let shadow_draws = log
+      .iter()
+      .filter(|command| matches!(command, Command::Draw { program, .. } if *program == Some(shadow_program)))
+      .count();
+    let mut program_defines: Vec<&Vec<String>> = log
+      .iter()
+      .filter_map(|command| match command {
+        Command::CreateProgram {
+          defines, uniforms, ..
+        } if uniforms.iter().any(|u| u == "lightColor") => Some(defines),
+        _ => None,
+      })
+      .collect();
+
+    // programs are compiled in variant key order
+    program_defines.sort();
+
+    assert_eq!(depth_maps, 2);
+    // both casters in each cascade, the ground is skipped
+    assert_eq!(shadow_draws, 4);
+    assert_eq!(
+      program_defines,
+      vec![
+        &vec![
+          "USE_DIRECTIONAL_SHADOWS".to_string(),
+          "USE_CASCADE1".to_string(),
+          "USE_SPOT_LIGHTS".to_string(),
+        ],
+        &vec!["USE_SPOT_LIGHTS".to_string()],
+      ]
+    );
+
+    // a sampler per cascade, from the first unit since the material has no maps
+    let units = |sampler: &str| -> Vec<f32> {
+      log
+        .iter()
+        .filter_map(|command| match command {
+          Command::SetUniform { name, value, .. } if name == sampler => Some(value[0]),
+          _ => None,
+        })
+        .collect()
+    };
+
+    assert!(units("cascadeMap0").iter().all(|unit| *unit == 0.0));
+    assert!(units("cascadeMap1").iter().all(|unit| *unit == 1.0));
+    assert!(!units("cascadeMap1").is_empty());
+    assert!(units("cascadeMap2").is_empty());
+    assert!(units("spotShadowMap0").is_empty());
+    assert!(log.iter().any(|command| matches!(
+      command,
+      Command::SetUniform { name, value, .. } if name == "cascadeCount" && value == &vec![2.0]
+    )));
+    assert_eq!(renderer.light_frame.shadow_maps().len(), 2);
+  }
+
+  #[test]
+  fn geometry_without_vertex_colors_reads_constant_white() {
+    let (mut renderer, log) = renderer();
+
+    let material = renderer.bake_material(PbrMaterial::new().set_vertex_colors(true).boxed());
+
+    let plain = renderer.bake_ball_geometry(1.0);
+    let colored = renderer.bake_ball_geometry(1.0);
+    let normals = renderer.geometries[colored].attributes[&AttributeName::Normal];
+    renderer.geometries[colored]
+      .attributes
+      .insert(AttributeName::Color0, normals);
+
+    add_node(&mut renderer, plain, material);
+    add_node(&mut renderer, colored, material);
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+    renderer.render_scene(root, camera);
+
+    let log = log.borrow();
+    let color_location = log
+      .iter()
+      .find_map(|command| match command {
+        Command::CreateProgram { attributes, .. } => {
+          attributes.iter().position(|name| name == "color")
+        }
+        _ => None,
+      })
+      .unwrap() as u32;
+    let constants = log
+      .iter()
+      .filter(|command| {
+        **command
+          == Command::ConstantAttribute {
+            location: color_location,
+            value: [1.0, 1.0, 1.0, 1.0],
+          }
+      })
+      .count();
+
+    assert_eq!(draws_with_depth_func(&log).len(), 2);
+    assert_eq!(constants, 1);
+  }
+
+  #[test]
+  fn deleted_meshes_leave_their_nodes_empty() {
+    let (mut renderer, log) = renderer();
+
+    let ball = renderer.bake_ball_geometry(1.0);
+    let pbr = renderer.bake_material(PbrMaterial::new().boxed());
+    let node = add_node(&mut renderer, ball, pbr);
+    let mesh = renderer.scene.get_node(node).unwrap().mesh.unwrap();
+
+    renderer.delete_mesh(mesh).unwrap();
+
+    assert_eq!(renderer.scene.get_node(node).unwrap().mesh, None);
+
+    // a node still pointing at a freed mesh is skipped too
+    renderer.scene.get_node_mut(node).unwrap().mesh = Some(mesh);
+    renderer.insert_light(Light::directional(Vector3::new(0.0, -1.0, 0.0)));
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+    renderer.render_shadows(root, camera);
+    renderer.render_scene(root, camera);
+
+    assert!(draws_with_depth_func(&log.borrow()).is_empty());
+  }
+
+  #[test]
+  fn deleted_materials_keep_render_target_textures() {
+    let (mut renderer, _) = renderer();
+
+    let target = renderer.bake_render_target(16, 16, Sampler::framebuffer(), true);
+    let color = renderer.targets[target].color_texture;
+    let material = renderer.bake_material(PbrMaterial::new().set_color_map(Some(color)).boxed());
+
+    renderer.delete_material(material).unwrap();
+
+    assert!(renderer.textures.get(color).is_some());
+  }
+
+  #[test]
+  fn referenced_resources_are_kept_and_missing_ones_skipped() {
+    let (mut renderer, log) = renderer();
+    let pixels = [255; 4];
+    let color =
+      renderer.bake_2d_texture_from_pixels(TextureFormat::RGBA, Sampler::default(), 1, 1, &pixels);
+    let target = renderer.bake_render_target(16, 16, Sampler::framebuffer(), false);
+    let target_color = renderer.targets[target].color_texture;
+
+    let ball = renderer.bake_ball_geometry(1.0);
+    let material = renderer.bake_material(PbrMaterial::new().set_color_map(Some(color)).boxed());
+    let post = renderer.bake_material(PbrMaterial::new().set_color_map(Some(target_color)).boxed());
+    add_node(&mut renderer, ball, material);
+
+    assert_eq!(renderer.delete_texture(color), None);
+    assert_eq!(renderer.delete_render_target(target), None);
+    assert_eq!(renderer.delete_geometry(ball), None);
+    assert!(renderer.textures.get(color).is_some());
+    assert!(renderer.targets.get(target).is_some());
+
+    renderer.delete_material(post).unwrap();
+    renderer.delete_render_target(target).unwrap();
+
+    // freed behind the renderer's back, the draw goes on without the map
+    renderer.textures.remove(color);
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+    log.borrow_mut().clear();
+    renderer.render_scene(root, camera);
+
+    let log = log.borrow();
+    let color_map_set = log
+      .iter()
+      .any(|command| matches!(command, Command::SetUniform { name, .. } if name == "colorMap"));
+
+    assert_eq!(draws_with_depth_func(&log).len(), 1);
+    assert!(!color_map_set);
+  }
+}
@@ -0,0 +1,209 @@
+use log::warn;
+use na::Point3;
+use std::cmp::Ordering;
+
+use super::material::Material;
+use super::renderer::{Camera, Geometry, Renderer};
+use super::shader_cache::VariantKey;
+use crate::handle::Handle;
+use crate::scene::node::Node;
+
+// Buckets in draw order. Background comes after the opaque buckets so the skybox,
+// drawn at the far plane with LEQUAL, only fills pixels nothing else covered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RenderQueue {
+  Opaque,
+  AlphaTest,
+  Background,
+  Transparent,
+  Overlay,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderItem {
+  pub queue: RenderQueue,
+  pub variant: VariantKey,
+  pub material: Handle<dyn Material>,
+  pub geometry: Handle<Geometry>,
+  pub node: Handle<Node>,
+  // view space distance of the node origin along the view direction
+  pub depth: f32,
+}
+
+fn material_order(a: Handle<dyn Material>, b: Handle<dyn Material>) -> Ordering {
+  a.index().into_raw_parts().cmp(&b.index().into_raw_parts())
+}
+
+fn compare_items(a: &RenderItem, b: &RenderItem) -> Ordering {
+  a.queue.cmp(&b.queue).then_with(|| match a.queue {
+    // grouped by program and material to avoid state changes, front to back for early z
+    RenderQueue::Opaque | RenderQueue::AlphaTest => a
+      .variant
+      .cmp(&b.variant)
+      .then_with(|| material_order(a.material, b.material))
+      .then_with(|| a.depth.partial_cmp(&b.depth).unwrap_or(Ordering::Equal)),
+    // back to front so blending composites correctly
+    RenderQueue::Transparent => b.depth.partial_cmp(&a.depth).unwrap_or(Ordering::Equal),
+    // scene order
+    RenderQueue::Background | RenderQueue::Overlay => Ordering::Equal,
+  })
+}
+
+impl Renderer {
+  pub fn build_render_queue(
+    &self,
+    root_handle: Handle<Node>,
+    camera_handle: Handle<Camera>,
+  ) -> Vec<RenderItem> {
+    let camera = match self.cameras.get(camera_handle) {
+      Some(camera) => camera,
+      None => {
+        warn!("camera {:?} is missing, the queue is empty", camera_handle);
+        return vec![];
+      }
+    };
+    let mut items = vec![];
+
+    for handle in self.scene.collect_visible_sub_items(root_handle) {
+      let node = match self.scene.get_node(handle) {
+        Some(node) => node,
+        None => continue,
+      };
+      let mesh = match node.mesh.and_then(|mesh| self.meshes.get(mesh)) {
+        Some(mesh) => mesh,
+        None => continue,
+      };
+
+      let origin = node.matrix_world.transform_point(&Point3::origin());
+      let depth = -camera.view.transform_point(&origin).z;
+
+      for primitive in &mesh.primitives {
+        if let Some(material_handle) = primitive.material {
+          let options = self.variant_options(node);
+
+          // e.g. a deleted material still set on the primitive
+          let (material, variant) = match (
+            self.materials.get(material_handle),
+            self.variant_key(material_handle, options),
+          ) {
+            (Some(material), Some(variant)) => (material, variant),
+            _ => {
+              warn!(
+                "skip primitive of {:?}, material {:?} is missing",
+                handle, material_handle
+              );
+              continue;
+            }
+          };
+
+          items.push(RenderItem {
+            queue: material.params().queue,
+            variant,
+            material: material_handle,
+            geometry: primitive.geometry,
+            node: handle,
+            depth,
+          });
+        }
+      }
+    }
+
+    // stable, equal items keep the scene order
+    items.sort_by(compare_items);
+
+    items
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use na::Vector3;
+
+  use super::*;
+  use crate::renderer::webgl::material::PbrMaterial;
+  use crate::renderer::webgl::recording::RecordingBackend;
+  use crate::renderer::webgl::renderer::VariantOptions;
+  use crate::scene::node::compose_matrix;
+
+  fn add_node(renderer: &mut Renderer, material: Handle<dyn Material>, z: f32) -> Handle<Node> {
+    let geometry = renderer.bake_cuboid_geometry(Vector3::new(1.0, 1.0, 1.0));
+    let mesh = renderer.compose_mesh(geometry, material, None);
+    let mut node = Node::new(Some(renderer.scene.get_root_handle()));
+
+    node.mesh = Some(mesh);
+    node.matrix_local = compose_matrix(Some(Vector3::new(0.0, 0.0, z)), None, None);
+
+    renderer.insert_node(node)
+  }
+
+  #[test]
+  fn items_are_sorted_by_queue_tag_and_depth() {
+    let mut renderer = Renderer::new(RecordingBackend::new());
+
+    let opaque = renderer.bake_material(PbrMaterial::new().boxed());
+    let unlit = renderer.bake_material(PbrMaterial::new().set_unlit(true).boxed());
+    let transparent = renderer.bake_material(
+      PbrMaterial::new()
+        .set_queue(RenderQueue::Transparent)
+        .boxed(),
+    );
+
+    // camera at the origin looking down -z
+    let far_transparent = add_node(&mut renderer, transparent, -20.0);
+    let near_transparent = add_node(&mut renderer, transparent, -2.0);
+    let far_opaque = add_node(&mut renderer, opaque, -10.0);
+    let unlit_node = add_node(&mut renderer, unlit, -1.0);
+    let near_opaque = add_node(&mut renderer, opaque, -5.0);
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+
+    let queue = renderer.build_render_queue(root, camera);
+    let nodes: Vec<Handle<Node>> = queue.iter().map(|item| item.node).collect();
+
+    // opaque variants are grouped in key order, front to back within a group
+    let options = VariantOptions::default();
+    let opaque_nodes =
+      if renderer.variant_key(opaque, options) < renderer.variant_key(unlit, options) {
+        vec![near_opaque, far_opaque, unlit_node]
+      } else {
+        vec![unlit_node, near_opaque, far_opaque]
+      };
+
+    assert_eq!(nodes[..3], opaque_nodes[..]);
+    assert_eq!(nodes[3..], [far_transparent, near_transparent]);
+    assert!(queue[0].variant <= queue[2].variant);
+  }
+
+  #[test]
+  fn primitives_of_missing_materials_are_skipped() {
+    let mut renderer = Renderer::new(RecordingBackend::new());
+
+    let kept = renderer.bake_material(PbrMaterial::new().boxed());
+    let deleted = renderer.bake_material(PbrMaterial::new().boxed());
+    let kept_node = add_node(&mut renderer, kept, -1.0);
+    add_node(&mut renderer, deleted, -2.0);
+
+    renderer.delete_material(deleted).unwrap();
+
+    let camera = renderer.cameras.insert(Camera::default());
+    let root = renderer.scene.get_root_handle();
+
+    renderer.scene.update_matrix_world();
+
+    let nodes: Vec<Handle<Node>> = renderer
+      .build_render_queue(root, camera)
+      .iter()
+      .map(|item| item.node)
+      .collect();
+
+    assert_eq!(nodes, [kept_node]);
+
+    renderer.cameras.remove(camera);
+
+    assert!(renderer.build_render_queue(root, camera).is_empty());
+    renderer.render_scene(root, camera);
+  }
+}
@@ -1,46 +1,72 @@
-use generational_arena::{Arena, Index};
-use log::info;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
 use na::Matrix4;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::default::Default;
-use web_sys::{WebGlBuffer, WebGlFramebuffer, WebGlTexture};
+use std::rc::Rc;
 
+use super::asset_cache::AssetCache;
+use super::backend::{as_bytes, Backend, GpuBuffer, GpuFramebuffer, GpuTexture};
 use super::context::{
-  BufferItem, BufferTarget, BufferUsage, Context, Feature, TexParam, TexParamName, TextureKind,
+  BufferItem, BufferTarget, BufferUsage, DrawMode, Feature, TexParam, TexParamName, TextureKind,
 };
-use super::material::Material;
+use super::define::Define;
+use super::light::{LightFeatures, LightFrame, Lights, ShadowTargetsByLight};
+use super::material::{Material, RenderContext, UniformValue};
 use super::shader::Shader;
+use super::shader_cache::{ShaderCache, ShaderVariant, VariantKey};
 
 use super::shader::{AttributeName, AttributeOptions};
-use crate::scene::node::Node;
+use crate::handle::{Handle, Pool};
+use crate::scene::node::{Node, UserData};
 use crate::scene::scene::Scene;
 
+#[derive(Debug, Clone)]
+pub struct Buffer {
+  pub gpu_buffer: GpuBuffer,
+  pub target: BufferTarget,
+  pub data: Vec<u8>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Accessor {
-  pub buffer: Index,
+  pub buffer: Handle<Buffer>,
   pub count: i32,
   pub options: AttributeOptions,
 }
 
-pub type Attributes = HashMap<AttributeName, Index>;
-pub type Indices = Option<Index>;
+pub type Attributes = HashMap<AttributeName, Handle<Accessor>>;
+pub type Indices = Option<Handle<Accessor>>;
 
 #[derive(Debug, Clone)]
 pub struct Geometry {
   pub attributes: Attributes,
   pub indices: Indices,
+  pub draw_mode: DrawMode,
 }
 
 #[derive(Debug, Clone)]
 pub struct Primitive {
-  pub geometry: Index,
-  pub material: Option<Index>,
+  pub geometry: Handle<Geometry>,
+  pub material: Option<Handle<dyn Material>>,
+  // material restored when no variant is selected
+  pub default_material: Option<Handle<dyn Material>>,
+  pub variants: HashMap<String, Handle<dyn Material>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Mesh {
   pub primitives: Vec<Primitive>,
   pub name: Option<String>,
+  pub user_data: UserData,
+}
+
+// per-instance translation, rotation and scale accessors drawn with divisor 1
+#[derive(Debug, Clone)]
+pub struct InstanceSet {
+  pub attributes: Attributes,
+  pub count: i32,
 }
 
 #[derive(Debug, Clone)]
@@ -72,7 +98,24 @@ impl Sampler {
     }
   }
 
-  pub fn set_params(&self, kind: TextureKind, ctx: &Context) {
+  // what WebGL1 can sample from a non power of two texture, no mipmaps and no repeat
+  pub fn without_mipmaps(&self) -> Self {
+    let min_filter = match self.min_filter {
+      TexParam::Nearest | TexParam::NearestMipMapNearest | TexParam::NearestMimMapLinear => {
+        TexParam::Nearest
+      }
+      _ => TexParam::Linear,
+    };
+
+    Sampler {
+      wrap_s: TexParam::ClampToEdge,
+      wrap_t: TexParam::ClampToEdge,
+      min_filter,
+      mag_filter: self.mag_filter,
+    }
+  }
+
+  pub fn set_params(&self, kind: TextureKind, ctx: &dyn Backend) {
     ctx.texture_parameter(kind, TexParamName::TextureMinFilter, self.min_filter);
     ctx.texture_parameter(kind, TexParamName::TextureMagFilter, self.mag_filter);
     ctx.texture_parameter(kind, TexParamName::TextureWrapS, self.wrap_s);
@@ -80,17 +123,31 @@ impl Sampler {
   }
 }
 
+#[derive(Debug, Clone)]
+pub struct ImageSource {
+  pub mime_type: String,
+  pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Image {
+  pub gpu_texture: GpuTexture,
+  pub source: Option<ImageSource>,
+  // approximate GPU memory, mipmaps included
+  pub byte_size: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct Texture {
-  pub source: Index,
-  pub sampler: Index,
+  pub source: Handle<Image>,
+  pub sampler: Handle<Sampler>,
 }
 
 #[derive(Debug, Clone)]
 pub struct RenderTarget {
-  pub fb: Index,
-  pub color_texture: Index,
-  pub depth_texture: Option<Index>,
+  pub fb: Handle<GpuFramebuffer>,
+  pub color_texture: Handle<Texture>,
+  pub depth_texture: Option<Handle<Texture>>,
 }
 
 #[derive(Debug, Clone)]
@@ -114,21 +171,30 @@ impl Camera {
   }
 }
 
-pub type Buffers = Arena<WebGlBuffer>;
-pub type Images = Arena<WebGlTexture>;
-pub type Framebuffers = Arena<WebGlFramebuffer>;
-pub type Targets = Arena<RenderTarget>;
-pub type Accessors = Arena<Accessor>;
-pub type Geometries = Arena<Geometry>;
-pub type Materials = Arena<Box<dyn Material>>;
-pub type Samplers = Arena<Sampler>;
-pub type Textures = Arena<Texture>;
-pub type Meshes = Arena<Mesh>;
-pub type Cameras = Arena<Camera>;
-pub type Shaders = HashMap<String, Shader>;
+pub type Buffers = Pool<Buffer>;
+pub type Images = Pool<Image>;
+pub type Framebuffers = Pool<GpuFramebuffer>;
+pub type Targets = Pool<RenderTarget>;
+pub type Accessors = Pool<Accessor>;
+pub type Geometries = Pool<Geometry>;
+pub type Materials = Pool<Box<dyn Material>, dyn Material>;
+pub type Samplers = Pool<Sampler>;
+pub type Textures = Pool<Texture>;
+pub type Meshes = Pool<Mesh>;
+pub type InstanceSets = Pool<InstanceSet>;
+pub type Cameras = Pool<Camera>;
+pub(crate) type VariantKeys = HashMap<(Handle<dyn Material>, VariantOptions), VariantKey>;
+
+// Draw state that picks a different variant of the same material.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct VariantOptions {
+  pub instanced: bool,
+  // only applied to materials that receive lights
+  pub lights: LightFeatures,
+}
 
 pub struct Renderer {
-  pub ctx: Context,
+  pub ctx: Box<dyn Backend>,
   pub buffers: Buffers,
   pub images: Images,
   pub framebuffers: Framebuffers,
@@ -139,19 +205,23 @@ pub struct Renderer {
   pub samplers: Samplers,
   pub textures: Textures,
   pub meshes: Meshes,
+  pub instance_sets: InstanceSets,
   pub cameras: Cameras,
+  pub lights: Lights,
+  // what the lights look like this frame, updated by render_shadows
+  pub light_frame: LightFrame,
+  pub(crate) shadow_targets: ShadowTargetsByLight,
   pub scene: Scene,
-  pub shaders: Shaders,
+  pub shaders: ShaderCache,
+  pub assets: AssetCache,
+  // hashing the sources is too slow to do per draw, keys are remembered by material
+  pub(crate) variant_keys: RefCell<VariantKeys>,
 }
 
 impl Renderer {
-  pub fn new(ctx: Context) -> Self {
-    ctx.get_extension("OES_element_index_uint").unwrap();
-    ctx.get_extension("WEBGL_depth_texture").unwrap();
-    ctx.get_extension("OES_texture_float").unwrap();
-
+  pub fn new(ctx: impl Backend + 'static) -> Self {
     Renderer {
-      ctx,
+      ctx: Box::new(ctx),
       buffers: Buffers::default(),
       images: Images::default(),
       framebuffers: Framebuffers::default(),
@@ -162,22 +232,116 @@ impl Renderer {
       samplers: Samplers::default(),
       textures: Textures::default(),
       meshes: Meshes::default(),
+      instance_sets: InstanceSets::default(),
       cameras: Cameras::default(),
+      lights: Lights::default(),
+      light_frame: LightFrame::default(),
+      shadow_targets: ShadowTargetsByLight::new(),
       scene: Scene::new(),
-      shaders: HashMap::new(),
+      shaders: ShaderCache::new(),
+      assets: AssetCache::new(),
+      variant_keys: RefCell::new(HashMap::new()),
+    }
+  }
+
+  pub fn variant_options(&self, node: &Node) -> VariantOptions {
+    VariantOptions {
+      instanced: node.instances.is_some(),
+      lights: self.light_features(node.receive_shadows),
+    }
+  }
+
+  pub fn variant_key(
+    &self,
+    material_handle: Handle<dyn Material>,
+    options: VariantOptions,
+  ) -> Option<VariantKey> {
+    if let Some(key) = self.variant_keys.borrow().get(&(material_handle, options)) {
+      return Some(*key);
     }
+
+    let material = self.materials.get(material_handle)?;
+    let key = material_variant(material.as_ref(), options).key();
+
+    self
+      .variant_keys
+      .borrow_mut()
+      .insert((material_handle, options), key);
+
+    Some(key)
   }
 
-  pub fn checkup_shader(&mut self, material: &Box<dyn Material>) {
-    let tag = material.get_tag();
+  // program for the material, compiled on first use. None when it fails to compile.
+  pub fn material_shader(
+    &self,
+    material_handle: Handle<dyn Material>,
+    options: VariantOptions,
+  ) -> Option<Rc<Shader>> {
+    let key = self.variant_key(material_handle, options)?;
 
-    if self.shaders.get(&tag).is_none() {
-      info!("compile shader: {}", tag);
+    if self.shaders.contains(key) {
+      return self.shaders.get(key);
+    }
 
-      self
-        .shaders
-        .insert(tag.clone(), material.create_shader(&self.ctx).unwrap());
-    };
+    let material = self.materials.get(material_handle)?;
+
+    self.shaders.get_or_compile(
+      self.ctx.as_ref(),
+      &material_variant(material.as_ref(), options),
+    )
+  }
+
+  // Compiles every variant the nodes under root may draw with, material variants
+  // included, so the first frame showing them doesn't stall. Lights should be inserted
+  // first since they change the variants. Returns the number of programs compiled.
+  pub fn warm_up_shaders(&self, root_handle: Handle<Node>) -> usize {
+    let compiled = self.shaders.len();
+
+    for handle in self.scene.collect_sub_items(root_handle) {
+      let node = self.scene.get_node(handle).unwrap();
+      let mesh = match node
+        .mesh
+        .and_then(|mesh_handle| self.meshes.get(mesh_handle))
+      {
+        Some(mesh) => mesh,
+        None => continue,
+      };
+
+      for primitive in &mesh.primitives {
+        let materials = primitive
+          .material
+          .iter()
+          .chain(primitive.default_material.iter())
+          .chain(primitive.variants.values());
+
+        for material_handle in materials {
+          self.material_shader(*material_handle, self.variant_options(node));
+        }
+      }
+    }
+
+    self.shaders.len() - compiled
+  }
+
+  // Rebuilds the program of a variant from new sources, keeping the defines it was
+  // compiled with. Materials built from the old sources keep drawing with it, and the
+  // previous program stays in use when compilation fails.
+  pub fn reload_shader(
+    &mut self,
+    key: VariantKey,
+    vertex_src: &str,
+    fragment_src: &str,
+  ) -> Result<()> {
+    let defines = self
+      .shaders
+      .defines(key)
+      .ok_or_else(|| anyhow!("no shader variant {}", key))?;
+    let shader = self.ctx.create_shader(vertex_src, fragment_src, &defines)?;
+
+    info!("reload shader variant: {}", key);
+    self.shaders.replace(key, shader);
+
+    Ok(())
   }
 
   pub fn insert_buffer<T: BufferItem>(
@@ -185,94 +349,154 @@ impl Renderer {
     target: BufferTarget,
     usage: BufferUsage,
     data: &[T],
-  ) -> Index {
-    self
-      .buffers
-      .insert(self.ctx.create_buffer(target, usage, data).unwrap())
+  ) -> Handle<Buffer> {
+    let gpu_buffer = self
+      .ctx
+      .create_buffer(target, usage, as_bytes(data))
+      .unwrap();
+
+    self.buffers.insert(Buffer {
+      gpu_buffer,
+      target,
+      data: as_bytes(data).to_vec(),
+    })
   }
 
-  pub fn bake_material(&mut self, material: Box<dyn Material>) -> Index {
-    self.checkup_shader(&material);
-
+  pub fn bake_material(&mut self, material: Box<dyn Material>) -> Handle<dyn Material> {
     self.materials.insert(material)
   }
 
-  pub fn insert_node(&mut self, node: Node) -> Index {
+  // Changes a value in the parameter block of the material, used by the next draw.
+  // None when the material has no block or the value would change the kind of an
+  // existing entry.
+  pub fn set_material_param(
+    &mut self,
+    material_handle: Handle<dyn Material>,
+    name: &str,
+    value: UniformValue,
+  ) -> Option<()> {
+    let block = self.materials.get_mut(material_handle)?.param_block_mut()?;
+
+    match block.get_mut(name) {
+      Some(current) if current.same_kind(&value) => *current = value,
+      Some(_) => return None,
+      None => {
+        block.insert(name.to_string(), value);
+      }
+    }
+
+    Some(())
+  }
+
+  // new material sharing the template and so the compiled program of the original
+  pub fn instance_material(
+    &mut self,
+    material_handle: Handle<dyn Material>,
+  ) -> Option<Handle<dyn Material>> {
+    let instance = self.materials.get(material_handle)?.instance()?;
+
+    Some(self.materials.insert(instance))
+  }
+
+  pub fn insert_node(&mut self, node: Node) -> Handle<Node> {
     self.scene.insert(node)
   }
 
-  pub fn insert_mesh(&mut self, mesh: Mesh) -> Index {
+  pub fn insert_mesh(&mut self, mesh: Mesh) -> Handle<Mesh> {
     self.meshes.insert(mesh)
   }
 
-  pub fn insert_accessor(&mut self, accessor: Accessor) -> Index {
+  pub fn insert_accessor(&mut self, accessor: Accessor) -> Handle<Accessor> {
     self.accessors.insert(accessor)
   }
 
-  pub fn insert_geometry(&mut self, geometry: Geometry) -> Index {
+  pub fn insert_geometry(&mut self, geometry: Geometry) -> Handle<Geometry> {
     self.geometries.insert(geometry)
   }
 
-  pub fn insert_image(&mut self, image: WebGlTexture) -> Index {
+  pub fn insert_image(&mut self, image: Image) -> Handle<Image> {
     self.images.insert(image)
   }
 
-  pub fn insert_sampler(&mut self, sampler: Sampler) -> Index {
+  pub fn insert_sampler(&mut self, sampler: Sampler) -> Handle<Sampler> {
     self.samplers.insert(sampler)
   }
 
-  pub fn insert_texture(&mut self, texture: Texture) -> Index {
+  pub fn insert_texture(&mut self, texture: Texture) -> Handle<Texture> {
     self.textures.insert(texture)
   }
 
-  pub fn insert_framebuffer(&mut self, fb: WebGlFramebuffer) -> Index {
+  pub fn insert_framebuffer(&mut self, fb: GpuFramebuffer) -> Handle<GpuFramebuffer> {
     self.framebuffers.insert(fb)
   }
 
-  pub fn insert_render_target(&mut self, target: RenderTarget) -> Index {
+  pub fn insert_render_target(&mut self, target: RenderTarget) -> Handle<RenderTarget> {
     self.targets.insert(target)
   }
 
-  pub fn render_scene(&self, root_handle: Index, camera_handle: Index) {
-    let visible_items = self.scene.collect_visible_sub_items(root_handle);
-    let camera = self.cameras.get(camera_handle).unwrap();
-
-    for handle in visible_items {
-      let node = self.scene.get_node(handle).unwrap();
-      let mesh = self.meshes.get(node.mesh.unwrap()).unwrap();
-
-      for primitive in &mesh.primitives {
-        if let Some(material_handle) = primitive.material {
-          let geometry = self.geometries.get(primitive.geometry).unwrap();
-          let material = self.materials.get(material_handle).unwrap();
+  pub fn render_scene(&self, root_handle: Handle<Node>, camera_handle: Handle<Camera>) {
+    let camera = match self.cameras.get(camera_handle) {
+      Some(camera) => camera,
+      None => {
+        warn!("skip render, camera {:?} is missing", camera_handle);
+        return;
+      }
+    };
 
-          self.draw_call(geometry, material, node, camera);
+    for item in self.build_render_queue(root_handle, camera_handle) {
+      let (node, geometry) = match (
+        self.scene.get_node(item.node),
+        self.geometries.get(item.geometry),
+      ) {
+        (Some(node), Some(geometry)) => (node, geometry),
+        _ => {
+          warn!("skip draw, geometry {:?} is missing", item.geometry);
+          continue;
         }
-      }
+      };
+
+      self.draw_call(geometry, item.material, node, camera);
     }
   }
 
   pub fn draw_call(
     &self,
     geometry: &Geometry,
-    material: &Box<dyn Material>,
+    material_handle: Handle<dyn Material>,
     node: &Node,
     camera: &Camera,
   ) {
-    let tag = material.get_tag();
+    let instances = node
+      .instances
+      .and_then(|handle| self.instance_sets.get(handle));
+
+    let material = match self.materials.get(material_handle) {
+      Some(material) => material,
+      None => {
+        warn!("skip draw, material {:?} is missing", material_handle);
+        return;
+      }
+    };
 
-    let shader = self.shaders.get(&tag).unwrap();
+    // materials without a program are skipped, the error was logged once
+    let shader = match self.material_shader(material_handle, self.variant_options(node)) {
+      Some(shader) => shader,
+      None => return,
+    };
 
     shader.bind();
 
     material.setup_shader(
-      &self.ctx,
-      &self.images,
-      &self.textures,
-      &self.samplers,
-      shader,
-      node,
-      camera,
+      &shader,
+      &RenderContext {
+        ctx: self.ctx.as_ref(),
+        images: &self.images,
+        textures: &self.textures,
+        samplers: &self.samplers,
+        node,
+        camera,
+        lights: &self.light_frame,
+      },
     );
 
     let params = material.params();
@@ -280,20 +504,76 @@ impl Renderer {
     self.ctx.set(Feature::CullFace, params.cull_face);
     self.ctx.set(Feature::DepthTest, params.depth_test);
     self.ctx.depth_func(params.depth_func);
+    self.ctx.depth_mask(params.depth_write);
+    self
+      .ctx
+      .set(Feature::SampleAlphaToCoverage, params.alpha_to_coverage);
+
+    match params.blend.factors() {
+      Some((src, dst)) => {
+        self.ctx.set(Feature::Blend, true);
+        self.ctx.blend_func(src, dst);
+      }
+      None => self.ctx.set(Feature::Blend, false),
+    }
+
+    self.draw_geometry(&shader, geometry, instances);
+  }
+
+  // the accessor with its buffer, a draw reading a deleted one is skipped
+  pub(crate) fn accessor_buffer(&self, handle: Handle<Accessor>) -> Option<(&Accessor, &Buffer)> {
+    let found = self
+      .accessors
+      .get(handle)
+      .and_then(|accessor| Some((accessor, self.buffers.get(accessor.buffer)?)));
 
+    if found.is_none() {
+      warn!("skip draw, accessor {:?} or its buffer is missing", handle);
+    }
+
+    found
+  }
+
+  // binds the attributes of the geometry, and of the instances when given, then draws
+  pub(crate) fn draw_geometry(
+    &self,
+    shader: &Shader,
+    geometry: &Geometry,
+    instances: Option<&InstanceSet>,
+  ) {
     let mut attr_amount = 0;
     let mut count = 0;
+    let mut divisors = vec![];
+    let mut missing = vec![];
 
-    for name in shader.get_attribute_locations().keys() {
+    for (name, location) in shader.get_attribute_locations() {
       if let Some(accessor_handle) = geometry.attributes.get(name) {
-        let accessor = self.accessors.get(*accessor_handle).unwrap();
-        let buffer = self.buffers.get(accessor.buffer).unwrap();
+        let (accessor, buffer) = match self.accessor_buffer(*accessor_handle) {
+          Some(found) => found,
+          None => return,
+        };
         self
           .ctx
-          .bind_buffer(BufferTarget::ArrayBuffer, Some(buffer));
+          .bind_buffer(BufferTarget::ArrayBuffer, Some(buffer.gpu_buffer));
         shader.bind_attribute(name, &accessor.options);
 
         count = accessor.count;
+      } else if let Some(accessor_handle) =
+        instances.and_then(|instances| instances.attributes.get(name))
+      {
+        let (accessor, buffer) = match self.accessor_buffer(*accessor_handle) {
+          Some(found) => found,
+          None => return,
+        };
+        self
+          .ctx
+          .bind_buffer(BufferTarget::ArrayBuffer, Some(buffer.gpu_buffer));
+        shader.bind_attribute(name, &accessor.options);
+        self.ctx.vertex_attrib_divisor(*location, 1);
+
+        divisors.push(*location);
+      } else {
+        missing.push((*location, missing_attribute_value(name)));
       }
 
       attr_amount += 1;
@@ -301,18 +581,66 @@ impl Renderer {
 
     self.ctx.switch_attributes(attr_amount);
 
+    // the shader may declare attributes this geometry lacks, e.g. a material with vertex colors
+    // shared by primitives without COLOR_0, they read a constant instead of a stale array
+    for (location, value) in missing {
+      self.ctx.constant_attribute(location, value);
+    }
+
+    if let Some(instances) = instances {
+      self.draw_instanced(geometry, count, instances.count);
+
+      for location in divisors {
+        self.ctx.vertex_attrib_divisor(location, 0);
+      }
+
+      return;
+    }
+
     if let Some(accessor_handle) = geometry.indices {
-      let accessor = self.accessors.get(accessor_handle).unwrap();
-      let indices = self.buffers.get(accessor.buffer).unwrap();
+      let (accessor, indices) = match self.accessor_buffer(accessor_handle) {
+        Some(found) => found,
+        None => return,
+      };
       count = accessor.count;
       self
         .ctx
-        .bind_buffer(BufferTarget::ElementArrayBuffer, Some(indices));
-      self
-        .ctx
-        .draw_elements(params.draw_mode, count, accessor.options.component_type, 0);
+        .bind_buffer(BufferTarget::ElementArrayBuffer, Some(indices.gpu_buffer));
+      self.ctx.draw_elements(
+        geometry.draw_mode,
+        count,
+        accessor.options.component_type,
+        0,
+      );
     } else {
-      self.ctx.draw_arrays(params.draw_mode, 0, count);
+      self.ctx.draw_arrays(geometry.draw_mode, 0, count);
+    }
+  }
+}
+
+// the variant a material draws with, instanced draws add USE_INSTANCING and lit
+// materials the defines of the light features
+pub fn material_variant(material: &dyn Material, options: VariantOptions) -> ShaderVariant<'_> {
+  let mut variant = material.shader_variant();
+
+  if options.instanced {
+    variant = variant.add_define(Define::def("USE_INSTANCING"));
+  }
+
+  if material.receives_lights() {
+    for define in options.lights.defines() {
+      variant = variant.add_define(define);
     }
   }
+
+  variant
+}
+
+// what a shader reads for an attribute the geometry has no accessor for,
+// vertex colors default to white so the material color shows unchanged
+fn missing_attribute_value(name: &AttributeName) -> [f32; 4] {
+  match name {
+    AttributeName::Color0 => [1.0, 1.0, 1.0, 1.0],
+    _ => [0.0, 0.0, 0.0, 1.0],
+  }
 }
@@ -0,0 +1,383 @@
+use std::fmt;
+
+use super::backend::GpuFramebuffer;
+
+use super::light::Light;
+use super::material::Material;
+use super::renderer::{
+  Accessor, Buffer, Geometry, Image, InstanceSet, Mesh, RenderTarget, Renderer, Texture,
+};
+use crate::handle::Handle;
+
+#[derive(Debug, Clone)]
+pub struct ResourceStats {
+  pub kind: &'static str,
+  pub count: usize,
+  // approximate, 0 when the size is not tracked
+  pub bytes: usize,
+  // the bytes are counted by another entry too and left out of the total
+  pub shared: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResourceReport {
+  pub entries: Vec<ResourceStats>,
+}
+
+impl ResourceReport {
+  pub fn total_bytes(&self) -> usize {
+    self
+      .entries
+      .iter()
+      .filter(|entry| !entry.shared)
+      .map(|entry| entry.bytes)
+      .sum()
+  }
+}
+
+impl fmt::Display for ResourceReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for entry in &self.entries {
+      writeln!(
+        f,
+        "{:<14} {:>6} {:>12} bytes",
+        entry.kind, entry.count, entry.bytes
+      )?;
+    }
+
+    write!(
+      f,
+      "{:<14} {:>6} {:>12} bytes",
+      "total",
+      "",
+      self.total_bytes()
+    )
+  }
+}
+
+fn target_textures(target: &RenderTarget) -> Vec<Handle<Texture>> {
+  Some(target.color_texture)
+    .into_iter()
+    .chain(target.depth_texture)
+    .collect()
+}
+
+fn geometry_accessors(geometry: &Geometry) -> Vec<Handle<Accessor>> {
+  geometry
+    .attributes
+    .values()
+    .copied()
+    .chain(geometry.indices)
+    .collect()
+}
+
+// The delete_* functions free the GL object where there is one and cascade to
+// dependencies that nothing else references anymore. They return None and keep
+// the resource while something still references it, draws would miss it otherwise.
+impl Renderer {
+  pub fn delete_buffer(&mut self, handle: Handle<Buffer>) -> Option<()> {
+    if self.accessors.iter().any(|(_, a)| a.buffer == handle) {
+      return None;
+    }
+
+    let buffer = self.buffers.remove(handle)?;
+
+    self.ctx.delete_buffer(buffer.gpu_buffer);
+
+    Some(())
+  }
+
+  pub fn delete_image(&mut self, handle: Handle<Image>) -> Option<()> {
+    if self.textures.iter().any(|(_, t)| t.source == handle) {
+      return None;
+    }
+
+    let image = self.images.remove(handle)?;
+
+    self.ctx.delete_texture(image.gpu_texture);
+
+    Some(())
+  }
+
+  pub fn delete_texture(&mut self, handle: Handle<Texture>) -> Option<()> {
+    if self.is_texture_used(handle) {
+      return None;
+    }
+
+    let texture = self.textures.remove(handle)?;
+
+    self.delete_image(texture.source);
+
+    if !self
+      .textures
+      .iter()
+      .any(|(_, t)| t.sampler == texture.sampler)
+    {
+      self.samplers.remove(texture.sampler);
+    }
+
+    Some(())
+  }
+
+  // shadow maps and post effects sample render target textures through materials too
+  fn is_texture_used(&self, handle: Handle<Texture>) -> bool {
+    self
+      .materials
+      .iter()
+      .any(|(_, m)| m.textures().contains(&handle))
+      || self
+        .targets
+        .iter()
+        .any(|(_, target)| target_textures(target).contains(&handle))
+  }
+
+  pub fn delete_framebuffer(&mut self, handle: Handle<GpuFramebuffer>) -> Option<()> {
+    let fb = self.framebuffers.remove(handle)?;
+
+    self.ctx.delete_framebuffer(fb);
+
+    Some(())
+  }
+
+  pub fn delete_render_target(&mut self, handle: Handle<RenderTarget>) -> Option<()> {
+    let target = self.targets.get(handle)?;
+    let sampled = target_textures(target).iter().any(|texture| {
+      self
+        .materials
+        .iter()
+        .any(|(_, m)| m.textures().contains(texture))
+    });
+
+    if sampled {
+      return None;
+    }
+
+    let target = self.targets.remove(handle)?;
+
+    self.delete_framebuffer(target.fb);
+    self.delete_texture(target.color_texture);
+
+    if let Some(depth_texture) = target.depth_texture {
+      self.delete_texture(depth_texture);
+    }
+
+    Some(())
+  }
+
+  pub fn delete_accessor(&mut self, handle: Handle<Accessor>) -> Option<()> {
+    if self.is_accessor_used(handle) {
+      return None;
+    }
+
+    let accessor = self.accessors.remove(handle)?;
+
+    self.delete_buffer(accessor.buffer);
+
+    Some(())
+  }
+
+  fn is_accessor_used(&self, handle: Handle<Accessor>) -> bool {
+    self
+      .geometries
+      .iter()
+      .any(|(_, geometry)| geometry_accessors(geometry).contains(&handle))
+      || self
+        .instance_sets
+        .iter()
+        .any(|(_, set)| set.attributes.values().any(|a| *a == handle))
+  }
+
+  pub fn delete_geometry(&mut self, handle: Handle<Geometry>) -> Option<()> {
+    let used = self.meshes.iter().any(|(_, mesh)| {
+      mesh
+        .primitives
+        .iter()
+        .any(|primitive| primitive.geometry == handle)
+    });
+
+    if used {
+      return None;
+    }
+
+    let geometry = self.geometries.remove(handle)?;
+
+    for accessor in geometry_accessors(&geometry) {
+      self.delete_accessor(accessor);
+    }
+
+    Some(())
+  }
+
+  pub fn delete_instance_set(&mut self, handle: Handle<InstanceSet>) -> Option<()> {
+    let set = self.instance_sets.remove(handle)?;
+
+    for accessor in set.attributes.values() {
+      self.delete_accessor(*accessor);
+    }
+
+    Some(())
+  }
+
+  pub fn delete_material(&mut self, handle: Handle<dyn Material>) -> Option<()> {
+    let material = self.materials.remove(handle)?;
+
+    self
+      .variant_keys
+      .borrow_mut()
+      .retain(|(material_handle, _), _| *material_handle != handle);
+
+    for texture in material.textures() {
+      self.delete_texture(texture);
+    }
+
+    Some(())
+  }
+
+  // nodes drawing the mesh are kept without one
+  pub fn delete_mesh(&mut self, handle: Handle<Mesh>) -> Option<()> {
+    let mesh = self.meshes.remove(handle)?;
+
+    for (_, node) in self.scene.iter_mut() {
+      if node.mesh == Some(handle) {
+        node.mesh = None;
+      }
+    }
+
+    for primitive in &mesh.primitives {
+      self.delete_geometry(primitive.geometry);
+
+      let materials = primitive
+        .material
+        .iter()
+        .chain(primitive.default_material.iter())
+        .chain(primitive.variants.values());
+
+      for material in materials {
+        let material_used = self.meshes.iter().any(|(_, m)| {
+          m.primitives.iter().any(|p| {
+            p.material == Some(*material)
+              || p.default_material == Some(*material)
+              || p.variants.values().any(|v| v == material)
+          })
+        });
+
+        if !material_used {
+          self.delete_material(*material);
+        }
+      }
+    }
+
+    Some(())
+  }
+
+  // frees the shadow maps of the light, the frame keeps it until the next render_shadows
+  pub fn delete_light(&mut self, handle: Handle<Light>) -> Option<()> {
+    self.lights.remove(handle)?;
+
+    if let Some(shadow_targets) = self.shadow_targets.remove(&handle) {
+      for target in shadow_targets.targets {
+        self.delete_render_target(target);
+      }
+    }
+
+    Some(())
+  }
+
+  fn texture_bytes(&self, handle: Handle<Texture>) -> usize {
+    self
+      .textures
+      .get(handle)
+      .and_then(|texture| self.images.get(texture.source))
+      .map(|image| image.byte_size)
+      .unwrap_or(0)
+  }
+
+  // textures and targets report the images they use, which the images entry counts already
+  pub fn resource_report(&self) -> ResourceReport {
+    let entries = vec![
+      ResourceStats {
+        kind: "buffers",
+        count: self.buffers.len(),
+        bytes: self.buffers.iter().map(|(_, b)| b.data.len()).sum(),
+        shared: false,
+      },
+      ResourceStats {
+        kind: "images",
+        count: self.images.len(),
+        bytes: self.images.iter().map(|(_, i)| i.byte_size).sum(),
+        shared: false,
+      },
+      ResourceStats {
+        kind: "framebuffers",
+        count: self.framebuffers.len(),
+        bytes: 0,
+        shared: false,
+      },
+      ResourceStats {
+        kind: "targets",
+        count: self.targets.len(),
+        bytes: self
+          .targets
+          .iter()
+          .flat_map(|(_, target)| target_textures(target))
+          .map(|texture| self.texture_bytes(texture))
+          .sum(),
+        shared: true,
+      },
+      ResourceStats {
+        kind: "accessors",
+        count: self.accessors.len(),
+        bytes: 0,
+        shared: false,
+      },
+      ResourceStats {
+        kind: "geometries",
+        count: self.geometries.len(),
+        bytes: 0,
+        shared: false,
+      },
+      ResourceStats {
+        kind: "instance_sets",
+        count: self.instance_sets.len(),
+        bytes: 0,
+        shared: false,
+      },
+      ResourceStats {
+        kind: "materials",
+        count: self.materials.len(),
+        bytes: 0,
+        shared: false,
+      },
+      ResourceStats {
+        kind: "textures",
+        count: self.textures.len(),
+        bytes: self
+          .textures
+          .iter()
+          .map(|(handle, _)| self.texture_bytes(handle))
+          .sum(),
+        shared: true,
+      },
+      ResourceStats {
+        kind: "samplers",
+        count: self.samplers.len(),
+        bytes: 0,
+        shared: false,
+      },
+      ResourceStats {
+        kind: "meshes",
+        count: self.meshes.len(),
+        bytes: 0,
+        shared: false,
+      },
+      ResourceStats {
+        kind: "shaders",
+        count: self.shaders.len(),
+        bytes: 0,
+        shared: false,
+      },
+    ];
+
+    ResourceReport { entries }
+  }
+}
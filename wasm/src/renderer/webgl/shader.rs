@@ -2,16 +2,30 @@ use anyhow::{anyhow, Result};
 use log::error;
 use na::{Matrix3, Matrix4, Vector2, Vector3, Vector4};
 use std::collections::HashMap;
+use std::rc::Rc;
 use web_sys::{WebGlProgram, WebGlRenderingContext, WebGlShader, WebGlUniformLocation};
 
+use super::backend::{Program, Uniform};
 use super::context::TypedArrayKind;
 use super::define::Define;
+use super::diagnostics::{parse_info_log, ShaderError, ShaderStage};
+use super::shader_cache::{sorted_defines, StageCache};
+use super::shader_chunks::{ShaderChunks, SourceMap};
+use super::state_cache::StateCache;
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub enum AttributeName {
   Position,
   Normal,
+  Tangent,
+  Color0,
   Uv,
+  Uv1,
+  Joints0,
+  Weights0,
+  InstanceTranslation,
+  InstanceRotation,
+  InstanceScale,
   Custom(String),
 }
 
@@ -20,7 +34,15 @@ impl AttributeName {
     match name {
       "position" => AttributeName::Position,
       "normal" => AttributeName::Normal,
+      "tangent" => AttributeName::Tangent,
+      "color" => AttributeName::Color0,
       "uv" => AttributeName::Uv,
+      "uv1" => AttributeName::Uv1,
+      "joints" => AttributeName::Joints0,
+      "weights" => AttributeName::Weights0,
+      "instanceTranslation" => AttributeName::InstanceTranslation,
+      "instanceRotation" => AttributeName::InstanceRotation,
+      "instanceScale" => AttributeName::InstanceScale,
       _ => AttributeName::Custom(name.to_string()),
     }
   }
@@ -28,141 +50,231 @@ impl AttributeName {
 
 #[derive(Debug)]
 pub struct Shader {
-  gl: WebGlRenderingContext,
-  program: WebGlProgram,
-  attribute_locations: HashMap<AttributeName, u32>,
-  uniform_locations: HashMap<String, WebGlUniformLocation>,
+  program: Box<dyn Program>,
 }
 
 impl Shader {
-  pub fn new(
-    gl: &WebGlRenderingContext,
-    vertex_src: &str,
-    fragment_src: &str,
-    defines: &[Define],
-  ) -> Result<Shader> {
-    let vert = add_header(vertex_src, defines, false);
-    let frag = add_header(fragment_src, defines, true);
-
-    let vert_shader = compile_shader(gl, WebGlRenderingContext::VERTEX_SHADER, &vert)?;
-    let frag_shader = compile_shader(gl, WebGlRenderingContext::FRAGMENT_SHADER, &frag)?;
-
-    let program = link_program(&gl, &vert_shader, &frag_shader)?;
-
-    let attribute_locations = collect_attributes(gl, &program);
-    let uniform_locations = collect_uniforms(gl, &program);
-
-    Ok(Shader {
-      gl: gl.clone(),
-      program,
-      attribute_locations,
-      uniform_locations,
-    })
+  pub fn new(program: Box<dyn Program>) -> Shader {
+    Shader { program }
   }
 
   pub fn bind(&self) {
-    self.gl.use_program(Some(&self.program));
+    self.program.bind();
   }
 
   pub fn bind_attribute(&self, name: &AttributeName, attribute: &AttributeOptions) -> Option<()> {
-    let location = self.attribute_locations.get(name)?;
+    let location = self.program.attribute_locations().get(name)?;
 
-    self.gl.vertex_attrib_pointer_with_i32(
-      *location,
-      attribute.item_size,
-      attribute.component_type.as_u32(),
-      attribute.normalized,
-      attribute.stride,
-      attribute.offset,
-    );
+    self.program.bind_attribute(*location, attribute);
 
     Some(())
   }
 
-  pub fn set_bool(&self, name: &str, v: bool) -> Option<()> {
-    let location = self.uniform_locations.get(name)?;
-
-    self.gl.uniform1i(Some(location), if v { 1 } else { 0 });
+  pub fn has_uniform(&self, name: &str) -> bool {
+    self.program.has_uniform(name)
+  }
 
-    Some(())
+  pub fn set_bool(&self, name: &str, v: bool) -> Option<()> {
+    self.program.set_uniform(name, Uniform::Bool(v))
   }
 
   pub fn set_float(&self, name: &str, v: f32) -> Option<()> {
-    let location = self.uniform_locations.get(name)?;
-
-    self.gl.uniform1f(Some(location), v);
-
-    Some(())
+    self.program.set_uniform(name, Uniform::Float(v))
   }
 
   pub fn set_integer(&self, name: &str, v: i32) -> Option<()> {
-    let location = self.uniform_locations.get(name)?;
-
-    self.gl.uniform1i(Some(location), v);
-
-    Some(())
+    self.program.set_uniform(name, Uniform::Integer(v))
   }
 
   pub fn set_vector4(&self, name: &str, v: &Vector4<f32>) -> Option<()> {
-    let location = self.uniform_locations.get(name)?;
-
-    self.gl.uniform4f(Some(location), v.x, v.y, v.z, v.w);
-
-    Some(())
+    self.program.set_uniform(name, Uniform::Vector4(v))
   }
 
   pub fn set_vector3(&self, name: &str, v: &Vector3<f32>) -> Option<()> {
-    let location = self.uniform_locations.get(name)?;
+    self.program.set_uniform(name, Uniform::Vector3(v))
+  }
 
-    self.gl.uniform3f(Some(location), v.x, v.y, v.z);
+  pub fn set_vector2(&self, name: &str, v: &Vector2<f32>) -> Option<()> {
+    self.program.set_uniform(name, Uniform::Vector2(v))
+  }
 
-    Some(())
+  pub fn set_matrix4(&self, name: &str, m: &Matrix4<f32>) -> Option<()> {
+    self.program.set_uniform(name, Uniform::Matrix4(m))
   }
 
-  pub fn set_vector2(&self, name: &str, v: &Vector2<f32>) -> Option<()> {
-    let location = self.uniform_locations.get(name)?;
+  pub fn set_matrix3(&self, name: &str, m: &Matrix3<f32>) -> Option<()> {
+    self.program.set_uniform(name, Uniform::Matrix3(m))
+  }
 
-    self.gl.uniform2f(Some(location), v.x, v.y);
+  pub fn set_matrix4_data(&self, name: &str, data: &[f32]) -> Option<()> {
+    self.program.set_uniform(name, Uniform::Matrix4Data(data))
+  }
 
-    Some(())
+  pub fn get_attribute_locations(&self) -> &HashMap<AttributeName, u32> {
+    self.program.attribute_locations()
   }
+}
 
-  pub fn set_matrix4(&self, name: &str, m: &Matrix4<f32>) -> Option<()> {
-    let location = self.uniform_locations.get(name)?;
+#[derive(Debug)]
+pub struct WebGlShaderProgram {
+  gl: WebGlRenderingContext,
+  cache: Rc<StateCache>,
+  stages: Rc<StageCache<WebGlShader>>,
+  // final stage sources, the keys of the stages in the stage cache
+  sources: [(ShaderStage, String); 2],
+  id: u32,
+  program: WebGlProgram,
+  attribute_locations: HashMap<AttributeName, u32>,
+  uniform_locations: HashMap<String, WebGlUniformLocation>,
+}
 
-    self
-      .gl
-      .uniform_matrix4fv_with_f32_array(Some(location), false, &m.data.as_slice());
+impl WebGlShaderProgram {
+  pub fn new(
+    gl: &WebGlRenderingContext,
+    cache: &Rc<StateCache>,
+    chunks: &ShaderChunks,
+    stages: &Rc<StageCache<WebGlShader>>,
+    vertex_src: &str,
+    fragment_src: &str,
+    defines: &[Define],
+  ) -> Result<WebGlShaderProgram> {
+    let (vert, vert_map) = stage_source(chunks, vertex_src, ShaderStage::Vertex, defines)?;
+    let (frag, frag_map) = stage_source(chunks, fragment_src, ShaderStage::Fragment, defines)?;
+
+    let vert_shader = stages.get_or_compile(ShaderStage::Vertex, &vert, || {
+      compile_shader(gl, WebGlRenderingContext::VERTEX_SHADER, &vert, &vert_map)
+    });
+    let frag_shader = stages.get_or_compile(ShaderStage::Fragment, &frag, || {
+      compile_shader(gl, WebGlRenderingContext::FRAGMENT_SHADER, &frag, &frag_map)
+    });
+
+    let release = |stage: ShaderStage, source: &str| {
+      if let Some(shader) = stages.release(stage, source) {
+        gl.delete_shader(Some(&shader));
+      }
+    };
+
+    // both stages are compiled so a failure reports every diagnostic at once
+    let (vert_shader, frag_shader) = match (vert_shader, frag_shader) {
+      (Ok(vert_shader), Ok(frag_shader)) => (vert_shader, frag_shader),
+      (vert_shader, frag_shader) => {
+        if vert_shader.is_ok() {
+          release(ShaderStage::Vertex, &vert);
+        }
+
+        if frag_shader.is_ok() {
+          release(ShaderStage::Fragment, &frag);
+        }
+
+        let diagnostics = vert_shader
+          .err()
+          .into_iter()
+          .chain(frag_shader.err())
+          .flat_map(|error| error.diagnostics)
+          .collect();
+
+        return Err(ShaderError::new(diagnostics).into());
+      }
+    };
+
+    let program = match link_program(gl, &vert_shader, &frag_shader) {
+      Ok(program) => program,
+      Err(err) => {
+        release(ShaderStage::Vertex, &vert);
+        release(ShaderStage::Fragment, &frag);
+        return Err(err);
+      }
+    };
 
-    Some(())
+    let attribute_locations = collect_attributes(gl, &program);
+    let uniform_locations = collect_uniforms(gl, &program);
+
+    Ok(WebGlShaderProgram {
+      gl: gl.clone(),
+      cache: cache.clone(),
+      stages: stages.clone(),
+      sources: [(ShaderStage::Vertex, vert), (ShaderStage::Fragment, frag)],
+      id: cache.next_program_id(),
+      program,
+      attribute_locations,
+      uniform_locations,
+    })
   }
+}
 
-  pub fn set_matrix3(&self, name: &str, m: &Matrix3<f32>) -> Option<()> {
-    let location = self.uniform_locations.get(name)?;
+// stages other programs still link stay in the stage cache
+impl Drop for WebGlShaderProgram {
+  fn drop(&mut self) {
+    self.gl.delete_program(Some(&self.program));
 
-    self
-      .gl
-      .uniform_matrix3fv_with_f32_array(Some(location), false, &m.data.as_slice());
+    for (stage, source) in &self.sources {
+      if let Some(shader) = self.stages.release(*stage, source) {
+        self.gl.delete_shader(Some(&shader));
+      }
+    }
+  }
+}
 
-    Some(())
+impl Program for WebGlShaderProgram {
+  fn bind(&self) {
+    if self.cache.use_program(self.id) {
+      self.gl.use_program(Some(&self.program));
+    }
   }
 
-  pub fn set_matrix4_data(&self, name: &str, data: &[f32]) -> Option<()> {
-    let location = self.uniform_locations.get(name)?;
+  fn attribute_locations(&self) -> &HashMap<AttributeName, u32> {
+    &self.attribute_locations
+  }
 
-    self
-      .gl
-      .uniform_matrix4fv_with_f32_array(Some(location), false, data);
+  fn bind_attribute(&self, location: u32, attribute: &AttributeOptions) {
+    if !self.cache.vertex_attrib_pointer(location, attribute) {
+      return;
+    }
 
-    Some(())
+    self.gl.vertex_attrib_pointer_with_i32(
+      location,
+      attribute.item_size,
+      attribute.component_type.as_u32(),
+      attribute.normalized,
+      attribute.stride,
+      attribute.offset,
+    );
   }
 
-  pub fn get_attribute_locations(&self) -> &HashMap<AttributeName, u32> {
-    &self.attribute_locations
+  fn has_uniform(&self, name: &str) -> bool {
+    self.uniform_locations.contains_key(name)
+  }
+
+  fn set_uniform(&self, name: &str, value: Uniform) -> Option<()> {
+    let location = Some(self.uniform_locations.get(name)?);
+
+    match value {
+      Uniform::Bool(v) => self.gl.uniform1i(location, if v { 1 } else { 0 }),
+      Uniform::Float(v) => self.gl.uniform1f(location, v),
+      Uniform::Integer(v) => self.gl.uniform1i(location, v),
+      Uniform::Vector2(v) => self.gl.uniform2f(location, v.x, v.y),
+      Uniform::Vector3(v) => self.gl.uniform3f(location, v.x, v.y, v.z),
+      Uniform::Vector4(v) => self.gl.uniform4f(location, v.x, v.y, v.z, v.w),
+      Uniform::Matrix3(m) => {
+        self
+          .gl
+          .uniform_matrix3fv_with_f32_array(location, false, &m.data.as_slice())
+      }
+      Uniform::Matrix4(m) => {
+        self
+          .gl
+          .uniform_matrix4fv_with_f32_array(location, false, &m.data.as_slice())
+      }
+      Uniform::Matrix4Data(data) => self
+        .gl
+        .uniform_matrix4fv_with_f32_array(location, false, data),
+    }
+
+    Some(())
   }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct AttributeOptions {
   pub component_type: TypedArrayKind,
   pub item_size: i32,
@@ -181,16 +293,94 @@ impl AttributeOptions {
       offset: 0,
     }
   }
+
+  pub fn component_size(&self) -> usize {
+    match self.component_type {
+      TypedArrayKind::Int8 | TypedArrayKind::Uint8 => 1,
+      TypedArrayKind::Int16 | TypedArrayKind::Uint16 | TypedArrayKind::HalfFloat => 2,
+      TypedArrayKind::Uint32 | TypedArrayKind::Float32 => 4,
+    }
+  }
+
+  // reads count items from data as floats, normalized integers are mapped
+  // to [0, 1] / [-1, 1] the same way the GPU does it when normalize is set
+  pub fn read_components(&self, data: &[u8], count: i32, normalize: bool) -> Option<Vec<f32>> {
+    let component_size = self.component_size();
+    let item_size = self.item_size as usize;
+    let stride = if self.stride > 0 {
+      self.stride as usize
+    } else {
+      item_size * component_size
+    };
+
+    let mut result = Vec::with_capacity(count as usize * item_size);
+
+    for i in 0..count as usize {
+      for c in 0..item_size {
+        let start = self.offset as usize + i * stride + c * component_size;
+        let b = data.get(start..start + component_size)?;
+
+        let value = match self.component_type {
+          TypedArrayKind::Float32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+          // texels only, no accessor has them
+          TypedArrayKind::HalfFloat => return None,
+          TypedArrayKind::Uint32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+          TypedArrayKind::Uint16 => {
+            let v = u16::from_le_bytes([b[0], b[1]]) as f32;
+            if normalize {
+              v / 65535.0
+            } else {
+              v
+            }
+          }
+          TypedArrayKind::Int16 => {
+            let v = i16::from_le_bytes([b[0], b[1]]) as f32;
+            if normalize {
+              (v / 32767.0).max(-1.0)
+            } else {
+              v
+            }
+          }
+          TypedArrayKind::Uint8 => {
+            let v = b[0] as f32;
+            if normalize {
+              v / 255.0
+            } else {
+              v
+            }
+          }
+          TypedArrayKind::Int8 => {
+            let v = b[0] as i8 as f32;
+            if normalize {
+              (v / 127.0).max(-1.0)
+            } else {
+              v
+            }
+          }
+        };
+
+        result.push(value);
+      }
+    }
+
+    Some(result)
+  }
 }
 
 pub fn compile_shader(
   gl: &WebGlRenderingContext,
   shader_type: u32,
   source: &str,
-) -> Result<WebGlShader> {
-  let shader = gl
-    .create_shader(shader_type)
-    .ok_or_else(|| anyhow!("Unable to create shader object"))?;
+  map: &SourceMap,
+) -> Result<WebGlShader, ShaderError> {
+  let stage = if shader_type == WebGlRenderingContext::VERTEX_SHADER {
+    ShaderStage::Vertex
+  } else {
+    ShaderStage::Fragment
+  };
+  let shader = gl.create_shader(shader_type).ok_or_else(|| {
+    ShaderError::new(parse_info_log("Unable to create shader object", stage, map))
+  })?;
   gl.shader_source(&shader, source);
   gl.compile_shader(&shader);
 
@@ -205,12 +395,11 @@ pub fn compile_shader(
       .get_shader_info_log(&shader)
       .unwrap_or_else(|| String::from("Unknown error creating shader"));
 
-    error!(
-      "{}",
-      &format!("\n{}\n\n{}\n", message, add_row_numbers(source))
-    );
+    let error = ShaderError::new(parse_info_log(&message, stage, map));
 
-    Err(anyhow!("shader compile error"))
+    error!("{}", &format!("\n{}\n\n{}\n", error, map.annotate(source)));
+
+    Err(error)
   }
 }
 
@@ -234,9 +423,20 @@ pub fn link_program(
   {
     Ok(program)
   } else {
-    Err(anyhow!(gl.get_program_info_log(&program).unwrap_or_else(
-      || String::from("Unknown error creating program object")
-    )))
+    let message = gl
+      .get_program_info_log(&program)
+      .unwrap_or_else(|| String::from("Unknown error creating program object"));
+
+    gl.delete_program(Some(&program));
+
+    Err(
+      ShaderError::new(parse_info_log(
+        &message,
+        ShaderStage::Link,
+        &SourceMap::default(),
+      ))
+      .into(),
+    )
   }
 }
 
@@ -295,8 +495,57 @@ pub fn add_row_numbers(src: &str) -> String {
   result
 }
 
-pub fn add_header(src: &str, defines: &[Define], with_precision: bool) -> String {
+// Resolves the includes of a stage and adds the header, the map skips the header lines.
+// Only the defines the stage mentions are written, sorted, so variants differing in
+// defines of the other stage compile to the same source and share it. Extension
+// directives have to come before the precision statement, they move into the header
+// and leave an empty line so the source keeps its line numbers.
+pub fn stage_source(
+  chunks: &ShaderChunks,
+  src: &str,
+  stage: ShaderStage,
+  defines: &[Define],
+) -> Result<(String, SourceMap)> {
+  let (resolved, map) = chunks.resolve(src, &stage.to_string())?;
+  let mut extensions = vec![];
+  let resolved: Vec<&str> = resolved
+    .split('\n')
+    .map(|line| {
+      if line.trim_start().starts_with("#extension") {
+        extensions.push(line.trim());
+        ""
+      } else {
+        line
+      }
+    })
+    .collect();
+  let resolved = resolved.join("\n");
+  let defines: Vec<Define> = sorted_defines(defines)
+    .into_iter()
+    .filter(|define| resolved.contains(&define.name))
+    .cloned()
+    .collect();
+  let with_precision = stage == ShaderStage::Fragment;
+  let header_lines = add_header("", &extensions, &defines, with_precision)
+    .lines()
+    .count();
+
+  Ok((
+    add_header(&resolved, &extensions, &defines, with_precision),
+    map.offset(header_lines, "header"),
+  ))
+}
+
+pub fn add_header(
+  src: &str,
+  extensions: &[&str],
+  defines: &[Define],
+  with_precision: bool,
+) -> String {
   let mut result = String::from("");
+  for extension in extensions {
+    result.push_str(&format!("{}\n", extension));
+  }
   if with_precision {
     result.push_str("precision highp float;\n\n");
   }
@@ -0,0 +1,302 @@
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use super::backend::Backend;
+use super::define::Define;
+use super::diagnostics::{ShaderError, ShaderStage};
+use super::shader::Shader;
+
+// Everything a program is built from, materials asking for equal variants share it.
+#[derive(Debug, Clone)]
+pub struct ShaderVariant<'a> {
+  pub vertex_src: &'a str,
+  pub fragment_src: &'a str,
+  pub defines: Vec<Define>,
+}
+
+impl<'a> ShaderVariant<'a> {
+  pub fn new(vertex_src: &'a str, fragment_src: &'a str, defines: Vec<Define>) -> Self {
+    ShaderVariant {
+      vertex_src,
+      fragment_src,
+      defines,
+    }
+  }
+
+  pub fn add_define(mut self, define: Define) -> Self {
+    self.defines.push(define);
+    self
+  }
+
+  // hash of the sources and the sorted define set, the order defines were added in
+  // doesn't matter
+  pub fn key(&self) -> VariantKey {
+    let mut hasher = DefaultHasher::new();
+
+    self.vertex_src.hash(&mut hasher);
+    self.fragment_src.hash(&mut hasher);
+    sorted_defines(&self.defines).hash(&mut hasher);
+
+    VariantKey(hasher.finish())
+  }
+}
+
+pub fn sorted_defines(defines: &[Define]) -> Vec<&Define> {
+  let mut sorted: Vec<&Define> = defines.iter().collect();
+
+  sorted.sort();
+  sorted.dedup();
+  sorted
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VariantKey(u64);
+
+impl fmt::Display for VariantKey {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{:016x}", self.0)
+  }
+}
+
+impl FromStr for VariantKey {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    u64::from_str_radix(s, 16)
+      .map(VariantKey)
+      .map_err(|_| anyhow!("invalid shader variant key {}", s))
+  }
+}
+
+#[derive(Debug)]
+struct CachedProgram {
+  // kept to rebuild the program from new sources
+  defines: Vec<Define>,
+  // None when the variant failed to compile
+  shader: Option<Rc<Shader>>,
+}
+
+// Programs by variant. They are compiled on first use, a failed variant is remembered so
+// it is reported once rather than recompiled every frame.
+#[derive(Debug, Default)]
+pub struct ShaderCache {
+  programs: RefCell<HashMap<VariantKey, CachedProgram>>,
+}
+
+impl ShaderCache {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn get(&self, key: VariantKey) -> Option<Rc<Shader>> {
+    self.programs.borrow().get(&key)?.shader.clone()
+  }
+
+  // true for failed variants as well
+  pub fn contains(&self, key: VariantKey) -> bool {
+    self.programs.borrow().contains_key(&key)
+  }
+
+  pub fn defines(&self, key: VariantKey) -> Option<Vec<Define>> {
+    Some(self.programs.borrow().get(&key)?.defines.clone())
+  }
+
+  // None when the variant failed to compile, now or before
+  pub fn get_or_compile(&self, ctx: &dyn Backend, variant: &ShaderVariant) -> Option<Rc<Shader>> {
+    let key = variant.key();
+
+    if let Some(program) = self.programs.borrow().get(&key) {
+      return program.shader.clone();
+    }
+
+    info!("compile shader variant: {}", key);
+
+    let shader = match ctx.create_shader(variant.vertex_src, variant.fragment_src, &variant.defines)
+    {
+      Ok(shader) => Some(Rc::new(shader)),
+      Err(e) => {
+        error!("shader variant {}: {}", key, e);
+        None
+      }
+    };
+
+    self.programs.borrow_mut().insert(
+      key,
+      CachedProgram {
+        defines: variant.defines.clone(),
+        shader: shader.clone(),
+      },
+    );
+
+    shader
+  }
+
+  // replaces the program of a known variant, used to hot swap its sources
+  pub fn replace(&self, key: VariantKey, shader: Shader) -> Option<()> {
+    self.programs.borrow_mut().get_mut(&key)?.shader = Some(Rc::new(shader));
+
+    Some(())
+  }
+
+  pub fn keys(&self) -> Vec<VariantKey> {
+    let mut keys: Vec<VariantKey> = self.programs.borrow().keys().copied().collect();
+
+    keys.sort();
+    keys
+  }
+
+  // compiled programs, failed variants aren't counted
+  pub fn len(&self) -> usize {
+    self
+      .programs
+      .borrow()
+      .values()
+      .filter(|program| program.shader.is_some())
+      .count()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+#[derive(Debug)]
+struct CachedStage<T> {
+  compiled: T,
+  // programs linking the stage
+  users: usize,
+}
+
+// Compiled vertex and fragment shaders by final source, shared by every program linking
+// the same stage. Programs release their stages when they go away, a stage nobody links
+// anymore is evicted and handed back to be deleted.
+#[derive(Debug)]
+pub struct StageCache<T> {
+  stages: RefCell<HashMap<(ShaderStage, String), CachedStage<T>>>,
+}
+
+impl<T> Default for StageCache<T> {
+  fn default() -> Self {
+    StageCache {
+      stages: RefCell::new(HashMap::new()),
+    }
+  }
+}
+
+impl<T: Clone> StageCache<T> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // Counts a user of the stage, every success has to be released. Failures aren't
+  // cached, fixed sources always differ anyway.
+  pub fn get_or_compile(
+    &self,
+    stage: ShaderStage,
+    source: &str,
+    compile: impl FnOnce() -> Result<T, ShaderError>,
+  ) -> Result<T, ShaderError> {
+    let key = (stage, source.to_string());
+
+    if let Some(cached) = self.stages.borrow_mut().get_mut(&key) {
+      cached.users += 1;
+      return Ok(cached.compiled.clone());
+    }
+
+    let compiled = compile()?;
+
+    self.stages.borrow_mut().insert(
+      key,
+      CachedStage {
+        compiled: compiled.clone(),
+        users: 1,
+      },
+    );
+
+    Ok(compiled)
+  }
+
+  // the compiled stage once its last user is gone
+  pub fn release(&self, stage: ShaderStage, source: &str) -> Option<T> {
+    let key = (stage, source.to_string());
+    let mut stages = self.stages.borrow_mut();
+    let cached = stages.get_mut(&key)?;
+
+    cached.users -= 1;
+
+    if cached.users > 0 {
+      return None;
+    }
+
+    stages.remove(&key).map(|cached| cached.compiled)
+  }
+
+  pub fn len(&self) -> usize {
+    self.stages.borrow().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn keys_follow_sources_and_define_sets() {
+    let a = ShaderVariant::new("v", "f", vec![Define::def("A"), Define::int("B", 2)]);
+    let reordered = ShaderVariant::new("v", "f", vec![Define::int("B", 2), Define::def("A")]);
+    let duplicated = a.clone().add_define(Define::def("A"));
+
+    assert_eq!(a.key(), reordered.key());
+    assert_eq!(a.key(), duplicated.key());
+    assert_ne!(a.key(), a.clone().add_define(Define::def("C")).key());
+    assert_ne!(
+      a.key(),
+      ShaderVariant::new("v", "f", vec![Define::def("A"), Define::int("B", 3)]).key()
+    );
+    assert_ne!(
+      a.key(),
+      ShaderVariant::new("v2", "f", a.defines.clone()).key()
+    );
+    assert_eq!(a.key().to_string().parse::<VariantKey>().unwrap(), a.key());
+  }
+
+  #[test]
+  fn stages_are_evicted_with_their_last_user() {
+    let stages: StageCache<u32> = StageCache::new();
+    let compile = |id: u32| move || Ok(id);
+
+    assert_eq!(
+      stages.get_or_compile(ShaderStage::Vertex, "v", compile(1)),
+      Ok(1)
+    );
+    assert_eq!(
+      stages.get_or_compile(ShaderStage::Vertex, "v", compile(2)),
+      Ok(1)
+    );
+    assert_eq!(
+      stages.get_or_compile(ShaderStage::Fragment, "v", compile(3)),
+      Ok(3)
+    );
+    assert_eq!(stages.len(), 2);
+
+    assert_eq!(stages.release(ShaderStage::Vertex, "v"), None);
+    assert_eq!(stages.release(ShaderStage::Vertex, "v"), Some(1));
+    assert_eq!(stages.release(ShaderStage::Vertex, "v"), None);
+    assert_eq!(stages.len(), 1);
+    assert_eq!(
+      stages.get_or_compile(ShaderStage::Vertex, "v", compile(4)),
+      Ok(4)
+    );
+  }
+}
@@ -0,0 +1,230 @@
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+
+const BUILT_IN: [(&str, &str); 7] = [
+  ("lighting", include_str!("./shaders/chunks/lighting.glsl")),
+  ("sampling", include_str!("./shaders/chunks/sampling.glsl")),
+  ("skinning", include_str!("./shaders/chunks/skinning.glsl")),
+  ("morphing", include_str!("./shaders/chunks/morphing.glsl")),
+  ("fog", include_str!("./shaders/chunks/fog.glsl")),
+  ("shadow", include_str!("./shaders/chunks/shadow.glsl")),
+  (
+    "tone_mapping",
+    include_str!("./shaders/chunks/tone_mapping.glsl"),
+  ),
+];
+
+// where a line of a resolved source comes from, lines start at 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+  pub file: String,
+  pub line: usize,
+}
+
+// one entry per line of a resolved source
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+  lines: Vec<SourceLine>,
+}
+
+impl SourceMap {
+  // origin of a line of the resolved source, lines start at 1
+  pub fn get(&self, line: usize) -> Option<&SourceLine> {
+    self.lines.get(line.checked_sub(1)?)
+  }
+
+  // shifts the map past lines prepended to the resolved source, e.g. the defines
+  pub fn offset(mut self, lines: usize, file: &str) -> Self {
+    let header = (1..=lines).map(|line| SourceLine {
+      file: file.to_string(),
+      line,
+    });
+
+    self.lines.splice(0..0, header);
+    self
+  }
+
+  // the resolved source with the original file and line in front of every row
+  pub fn annotate(&self, src: &str) -> String {
+    let mut result = String::new();
+
+    for (i, row) in src.lines().enumerate() {
+      match self.get(i + 1) {
+        Some(source) => result.push_str(&format!("{}:{}  {}\n", source.file, source.line, row)),
+        None => result.push_str(&format!("{}  {}\n", i + 1, row)),
+      }
+    }
+
+    result
+  }
+}
+
+// Named GLSL snippets pulled into shaders with "#include <name>". Starts with the built-in
+// chunks, registering a chunk with the same name replaces it.
+#[derive(Debug, Clone)]
+pub struct ShaderChunks {
+  chunks: HashMap<String, String>,
+}
+
+impl Default for ShaderChunks {
+  fn default() -> Self {
+    ShaderChunks {
+      chunks: BUILT_IN
+        .iter()
+        .map(|(name, src)| (name.to_string(), src.to_string()))
+        .collect(),
+    }
+  }
+}
+
+impl ShaderChunks {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn register(&mut self, name: &str, src: &str) {
+    self.chunks.insert(name.to_string(), src.to_string());
+  }
+
+  pub fn get(&self, name: &str) -> Option<&str> {
+    self.chunks.get(name).map(|src| src.as_str())
+  }
+
+  // Replaces every include with its chunk, recursively. A chunk is pulled in once per
+  // source, later includes of it are dropped so chunks can include their dependencies.
+  pub fn resolve(&self, src: &str, file: &str) -> Result<(String, SourceMap)> {
+    let mut result = String::new();
+    let mut map = SourceMap::default();
+    let mut included = HashSet::new();
+    let mut stack = vec![file.to_string()];
+
+    self.resolve_into(src, &mut stack, &mut included, &mut result, &mut map)?;
+
+    Ok((result, map))
+  }
+
+  fn resolve_into(
+    &self,
+    src: &str,
+    stack: &mut Vec<String>,
+    included: &mut HashSet<String>,
+    result: &mut String,
+    map: &mut SourceMap,
+  ) -> Result<()> {
+    let file = stack.last().cloned().unwrap_or_default();
+
+    for (i, line) in src.lines().enumerate() {
+      let name = match parse_include(line) {
+        Some(name) => name.map_err(|e| anyhow!("{}:{}: {}", file, i + 1, e))?,
+        None => {
+          result.push_str(line);
+          result.push('\n');
+          map.lines.push(SourceLine {
+            file: file.clone(),
+            line: i + 1,
+          });
+          continue;
+        }
+      };
+      let chunk_file = format!("<{}>", name);
+
+      if stack.contains(&chunk_file) {
+        stack.push(chunk_file);
+
+        return Err(anyhow!("include cycle: {}", stack.join(" -> ")));
+      }
+
+      if !included.insert(name.to_string()) {
+        continue;
+      }
+
+      let chunk = self
+        .get(name)
+        .ok_or_else(|| anyhow!("{}:{}: unknown shader chunk {}", file, i + 1, chunk_file))?;
+
+      stack.push(chunk_file);
+      self.resolve_into(chunk, stack, included, result, map)?;
+      stack.pop();
+    }
+
+    Ok(())
+  }
+}
+
+// None for lines that are not includes, the chunk name in "#include <name>" otherwise
+fn parse_include(line: &str) -> Option<Result<&str>> {
+  let rest = line.trim().strip_prefix("#include")?;
+  let name = rest
+    .trim()
+    .strip_prefix('<')
+    .and_then(|rest| rest.strip_suffix('>'))
+    .map(|name| name.trim())
+    .filter(|name| !name.is_empty());
+
+  Some(name.ok_or_else(|| anyhow!("malformed include {}", line.trim())))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn chunks() -> ShaderChunks {
+    let mut chunks = ShaderChunks::new();
+
+    chunks.register("a", "float a;\n#include <b>\nfloat a2;");
+    chunks.register("b", "float b;");
+    chunks.register("loop", "#include <cycle>");
+    chunks.register("cycle", "float c;\n#include <loop>");
+    chunks
+  }
+
+  fn location(file: &str, line: usize) -> SourceLine {
+    SourceLine {
+      file: file.to_string(),
+      line,
+    }
+  }
+
+  #[test]
+  fn includes_are_resolved_once_and_mapped_back() {
+    let src = "#include <a>\n  #include < b >\nvoid main() {}";
+    let (resolved, map) = chunks().resolve(src, "fragment").unwrap();
+
+    assert_eq!(resolved, "float a;\nfloat b;\nfloat a2;\nvoid main() {}\n");
+    assert_eq!(map.get(1), Some(&location("<a>", 1)));
+    assert_eq!(map.get(2), Some(&location("<b>", 1)));
+    assert_eq!(map.get(3), Some(&location("<a>", 3)));
+    assert_eq!(map.get(4), Some(&location("fragment", 3)));
+    assert_eq!(map.get(5), None);
+  }
+
+  #[test]
+  fn cycles_and_unknown_chunks_are_errors() {
+    let cycle = chunks()
+      .resolve("#include <loop>", "vertex")
+      .unwrap_err()
+      .to_string();
+    let unknown = chunks()
+      .resolve("void f();\n#include <missing>", "vertex")
+      .unwrap_err()
+      .to_string();
+
+    assert_eq!(
+      cycle,
+      "include cycle: vertex -> <loop> -> <cycle> -> <loop>"
+    );
+    assert_eq!(unknown, "vertex:2: unknown shader chunk <missing>");
+    assert!(chunks().resolve("#include lighting", "vertex").is_err());
+  }
+
+  #[test]
+  fn built_in_chunks_resolve() {
+    let chunks = ShaderChunks::new();
+
+    for (name, _) in &BUILT_IN {
+      assert!(chunks
+        .resolve(&format!("#include <{}>", name), "test")
+        .is_ok());
+    }
+  }
+}
@@ -0,0 +1,8 @@
+uniform vec3 fogColor;
+uniform float fogNear;
+uniform float fogFar;
+
+// linear fog by view space depth
+vec3 applyFog(vec3 color, float depth) {
+  return mix(color, fogColor, smoothstep(fogNear, fogFar, depth));
+}
@@ -0,0 +1,27 @@
+const float PI = 3.14159265359;
+
+// GGX / Trowbridge-Reitz normal distribution, alpha is the squared roughness
+float distributionGGX(float NdotH, float alpha) {
+  float alpha2 = alpha * alpha;
+  float d = NdotH * NdotH * (alpha2 - 1.0) + 1.0;
+
+  return alpha2 / (PI * d * d);
+}
+
+// Smith geometry term with the Schlick-GGX approximation. k is (roughness + 1)^2 / 8
+// for direct light and roughness^2 / 2 for image based lighting.
+float geometrySmith(float NdotV, float NdotL, float k) {
+  float gv = NdotV / (NdotV * (1.0 - k) + k);
+  float gl = NdotL / (NdotL * (1.0 - k) + k);
+
+  return gv * gl;
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 f0) {
+  return f0 + (1.0 - f0) * pow(1.0 - cosTheta, 5.0);
+}
+
+// rough surfaces reflect less at grazing angles, the environment has no half vector
+vec3 fresnelSchlickRoughness(float cosTheta, vec3 f0, float roughness) {
+  return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - cosTheta, 5.0);
+}
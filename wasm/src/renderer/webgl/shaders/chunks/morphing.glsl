@@ -0,0 +1,15 @@
+// up to four position targets added to the base position by weight
+attribute vec3 morphTarget0;
+attribute vec3 morphTarget1;
+attribute vec3 morphTarget2;
+attribute vec3 morphTarget3;
+
+uniform vec4 morphWeights;
+
+vec3 morphPosition(vec3 position) {
+  return position +
+         morphTarget0 * morphWeights.x +
+         morphTarget1 * morphWeights.y +
+         morphTarget2 * morphWeights.z +
+         morphTarget3 * morphWeights.w;
+}
@@ -0,0 +1,31 @@
+#include <lighting>
+
+// van der Corput sequence with float math, GLSL ES 1.0 has no bit operations
+float radicalInverse(int index) {
+  float value = float(index);
+  float inverse = 0.0;
+  float base = 0.5;
+
+  for (int i = 0; i < 16; i++) {
+    inverse += mod(value, 2.0) * base;
+    value = floor(value / 2.0);
+    base *= 0.5;
+  }
+
+  return inverse;
+}
+
+// half vector around the normal distributed by GGX, alpha is the squared roughness
+vec3 importanceSampleGGX(vec2 xi, vec3 normal, float alpha) {
+  float phi = 2.0 * PI * xi.x;
+  float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+  float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+
+  vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+  vec3 tangent = normalize(cross(up, normal));
+  vec3 bitangent = cross(normal, tangent);
+
+  return normalize(
+    tangent * cos(phi) * sinTheta + bitangent * sin(phi) * sinTheta + normal * cosTheta
+  );
+}
@@ -0,0 +1,25 @@
+// Fraction of a 3x3 texel neighbourhood that is lit, lightPosition is the fragment
+// in the clip space of the light. Outside the map counts as lit.
+float sampleShadow(sampler2D shadowMap, vec4 lightPosition, float bias, float texelSize) {
+  vec3 coords = lightPosition.xyz / lightPosition.w * 0.5 + 0.5;
+
+  if (coords.x < 0.0 || coords.x > 1.0 || coords.y < 0.0 || coords.y > 1.0 || coords.z > 1.0) {
+    return 1.0;
+  }
+
+  float lit = 0.0;
+
+  for (int x = -1; x <= 1; x++) {
+    for (int y = -1; y <= 1; y++) {
+      float depth = texture2D(shadowMap, coords.xy + vec2(float(x), float(y)) * texelSize).r;
+      lit += coords.z - bias <= depth ? 1.0 : 0.0;
+    }
+  }
+
+  return lit / 9.0;
+}
+
+// grows the bias on surfaces at a grazing angle to the light, where acne shows first
+float slopeBias(float bias, float NdotL) {
+  return bias * (1.0 + 4.0 * (1.0 - NdotL));
+}
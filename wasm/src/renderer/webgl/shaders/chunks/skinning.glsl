@@ -0,0 +1,21 @@
+// one row of 4 RGBA float texels per bone, numBones rows
+uniform sampler2D boneMatrixTexture;
+uniform float numBones;
+
+mat4 getBoneMatrix(float boneIndex) {
+  float v = (boneIndex + 0.5) / numBones;
+
+  return mat4(
+    texture2D(boneMatrixTexture, vec2(0.5 / 4.0, v)),
+    texture2D(boneMatrixTexture, vec2(1.5 / 4.0, v)),
+    texture2D(boneMatrixTexture, vec2(2.5 / 4.0, v)),
+    texture2D(boneMatrixTexture, vec2(3.5 / 4.0, v))
+  );
+}
+
+vec4 skinPosition(vec4 position, vec4 boneIndices, vec4 weights) {
+  return getBoneMatrix(boneIndices.x) * position * weights.x +
+         getBoneMatrix(boneIndices.y) * position * weights.y +
+         getBoneMatrix(boneIndices.z) * position * weights.z +
+         getBoneMatrix(boneIndices.w) * position * weights.w;
+}
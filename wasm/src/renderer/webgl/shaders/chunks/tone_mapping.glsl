@@ -0,0 +1,16 @@
+vec3 toneMapReinhard(vec3 color) {
+  return color / (color + vec3(1.0));
+}
+
+// ACES filmic curve fitted by Krzysztof Narkowicz
+vec3 toneMapACES(vec3 color) {
+  return clamp(
+    (color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14),
+    0.0,
+    1.0
+  );
+}
+
+vec3 linearToSrgb(vec3 color) {
+  return pow(color, vec3(1.0 / 2.2));
+}
@@ -0,0 +1,41 @@
+varying vec2 v_uv;
+
+const int SAMPLE_COUNT = 256;
+
+#include <sampling>
+
+// split sum lookup table, x is N·V and y the roughness, stores the scale and bias
+// applied to F0 in red and green
+void main() {
+  vec2 uv = v_uv * 0.5 + 0.5;
+  float NdotV = max(uv.x, 1e-3);
+  float roughness = uv.y;
+
+  // normal along +z, view in the xz plane
+  vec3 v = vec3(sqrt(1.0 - NdotV * NdotV), 0.0, NdotV);
+  // k remapped for image based lighting
+  float k = roughness * roughness / 2.0;
+
+  float scale = 0.0;
+  float bias = 0.0;
+
+  for (int i = 0; i < SAMPLE_COUNT; i++) {
+    vec2 xi = vec2(float(i) / float(SAMPLE_COUNT), radicalInverse(i));
+    vec3 h = importanceSampleGGX(xi, vec3(0.0, 0.0, 1.0), roughness * roughness);
+    vec3 l = normalize(2.0 * dot(v, h) * h - v);
+
+    float NdotL = max(l.z, 0.0);
+    float NdotH = max(h.z, 0.0);
+    float VdotH = max(dot(v, h), 0.0);
+
+    if (NdotL > 0.0) {
+      float visibility = geometrySmith(NdotV, NdotL, k) * VdotH / (NdotH * NdotV);
+      float fresnel = pow(1.0 - VdotH, 5.0);
+
+      scale += (1.0 - fresnel) * visibility;
+      bias += fresnel * visibility;
+    }
+  }
+
+  gl_FragColor = vec4(vec2(scale, bias) / float(SAMPLE_COUNT), 0.0, 1.0);
+}
@@ -0,0 +1,35 @@
+uniform samplerCube environmentMap;
+// maps face coordinates in -1..1 to a direction, see ibl.rs
+uniform mat3 faceMatrix;
+
+varying vec2 v_uv;
+
+const int PHI_STEPS = 32;
+const int THETA_STEPS = 8;
+
+#include <lighting>
+
+void main() {
+  vec3 normal = normalize(faceMatrix * vec3(v_uv, 1.0));
+  vec3 up = abs(normal.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(0.0, 0.0, 1.0);
+  vec3 right = normalize(cross(up, normal));
+  up = cross(normal, right);
+
+  vec3 irradiance = vec3(0.0);
+
+  // cosine weighted riemann sum over the hemisphere around the normal
+  for (int i = 0; i < PHI_STEPS; i++) {
+    float phi = 2.0 * PI * (float(i) + 0.5) / float(PHI_STEPS);
+
+    for (int j = 0; j < THETA_STEPS; j++) {
+      float theta = 0.5 * PI * (float(j) + 0.5) / float(THETA_STEPS);
+      vec3 tangentSample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+      vec3 direction = tangentSample.x * right + tangentSample.y * up + tangentSample.z * normal;
+
+      // biased towards a blurry mip so the coarse grid does not alias
+      irradiance += textureCube(environmentMap, direction, 2.0).rgb * cos(theta) * sin(theta);
+    }
+  }
+
+  gl_FragColor = vec4(PI * irradiance / float(PHI_STEPS * THETA_STEPS), 1.0);
+}
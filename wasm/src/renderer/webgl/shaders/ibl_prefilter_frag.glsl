@@ -0,0 +1,49 @@
+uniform samplerCube environmentMap;
+// maps face coordinates in -1..1 to a direction, see ibl.rs
+uniform mat3 faceMatrix;
+uniform float roughness;
+// width of the face being rendered, the implicit lod already accounts for the ratio
+// between the environment map and the face
+uniform float faceSize;
+
+varying vec2 v_uv;
+
+const int SAMPLE_COUNT = 64;
+
+#include <sampling>
+
+void main() {
+  // view and reflection direction are assumed to equal the normal
+  vec3 normal = normalize(faceMatrix * vec3(v_uv, 1.0));
+  float alpha = roughness * roughness;
+  float texelSolidAngle = 4.0 * PI / (6.0 * faceSize * faceSize);
+
+  vec3 color = vec3(0.0);
+  float weight = 0.0;
+
+  for (int i = 0; i < SAMPLE_COUNT; i++) {
+    vec2 xi = vec2(float(i) / float(SAMPLE_COUNT), radicalInverse(i));
+    vec3 h = importanceSampleGGX(xi, normal, alpha);
+    vec3 l = normalize(2.0 * dot(normal, h) * h - normal);
+    float NdotL = dot(normal, l);
+
+    if (NdotL > 0.0) {
+      // blurs by the solid angle of the sample against a face texel, avoids fireflies
+      float lod = 0.0;
+
+      if (roughness > 0.0) {
+        float NdotH = max(dot(normal, h), 0.0);
+        float pdf = distributionGGX(NdotH, alpha) / 4.0 + 1e-4;
+        float sampleSolidAngle = 1.0 / (float(SAMPLE_COUNT) * pdf);
+
+        lod = 0.5 * log2(sampleSolidAngle / texelSolidAngle);
+      }
+
+      // a bias rather than an explicit lod, WebGL1 needs an extension for the latter
+      color += textureCube(environmentMap, l, lod).rgb * NdotL;
+      weight += NdotL;
+    }
+  }
+
+  gl_FragColor = vec4(color / weight, 1.0);
+}
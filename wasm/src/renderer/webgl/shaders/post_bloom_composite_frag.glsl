@@ -0,0 +1,11 @@
+uniform sampler2D inputMap;
+uniform sampler2D bloomMap;
+uniform float intensity;
+
+varying vec2 v_uv;
+
+void main() {
+  vec4 base = texture2D(inputMap, v_uv);
+
+  gl_FragColor = vec4(base.rgb + texture2D(bloomMap, v_uv).rgb * intensity, base.a);
+}
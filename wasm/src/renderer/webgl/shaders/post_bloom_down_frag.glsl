@@ -0,0 +1,31 @@
+uniform sampler2D inputMap;
+// of the source level
+uniform vec2 texelSize;
+
+#ifdef USE_THRESHOLD
+uniform float threshold;
+#endif
+
+varying vec2 v_uv;
+
+void main() {
+  // four bilinear taps average a 4x4 block of the source
+  vec3 color = 0.25 * (
+    texture2D(inputMap, v_uv + vec2(-1.0, -1.0) * texelSize).rgb +
+    texture2D(inputMap, v_uv + vec2(1.0, -1.0) * texelSize).rgb +
+    texture2D(inputMap, v_uv + vec2(-1.0, 1.0) * texelSize).rgb +
+    texture2D(inputMap, v_uv + vec2(1.0, 1.0) * texelSize).rgb
+  );
+
+#ifdef USE_THRESHOLD
+  // soft knee so pixels near the threshold fade in rather than pop
+  float brightness = max(color.r, max(color.g, color.b));
+  float knee = threshold * 0.5;
+  float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+
+  soft = soft * soft / (4.0 * knee + 1e-4);
+  color *= max(soft, brightness - threshold) / max(brightness, 1e-4);
+#endif
+
+  gl_FragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,19 @@
+uniform sampler2D inputMap;
+// of the source level
+uniform vec2 texelSize;
+
+varying vec2 v_uv;
+
+vec3 tap(float x, float y) {
+  return texture2D(inputMap, v_uv + vec2(x, y) * texelSize).rgb;
+}
+
+// 3x3 tent filter, added onto the larger level by the blend state
+void main() {
+  vec3 color = tap(0.0, 0.0) * 4.0;
+
+  color += (tap(-1.0, 0.0) + tap(1.0, 0.0) + tap(0.0, -1.0) + tap(0.0, 1.0)) * 2.0;
+  color += tap(-1.0, -1.0) + tap(1.0, -1.0) + tap(-1.0, 1.0) + tap(1.0, 1.0);
+
+  gl_FragColor = vec4(color / 16.0, 1.0);
+}
@@ -0,0 +1,28 @@
+uniform sampler2D inputMap;
+// lutSize slices of lutSize x lutSize side by side, red along u and green along v in a
+// slice, blue picks the slice
+uniform sampler2D lutMap;
+uniform float lutSize;
+uniform float intensity;
+
+varying vec2 v_uv;
+
+vec3 lookup(vec3 color) {
+  float slice = color.b * (lutSize - 1.0);
+  float slice0 = floor(slice);
+  float slice1 = min(slice0 + 1.0, lutSize - 1.0);
+  // centers of the texels, neighbouring slices don't bleed in
+  vec2 uv = (color.rg * (lutSize - 1.0) + 0.5) / vec2(lutSize * lutSize, lutSize);
+
+  vec3 a = texture2D(lutMap, uv + vec2(slice0 / lutSize, 0.0)).rgb;
+  vec3 b = texture2D(lutMap, uv + vec2(slice1 / lutSize, 0.0)).rgb;
+
+  return mix(a, b, slice - slice0);
+}
+
+void main() {
+  vec4 base = texture2D(inputMap, v_uv);
+  vec3 color = clamp(base.rgb, 0.0, 1.0);
+
+  gl_FragColor = vec4(mix(color, lookup(color), intensity), base.a);
+}
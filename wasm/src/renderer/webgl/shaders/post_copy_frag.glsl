@@ -0,0 +1,7 @@
+uniform sampler2D inputMap;
+
+varying vec2 v_uv;
+
+void main() {
+  gl_FragColor = texture2D(inputMap, v_uv);
+}
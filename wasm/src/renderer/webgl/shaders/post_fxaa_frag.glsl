@@ -0,0 +1,46 @@
+// FXAA after Timothy Lottes, the single pass variant without edge search. Expects a
+// tone mapped and gamma corrected base, edges are found by perceived luma.
+uniform sampler2D inputMap;
+uniform vec2 texelSize;
+
+varying vec2 v_uv;
+
+const float REDUCE_MIN = 1.0 / 128.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float SPAN_MAX = 8.0;
+const vec3 LUMA = vec3(0.299, 0.587, 0.114);
+
+void main() {
+  vec4 center = texture2D(inputMap, v_uv);
+
+  float lumaNW = dot(texture2D(inputMap, v_uv + vec2(-1.0, -1.0) * texelSize).rgb, LUMA);
+  float lumaNE = dot(texture2D(inputMap, v_uv + vec2(1.0, -1.0) * texelSize).rgb, LUMA);
+  float lumaSW = dot(texture2D(inputMap, v_uv + vec2(-1.0, 1.0) * texelSize).rgb, LUMA);
+  float lumaSE = dot(texture2D(inputMap, v_uv + vec2(1.0, 1.0) * texelSize).rgb, LUMA);
+  float lumaM = dot(center.rgb, LUMA);
+
+  float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+  float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+  // blur along the edge, perpendicular to the luma gradient
+  vec2 dir = vec2(-((lumaNW + lumaNE) - (lumaSW + lumaSE)), (lumaNW + lumaSW) - (lumaNE + lumaSE));
+  float dirReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+  float rcpDirMin = 1.0 / (min(abs(dir.x), abs(dir.y)) + dirReduce);
+
+  dir = clamp(dir * rcpDirMin, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texelSize;
+
+  vec3 rgbA = 0.5 * (
+    texture2D(inputMap, v_uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+    texture2D(inputMap, v_uv + dir * (2.0 / 3.0 - 0.5)).rgb
+  );
+  vec3 rgbB = rgbA * 0.5 + 0.25 * (
+    texture2D(inputMap, v_uv - dir * 0.5).rgb +
+    texture2D(inputMap, v_uv + dir * 0.5).rgb
+  );
+  float lumaB = dot(rgbB, LUMA);
+
+  // the wider blur crossed another edge, keep the narrow one
+  vec3 color = lumaB < lumaMin || lumaB > lumaMax ? rgbA : rgbB;
+
+  gl_FragColor = vec4(color, center.a);
+}
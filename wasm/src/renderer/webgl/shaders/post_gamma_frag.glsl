@@ -0,0 +1,10 @@
+uniform sampler2D inputMap;
+uniform float gamma;
+
+varying vec2 v_uv;
+
+void main() {
+  vec4 base = texture2D(inputMap, v_uv);
+
+  gl_FragColor = vec4(pow(base.rgb, vec3(1.0 / gamma)), base.a);
+}
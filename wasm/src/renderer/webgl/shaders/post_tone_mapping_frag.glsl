@@ -0,0 +1,19 @@
+#include <tone_mapping>
+
+uniform sampler2D inputMap;
+uniform float exposure;
+
+varying vec2 v_uv;
+
+void main() {
+  vec4 base = texture2D(inputMap, v_uv);
+  vec3 color = base.rgb * exposure;
+
+#ifdef TONE_MAP_ACES
+  color = toneMapACES(color);
+#else
+  color = toneMapReinhard(color);
+#endif
+
+  gl_FragColor = vec4(color, base.a);
+}
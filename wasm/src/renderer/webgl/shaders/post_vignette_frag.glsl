@@ -0,0 +1,15 @@
+uniform sampler2D inputMap;
+uniform float intensity;
+// width of the falloff, 1.0 starts darkening at the center
+uniform float smoothness;
+
+varying vec2 v_uv;
+
+void main() {
+  vec4 base = texture2D(inputMap, v_uv);
+  // 1.0 in the corners
+  float edge = length(v_uv - 0.5) * 1.41421356;
+  float vignette = 1.0 - intensity * smoothstep(1.0 - smoothness, 1.0, edge);
+
+  gl_FragColor = vec4(base.rgb * vignette, base.a);
+}
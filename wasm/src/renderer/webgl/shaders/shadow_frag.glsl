@@ -0,0 +1,4 @@
+// only depth is kept, the color attachment is never sampled
+void main() {
+  gl_FragColor = vec4(1.0);
+}
@@ -0,0 +1,24 @@
+attribute vec3 position;
+
+#ifdef USE_INSTANCING
+attribute vec3 instanceTranslation;
+attribute vec4 instanceRotation;
+attribute vec3 instanceScale;
+
+vec3 rotateByQuat(vec4 q, vec3 v) {
+  return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+#endif
+
+uniform mat4 lightViewProjection;
+uniform mat4 modelMatrix;
+
+void main() {
+  vec3 localPosition = position;
+
+#ifdef USE_INSTANCING
+  localPosition = rotateByQuat(instanceRotation, position * instanceScale) + instanceTranslation;
+#endif
+
+  gl_Position = lightViewProjection * modelMatrix * vec4(localPosition, 1.0);
+}
@@ -0,0 +1,230 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::backend::{GpuBuffer, GpuTexture};
+use super::context::{
+  BlendFactor, BufferTarget, DepthFunc, Feature, TexParam, TexParamName, TextureKind,
+};
+use super::shader::AttributeOptions;
+
+// Shadow copy of the GL state last sent by Context. Every method returns true when the
+// call has to reach GL, unchanged state is counted as a skipped call instead.
+#[derive(Debug, Default)]
+pub struct StateCache {
+  program: Cell<Option<u32>>,
+  buffers: RefCell<HashMap<BufferTarget, Option<GpuBuffer>>>,
+  active_unit: Cell<u32>,
+  textures: RefCell<HashMap<(u32, TextureKind), Option<GpuTexture>>>,
+  tex_params: RefCell<HashMap<(GpuTexture, TexParamName), TexParam>>,
+  features: RefCell<HashMap<Feature, bool>>,
+  depth_func: Cell<Option<DepthFunc>>,
+  blend_func: Cell<Option<(BlendFactor, BlendFactor)>>,
+  depth_mask: Cell<Option<bool>>,
+  attrib_pointers: RefCell<HashMap<u32, (Option<GpuBuffer>, AttributeOptions)>>,
+  next_program: Cell<u32>,
+  skipped: Cell<usize>,
+}
+
+impl StateCache {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  fn changed(&self, changed: bool) -> bool {
+    if !changed {
+      self.skipped.set(self.skipped.get() + 1);
+    }
+
+    changed
+  }
+
+  fn update<K: Eq + Hash, V: PartialEq>(
+    &self,
+    map: &RefCell<HashMap<K, V>>,
+    key: K,
+    value: V,
+  ) -> bool {
+    let mut map = map.borrow_mut();
+    let changed = map.get(&key) != Some(&value);
+
+    if changed {
+      map.insert(key, value);
+    }
+
+    self.changed(changed)
+  }
+
+  fn update_cell<V: Copy + PartialEq>(&self, cell: &Cell<V>, value: V) -> bool {
+    let changed = cell.get() != value;
+
+    cell.set(value);
+
+    self.changed(changed)
+  }
+
+  pub fn next_program_id(&self) -> u32 {
+    let id = self.next_program.get() + 1;
+
+    self.next_program.set(id);
+
+    id
+  }
+
+  pub fn use_program(&self, program: u32) -> bool {
+    self.update_cell(&self.program, Some(program))
+  }
+
+  pub fn bind_buffer(&self, target: BufferTarget, buffer: Option<GpuBuffer>) -> bool {
+    self.update(&self.buffers, target, buffer)
+  }
+
+  pub fn active_texture(&self, unit: u32) -> bool {
+    self.update_cell(&self.active_unit, unit)
+  }
+
+  pub fn bind_texture(&self, target: TextureKind, texture: Option<GpuTexture>) -> bool {
+    self.update(&self.textures, (self.active_unit.get(), target), texture)
+  }
+
+  // sampler parameters belong to the texture bound to the active unit
+  pub fn texture_parameter(
+    &self,
+    target: TextureKind,
+    name: TexParamName,
+    param: TexParam,
+  ) -> bool {
+    let bound = self
+      .textures
+      .borrow()
+      .get(&(self.active_unit.get(), target))
+      .copied()
+      .flatten();
+
+    match bound {
+      Some(texture) => self.update(&self.tex_params, (texture, name), param),
+      None => true,
+    }
+  }
+
+  pub fn set_feature(&self, feature: Feature, enabled: bool) -> bool {
+    self.update(&self.features, feature, enabled)
+  }
+
+  pub fn depth_func(&self, func: DepthFunc) -> bool {
+    self.update_cell(&self.depth_func, Some(func))
+  }
+
+  pub fn blend_func(&self, src: BlendFactor, dst: BlendFactor) -> bool {
+    self.update_cell(&self.blend_func, Some((src, dst)))
+  }
+
+  pub fn depth_mask(&self, write: bool) -> bool {
+    self.update_cell(&self.depth_mask, Some(write))
+  }
+
+  // the pointer captures the array buffer bound at the time of the call
+  pub fn vertex_attrib_pointer(&self, location: u32, options: &AttributeOptions) -> bool {
+    let buffer = self
+      .buffers
+      .borrow()
+      .get(&BufferTarget::ArrayBuffer)
+      .copied()
+      .flatten();
+
+    self.update(&self.attrib_pointers, location, (buffer, options.clone()))
+  }
+
+  pub fn forget_buffer(&self, buffer: GpuBuffer) {
+    self
+      .buffers
+      .borrow_mut()
+      .retain(|_, bound| *bound != Some(buffer));
+    self
+      .attrib_pointers
+      .borrow_mut()
+      .retain(|_, (bound, _)| *bound != Some(buffer));
+  }
+
+  pub fn forget_texture(&self, texture: GpuTexture) {
+    self
+      .textures
+      .borrow_mut()
+      .retain(|_, bound| *bound != Some(texture));
+    self
+      .tex_params
+      .borrow_mut()
+      .retain(|(bound, _), _| *bound != texture);
+  }
+
+  // skipped calls since the previous call, meant to be read once per frame
+  pub fn take_skipped(&self) -> usize {
+    self.skipped.replace(0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::renderer::webgl::context::TypedArrayKind;
+
+  #[test]
+  fn repeated_state_is_skipped() {
+    let cache = StateCache::new();
+
+    assert!(cache.use_program(1));
+    assert!(!cache.use_program(1));
+    assert!(cache.set_feature(Feature::DepthTest, true));
+    assert!(!cache.set_feature(Feature::DepthTest, true));
+    assert!(cache.set_feature(Feature::DepthTest, false));
+    assert!(cache.depth_func(DepthFunc::Less));
+    assert!(!cache.depth_func(DepthFunc::Less));
+
+    assert_eq!(cache.take_skipped(), 3);
+    assert_eq!(cache.take_skipped(), 0);
+  }
+
+  #[test]
+  fn textures_are_tracked_per_unit() {
+    let cache = StateCache::new();
+    let texture = GpuTexture(1);
+
+    assert!(cache.bind_texture(TextureKind::Texture2d, Some(texture)));
+    assert!(cache.texture_parameter(
+      TextureKind::Texture2d,
+      TexParamName::TextureMinFilter,
+      TexParam::Linear
+    ));
+    assert!(!cache.texture_parameter(
+      TextureKind::Texture2d,
+      TexParamName::TextureMinFilter,
+      TexParam::Linear
+    ));
+
+    assert!(cache.active_texture(1));
+    assert!(cache.bind_texture(TextureKind::Texture2d, Some(texture)));
+    assert!(!cache.bind_texture(TextureKind::Texture2d, Some(texture)));
+
+    cache.forget_texture(texture);
+
+    assert!(cache.bind_texture(TextureKind::Texture2d, Some(texture)));
+  }
+
+  #[test]
+  fn attrib_pointers_follow_the_array_buffer() {
+    let cache = StateCache::new();
+    let options = AttributeOptions::new(TypedArrayKind::Float32, 3);
+
+    cache.bind_buffer(BufferTarget::ArrayBuffer, Some(GpuBuffer(1)));
+    assert!(cache.vertex_attrib_pointer(0, &options));
+    assert!(!cache.vertex_attrib_pointer(0, &options));
+
+    cache.bind_buffer(BufferTarget::ArrayBuffer, Some(GpuBuffer(2)));
+    assert!(cache.vertex_attrib_pointer(0, &options));
+
+    cache.forget_buffer(GpuBuffer(2));
+
+    assert!(cache.bind_buffer(BufferTarget::ArrayBuffer, Some(GpuBuffer(2))));
+    assert!(cache.vertex_attrib_pointer(0, &options));
+  }
+}
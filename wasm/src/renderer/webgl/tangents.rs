@@ -0,0 +1,328 @@
+use na::{Point2, Point3, Vector3, Vector4};
+
+use super::context::{BufferTarget, DrawMode};
+use super::renderer::{Accessor, Geometry, Renderer};
+use super::shader::AttributeName;
+use crate::handle::Handle;
+
+// Follows the MikkTSpace rules for a welded index buffer: per-corner directions are
+// projected onto the normal plane, weighted by corner angle and accumulated per vertex,
+// and the sign of the bitangent is stored in w.
+pub fn generate_tangents(
+  positions: &[Point3<f32>],
+  normals: &[Vector3<f32>],
+  uvs: &[Point2<f32>],
+  indices: &[u32],
+) -> Vec<Vector4<f32>> {
+  let count = positions.len();
+
+  let mut tangents = vec![Vector3::zeros(); count];
+  let mut bitangents = vec![Vector3::zeros(); count];
+
+  for triangle in indices.chunks_exact(3) {
+    let i = [
+      triangle[0] as usize,
+      triangle[1] as usize,
+      triangle[2] as usize,
+    ];
+
+    let e1 = positions[i[1]] - positions[i[0]];
+    let e2 = positions[i[2]] - positions[i[0]];
+    let d1 = uvs[i[1]] - uvs[i[0]];
+    let d2 = uvs[i[2]] - uvs[i[0]];
+
+    let area = d1.x * d2.y - d2.x * d1.y;
+
+    if area.abs() <= f32::EPSILON {
+      continue;
+    }
+
+    let s_dir = (e1 * d2.y - e2 * d1.y) / area;
+    let t_dir = (e2 * d1.x - e1 * d2.x) / area;
+
+    for corner in 0..3 {
+      let v = i[corner];
+      let prev = i[(corner + 2) % 3];
+      let next = i[(corner + 1) % 3];
+
+      let n = normals[v];
+      let a = (positions[next] - positions[v]).try_normalize(f32::EPSILON);
+      let b = (positions[prev] - positions[v]).try_normalize(f32::EPSILON);
+
+      let angle = match (a, b) {
+        (Some(a), Some(b)) => a.dot(&b).clamp(-1.0, 1.0).acos(),
+        _ => continue,
+      };
+
+      let os = project_on_plane(&s_dir, &n);
+      let ot = project_on_plane(&t_dir, &n);
+
+      tangents[v] += os * angle;
+      bitangents[v] += ot * angle;
+    }
+  }
+
+  (0..count)
+    .map(|v| {
+      let n = normals[v];
+      let t = project_on_plane(&tangents[v], &n);
+
+      let t = if t.norm_squared() > f32::EPSILON {
+        t.normalize()
+      } else {
+        any_perpendicular(&n)
+      };
+
+      let w = if n.cross(&t).dot(&bitangents[v]) < 0.0 {
+        -1.0
+      } else {
+        1.0
+      };
+
+      Vector4::new(t.x, t.y, t.z, w)
+    })
+    .collect()
+}
+
+fn project_on_plane(v: &Vector3<f32>, n: &Vector3<f32>) -> Vector3<f32> {
+  let projected = v - n * n.dot(v);
+
+  projected.try_normalize(f32::EPSILON).unwrap_or(projected)
+}
+
+fn any_perpendicular(n: &Vector3<f32>) -> Vector3<f32> {
+  let axis = if n.x.abs() < 0.9 {
+    Vector3::x()
+  } else {
+    Vector3::y()
+  };
+
+  project_on_plane(&axis, n).normalize()
+}
+
+impl Renderer {
+  pub fn read_accessor_f32(&self, accessor_handle: Handle<Accessor>) -> Option<Vec<f32>> {
+    let accessor = self.accessors.get(accessor_handle)?;
+    let buffer = self.buffers.get(accessor.buffer)?;
+
+    accessor
+      .options
+      .read_components(&buffer.data, accessor.count, accessor.options.normalized)
+  }
+
+  pub fn ensure_tangents(&mut self, geometry_handle: Handle<Geometry>) -> Option<()> {
+    let geometry = self.geometries.get(geometry_handle)?;
+
+    if geometry.attributes.contains_key(&AttributeName::Tangent) {
+      return Some(());
+    }
+
+    if geometry.draw_mode != DrawMode::Triangles {
+      return None;
+    }
+
+    let positions: Vec<Point3<f32>> = self
+      .read_accessor_f32(*geometry.attributes.get(&AttributeName::Position)?)?
+      .chunks_exact(3)
+      .map(|p| Point3::new(p[0], p[1], p[2]))
+      .collect();
+    let normals: Vec<Vector3<f32>> = self
+      .read_accessor_f32(*geometry.attributes.get(&AttributeName::Normal)?)?
+      .chunks_exact(3)
+      .map(|n| Vector3::new(n[0], n[1], n[2]))
+      .collect();
+    let uvs: Vec<Point2<f32>> = self
+      .read_accessor_f32(*geometry.attributes.get(&AttributeName::Uv)?)?
+      .chunks_exact(2)
+      .map(|uv| Point2::new(uv[0], uv[1]))
+      .collect();
+    let indices: Vec<u32> = match geometry.indices {
+      Some(accessor_handle) => self
+        .read_accessor_f32(accessor_handle)?
+        .iter()
+        .map(|i| *i as u32)
+        .collect(),
+      None => (0..positions.len() as u32).collect(),
+    };
+
+    if normals.len() != positions.len() || uvs.len() != positions.len() {
+      return None;
+    }
+
+    let tangents: Vec<f32> = generate_tangents(&positions, &normals, &uvs, &indices)
+      .iter()
+      .flat_map(|t| vec![t.x, t.y, t.z, t.w])
+      .collect();
+
+    let accessor = self.bake_buffer_accessor(
+      BufferTarget::ArrayBuffer,
+      &tangents,
+      4,
+      positions.len() as i32,
+    );
+
+    self
+      .geometries
+      .get_mut(geometry_handle)?
+      .attributes
+      .insert(AttributeName::Tangent, accessor);
+
+    Some(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ncollide3d::procedural::{unit_sphere, IndexBuffer};
+
+  fn quad(
+    normal: Vector3<f32>,
+    u: Vector3<f32>,
+    v: Vector3<f32>,
+    positions: &mut Vec<Point3<f32>>,
+    normals: &mut Vec<Vector3<f32>>,
+    uvs: &mut Vec<Point2<f32>>,
+    indices: &mut Vec<u32>,
+  ) {
+    let base = positions.len() as u32;
+
+    for (s, t) in &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+      let p = normal + u * (2.0 * s - 1.0) + v * (2.0 * t - 1.0);
+
+      positions.push(Point3::from(p));
+      normals.push(normal);
+      uvs.push(Point2::new(*s, *t));
+    }
+
+    indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+  }
+
+  fn assert_tangent(actual: &Vector4<f32>, expected: &Vector4<f32>) {
+    assert!(
+      (actual - expected).norm() < 1e-5,
+      "expected {:?}, got {:?}",
+      expected,
+      actual
+    );
+  }
+
+  #[test]
+  fn cube_tangents_match_reference() {
+    let faces = [
+      // normal, u axis, v axis, expected tangent
+      (
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector4::new(0.0, 0.0, -1.0, 1.0),
+      ),
+      (
+        Vector3::new(-1.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, 1.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector4::new(0.0, 0.0, 1.0, 1.0),
+      ),
+      (
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, -1.0),
+        Vector4::new(1.0, 0.0, 0.0, 1.0),
+      ),
+      (
+        Vector3::new(0.0, -1.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, 1.0),
+        Vector4::new(1.0, 0.0, 0.0, 1.0),
+      ),
+      (
+        Vector3::new(0.0, 0.0, 1.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector4::new(1.0, 0.0, 0.0, 1.0),
+      ),
+      // mirrored uv layout flips the bitangent sign
+      (
+        Vector3::new(0.0, 0.0, -1.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector4::new(1.0, 0.0, 0.0, -1.0),
+      ),
+    ];
+
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut indices = vec![];
+
+    for (normal, u, v, _) in &faces {
+      quad(
+        *normal,
+        *u,
+        *v,
+        &mut positions,
+        &mut normals,
+        &mut uvs,
+        &mut indices,
+      );
+    }
+
+    let tangents = generate_tangents(&positions, &normals, &uvs, &indices);
+
+    for (face, (_, _, _, expected)) in faces.iter().enumerate() {
+      for corner in 0..4 {
+        assert_tangent(&tangents[face * 4 + corner], expected);
+      }
+    }
+  }
+
+  #[test]
+  fn sphere_tangents_match_reference() {
+    let mut sphere = unit_sphere::<f32>(32, 16, true);
+    sphere.unify_index_buffer();
+
+    let normals = sphere.normals.clone().unwrap();
+    let uvs = sphere.uvs.clone().unwrap();
+    let indices: Vec<u32> = match &sphere.indices {
+      IndexBuffer::Unified(indices) => indices.iter().flat_map(|i| vec![i.x, i.y, i.z]).collect(),
+      IndexBuffer::Split(_) => unreachable!(),
+    };
+
+    let tangents = generate_tangents(&sphere.coords, &normals, &uvs, &indices);
+
+    let mut checked = 0;
+
+    for (i, p) in sphere.coords.iter().enumerate() {
+      let ring_radius = (p.x * p.x + p.z * p.z).sqrt();
+
+      // tangents at the poles are undefined
+      if ring_radius < 0.2 {
+        continue;
+      }
+
+      // u grows with the longitude and v towards +y, which is left handed
+      // around the outward normal so the bitangent sign is negative
+      let expected = Vector4::new(-p.z / ring_radius, 0.0, p.x / ring_radius, -1.0);
+      let actual = tangents[i];
+      // a vertex on the uv seam only sees the faces on one side, whose chords lean
+      // by half a longitude step
+      let tolerance = if uvs[i].x == 0.0 || uvs[i].x >= 1.0 - 1e-4 {
+        0.1
+      } else {
+        1e-2
+      };
+
+      assert!(
+        (actual - expected).norm() < tolerance,
+        "vertex {} expected {:?}, got {:?}",
+        i,
+        expected,
+        actual
+      );
+
+      checked += 1;
+    }
+
+    assert!(checked > 0);
+  }
+}
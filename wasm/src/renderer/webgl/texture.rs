@@ -1,8 +1,10 @@
-use generational_arena::Index;
-use web_sys::{HtmlImageElement, WebGlTexture};
+use web_sys::HtmlImageElement;
+
+use super::backend::{GpuTexture, TextureLayout};
+use crate::handle::Handle;
 
 use super::context::{TextureFormat, TextureKind, TypedArrayKind};
-use super::renderer::{Renderer, Sampler, Texture};
+use super::renderer::{Image, Renderer, Sampler, Texture};
 
 impl Renderer {
   pub fn bake_2d_texture(
@@ -10,12 +12,12 @@ impl Renderer {
     format: TextureFormat,
     sampler: Sampler,
     image: &HtmlImageElement,
-  ) -> Index {
-    let webgl_texture = self.ctx.create_texture().unwrap();
+  ) -> Handle<Texture> {
+    let gpu_texture = self.ctx.create_texture().unwrap();
 
     self
       .ctx
-      .bind_texture(TextureKind::Texture2d, Some(&webgl_texture));
+      .bind_texture(TextureKind::Texture2d, Some(gpu_texture));
 
     self
       .ctx
@@ -33,7 +35,74 @@ impl Renderer {
 
     self.ctx.bind_texture(TextureKind::Texture2d, None);
 
-    self.compose_texture(webgl_texture, sampler)
+    let byte_size = with_mipmaps(image_byte_size(format, image));
+
+    self.compose_texture(gpu_texture, sampler, byte_size)
+  }
+
+  // Uploads decoded 8 bit pixels, rows from the top as images store them. Mipmaps are
+  // only generated for power of two sizes, WebGL1 can't sample others with them.
+  pub fn bake_image_from_pixels(
+    &mut self,
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+  ) -> Handle<Image> {
+    let gpu_texture = self.ctx.create_texture().unwrap();
+    let mipmaps = is_power_of_two(width, height);
+
+    self
+      .ctx
+      .bind_texture(TextureKind::Texture2d, Some(gpu_texture));
+
+    self
+      .ctx
+      .texture_data(
+        &TextureLayout::new(width as i32, height as i32, format, TypedArrayKind::Uint8),
+        pixels,
+      )
+      .unwrap();
+
+    if mipmaps {
+      self.ctx.generate_mipmap(TextureKind::Texture2d);
+    }
+
+    self.ctx.bind_texture(TextureKind::Texture2d, None);
+
+    let byte_size = width as usize * height as usize * format.bytes_per_pixel();
+
+    self.insert_image(Image {
+      gpu_texture,
+      source: None,
+      byte_size: if mipmaps {
+        with_mipmaps(byte_size)
+      } else {
+        byte_size
+      },
+    })
+  }
+
+  pub fn bake_2d_texture_from_pixels(
+    &mut self,
+    format: TextureFormat,
+    sampler: Sampler,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+  ) -> Handle<Texture> {
+    let image = self.bake_image_from_pixels(format, width, height, pixels);
+    let sampler = if is_power_of_two(width, height) {
+      sampler
+    } else {
+      sampler.without_mipmaps()
+    };
+    let texture = Texture {
+      source: image,
+      sampler: self.insert_sampler(sampler),
+    };
+
+    self.insert_texture(texture)
   }
 
   pub fn bake_cube_map_texture(
@@ -41,12 +110,12 @@ impl Renderer {
     format: TextureFormat,
     sampler: Sampler,
     src: &[(TextureKind, &HtmlImageElement)],
-  ) -> Index {
-    let webgl_texture = self.ctx.create_texture().unwrap();
+  ) -> Handle<Texture> {
+    let gpu_texture = self.ctx.create_texture().unwrap();
 
     self
       .ctx
-      .bind_texture(TextureKind::CubeMap, Some(&webgl_texture));
+      .bind_texture(TextureKind::CubeMap, Some(gpu_texture));
 
     for image in src {
       self
@@ -59,11 +128,27 @@ impl Renderer {
 
     self.ctx.bind_texture(TextureKind::CubeMap, None);
 
-    self.compose_texture(webgl_texture, sampler)
+    let byte_size = with_mipmaps(
+      src
+        .iter()
+        .map(|(_, image)| image_byte_size(format, image))
+        .sum(),
+    );
+
+    self.compose_texture(gpu_texture, sampler, byte_size)
   }
 
-  pub fn compose_texture(&mut self, image: WebGlTexture, sampler: Sampler) -> Index {
-    let image_handle = self.insert_image(image);
+  pub fn compose_texture(
+    &mut self,
+    gpu_texture: GpuTexture,
+    sampler: Sampler,
+    byte_size: usize,
+  ) -> Handle<Texture> {
+    let image_handle = self.insert_image(Image {
+      gpu_texture,
+      source: None,
+      byte_size,
+    });
     let sampler_handle = self.insert_sampler(sampler);
 
     let texture = Texture {
@@ -74,3 +159,16 @@ impl Renderer {
     self.insert_texture(texture)
   }
 }
+
+fn image_byte_size(format: TextureFormat, image: &HtmlImageElement) -> usize {
+  image.natural_width() as usize * image.natural_height() as usize * format.bytes_per_pixel()
+}
+
+pub(crate) fn is_power_of_two(width: u32, height: u32) -> bool {
+  width.is_power_of_two() && height.is_power_of_two()
+}
+
+// a full mip chain adds a third on top of the base level
+pub(crate) fn with_mipmaps(byte_size: usize) -> usize {
+  byte_size * 4 / 3
+}
@@ -1,8 +1,8 @@
-use generational_arena::Index;
 use na::{Isometry3, Point2, Point3, Vector3};
 use std::f32::consts::PI;
 
-use super::renderer::Renderer;
+use super::renderer::{Camera, Renderer};
+use crate::handle::Handle;
 
 pub struct Turntable {
   pub roll: f32,
@@ -44,7 +44,7 @@ impl Turntable {
     self.cursor_position = cursor_position;
   }
 
-  pub fn update_camera(&self, renderer: &mut Renderer, camera_handle: Index) {
+  pub fn update_camera(&self, renderer: &mut Renderer, camera_handle: Handle<Camera>) {
     let r = self.radius;
 
     let position = Point3::new(
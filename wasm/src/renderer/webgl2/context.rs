@@ -132,6 +132,33 @@ impl Context {
       .gl
       .draw_elements_with_i32(mode.as_u32(), count, component_type as u32, offset);
   }
+
+  pub fn vertex_attrib_divisor(&self, location: u32, divisor: u32) {
+    self.gl.vertex_attrib_divisor(location, divisor);
+  }
+
+  pub fn draw_arrays_instanced(&self, mode: DrawMode, first: i32, count: i32, instances: i32) {
+    self
+      .gl
+      .draw_arrays_instanced(mode.as_u32(), first, count, instances);
+  }
+
+  pub fn draw_elements_instanced(
+    &self,
+    mode: DrawMode,
+    count: i32,
+    component_type: ComponentType,
+    offset: i32,
+    instances: i32,
+  ) {
+    self.gl.draw_elements_instanced_with_i32(
+      mode.as_u32(),
+      count,
+      component_type as u32,
+      offset,
+      instances,
+    );
+  }
 }
 
 pub enum Cleaning {
@@ -218,14 +245,27 @@ impl BufferBaseTarget {
   }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DrawMode {
+  Points,
+  Lines,
+  LineLoop,
+  LineStrip,
   Triangles,
+  TriangleStrip,
+  TriangleFan,
 }
 
 impl DrawMode {
   pub fn as_u32(&self) -> u32 {
     match self {
+      Self::Points => WebGl2RenderingContext::POINTS,
+      Self::Lines => WebGl2RenderingContext::LINES,
+      Self::LineLoop => WebGl2RenderingContext::LINE_LOOP,
+      Self::LineStrip => WebGl2RenderingContext::LINE_STRIP,
       Self::Triangles => WebGl2RenderingContext::TRIANGLES,
+      Self::TriangleStrip => WebGl2RenderingContext::TRIANGLE_STRIP,
+      Self::TriangleFan => WebGl2RenderingContext::TRIANGLE_FAN,
     }
   }
 }
@@ -1,27 +1,42 @@
-use generational_arena::Index;
 use na::{Isometry3, Matrix4, UnitQuaternion, Vector3, Vector4};
+use serde_json::{Map, Value};
+
+use crate::handle::Handle;
+use crate::renderer::webgl::renderer::{InstanceSet, Mesh};
+
+// custom properties attached by the authoring tool, e.g. glTF extras
+pub type UserData = Map<String, Value>;
 
 #[derive(Debug, Clone)]
 pub struct Node {
-  pub parent: Option<Index>,
-  pub children: Vec<Index>,
+  pub parent: Option<Handle<Node>>,
+  pub children: Vec<Handle<Node>>,
   pub matrix_local: Matrix4<f32>,
   pub matrix_world: Matrix4<f32>,
-  pub mesh: Option<Index>,
+  pub mesh: Option<Handle<Mesh>>,
+  pub instances: Option<Handle<InstanceSet>>,
   pub visible: bool,
+  // rendered into shadow maps, and darkened by them
+  pub cast_shadows: bool,
+  pub receive_shadows: bool,
   pub name: Option<String>,
+  pub user_data: UserData,
 }
 
 impl Node {
-  pub fn new(parent: Option<Index>) -> Self {
+  pub fn new(parent: Option<Handle<Node>>) -> Self {
     Node {
       parent,
       children: vec![],
       matrix_local: Matrix4::identity(),
       matrix_world: Matrix4::identity(),
       mesh: None,
+      instances: None,
       visible: true,
+      cast_shadows: true,
+      receive_shadows: true,
       name: None,
+      user_data: UserData::new(),
     }
   }
 }
@@ -40,3 +55,30 @@ pub fn compose_matrix(
 
   isometry.to_homogeneous() * node_scale_matrix
 }
+
+pub fn decompose_matrix(
+  matrix: &Matrix4<f32>,
+) -> (Vector3<f32>, UnitQuaternion<f32>, Vector3<f32>) {
+  let position = Vector3::new(matrix[(0, 3)], matrix[(1, 3)], matrix[(2, 3)]);
+
+  let mut basis = matrix.fixed_slice::<3, 3>(0, 0).into_owned();
+  let mut scale = Vector3::new(
+    basis.column(0).norm(),
+    basis.column(1).norm(),
+    basis.column(2).norm(),
+  );
+
+  if basis.determinant() < 0.0 {
+    scale.x = -scale.x;
+  }
+
+  for i in 0..3 {
+    if scale[i] != 0.0 {
+      basis.column_mut(i).scale_mut(1.0 / scale[i]);
+    }
+  }
+
+  let rotation = UnitQuaternion::from_matrix(&basis);
+
+  (position, rotation, scale)
+}
@@ -1,24 +1,25 @@
-use generational_arena::{Arena, Index};
 use na::Matrix4;
+use serde_json::Value;
 
 use super::node::Node;
+use crate::handle::{Handle, Pool};
 
 #[derive(Debug)]
 pub struct Scene {
-  root_handle: Index,
-  nodes: Arena<Node>,
+  root_handle: Handle<Node>,
+  nodes: Pool<Node>,
 }
 
 impl Scene {
   pub fn new() -> Self {
-    let mut nodes: Arena<Node> = Arena::new();
+    let mut nodes: Pool<Node> = Pool::new();
     let root_object = Node::new(None);
     let root_handle = nodes.insert(root_object);
 
     Scene { nodes, root_handle }
   }
 
-  pub fn insert(&mut self, object: Node) -> Index {
+  pub fn insert(&mut self, object: Node) -> Handle<Node> {
     let parent_handle_option = object.parent;
     let handle = self.nodes.insert(object);
 
@@ -30,7 +31,7 @@ impl Scene {
     handle
   }
 
-  pub fn set_parent(&mut self, child_handle: Index, parent_handle: Index) {
+  pub fn set_parent(&mut self, child_handle: Handle<Node>, parent_handle: Handle<Node>) {
     if let Some(current_parent_handle) = self.get_parent_handle(child_handle) {
       let parent = self.get_node_mut(current_parent_handle).unwrap();
 
@@ -46,7 +47,7 @@ impl Scene {
     parent.children.push(child_handle);
   }
 
-  fn remove_subtree(&mut self, handle: Index) -> Option<()> {
+  fn remove_subtree(&mut self, handle: Handle<Node>) -> Option<()> {
     let node = self.get_node(handle)?;
     let children = node.children.clone();
 
@@ -59,15 +60,36 @@ impl Scene {
     Some(())
   }
 
-  pub fn remove(&mut self, handle: Index) -> Option<()> {
+  // copies the subtree under a new parent, meshes and instance sets stay shared
+  pub fn clone_subtree(
+    &mut self,
+    handle: Handle<Node>,
+    parent_handle: Handle<Node>,
+  ) -> Option<Handle<Node>> {
+    let mut node = self.get_node(handle)?.clone();
+    let children = std::mem::take(&mut node.children);
+
+    node.parent = Some(parent_handle);
+
+    let clone_handle = self.insert(node);
+
+    for child_handle in children {
+      self.clone_subtree(child_handle, clone_handle);
+    }
+
+    Some(clone_handle)
+  }
+
+  pub fn remove(&mut self, handle: Handle<Node>) -> Option<()> {
     if handle == self.root_handle {
       panic!("cant remove root node");
     }
 
+    let parent_handle = self.get_parent_handle(handle);
+
     self.remove_subtree(handle);
 
-    let parent_handle = self.get_parent_handle(handle)?;
-    let parent = self.nodes.get_mut(parent_handle).unwrap();
+    let parent = self.nodes.get_mut(parent_handle?).unwrap();
 
     parent
       .children
@@ -80,7 +102,7 @@ impl Scene {
     self.update_matrix_world_subtree(self.root_handle);
   }
 
-  pub fn update_matrix_world_subtree(&mut self, handle: Index) {
+  pub fn update_matrix_world_subtree(&mut self, handle: Handle<Node>) {
     let parent_matrix_world = match self.get_parent_handle(handle) {
       Some(parent_handle) => self.get_node(parent_handle).unwrap().matrix_world,
       None => Matrix4::identity(),
@@ -97,23 +119,27 @@ impl Scene {
     }
   }
 
-  pub fn collect_visible_items(&self) -> Vec<Index> {
-    let mut items: Vec<Index> = vec![];
+  pub fn collect_visible_items(&self) -> Vec<Handle<Node>> {
+    let mut items: Vec<Handle<Node>> = vec![];
 
     self.collect_visible_items_subtree(self.root_handle, &mut items);
 
     items
   }
 
-  pub fn collect_visible_sub_items(&self, parent_handle: Index) -> Vec<Index> {
-    let mut items: Vec<Index> = vec![];
+  pub fn collect_visible_sub_items(&self, parent_handle: Handle<Node>) -> Vec<Handle<Node>> {
+    let mut items: Vec<Handle<Node>> = vec![];
 
     self.collect_visible_items_subtree(parent_handle, &mut items);
 
     items
   }
 
-  pub fn collect_visible_items_subtree(&self, handle: Index, items: &mut Vec<Index>) -> Option<()> {
+  pub fn collect_visible_items_subtree(
+    &self,
+    handle: Handle<Node>,
+    items: &mut Vec<Handle<Node>>,
+  ) -> Option<()> {
     let node = self.get_node(handle)?;
 
     if node.visible {
@@ -129,20 +155,65 @@ impl Scene {
     Some(())
   }
 
-  pub fn get_node(&self, handle: Index) -> Option<&Node> {
+  // same as collect_visible_sub_items, but hidden subtrees are included
+  pub fn collect_sub_items(&self, parent_handle: Handle<Node>) -> Vec<Handle<Node>> {
+    self.find_sub_items(parent_handle, |node| node.mesh.is_some())
+  }
+
+  pub fn find_sub_items<F>(&self, parent_handle: Handle<Node>, predicate: F) -> Vec<Handle<Node>>
+  where
+    F: Fn(&Node) -> bool,
+  {
+    let mut items: Vec<Handle<Node>> = vec![];
+    let mut stack = vec![parent_handle];
+
+    while let Some(handle) = stack.pop() {
+      if let Some(node) = self.get_node(handle) {
+        if predicate(node) {
+          items.push(handle);
+        }
+
+        stack.extend(node.children.iter().rev());
+      }
+    }
+
+    items
+  }
+
+  pub fn find_by_name(&self, parent_handle: Handle<Node>, name: &str) -> Option<Handle<Node>> {
+    self
+      .find_sub_items(parent_handle, |node| node.name.as_deref() == Some(name))
+      .first()
+      .copied()
+  }
+
+  pub fn find_by_user_data(&self, parent_handle: Handle<Node>, key: &str) -> Vec<Handle<Node>> {
+    self.find_sub_items(parent_handle, |node| node.user_data.contains_key(key))
+  }
+
+  pub fn get_user_data(&self, handle: Handle<Node>, key: &str) -> Option<&Value> {
+    self.get_node(handle)?.user_data.get(key)
+  }
+
+  pub fn get_node(&self, handle: Handle<Node>) -> Option<&Node> {
     self.nodes.get(handle)
   }
 
-  pub fn get_node_mut(&mut self, handle: Index) -> Option<&mut Node> {
+  pub fn get_node_mut(&mut self, handle: Handle<Node>) -> Option<&mut Node> {
     self.nodes.get_mut(handle)
   }
 
-  pub fn get_parent_handle(&self, handle: Index) -> Option<Index> {
+  // every node, attached to the root or not
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<Node>, &mut Node)> {
+    self.nodes.iter_mut()
+  }
+
+  pub fn get_parent_handle(&self, handle: Handle<Node>) -> Option<Handle<Node>> {
     let node = self.nodes.get(handle)?;
     node.parent
   }
 
-  pub fn get_root_handle(&self) -> Index {
+  pub fn get_root_handle(&self) -> Handle<Node> {
     self.root_handle
   }
 }